@@ -0,0 +1,320 @@
+use std::collections::HashMap;
+use std::fmt::{Display, Formatter};
+
+use crate::cell::{Cell, DoubleCell};
+use crate::literal::parse_literal;
+use crate::machine_error::MachineError;
+use crate::machine_memory::MachineMemory;
+use crate::mem::Address;
+use crate::opcodes::{ALL_OP_CODES, OpCode};
+
+#[derive(Debug)]
+pub enum AsmError {
+    UnknownMnemonic { line: usize, mnemonic: String },
+    /// Operand is missing, is not a number or label, or does not fit into the instruction.
+    InvalidOperand { line: usize, operand: String },
+    UnresolvedLabel { line: usize, label: String },
+    DuplicateLabel { line: usize, label: String },
+    /// Assembled code does not fit into dictionary.
+    MachineError { line: usize, err: MachineError },
+}
+
+impl Display for AsmError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AsmError::UnknownMnemonic { line, mnemonic } => write!(f, "line {}: unknown mnemonic {}", line, mnemonic),
+            AsmError::InvalidOperand { line, operand } => write!(f, "line {}: invalid operand \"{}\"", line, operand),
+            AsmError::UnresolvedLabel { line, label } => write!(f, "line {}: unresolved label {}", line, label),
+            AsmError::DuplicateLabel { line, label } => write!(f, "line {}: duplicate label {}", line, label),
+            AsmError::MachineError { line, err } => write!(f, "line {}: {:?}", line, err),
+        }
+    }
+}
+
+/// Operand of a branch or call instruction.
+enum Target<'s> {
+    Address(Address),
+    Label(&'s str),
+}
+
+enum Operand<'s> {
+    None,
+    Value(DoubleCell),
+    Target(Target<'s>),
+    Bytes(&'s [u8]),
+}
+
+struct Instruction<'s> {
+    line: usize,
+    op_code: OpCode,
+    operand: Operand<'s>,
+}
+
+impl Instruction<'_> {
+    fn size(&self) -> u16 {
+        match &self.operand {
+            Operand::Bytes(bytes) => 2 + bytes.len() as u16,
+            _ => 1 + self.op_code.operand_size().unwrap_or(0),
+        }
+    }
+}
+
+fn find_op_code(mnemonic: &str) -> Option<OpCode> {
+    ALL_OP_CODES.iter().copied().find(|op_code| op_code.mnemonic() == mnemonic)
+}
+
+fn is_label(name: &str) -> bool {
+    name.chars().next().is_some_and(|first| first.is_ascii_alphabetic() || first == '_')
+        && name.chars().all(|chr| chr.is_ascii_alphanumeric() || chr == '_')
+}
+
+/// Remove address prefix (`XXXX: `) printed by disassembler.
+fn strip_address(line: &str) -> &str {
+    match line.split_once(": ") {
+        Some((address, rest)) if address.len() == 4 && address.chars().all(|chr| chr.is_ascii_hexdigit()) => rest,
+        _ => line,
+    }
+}
+
+fn parse_line(line: usize, text: &str) -> Result<Instruction<'_>, AsmError> {
+    let (mnemonic, rest) = text.split_once(' ').unwrap_or((text, ""));
+    let op_code = find_op_code(mnemonic)
+        .ok_or_else(|| AsmError::UnknownMnemonic { line, mnemonic: mnemonic.to_string() })?;
+
+    // Disassembler follows operands by their other representations in parentheses, only the first word matters
+    let first_word = rest.split_whitespace().next().unwrap_or("");
+    let invalid_operand = || AsmError::InvalidOperand { line, operand: rest.to_string() };
+    let number = |radix| {
+        let value: Cell = parse_literal(first_word.as_bytes(), radix).ok_or_else(invalid_operand)?;
+
+        Ok(value as DoubleCell)
+    };
+    let target = |radix| {
+        if is_label(first_word) && parse_literal(first_word.as_bytes(), radix).is_none() {
+            Ok(Operand::Target(Target::Label(first_word)))
+        } else {
+            number(radix).map(|value| Operand::Target(Target::Address(value as Address)))
+        }
+    };
+
+    let operand = match op_code {
+        OpCode::LiteralString | OpCode::ExecBuiltin if rest.len() <= u8::MAX as usize => Operand::Bytes(rest.as_bytes()),
+        OpCode::LiteralString | OpCode::ExecBuiltin => return Err(invalid_operand()),
+        OpCode::Literal16 => Operand::Value(number(16)?),
+        OpCode::Literal32 => {
+            Operand::Value(DoubleCell::from_str_radix(first_word, 16).map_err(|_| invalid_operand())?)
+        }
+        OpCode::Literal8 => match number(16)? {
+            value @ 0..=0xFF => Operand::Value(value),
+            _ => return Err(invalid_operand()),
+        },
+        OpCode::ExecNative => Operand::Value(number(10)?),
+        OpCode::Call | OpCode::CompileCall | OpCode::GoTo | OpCode::GoToIfZ => target(16)?,
+        // Relative offsets are decimal and signed, e.g. `+12` or `-7`
+        OpCode::BranchRel | OpCode::BranchRelIfZ | OpCode::CallRel => {
+            target(10).map_err(|_| invalid_operand())?
+        }
+        _ if rest.trim().is_empty() => Operand::None,
+        _ => return Err(invalid_operand()),
+    };
+
+    Ok(Instruction { line, op_code, operand })
+}
+
+/// Assemble code written in notation printed by disassembler (`OpCode::format`) and write it to dictionary.
+///
+/// Each line contains an instruction (optionally prefixed by an address as printed by `OpCode::format_at`) or a
+/// label definition `name:`. Labels can be used instead of addresses in `call`, `jump` and similar instructions and
+/// instead of offsets in relative ones. Empty lines and lines starting with `\` are ignored. Operands of relative
+/// instructions are offsets, so a listing of a single word can be assembled at a different address; the target
+/// addresses in parentheses are ignored.
+///
+/// Returns address of the first assembled instruction.
+pub fn assemble(source: &str, memory: &mut MachineMemory) -> Result<Address, AsmError> {
+    let start_address = memory.get_dict_ptr();
+    let mut address = start_address;
+    let mut labels = HashMap::new();
+    let mut instructions = Vec::new();
+
+    for (index, text) in source.lines().enumerate() {
+        let line = index + 1;
+        let text = strip_address(text.trim_start());
+
+        if text.trim().is_empty() || text.starts_with('\\') {
+            continue;
+        }
+
+        if let Some(label) = text.trim_end().strip_suffix(':').filter(|label| is_label(label)) {
+            if labels.insert(label, address).is_some() {
+                return Err(AsmError::DuplicateLabel { line, label: label.to_string() });
+            }
+
+            continue;
+        }
+
+        let instruction = parse_line(line, text.trim_end())?;
+        address = address.wrapping_add(instruction.size());
+        instructions.push(instruction);
+    }
+
+    // Check labels before writing anything, so dictionary is left intact on failure
+    for instruction in &instructions {
+        if let Operand::Target(Target::Label(label)) = instruction.operand {
+            if !labels.contains_key(label) {
+                return Err(AsmError::UnresolvedLabel { line: instruction.line, label: label.to_string() });
+            }
+        }
+    }
+
+    for instruction in instructions {
+        let line = instruction.line;
+        let instruction_address = memory.get_dict_ptr();
+        let resolve = |target: &Target| match target {
+            Target::Address(address) => *address,
+            Target::Label(label) => labels[label],
+        };
+
+        let result = match (&instruction.operand, instruction.op_code) {
+            (operand, op_code @ (OpCode::BranchRel | OpCode::BranchRelIfZ | OpCode::CallRel)) => {
+                let offset = match operand {
+                    Operand::Target(Target::Address(offset)) => *offset,
+                    Operand::Target(label) => resolve(label).wrapping_sub(instruction_address.wrapping_add(3)),
+                    _ => unreachable!("relative instructions always have a target"),
+                };
+
+                memory.dict_write_opcode(op_code).and_then(|_| memory.dict_write_u16(offset))
+            }
+            (Operand::Target(target), op_code) => {
+                let target = resolve(target);
+
+                memory.dict_write_opcode(op_code).and_then(|_| memory.dict_write_u16(target))
+            }
+            (Operand::Value(value), OpCode::Literal16) => {
+                memory.dict_write_opcode(OpCode::Literal16).and_then(|_| memory.dict_write_cell(*value as _))
+            }
+            (Operand::Value(value), OpCode::Literal32) => {
+                memory.dict_write_opcode(OpCode::Literal32).and_then(|_| memory.dict_write_double_cell(*value))
+            }
+            (Operand::Value(value), OpCode::Literal8) => {
+                memory.dict_write_opcode(OpCode::Literal8).and_then(|_| memory.dict_write_u8(*value as u8))
+            }
+            (Operand::Value(value), op_code) => {
+                memory.dict_write_opcode(op_code).and_then(|_| memory.dict_write_u16(*value as u16))
+            }
+            (Operand::Bytes(bytes), op_code) => {
+                memory.dict_write_opcode(op_code)
+                    .and_then(|_| memory.dict_write_u8(bytes.len() as u8))
+                    .and_then(|_| bytes.iter().try_for_each(|byte| memory.dict_write_u8(*byte)))
+            }
+            (Operand::None, op_code) => memory.dict_write_opcode(op_code),
+        };
+
+        result.map_err(|err| AsmError::MachineError { line, err })?;
+    }
+
+    Ok(start_address)
+}
+
+#[cfg(test)]
+mod test {
+    use std::str::from_utf8;
+
+    use crate::cell::Cell;
+    use crate::input::StaticStringInput;
+    use crate::machine_testing::*;
+
+    use super::*;
+
+    #[test]
+    fn test_assemble_with_labels() {
+        let mut machine = TestMachine::default();
+        let start = assemble("
+            \\ Sum of numbers from n down to 1
+            push0
+            swap
+            loop:
+            dup
+            jumpz done
+            dup
+            rot
+            add
+            swap
+            push1
+            sub
+            rjump loop
+            done:
+            drop
+            ret
+        ", &mut machine.memory).unwrap();
+
+        machine.memory.data_push_cell(10).unwrap();
+        machine.run_until_exit(start).unwrap();
+
+        machine.assert_data_stack_state(&[StackElement::Cell(55)]);
+    }
+
+    #[test]
+    fn test_reassemble_disassembly() {
+        let mut machine = TestMachine::default();
+        machine.extensions.input = StaticStringInput::new("
+            : classify 100000. DROP DROP DUP 0 < IF DROP S\" negative\" ELSE 300 + S\" ok\" THEN TYPE ;
+        ");
+        machine.interpret_input().unwrap();
+
+        let article = machine.memory.lookup_article(b"classify").unwrap().unwrap();
+        let (body_address, end_address) = (article.body_address(), machine.memory.article_end(&article));
+        let mut listing = Vec::new();
+        let mut address = body_address;
+
+        while address < end_address {
+            address = OpCode::format_at(&mut listing, &machine, address).unwrap();
+        }
+
+        let listing = from_utf8(&listing).unwrap().to_string();
+        let start = assemble(&listing, &mut machine.memory).unwrap();
+
+        assert_eq!(
+            machine.memory.raw_memory.address_slice(start, (end_address - body_address) as usize),
+            machine.memory.raw_memory.address_slice(body_address, (end_address - body_address) as usize),
+        );
+
+        for (value, output) in [(5 as Cell, "ok"), ((-5i16) as Cell, "negative")] {
+            machine.memory.data_push_cell(value).unwrap();
+            machine.run_until_exit(start).unwrap();
+
+            assert_eq!(machine.extensions.output.content.take(), output.as_bytes());
+        }
+
+        machine.assert_data_stack_state(&[StackElement::Cell(305)]);
+    }
+
+    #[test]
+    fn test_unknown_mnemonic() {
+        let mut machine = TestMachine::default();
+
+        assert!(matches!(
+            assemble("dup\nfrobnicate 12\n", &mut machine.memory),
+            Err(AsmError::UnknownMnemonic { line: 2, mnemonic }) if mnemonic == "frobnicate"
+        ));
+    }
+
+    #[test]
+    fn test_unresolved_label() {
+        let mut machine = TestMachine::default();
+        let dict_ptr = machine.memory.get_dict_ptr();
+
+        assert!(matches!(
+            assemble("start:\nrjump start\njumpz finish\n", &mut machine.memory),
+            Err(AsmError::UnresolvedLabel { line: 3, label }) if label == "finish"
+        ));
+        assert!(matches!(
+            assemble("a:\na:\n", &mut machine.memory),
+            Err(AsmError::DuplicateLabel { line: 2, .. })
+        ));
+        assert!(matches!(
+            assemble("push8 1FF\n", &mut machine.memory),
+            Err(AsmError::InvalidOperand { line: 1, .. })
+        ));
+        assert_eq!(machine.memory.get_dict_ptr(), dict_ptr);
+    }
+}
@@ -1,27 +1,257 @@
+use std::marker::PhantomData;
+use std::sync::OnceLock;
+
 use int_enum::IntEnum;
 
-use crate::literal::parse_literal;
+use crate::cell::{aligned, Cell, CELL_BYTES, DOUBLE_CELL_BYTES, DoubleCell, FALSE, TRUE};
+use crate::input::PromptContext;
 use crate::machine::{Machine, MachineExtensions};
-use crate::input::Input;
 use crate::machine_error::MachineError;
-use crate::machine_memory::ReservedAddresses;
+use crate::machine_memory::{MachineMemory, ReservedAddresses};
 use crate::machine_state::MachineState;
-use crate::mem::{Address, MemoryAccessError};
-use crate::opcodes::OpCode;
-use crate::output::Output;
+use crate::mem::{AccessKind, Address};
+use crate::memory_segment::DictionarySegment;
+use crate::opcodes::{
+    compile_call, compile_relative_jump, find_opcode_synonym, next_name_token, store_double_cell,
+    validate_execution_token, OpCode,
+};
+use crate::output::{Output, OutputError};
 use crate::readable_article::ReadableArticle;
 use crate::sized_string::{ReadableSizedString, SizedStringWriter};
 use crate::stack_effect::stack_effect;
 
-fn compile_u16_literal<TExt: MachineExtensions>(machine: &mut Machine<TExt>, value: u16) -> Result<(), MemoryAccessError> {
+/// Function implementing a builtin word, receives address of the word name stored as a sized string.
+pub type BuiltinHandler<TExt> = fn(&mut Machine<TExt>, Address) -> Result<(), MachineError>;
+
+/// How a builtin word behaves in compiler state.
+#[derive(Clone, Copy, Default, Debug, PartialEq, Eq)]
+pub struct BuiltinFlags {
+    /// The word does its job while a definition is being compiled instead of being compiled into it.
+    pub immediate: bool,
+
+    /// The word fails with `MachineError::IllegalMode` in interpreter state.
+    pub compile_only: bool,
+}
+
+impl BuiltinFlags {
+    pub const NONE: BuiltinFlags = BuiltinFlags { immediate: false, compile_only: false };
+    pub const IMMEDIATE: BuiltinFlags = BuiltinFlags { immediate: true, compile_only: false };
+    pub const COMPILE_ONLY: BuiltinFlags = BuiltinFlags { immediate: false, compile_only: true };
+    pub const IMMEDIATE_COMPILE_ONLY: BuiltinFlags = BuiltinFlags { immediate: true, compile_only: true };
+}
+
+/// Entry of the builtin word registry.
+pub struct BuiltinWord<TExt: MachineExtensions> {
+    pub name: &'static str,
+    pub flags: BuiltinFlags,
+    pub handler: BuiltinHandler<TExt>,
+}
+
+impl<TExt: MachineExtensions> Clone for BuiltinWord<TExt> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<TExt: MachineExtensions> Copy for BuiltinWord<TExt> {}
+
+impl<TExt: MachineExtensions> BuiltinWord<TExt> {
+    pub const fn new(name: &'static str, flags: BuiltinFlags, handler: BuiltinHandler<TExt>) -> Self {
+        BuiltinWord { name, flags, handler }
+    }
+}
+
+/// Builtin words added or overridden by host, consulted before the builtin word table.
+pub struct BuiltinWords<TExt: MachineExtensions> {
+    words: Vec<BuiltinWord<TExt>>,
+}
+
+impl<TExt: MachineExtensions> Default for BuiltinWords<TExt> {
+    fn default() -> Self {
+        BuiltinWords { words: Vec::new() }
+    }
+}
+
+impl<TExt: MachineExtensions> Clone for BuiltinWords<TExt> {
+    fn clone(&self) -> Self {
+        BuiltinWords { words: self.words.clone() }
+    }
+}
+
+impl<TExt: MachineExtensions> BuiltinWords<TExt> {
+    fn find(&self, name: &[u8]) -> Option<&BuiltinWord<TExt>> {
+        self.words.iter().find(|word| word.name.as_bytes() == name)
+    }
+}
+
+impl<TExt: MachineExtensions> Machine<TExt> {
+    /// Add a builtin word, replacing a builtin word with the same name if there is one.
+    ///
+    /// Unless the word is immediate, its handler runs only in interpreter state; in compiler state a call of the word
+    /// by name is compiled instead.
+    ///
+    /// Code compiled earlier keeps op-codes the replaced word compiled to, only words that are looked up by name at
+    /// run time (such as postponed builtin words) use the new handler.
+    pub fn register_builtin_word(&mut self, word: BuiltinWord<TExt>) {
+        match self.builtin_words.words.iter_mut().find(|registered| registered.name == word.name) {
+            Some(registered) => *registered = word,
+            None => self.builtin_words.words.push(word),
+        }
+    }
+
+    /// Remove a builtin word added by `register_builtin_word`, restoring the original word it replaced.
+    ///
+    /// Returns `false` if there is no such word.
+    pub fn unregister_builtin_word(&mut self, name: &str) -> bool {
+        let length = self.builtin_words.words.len();
+        self.builtin_words.words.retain(|word| word.name != name);
+
+        self.builtin_words.words.len() != length
+    }
+
+    /// Find a builtin word with given name, taking words registered by host into account.
+    pub fn find_builtin_word(&self, name: &[u8]) -> Option<BuiltinWord<TExt>> {
+        self.builtin_words.find(name).or_else(|| find_table_word(name)).copied()
+    }
+
+    /// All builtin words: words of `builtin_word_table` in order, with overridden words replaced, followed by words
+    /// added by host.
+    pub fn all_builtin_words(&self) -> Vec<BuiltinWord<TExt>> {
+        let table = builtin_word_table::<TExt>().iter()
+            .map(|word| *self.builtin_words.find(word.name.as_bytes()).unwrap_or(word));
+        let added = self.builtin_words.words.iter()
+            .filter(|word| find_table_word::<TExt>(word.name.as_bytes()).is_none())
+            .copied();
+
+        table.chain(added).collect()
+    }
+}
+
+/// Builtin words compiled to a single op-code along with their stack comments, used to show compiled code as source
+/// and to name the word that failed in stack errors.
+pub const OPCODE_WORDS: &[(OpCode, &str, &str)] = &[
+    (OpCode::Over16, "OVER", "x1 x2 -- x1 x2 x1"),
+    (OpCode::Over32, "2OVER", "x1 x2 x3 x4 -- x1 x2 x3 x4 x1 x2"),
+    (OpCode::Swap16, "SWAP", "x1 x2 -- x2 x1"),
+    (OpCode::Swap32, "2SWAP", "x1 x2 x3 x4 -- x3 x4 x1 x2"),
+    (OpCode::Dup16, "DUP", "x -- x x"),
+    (OpCode::Dup32, "2DUP", "x1 x2 -- x1 x2 x1 x2"),
+    (OpCode::Drop16, "DROP", "x --"),
+    (OpCode::Rot16, "ROT", "x1 x2 x3 -- x2 x3 x1"),
+    (OpCode::Add16, "+", "n1 n2 -- n3"),
+    (OpCode::Sub16, "-", "n1 n2 -- n3"),
+    (OpCode::Mul16, "*", "n1 n2 -- n3"),
+    (OpCode::Div16, "/", "n1 n2 -- n3"),
+    (OpCode::Load16, "@", "a-addr -- x"),
+    (OpCode::Store16, "!", "x a-addr --"),
+    (OpCode::Load8, "C@", "c-addr -- char"),
+    (OpCode::Store8, "C!", "char c-addr --"),
+    (OpCode::Load32, "2@", "a-addr -- x1 x2"),
+    (OpCode::Store32, "2!", "x1 x2 a-addr --"),
+    (OpCode::Lt16, "<", "n1 n2 -- flag"),
+    (OpCode::Gt16, ">", "n1 n2 -- flag"),
+    (OpCode::Eq16, "=", "x1 x2 -- flag"),
+    (OpCode::Invert16, "INVERT", "x1 -- x2"),
+    (OpCode::And16, "AND", "x1 x2 -- x3"),
+    (OpCode::Or16, "OR", "x1 x2 -- x3"),
+    (OpCode::Xor16, "XOR", "x1 x2 -- x3"),
+    (OpCode::I16ToI32, "S>D", "n -- d"),
+    (OpCode::CallRead16, "R@", "-- x ) ( R: x -- x"),
+    (OpCode::CallRead32, "2R@", "-- x1 x2 ) ( R: x1 x2 -- x1 x2"),
+    (OpCode::CallPush16, ">R", "x -- ) ( R: -- x"),
+    (OpCode::CallPop16, "R>", "-- x ) ( R: x --"),
+    (OpCode::CallPush32, "2>R", "x1 x2 -- ) ( R: -- x1 x2"),
+    (OpCode::CallPop32, "2R>", "-- x1 x2 ) ( R: x1 x2 --"),
+    (OpCode::Abs16, "ABS", "n -- u"),
+    (OpCode::Align, "ALIGN", "--"),
+    (OpCode::Aligned, "ALIGNED", "addr -- a-addr"),
+    (OpCode::Comma, ",", "x --"),
+    (OpCode::CommaByte, "C,", "char --"),
+    (OpCode::Emit, "EMIT", "x --"),
+    (OpCode::XEmit, "XEMIT", "xchar --"),
+    (OpCode::Flush, "FLUSH", "--"),
+    (OpCode::Ms, "MS", "u --"),
+    (OpCode::TimeAndDate, "TIME&DATE", "-- +n1 +n2 +n3 +n4 +n5 +n6"),
+    (OpCode::Bye, "BYE", "--"),
+    (OpCode::Cr, "CR", "--"),
+    (OpCode::Space, "SPACE", "--"),
+    (OpCode::Spaces, "SPACES", "n --"),
+    (OpCode::OpenFile, "OPEN-FILE", "c-addr u fam -- fileid ior"),
+    (OpCode::CreateFile, "CREATE-FILE", "c-addr u fam -- fileid ior"),
+    (OpCode::CloseFile, "CLOSE-FILE", "fileid -- ior"),
+    (OpCode::ReadFile, "READ-FILE", "c-addr u1 fileid -- u2 ior"),
+    (OpCode::ReadLine, "READ-LINE", "c-addr u1 fileid -- u2 flag ior"),
+    (OpCode::WriteFile, "WRITE-FILE", "c-addr u fileid -- ior"),
+    (OpCode::WriteLine, "WRITE-LINE", "c-addr u fileid -- ior"),
+    (OpCode::FilePosition, "FILE-POSITION", "fileid -- ud ior"),
+    (OpCode::RepositionFile, "REPOSITION-FILE", "ud fileid -- ior"),
+    (OpCode::FileSize, "FILE-SIZE", "fileid -- ud ior"),
+    (OpCode::DeleteFile, "DELETE-FILE", "c-addr u -- ior"),
+    (OpCode::SaveImage, "SAVE-IMAGE", "addr u --"),
+    (OpCode::EmitString, "TYPE", "c-addr u --"),
+    (OpCode::PnoInit, "<#", "--"),
+    (OpCode::PnoPut, "HOLD", "char --"),
+    (OpCode::PnoFinish, "#>", "xd -- c-addr u"),
+    (OpCode::PnoPutDigit, "#", "ud1 -- ud2"),
+    (OpCode::Break, "BREAK", "--"),
+    (OpCode::UTime, "UTIME", "-- ud"),
+    (OpCode::Counter, "COUNTER", "-- ud"),
+    (OpCode::SpFetch, "SP@", "-- addr"),
+    (OpCode::SpStore, "SP!", "addr --"),
+    (OpCode::RpFetch, "RP@", "-- addr"),
+    (OpCode::RpStore, "RP!", "addr --"),
+    (OpCode::Allocate, "ALLOCATE", "u -- a-addr ior"),
+    (OpCode::Free, "FREE", "a-addr -- ior"),
+    (OpCode::Resize, "RESIZE", "a-addr1 u -- a-addr2 ior"),
+    (OpCode::CallPushN, "N>R", "i*x +n -- ) ( R: -- j*x +n"),
+    (OpCode::CallPopN, "NR>", "-- i*x +n ) ( R: j*x +n --"),
+    (OpCode::Execute, "EXECUTE", "i*x xt -- j*x"),
+    (OpCode::NameToString, "NAME>STRING", "nt -- c-addr u"),
+    (OpCode::NameToInterpret, "NAME>INTERPRET", "nt -- xt|0"),
+    (OpCode::CompileXt, "COMPILE,", "xt --"),
+    (OpCode::NameIsImmediate, "IMMEDIATE?", "nt -- flag"),
+];
+
+/// Name of the builtin word compiled to given op-code, if there is one.
+pub fn opcode_word(op_code: OpCode) -> Option<&'static str> {
+    OPCODE_WORDS.iter().find(|(code, _, _)| *code == op_code).map(|(_, name, _)| *name)
+}
+
+/// Standard stack comment of the builtin word compiled to given op-code, if there is one.
+pub fn opcode_word_stack_comment(op_code: OpCode) -> Option<&'static str> {
+    OPCODE_WORDS.iter().find(|(code, _, _)| *code == op_code).map(|(_, _, comment)| *comment)
+}
+
+/// Compile a literal using the shortest op-code able to represent given value.
+pub(crate) fn compile_cell_literal<TExt: MachineExtensions>(machine: &mut Machine<TExt>, value: Cell) -> Result<(), MachineError> {
+    match value {
+        0 => machine.memory.dict_write_opcode(OpCode::Push0),
+        1 => machine.memory.dict_write_opcode(OpCode::Push1),
+        TRUE => machine.memory.dict_write_opcode(OpCode::PushTrue),
+        2..=0xFF => {
+            machine.memory.dict_write_opcode(OpCode::Literal8)?;
+            machine.memory.dict_write_u8(value as u8)
+        }
+        _ => compile_full_cell_literal(machine, value),
+    }
+}
+
+/// Compile a literal taking a full cell regardless of value.
+fn compile_full_cell_literal<TExt: MachineExtensions>(machine: &mut Machine<TExt>, value: Cell) -> Result<(), MachineError> {
     machine.memory.dict_write_opcode(OpCode::Literal16)?;
-    machine.memory.dict_write_u16(value)
+    machine.memory.dict_write_cell(value)
+}
+
+/// Compile a double-cell literal.
+pub(crate) fn compile_double_literal<TExt: MachineExtensions>(machine: &mut Machine<TExt>, value: DoubleCell) -> Result<(), MachineError> {
+    machine.memory.dict_write_opcode(OpCode::Literal32)?;
+    machine.memory.dict_write_double_cell(value)
 }
 
-fn process_literal<TExt: MachineExtensions>(machine: &mut Machine<TExt>, value: u16) -> Result<(), MemoryAccessError> {
+fn process_literal<TExt: MachineExtensions>(machine: &mut Machine<TExt>, value: Cell) -> Result<(), MachineError> {
     match machine.memory.get_state() {
-        MachineState::Interpreter => machine.memory.data_push_u16(value),
-        MachineState::Compiler => compile_u16_literal(machine, value)
+        MachineState::Interpreter => machine.push(value),
+        MachineState::Compiler => compile_cell_literal(machine, value)
     }
 }
 
@@ -53,17 +283,26 @@ pub fn process_compile_only_opcode<TExt: MachineExtensions>(machine: &mut Machin
 pub fn compile_string_literal<TExt: MachineExtensions>(machine: &mut Machine<TExt>) -> Result<(), MachineError> {
     machine.memory.dict_write_opcode(OpCode::LiteralString)?;
 
-    let start_address = machine.memory.get_dict_ptr();
-    let safe_range = machine.memory.get_free_data_segment();
-    let mut writer = SizedStringWriter::new(&mut machine.memory.raw_memory, start_address, u8::MAX, safe_range)?;
+    let mut content = Vec::new();
+    machine.input().set_prompt_context(PromptContext::String);
 
     loop {
-        let ch = machine.extensions.get_input().read()?.ok_or(MachineError::UnexpectedInputEOF)?;
+        let ch = machine.input().read()?.ok_or(MachineError::UnexpectedInputEOF)?;
 
         if ch == b'"' {
             break;
         }
 
+        content.push(ch);
+    }
+
+    let start_address = machine.memory.get_dict_ptr();
+    let safe_range = machine.memory.get_free_data_segment();
+    let available = machine.memory.free_data_space();
+    let mut writer = SizedStringWriter::new(&mut machine.memory.raw_memory, start_address, u8::MAX, safe_range)
+        .map_err(|_| MachineError::OutOfDataSpace { needed: u8::MAX as u16 + 1, available })?;
+
+    for ch in content {
         writer.append_u8(ch)?;
     }
 
@@ -73,270 +312,1527 @@ pub fn compile_string_literal<TExt: MachineExtensions>(machine: &mut Machine<TEx
     Ok(())
 }
 
-pub fn process_constant<TExt: MachineExtensions>(machine: &mut Machine<TExt>, value: u16) -> Result<(), MachineError> {
+pub fn process_constant<TExt: MachineExtensions>(machine: &mut Machine<TExt>, value: Cell) -> Result<(), MachineError> {
     match machine.memory.get_state() {
-        MachineState::Interpreter => machine.memory.data_push_u16(value)?,
-        MachineState::Compiler => {
-            machine.memory.dict_write_opcode(OpCode::Literal16)?;
-            machine.memory.dict_write_u16(value)?
-        }
+        MachineState::Interpreter => machine.push(value)?,
+        MachineState::Compiler => compile_cell_literal(machine, value)?,
     }
 
     Ok(())
 }
 
-const TRUE: u16 = 0xFFFF;
-const FALSE: u16 = 0;
+/// Read name of a new article from input and write header of the article followed by `DefaultArticleStart`.
+///
+/// Returns address of the header. The article is not added to the list of articles.
+fn create_article_header<TExt: MachineExtensions>(machine: &mut Machine<TExt>) -> Result<Address, MachineError> {
+    let name_buffer_address = machine.read_input_word()?.ok_or(MachineError::UnexpectedInputEOF)?;
 
-pub fn process_builtin_word<TExt: MachineExtensions>(machine: &mut Machine<TExt>, name_address: Address) -> Result<(), MachineError> {
-    match ReadableSizedString::new(&machine.memory.raw_memory, name_address, machine.memory.raw_memory.address_range())?
-        .as_bytes() {
-        b":" => {
-            machine.expect_state(MachineState::Interpreter)?;
+    let article_start_address = machine.memory.get_dict_ptr();
+    let previous_article_address = machine.memory.last_article_ptr.unwrap_or(Address::MAX);
 
-            if let Some(_) = machine.memory.get_current_word() {
-                return Err(MachineError::IllegalCompilerState);
-            }
+    machine.memory.dict_write_u16(previous_article_address)?;
+    machine.memory.dict_write_sized_string(name_buffer_address)?;
+    machine.memory.dict_write_opcode(OpCode::DefaultArticleStart)?;
 
-            let name_buffer_address = machine.memory
-                .read_input_word(machine.extensions.get_input())?
-                .ok_or(MachineError::UnexpectedInputEOF)?;
+    Ok(article_start_address)
+}
 
-            let article_start_address = machine.memory.get_dict_ptr();
-            let previous_article_address = machine.memory.last_article_ptr.unwrap_or(Address::MAX);
+fn include_file<TExt: MachineExtensions>(machine: &mut Machine<TExt>, path: &str, required: bool) -> Result<(), MachineError> {
+    if required {
+        machine.require_file(path)
+    } else {
+        machine.include_file(path)
+    }
+}
+/// Compile `ExecBuiltin` running the builtin word with given name when the compiled code is executed.
+fn compile_exec_builtin<TExt: MachineExtensions>(machine: &mut Machine<TExt>, name_address: Address) -> Result<(), MachineError> {
+    machine.memory.dict_write_opcode(OpCode::ExecBuiltin)?;
 
-            machine.memory.dict_write_u16(previous_article_address)?;
-            machine.memory.dict_write_sized_string(name_buffer_address)?;
-            machine.memory.dict_write_opcode(OpCode::DefaultArticleStart)?;
+    machine.memory.dict_write_sized_string(name_address)
+}
 
-            machine.memory.set_current_word(Some(article_start_address));
+fn begin_definition<TExt: MachineExtensions>(machine: &mut Machine<TExt>, _: Address) -> Result<(), MachineError> {
+    machine.expect_state(MachineState::Interpreter)?;
 
-            machine.memory.set_state(MachineState::Compiler);
-        }
-        b";" => {
-            machine.expect_state(MachineState::Compiler)?;
-            let article_start_address = machine.memory.get_current_word().ok_or(MachineError::IllegalCompilerState)?;
+    if machine.memory.get_current_word().is_some() {
+        return Err(MachineError::IllegalCompilerState);
+    }
+
+    let article_start_address = create_article_header(machine)?;
+
+    machine.memory.set_current_word(Some(article_start_address));
 
-            machine.memory.dict_write_opcode(OpCode::Return)?;
+    machine.memory.set_state(MachineState::Compiler);
+
+    Ok(())
+}
+
+fn end_definition<TExt: MachineExtensions>(machine: &mut Machine<TExt>, _: Address) -> Result<(), MachineError> {
+    let article_start_address = machine.memory.get_current_word().ok_or(MachineError::IllegalCompilerState)?;
+
+    machine.memory.dict_write_opcode(OpCode::Return)?;
+
+    machine.memory.last_article_ptr = Some(article_start_address);
+    machine.memory.set_current_word(None);
+    machine.memory.set_state(MachineState::Interpreter);
+
+    Ok(())
+}
+
+fn create_variable<TExt: MachineExtensions>(machine: &mut Machine<TExt>, _: Address) -> Result<(), MachineError> {
+    machine.expect_state(MachineState::Interpreter)?;
+
+    create_data_word(machine, CELL_BYTES)
+}
+
+/// `BUFFER:`, creating a word pushing address of given number of bytes of data space.
+fn create_buffer<TExt: MachineExtensions>(machine: &mut Machine<TExt>, _: Address) -> Result<(), MachineError> {
+    machine.expect_state(MachineState::Interpreter)?;
+
+    let fx = stack_effect!(machine; size: Cell =>)?;
+    let size = fx.size();
+    create_data_word(fx.machine, size as Address)?;
+    fx.commit();
+
+    Ok(())
+}
+
+/// Create a word pushing address of `size` zeroed bytes of data space reserved right after it.
+fn create_data_word<TExt: MachineExtensions>(machine: &mut Machine<TExt>, size: u16) -> Result<(), MachineError> {
+    let article_start_address = create_article_header(machine)?;
+
+    // Article body pushes address of the data stored right after it
+    let data_address = aligned(machine.memory.get_dict_ptr().wrapping_add(1 + CELL_BYTES + 1));
+    machine.memory.mark_relocation(machine.memory.get_dict_ptr().wrapping_add(1));
+    compile_full_cell_literal(machine, data_address as Cell)?;
+    machine.memory.dict_write_opcode(OpCode::Return)?;
+    machine.memory.dict_align()?;
+
+    let available = machine.memory.free_data_space();
+    if size > available {
+        return Err(MachineError::OutOfDataSpace { needed: size, available });
+    }
+
+    for _ in 0..size {
+        machine.memory.dict_write_u8(0)?;
+    }
 
-            machine.memory.last_article_ptr = Some(article_start_address);
-            machine.memory.set_current_word(None);
-            machine.memory.set_state(MachineState::Interpreter);
+    if size > 0 {
+        machine.memory.mark_data_space(data_address..=data_address.wrapping_add(size - 1));
+    }
+
+    machine.memory.last_article_ptr = Some(article_start_address);
+
+    Ok(())
+}
+
+/// `VALUE` and `2VALUE`, creating a word pushing a single- or double-cell value stored right after it.
+///
+/// The word has the layout of a variable followed by a load, behind a `ValuePrologue` op-code `TO` recognizes values
+/// by (see `value_data`).
+fn create_value<TExt: MachineExtensions>(machine: &mut Machine<TExt>, double: bool) -> Result<(), MachineError> {
+    machine.expect_state(MachineState::Interpreter)?;
+
+    let value = if double { machine.pop::<DoubleCell>()? } else { machine.pop::<Cell>()? as DoubleCell };
+    let article_start_address = create_article_header(machine)?;
+
+    machine.memory.dict_write_opcode(OpCode::ValuePrologue)?;
+    let data_address = aligned(machine.memory.get_dict_ptr().wrapping_add(1 + CELL_BYTES + 2));
+    machine.memory.mark_relocation(machine.memory.get_dict_ptr().wrapping_add(1));
+    compile_full_cell_literal(machine, data_address as Cell)?;
+    machine.memory.dict_write_opcode(if double { OpCode::Load32 } else { OpCode::Load16 })?;
+    machine.memory.dict_write_opcode(OpCode::Return)?;
+    machine.memory.dict_align()?;
+
+    let size = if double {
+        machine.memory.dict_write_double_cell(value)?;
+        DOUBLE_CELL_BYTES
+    } else {
+        machine.memory.dict_write_cell(value as Cell)?;
+        CELL_BYTES
+    };
+    machine.memory.mark_data_space(data_address..=data_address.wrapping_add(size - 1));
+
+    machine.memory.last_article_ptr = Some(article_start_address);
+
+    Ok(())
+}
+
+/// Address of data of a word created by `VALUE` or `2VALUE` and whether the value is double-cell, `None` if the
+/// article is not a value.
+fn value_data(memory: &MachineMemory, article: &ReadableArticle) -> Option<(Address, bool)> {
+    let raw_memory = &memory.raw_memory;
+    let prologue_address = article.call_address();
+    let literal_address = prologue_address.wrapping_add(1);
+    let load_address = literal_address.wrapping_add(1 + CELL_BYTES);
+    let data_address = raw_memory.read_u16(literal_address.wrapping_add(1));
+
+    let double = match OpCode::from_int(raw_memory.read_u8(load_address)) {
+        Ok(OpCode::Load16) => false,
+        Ok(OpCode::Load32) => true,
+        _ => return None,
+    };
+
+    let is_value = raw_memory.read_u8(prologue_address) == OpCode::ValuePrologue.int_value()
+        && raw_memory.read_u8(literal_address) == OpCode::Literal16.int_value()
+        && raw_memory.read_u8(load_address.wrapping_add(1)) == OpCode::Return.int_value()
+        && data_address == aligned(load_address.wrapping_add(2));
+
+    is_value.then_some((data_address, double))
+}
+
+/// `TO`, storing a value taken from data stack to a word created by `VALUE` or `2VALUE`, or compiling the store.
+fn to<TExt: MachineExtensions>(machine: &mut Machine<TExt>, _: Address) -> Result<(), MachineError> {
+    let name_address = machine.read_input_word()?.ok_or(MachineError::UnexpectedInputEOF)?;
+    let article = machine.memory.lookup_article_name_buf(name_address)?
+        .ok_or(MachineError::IllegalWord(Some(name_address)))?;
+    let (data_address, double) = value_data(&machine.memory, &article)
+        .ok_or_else(|| MachineError::NotAValue { name: article.name().to_string() })?;
+
+    match (machine.memory.get_state(), double) {
+        (MachineState::Interpreter, false) => {
+            let value = machine.pop::<Cell>()?;
+
+            machine.store(data_address, value)
         }
-        b"RECURSE" => {
-            machine.expect_state(MachineState::Compiler)?;
-            let article_header_address = machine.memory.get_current_word().ok_or(MachineError::IllegalCompilerState)?;
-            let article_body_address = ReadableArticle::new(
-                &machine.memory.raw_memory,
-                article_header_address,
-                machine.memory.get_used_dict_segment(),
-            )?.body_address();
-
-            machine.memory.dict_write_opcode(OpCode::Call)?;
-            machine.memory.dict_write_u16(article_body_address)?;
+        (MachineState::Interpreter, true) => {
+            let value = machine.pop::<DoubleCell>()?;
+
+            store_double_cell(machine, data_address, value)
+        }
+        (MachineState::Compiler, _) => {
+            machine.memory.mark_relocation(machine.memory.get_dict_ptr().wrapping_add(1));
+            compile_full_cell_literal(machine, data_address as Cell)?;
+            machine.memory.dict_write_opcode(if double { OpCode::Store32 } else { OpCode::Store16 })
         }
-        b"IMMEDIATE" => {
-            machine.expect_state(MachineState::Interpreter)?;
+    }
+}
 
-            let body_address = machine.memory
-                .articles().next()
-                .ok_or(MachineError::NoArticle)?.body_address();
+/// `BEGIN-STRUCTURE`, creates a word pushing size of the structure set later by `END-STRUCTURE`.
+fn begin_structure<TExt: MachineExtensions>(machine: &mut Machine<TExt>, _: Address) -> Result<(), MachineError> {
+    machine.expect_state(MachineState::Interpreter)?;
 
-            if machine.memory.raw_memory.read_u8(body_address) != OpCode::DefaultArticleStart.int_value() {
-                return Err(MachineError::UnexpectedArticleType);
-            }
+    let article_start_address = create_article_header(machine)?;
+
+    let size_address = machine.memory.get_dict_ptr().wrapping_add(1);
+    compile_full_cell_literal(machine, 0)?;
+    machine.memory.dict_write_opcode(OpCode::Return)?;
+
+    machine.memory.last_article_ptr = Some(article_start_address);
 
-            machine.memory.raw_memory.write_u8(body_address, OpCode::Noop.int_value());
+    machine.push(size_address as Cell)?;
+    machine.push(0 as Cell)
+}
+
+fn end_structure<TExt: MachineExtensions>(machine: &mut Machine<TExt>, _: Address) -> Result<(), MachineError> {
+    machine.expect_state(MachineState::Interpreter)?;
+
+    let fx = stack_effect!(machine; size_address: Address, size: Cell =>)?;
+    let (size_address, size) = (fx.size_address(), fx.size());
+
+    fx.machine.memory.validate_segment_access(
+        &DictionarySegment,
+        size_address..=size_address.wrapping_add(CELL_BYTES - 1),
+        AccessKind::Write,
+    )?;
+    fx.commit();
+
+    machine.memory.raw_memory.write_cell(size_address, size);
+
+    Ok(())
+}
+
+/// Compile `ExecBuiltin` running builtin word with given name.
+fn compile_exec_builtin_named<TExt: MachineExtensions>(machine: &mut Machine<TExt>, name: &str) -> Result<(), MachineError> {
+    machine.memory.dict_write_opcode(OpCode::ExecBuiltin)?;
+    machine.memory.dict_write_u8(name.len() as u8)?;
+
+    for &byte in name.as_bytes() {
+        machine.memory.dict_write_u8(byte)?;
+    }
+
+    Ok(())
+}
+
+/// Create an immediate word adding given offset to an address on data stack, as `+FIELD` does.
+///
+/// The word compiles the addition (as `LITERAL +`) when used in a definition, so no call is left in compiled code.
+fn create_field<TExt: MachineExtensions>(machine: &mut Machine<TExt>, offset: Cell) -> Result<(), MachineError> {
+    let article_start_address = create_article_header(machine)?;
+
+    compile_cell_literal(machine, offset)?;
+    compile_cell_literal(machine, machine.memory.get_reserved_address(ReservedAddresses::StateVar) as Cell)?;
+    machine.memory.dict_write_opcode(OpCode::Load16)?;
+    machine.memory.dict_write_opcode(OpCode::BranchRelIfZ)?;
+    let interpreter_branch = machine.memory.create_forward_reference()?;
+    compile_exec_builtin_named(machine, "LITERAL")?;
+    compile_exec_builtin_named(machine, "+")?;
+    machine.memory.dict_write_opcode(OpCode::Return)?;
+    machine.memory.resolve_relative_forward_reference(interpreter_branch)?;
+    machine.memory.dict_write_opcode(OpCode::Add16)?;
+    machine.memory.dict_write_opcode(OpCode::Return)?;
+
+    machine.memory.last_article_ptr = Some(article_start_address);
+
+    mark_last_article(machine, true, false)
+}
+
+/// `+FIELD`, `FIELD:` and `CFIELD:`, creating a field of given size (taken from data stack if `None`) at offset
+/// optionally aligned to a cell boundary.
+fn add_field<TExt: MachineExtensions>(
+    machine: &mut Machine<TExt>,
+    size: Option<Cell>,
+    align: bool,
+) -> Result<(), MachineError> {
+    machine.expect_state(MachineState::Interpreter)?;
+
+    let field_offset = |offset: Cell| if align { aligned(offset as Address) as Cell } else { offset };
+
+    // Arguments stay on stack until the field word is created
+    match size {
+        Some(size) => {
+            let mut fx = stack_effect!(machine; offset: Cell => next_offset: Cell)?;
+            let offset = field_offset(fx.offset());
+            create_field(fx.machine, offset)?;
+            fx.next_offset(offset.wrapping_add(size));
+            fx.commit();
         }
-        b"IF" => {
-            machine.expect_state(MachineState::Compiler)?;
+        None => {
+            let mut fx = stack_effect!(machine; offset: Cell, size: Cell => next_offset: Cell)?;
+            let (offset, size) = (field_offset(fx.offset()), fx.size());
+            create_field(fx.machine, offset)?;
+            fx.next_offset(offset.wrapping_add(size));
+            fx.commit();
+        }
+    }
 
-            machine.memory.dict_write_opcode(OpCode::GoToIfZ)?;
-            let forward_ref = machine.memory.create_forward_reference()?;
-            machine.memory.data_push_u16(forward_ref)?;
+    Ok(())
+}
+
+fn compile_recurse<TExt: MachineExtensions>(machine: &mut Machine<TExt>, _: Address) -> Result<(), MachineError> {
+    let article_header_address = machine.memory.get_current_word().ok_or(MachineError::IllegalCompilerState)?;
+    let article_body_address = ReadableArticle::new(
+        &machine.memory.raw_memory,
+        article_header_address,
+        machine.memory.get_used_dict_segment(),
+    )?.body_address();
+
+    compile_call(machine, article_body_address)
+}
+
+/// Replace op-code the last article starts with to make the article immediate and/or compile-only.
+///
+/// Fails with `MachineError::UnexpectedArticleType` if the article doesn't start with one of article start op-codes.
+fn mark_last_article<TExt: MachineExtensions>(machine: &mut Machine<TExt>, immediate: bool, compile_only: bool) -> Result<(), MachineError> {
+    machine.expect_state(MachineState::Interpreter)?;
+
+    let body_address = machine.memory
+        .articles().next()
+        .ok_or(MachineError::NoArticle)?.body_address();
+
+    let (was_immediate, was_compile_only) = match OpCode::from_int(machine.memory.raw_memory.read_u8(body_address)) {
+        Ok(OpCode::DefaultArticleStart) => (false, false),
+        Ok(OpCode::ImmediateArticleStart | OpCode::Noop) => (true, false),
+        Ok(OpCode::CompileOnlyArticleStart) => (false, true),
+        Ok(OpCode::ImmediateCompileOnlyArticleStart) => (true, true),
+        _ => return Err(MachineError::UnexpectedArticleType),
+    };
+
+    let start = article_start_opcode(immediate || was_immediate, compile_only || was_compile_only);
+
+    machine.memory.raw_memory.write_u8(body_address, start.int_value());
+
+    Ok(())
+}
+
+/// Op-code an article with given flags starts with.
+fn article_start_opcode(immediate: bool, compile_only: bool) -> OpCode {
+    match (immediate, compile_only) {
+        (false, false) => OpCode::DefaultArticleStart,
+        (true, false) => OpCode::ImmediateArticleStart,
+        (false, true) => OpCode::CompileOnlyArticleStart,
+        (true, true) => OpCode::ImmediateCompileOnlyArticleStart,
+    }
+}
+
+/// `SYNONYM newname oldname`.
+///
+/// A synonym of an article starts the same way the article does and jumps to the article's code, so calls don't
+/// get an extra call stack frame. A synonym of a builtin word executes it's op-code, or executes the builtin word by
+/// name if it has no op-code. A synonym of an op-code word (or of a synonym of one) is marked with
+/// `SynonymPrologue` and references to it are compiled as the op-code (see `find_opcode_synonym`), so words
+/// accessing call stack work through synonyms as well.
+fn create_synonym<TExt: MachineExtensions>(machine: &mut Machine<TExt>, _: Address) -> Result<(), MachineError> {
+    machine.expect_state(MachineState::Interpreter)?;
+
+    let header_address = create_article_header(machine)?;
+    let start_address = machine.memory.get_dict_ptr() - 1;
+
+    if let Err(err) = write_synonym_body(machine, start_address) {
+        machine.memory.set_dict_ptr(header_address);
+
+        return Err(err);
+    }
+
+    machine.memory.last_article_ptr = Some(header_address);
+
+    Ok(())
+}
+
+/// Read name of the original word and write body of a synonym, replacing the article start op-code at given address.
+fn write_synonym_body<TExt: MachineExtensions>(machine: &mut Machine<TExt>, start_address: Address) -> Result<(), MachineError> {
+    let name_address = machine.read_input_word()?.ok_or(MachineError::UnexpectedInputEOF)?;
+
+    if let Some(article) = machine.memory.lookup_article_name_buf(name_address)? {
+        let (start, target) = (
+            article_start_opcode(article.is_immediate(), article.is_compile_only()),
+            article.call_address(),
+        );
+        machine.memory.raw_memory.write_u8(start_address, start.int_value());
+
+        return match find_opcode_synonym(machine, target) {
+            Some(op_code) => write_opcode_synonym_body(machine, op_code),
+            None => compile_relative_jump(machine, OpCode::BranchRel, target),
+        };
+    }
+
+    let mut name_buffer = [0u8; u8::MAX as usize];
+    let name = ReadableSizedString::new(&machine.memory.raw_memory, name_address, machine.memory.raw_memory.address_range())?
+        .copy_to(&mut name_buffer);
+    let word = machine.find_builtin_word(name).ok_or(MachineError::IllegalWord(Some(name_address)))?;
+    let start = article_start_opcode(word.flags.immediate, word.flags.compile_only);
+    machine.memory.raw_memory.write_u8(start_address, start.int_value());
+
+    match postponed_opcode(machine, name) {
+        Some(op_code) => write_opcode_synonym_body(machine, op_code),
+        None => {
+            compile_exec_builtin(machine, name_address)?;
+
+            machine.memory.dict_write_opcode(OpCode::Return)
         }
-        b"ELSE" => {
-            machine.expect_state(MachineState::Compiler)?;
+    }
+}
 
-            let mut fx = stack_effect!(machine; old_ref:Address => new_ref: Address)?;
-            let old_ref = fx.old_ref();
+/// Write body of a synonym of an op-code word.
+fn write_opcode_synonym_body<TExt: MachineExtensions>(machine: &mut Machine<TExt>, op_code: OpCode) -> Result<(), MachineError> {
+    machine.memory.dict_write_opcode(OpCode::SynonymPrologue)?;
+    machine.memory.dict_write_opcode(op_code)?;
 
-            fx.machine.memory.dict_write_opcode(OpCode::GoTo)?;
-            let new_ref = fx.machine.memory.create_forward_reference()?;
-            fx.new_ref(new_ref);
-            fx.machine.memory.resolve_forward_reference(old_ref)?;
+    machine.memory.dict_write_opcode(OpCode::Return)
+}
 
-            fx.commit();
+fn compile_if<TExt: MachineExtensions>(machine: &mut Machine<TExt>, _: Address) -> Result<(), MachineError> {
+    machine.memory.dict_write_opcode(OpCode::BranchRelIfZ)?;
+    let forward_ref = machine.memory.create_forward_reference()?;
+
+    machine.push(forward_ref as Cell)
+}
+
+fn compile_else<TExt: MachineExtensions>(machine: &mut Machine<TExt>, _: Address) -> Result<(), MachineError> {
+    let mut fx = stack_effect!(machine; old_ref:Address => new_ref: Address)?;
+    let old_ref = fx.old_ref();
+
+    fx.machine.memory.dict_write_opcode(OpCode::BranchRel)?;
+    let new_ref = fx.machine.memory.create_forward_reference()?;
+    fx.new_ref(new_ref);
+    fx.machine.memory.resolve_relative_forward_reference(old_ref)?;
+
+    fx.commit();
+
+    Ok(())
+}
+
+fn compile_then<TExt: MachineExtensions>(machine: &mut Machine<TExt>, _: Address) -> Result<(), MachineError> {
+    let reference = machine.pop::<Cell>()?;
+
+    Ok(machine.memory.resolve_relative_forward_reference(reference as Address)?)
+}
+
+fn compile_begin<TExt: MachineExtensions>(machine: &mut Machine<TExt>, _: Address) -> Result<(), MachineError> {
+    machine.push(machine.memory.get_dict_ptr() as Cell)
+}
+
+fn compile_while<TExt: MachineExtensions>(machine: &mut Machine<TExt>, _: Address) -> Result<(), MachineError> {
+    let mut fx = stack_effect!(machine; old_dest: Address => orig: Address, new_dest: Address)?;
+    let dest = fx.old_dest();
+    fx.new_dest(dest);
+
+    fx.machine.memory.dict_write_opcode(OpCode::BranchRelIfZ)?;
+    let orig = fx.machine.memory.create_forward_reference()?;
+    fx.orig(orig);
+    fx.commit();
+
+    Ok(())
+}
+
+fn compile_repeat<TExt: MachineExtensions>(machine: &mut Machine<TExt>, _: Address) -> Result<(), MachineError> {
+    let fx = stack_effect!(machine; orig: Address, dest: Address => )?;
+    let (dest, orig) = (fx.dest(), fx.orig());
+
+    compile_relative_jump(fx.machine, OpCode::BranchRel, dest)?;
+    fx.machine.memory.resolve_relative_forward_reference(orig)?;
+
+    fx.commit();
+
+    Ok(())
+}
+
+fn compile_exit<TExt: MachineExtensions>(machine: &mut Machine<TExt>, _: Address) -> Result<(), MachineError> {
+    machine.memory.dict_write_opcode(OpCode::Return)
+}
+
+/// Op-code compiled by a non-immediate builtin word with given name, `None` for other words and words registered by
+/// host.
+fn postponed_opcode<TExt: MachineExtensions>(machine: &Machine<TExt>, name: &[u8]) -> Option<OpCode> {
+    if machine.builtin_words.find(name).is_some() {
+        return None;
+    }
+
+    find_table_word::<TExt>(name).filter(|word| !word.flags.immediate)?;
+
+    OPCODE_WORDS.iter().find(|(_, word_name, _)| word_name.as_bytes() == name).map(|(op_code, _, _)| *op_code)
+}
+
+/// Immediate words get their execution compiled, so the word being defined performs their compilation action.
+/// Non-immediate words get compiled code compiling them: a call of an article or op-code of a builtin word.
+///
+/// Other builtin words are executed by compiled code, doing what they would do in the state the machine is in when
+/// the code runs.
+fn postpone<TExt: MachineExtensions>(machine: &mut Machine<TExt>, _: Address) -> Result<(), MachineError> {
+    let name_address = machine.read_input_word()?.ok_or(MachineError::UnexpectedInputEOF)?;
+
+    if let Some(article) = machine.memory.lookup_article_name_buf(name_address)? {
+        if article.is_immediate() {
+            let body_address = article.body_address();
+
+            compile_call(machine, body_address)?;
+        } else {
+            let call_address = article.call_address();
+
+            machine.memory.dict_write_opcode(OpCode::CompileCall)?;
+            machine.memory.mark_relocation(machine.memory.get_dict_ptr());
+            machine.memory.dict_write_u16(call_address)?;
         }
-        b"THEN" => {
-            machine.expect_state(MachineState::Compiler)?;
 
-            let reference = machine.memory.data_pop_u16()?;
-            machine.memory.resolve_forward_reference(reference)?;
+        return Ok(());
+    }
+
+    let mut name_buffer = [0u8; u8::MAX as usize];
+    let name = ReadableSizedString::new(&machine.memory.raw_memory, name_address, machine.memory.raw_memory.address_range())?
+        .copy_to(&mut name_buffer);
+
+    match postponed_opcode(machine, name) {
+        Some(op_code) => {
+            compile_cell_literal(machine, op_code.int_value() as Cell)?;
+
+            Ok(machine.memory.dict_write_opcode(OpCode::CommaByte)?)
         }
-        b"BEGIN" => {
-            machine.expect_state(MachineState::Compiler)?;
+        None => compile_exec_builtin(machine, name_address),
+    }
+}
+
+fn skip_comment<TExt: MachineExtensions>(machine: &mut Machine<TExt>, _: Address) -> Result<(), MachineError> {
+    machine.input().set_prompt_context(PromptContext::Comment);
+
+    skip_input_until(machine, b')')
+}
 
-            machine.memory.data_push_u16(machine.memory.get_dict_ptr())?;
+/// Skip input characters up to and including the given one.
+fn skip_input_until<TExt: MachineExtensions>(machine: &mut Machine<TExt>, terminator: u8) -> Result<(), MachineError> {
+    loop {
+        match machine.input().read()? {
+            None => { return Err(MachineError::UnexpectedInputEOF); }
+            Some(c) if c == terminator => { return Ok(()); }
+            Some(_) => { continue; }
         }
-        b"WHILE" => {
-            let mut fx = stack_effect!(machine; old_dest: Address => orig: Address, new_dest: Address)?;
-            let dest = fx.old_dest();
-            fx.new_dest(dest);
-
-            fx.machine.memory.dict_write_opcode(OpCode::GoToIfZ)?;
-            let orig = fx.machine.memory.create_forward_reference()?;
-            fx.orig(orig);
-            fx.commit();
+    }
+}
+
+/// `[DEFINED]` and `[UNDEFINED]`, looking the word up among articles and builtin words.
+fn check_defined<TExt: MachineExtensions>(machine: &mut Machine<TExt>, defined: bool) -> Result<(), MachineError> {
+    let name_address = machine.read_input_word()?.ok_or(MachineError::UnexpectedInputEOF)?;
+    let mut name_buffer = [0u8; u8::MAX as usize];
+    let name = ReadableSizedString::new(&machine.memory.raw_memory, name_address, machine.memory.raw_memory.address_range())?
+        .copy_to(&mut name_buffer);
+    let found = machine.find_builtin_word(name).is_some()
+        || machine.memory.lookup_article_name_buf(name_address)?.is_some();
+
+    machine.push(if found == defined { TRUE } else { FALSE })
+}
+
+/// Skip input words up to the `[ELSE]` or `[THEN]` matching a false `[IF]`, or up to the `[THEN]` matching an
+/// `[ELSE]` if `stop_at_else` is false, nested `[IF]`s are skipped whole.
+///
+/// Nothing skipped is executed. Strings of `."` and `S"` and comments are skipped as well, so bracket words inside
+/// them are ignored.
+fn skip_conditional<TExt: MachineExtensions>(machine: &mut Machine<TExt>, stop_at_else: bool) -> Result<(), MachineError> {
+    let mut depth = 0usize;
+
+    loop {
+        let name_address = machine.read_input_word()?.ok_or(MachineError::UnexpectedInputEOF)?;
+        let mut name_buffer = [0u8; u8::MAX as usize];
+        let name = ReadableSizedString::new(&machine.memory.raw_memory, name_address, machine.memory.raw_memory.address_range())?
+            .copy_to(&mut name_buffer);
+
+        match name {
+            b".\"" | b"S\"" => skip_input_until(machine, b'"')?,
+            b"(" => skip_input_until(machine, b')')?,
+            b"[IF]" => depth += 1,
+            b"[ELSE]" if depth == 0 && stop_at_else => return Ok(()),
+            b"[THEN]" if depth == 0 => return Ok(()),
+            b"[THEN]" => depth -= 1,
+            _ => {}
         }
-        b"REPEAT" => {
-            let fx = stack_effect!(machine; orig: Address, dest: Address => )?;
-            let (dest, orig) = (fx.dest(), fx.orig());
+    }
+}
 
-            fx.machine.memory.dict_write_opcode(OpCode::GoTo)?;
-            fx.machine.memory.dict_write_u16(dest)?;
-            fx.machine.memory.resolve_forward_reference(orig)?;
+fn bracket_if<TExt: MachineExtensions>(machine: &mut Machine<TExt>, _: Address) -> Result<(), MachineError> {
+    if machine.pop::<Cell>()? == FALSE {
+        skip_conditional(machine, true)?;
+    }
 
-            fx.commit();
+    Ok(())
+}
+
+fn enter_interpreter_state<TExt: MachineExtensions>(machine: &mut Machine<TExt>, _: Address) -> Result<(), MachineError> {
+    machine.memory.set_state(MachineState::Interpreter);
+
+    Ok(())
+}
+
+fn enter_compiler_state<TExt: MachineExtensions>(machine: &mut Machine<TExt>, _: Address) -> Result<(), MachineError> {
+    machine.expect_state(MachineState::Interpreter)?;
+    machine.memory.set_state(MachineState::Compiler);
+
+    Ok(())
+}
+
+fn compile_literal<TExt: MachineExtensions>(machine: &mut Machine<TExt>, _: Address) -> Result<(), MachineError> {
+    let value = machine.pop::<Cell>()?;
+
+    compile_cell_literal(machine, value)
+}
+
+fn compile_2literal<TExt: MachineExtensions>(machine: &mut Machine<TExt>, _: Address) -> Result<(), MachineError> {
+    let value = machine.pop::<DoubleCell>()?;
+
+    compile_double_literal(machine, value)
+}
+
+/// Read a name from input and find execution token of the article with that name.
+fn read_execution_token<TExt: MachineExtensions>(machine: &mut Machine<TExt>) -> Result<Address, MachineError> {
+    let name_address = machine.read_input_word()?.ok_or(MachineError::UnexpectedInputEOF)?;
+    let article = machine.memory.lookup_article_name_buf(name_address)?
+        .ok_or(MachineError::IllegalWord(Some(name_address)))?;
+
+    Ok(article.call_address())
+}
+
+/// `'`, builtin words have no execution tokens.
+fn tick<TExt: MachineExtensions>(machine: &mut Machine<TExt>, name_address: Address) -> Result<(), MachineError> {
+    if machine.memory.get_state() == MachineState::Compiler {
+        return compile_exec_builtin(machine, name_address);
+    }
+
+    let xt = read_execution_token(machine)?;
+
+    machine.push(xt)
+}
+
+fn compile_tick<TExt: MachineExtensions>(machine: &mut Machine<TExt>, _: Address) -> Result<(), MachineError> {
+    let xt = read_execution_token(machine)?;
+
+    machine.memory.mark_relocation(machine.memory.get_dict_ptr().wrapping_add(1));
+    compile_full_cell_literal(machine, xt as Cell)
+}
+
+fn execute<TExt: MachineExtensions>(machine: &mut Machine<TExt>, _: Address) -> Result<(), MachineError> {
+    if machine.memory.get_state() == MachineState::Compiler {
+        return machine.memory.dict_write_opcode(OpCode::Execute);
+    }
+
+    let xt = machine.peek::<Address>(0)?;
+    validate_execution_token(&machine.memory, xt)?;
+    machine.pop::<Address>()?;
+
+    machine.run_until_exit(xt)
+}
+
+/// `TRAVERSE-WORDLIST`, there is a single word list so word list identifier is ignored.
+fn traverse_wordlist<TExt: MachineExtensions>(machine: &mut Machine<TExt>, _: Address) -> Result<(), MachineError> {
+    if machine.memory.get_state() == MachineState::Compiler {
+        machine.memory.dict_write_opcode(OpCode::TraverseWordlist)?;
+
+        return machine.memory.dict_write_opcode(OpCode::TraverseWordlistNext);
+    }
+
+    let xt = machine.peek::<Address>(1)?;
+    validate_execution_token(&machine.memory, xt)?;
+    stack_effect!(machine; _xt: Address, _wid: Cell => )?.commit();
+
+    let mut nt = next_name_token(&machine.memory, None)?;
+
+    while let Some(name_token) = nt {
+        machine.push(name_token)?;
+        machine.run_until_exit(xt)?;
+
+        if machine.pop::<Cell>()? == FALSE {
+            break;
         }
-        b"EXIT" => {
-            machine.expect_state(MachineState::Compiler)?;
 
-            machine.memory.dict_write_opcode(OpCode::Return)?;
+        nt = next_name_token(&machine.memory, Some(name_token))?;
+    }
+
+    Ok(())
+}
+
+/// Perform given action in interpreter state, compile running the builtin word in compiler state.
+fn interpret_or_compile<TExt: MachineExtensions>(
+    machine: &mut Machine<TExt>,
+    name_address: Address,
+    action: fn(&mut Machine<TExt>) -> Result<(), MachineError>,
+) -> Result<(), MachineError> {
+    match machine.memory.get_state() {
+        MachineState::Compiler => compile_exec_builtin(machine, name_address),
+        MachineState::Interpreter => action(machine),
+    }
+}
+
+fn print_words<TExt: MachineExtensions>(machine: &mut Machine<TExt>, name_address: Address) -> Result<(), MachineError> {
+    match machine.memory.get_state() {
+        MachineState::Compiler => compile_exec_builtin(machine, name_address),
+        MachineState::Interpreter => machine.print_words(),
+    }
+}
+
+fn print_data_stack<TExt: MachineExtensions>(machine: &mut Machine<TExt>, name_address: Address) -> Result<(), MachineError> {
+    if machine.memory.get_state() == MachineState::Compiler {
+        return compile_exec_builtin(machine, name_address);
+    }
+
+    let mut listing = Vec::new();
+
+    machine.print_data_stack(&mut listing).map_err(OutputError::from)?;
+
+    Ok(machine.output().puts(&listing)?)
+}
+
+fn dump_memory<TExt: MachineExtensions>(machine: &mut Machine<TExt>, name_address: Address) -> Result<(), MachineError> {
+    if machine.memory.get_state() == MachineState::Compiler {
+        return compile_exec_builtin(machine, name_address);
+    }
+
+    let fx = stack_effect!(machine; start: Cell, length: Cell =>)?;
+    let (start, length) = (fx.start(), fx.length());
+
+    fx.machine.dump_memory(start, length)?;
+    fx.commit();
+
+    Ok(())
+}
+
+fn print_memory_map<TExt: MachineExtensions>(machine: &mut Machine<TExt>, name_address: Address) -> Result<(), MachineError> {
+    if machine.memory.get_state() == MachineState::Compiler {
+        return compile_exec_builtin(machine, name_address);
+    }
+
+    let (memory, mut writer) = machine.memory_and_output_writer();
+
+    Ok(memory.print_memory_map(&mut writer).map_err(OutputError::from)?)
+}
+
+fn see<TExt: MachineExtensions>(machine: &mut Machine<TExt>, _: Address) -> Result<(), MachineError> {
+    let name_address = machine.read_input_word()?.ok_or(MachineError::UnexpectedInputEOF)?;
+    let mut listing = Vec::new();
+
+    machine.print_word_definition(&mut listing, name_address)?;
+
+    Ok(machine.output().puts(&listing)?)
+}
+
+fn break_on<TExt: MachineExtensions>(machine: &mut Machine<TExt>, _: Address) -> Result<(), MachineError> {
+    let name_address = machine.read_input_word()?.ok_or(MachineError::UnexpectedInputEOF)?;
+    let article = machine.memory.lookup_article_name_buf(name_address)?
+        .ok_or(MachineError::IllegalWord(Some(name_address)))?;
+    let address = article.call_address();
+
+    machine.set_breakpoint(address);
+
+    Ok(())
+}
+
+/// `INCLUDED` and `REQUIRED`, taking path from data stack.
+fn include_named_file<TExt: MachineExtensions>(machine: &mut Machine<TExt>, name_address: Address, required: bool) -> Result<(), MachineError> {
+    if machine.memory.get_state() == MachineState::Compiler {
+        return compile_exec_builtin(machine, name_address);
+    }
+
+    let fx = stack_effect!(machine; addr: Address, size: u16 => )?;
+    let (addr, size) = (fx.addr(), fx.size());
+    fx.commit();
+
+    let path = machine.memory.read_string(addr, size)?;
+
+    include_file(machine, &path, required)
+}
+
+/// `INCLUDE` and `REQUIRE`, reading path from input.
+fn include_parsed_file<TExt: MachineExtensions>(machine: &mut Machine<TExt>, required: bool) -> Result<(), MachineError> {
+    machine.expect_state(MachineState::Interpreter)?;
+
+    let path_address = machine.read_input_word()?.ok_or(MachineError::UnexpectedInputEOF)?;
+    let path_bytes = ReadableSizedString::new(&machine.memory.raw_memory, path_address, machine.memory.raw_memory.address_range())?
+        .as_bytes();
+    let path = String::from_utf8_lossy(&path_bytes).into_owned();
+
+    include_file(machine, &path, required)
+}
+
+fn load_image<TExt: MachineExtensions>(machine: &mut Machine<TExt>, _: Address) -> Result<(), MachineError> {
+    if machine.memory.get_state() == MachineState::Compiler {
+        return machine.memory.dict_write_opcode(OpCode::LoadImage);
+    }
+
+    let fx = stack_effect!(machine; addr: Address, size: u16 => )?;
+    let (addr, size) = (fx.addr(), fx.size());
+    fx.commit();
+
+    let path = machine.memory.read_string(addr, size)?;
+
+    machine.load_image(&path)
+}
+
+fn print_string<TExt: MachineExtensions>(machine: &mut Machine<TExt>, _: Address) -> Result<(), MachineError> {
+    if machine.memory.get_state() == MachineState::Compiler {
+        compile_string_literal(machine)?;
+
+        return machine.memory.dict_write_opcode(OpCode::EmitString);
+    }
+
+    machine.input().set_prompt_context(PromptContext::String);
+
+    loop {
+        let c = machine.input().read()?.ok_or(MachineError::UnexpectedInputEOF)?;
+
+        if c == b'"' {
+            return Ok(());
         }
-        b"POSTPONE" => {
-            let name_address = machine.read_input_word()?.ok_or(MachineError::UnexpectedInputEOF)?;
 
-            if let Some(article) = machine.memory.lookup_article_name_buf(name_address)? {
-                let body_address = article.body_address();
+        machine.output().putc(c as u16)?;
+    }
+}
 
-                machine.memory.dict_write_opcode(OpCode::Call)?;
-                machine.memory.dict_write_u16(body_address)?;
-            } else {
-                machine.memory.dict_write_opcode(OpCode::ExecBuiltin)?;
-                machine.memory.dict_write_sized_string(name_address)?;
-            }
+/// Word registered by `Machine::register_custom_op_word`, executing the op-code in interpreter state.
+///
+/// `process_builtin_word` compiles the op-code in compiler state. Like op-codes of `opcode_word!` words, the op-code
+/// is executed outside of any code, as if located at address 0, so it must neither read an operand nor jump. Fails
+/// with `MachineError::InvalidJumpTarget` if the handler returns an address other than the one following the
+/// op-code.
+pub(crate) fn process_custom_op_word<TExt: MachineExtensions>(machine: &mut Machine<TExt>, name_address: Address) -> Result<(), MachineError> {
+    let mut name_buffer = [0u8; u8::MAX as usize];
+    let name = ReadableSizedString::new(&machine.memory.raw_memory, name_address, machine.memory.raw_memory.address_range())?
+        .copy_to(&mut name_buffer);
+    let op_code = machine.custom_op_word(name).ok_or(MachineError::IllegalWord(Some(name_address)))?;
+
+    let next_address = machine.execute_custom_op(op_code, 0)?;
+
+    if next_address != 1 {
+        return Err(MachineError::InvalidJumpTarget { from: 0, to: next_address });
+    }
+
+    Ok(())
+}
+
+/// Builtin word compiled to a single op-code, executed immediately in interpreter state.
+macro_rules! opcode_word {
+    ($name:literal, $op_code:ident) => {
+        BuiltinWord::new($name, BuiltinFlags::NONE, |machine, _| process_trivial_opcode(machine, OpCode::$op_code))
+    };
+}
+
+/// Builtin word compiled to a single op-code that is only valid inside a definition.
+macro_rules! compile_only_opcode_word {
+    ($name:literal, $op_code:ident) => {
+        BuiltinWord::new(
+            $name, BuiltinFlags::COMPILE_ONLY, |machine, _| process_compile_only_opcode(machine, OpCode::$op_code),
+        )
+    };
+}
+
+/// Builtin word pushing a constant in interpreter state and compiling it as a literal in compiler state.
+macro_rules! constant_word {
+    ($name:literal, |$machine:ident| $value:expr) => {
+        BuiltinWord::new($name, BuiltinFlags::NONE, |$machine, _| process_constant($machine, $value))
+    };
+}
+
+/// Holder of the builtin word table, the table borrows nothing but it's type refers to machine extensions which may
+/// be bound to a lifetime.
+struct BuiltinWordTable<'a, TExt: MachineExtensions>(PhantomData<&'a TExt>);
+
+impl<'a, TExt: MachineExtensions + 'a> BuiltinWordTable<'a, TExt> {
+    const WORDS: &'a [BuiltinWord<TExt>] = &[
+        BuiltinWord::new(":", BuiltinFlags::NONE, begin_definition),
+        BuiltinWord::new(";", BuiltinFlags::IMMEDIATE_COMPILE_ONLY, end_definition),
+        BuiltinWord::new("VARIABLE", BuiltinFlags::NONE, create_variable),
+        BuiltinWord::new("BUFFER:", BuiltinFlags::NONE, create_buffer),
+        BuiltinWord::new("VALUE", BuiltinFlags::NONE, |machine, _| create_value(machine, false)),
+        BuiltinWord::new("2VALUE", BuiltinFlags::NONE, |machine, _| create_value(machine, true)),
+        BuiltinWord::new("TO", BuiltinFlags::IMMEDIATE, to),
+        BuiltinWord::new("BEGIN-STRUCTURE", BuiltinFlags::NONE, begin_structure),
+        BuiltinWord::new("END-STRUCTURE", BuiltinFlags::NONE, end_structure),
+        BuiltinWord::new("+FIELD", BuiltinFlags::NONE, |machine, _| add_field(machine, None, false)),
+        BuiltinWord::new("FIELD:", BuiltinFlags::NONE, |machine, _| add_field(machine, Some(CELL_BYTES as Cell), true)),
+        BuiltinWord::new("CFIELD:", BuiltinFlags::NONE, |machine, _| add_field(machine, Some(1), false)),
+        BuiltinWord::new("RECURSE", BuiltinFlags::IMMEDIATE_COMPILE_ONLY, compile_recurse),
+        BuiltinWord::new("IMMEDIATE", BuiltinFlags::NONE, |machine, _| mark_last_article(machine, true, false)),
+        BuiltinWord::new("COMPILE-ONLY", BuiltinFlags::NONE, |machine, _| mark_last_article(machine, false, true)),
+        BuiltinWord::new("SYNONYM", BuiltinFlags::NONE, create_synonym),
+        BuiltinWord::new("'", BuiltinFlags::NONE, tick),
+        BuiltinWord::new("[']", BuiltinFlags::IMMEDIATE_COMPILE_ONLY, compile_tick),
+        BuiltinWord::new("EXECUTE", BuiltinFlags::NONE, execute),
+        // There is a single word list
+        constant_word!("FORTH-WORDLIST", |machine| 1),
+        BuiltinWord::new("TRAVERSE-WORDLIST", BuiltinFlags::NONE, traverse_wordlist),
+        opcode_word!("NAME>STRING", NameToString),
+        opcode_word!("NAME>INTERPRET", NameToInterpret),
+        opcode_word!("COMPILE,", CompileXt),
+        opcode_word!("IMMEDIATE?", NameIsImmediate),
+        BuiltinWord::new("IF", BuiltinFlags::IMMEDIATE_COMPILE_ONLY, compile_if),
+        BuiltinWord::new("ELSE", BuiltinFlags::IMMEDIATE_COMPILE_ONLY, compile_else),
+        BuiltinWord::new("THEN", BuiltinFlags::IMMEDIATE_COMPILE_ONLY, compile_then),
+        BuiltinWord::new("BEGIN", BuiltinFlags::IMMEDIATE_COMPILE_ONLY, compile_begin),
+        BuiltinWord::new("WHILE", BuiltinFlags::IMMEDIATE_COMPILE_ONLY, compile_while),
+        BuiltinWord::new("REPEAT", BuiltinFlags::IMMEDIATE_COMPILE_ONLY, compile_repeat),
+        BuiltinWord::new("EXIT", BuiltinFlags::IMMEDIATE_COMPILE_ONLY, compile_exit),
+        BuiltinWord::new("POSTPONE", BuiltinFlags::IMMEDIATE_COMPILE_ONLY, postpone),
+        BuiltinWord::new("(", BuiltinFlags::IMMEDIATE, skip_comment),
+        BuiltinWord::new("[", BuiltinFlags::IMMEDIATE_COMPILE_ONLY, enter_interpreter_state),
+        BuiltinWord::new("]", BuiltinFlags::NONE, enter_compiler_state),
+        BuiltinWord::new("[DEFINED]", BuiltinFlags::IMMEDIATE, |machine, _| check_defined(machine, true)),
+        BuiltinWord::new("[UNDEFINED]", BuiltinFlags::IMMEDIATE, |machine, _| check_defined(machine, false)),
+        BuiltinWord::new("[IF]", BuiltinFlags::IMMEDIATE, bracket_if),
+        BuiltinWord::new("[ELSE]", BuiltinFlags::IMMEDIATE, |machine, _| skip_conditional(machine, false)),
+        BuiltinWord::new("[THEN]", BuiltinFlags::IMMEDIATE, |_, _| Ok(())),
+        constant_word!("BASE", |machine| machine.memory.get_reserved_address(ReservedAddresses::BaseVar) as Cell),
+        constant_word!("HERE", |machine| machine.memory.get_reserved_address(ReservedAddresses::HereVar) as Cell),
+        constant_word!("STATE", |machine| machine.memory.get_reserved_address(ReservedAddresses::StateVar) as Cell),
+        BuiltinWord::new(
+            "PAD", BuiltinFlags::NONE, |machine, _| process_literal(machine, machine.memory.get_pad_address() as Cell),
+        ),
+        opcode_word!("OVER", Over16),
+        opcode_word!("2OVER", Over32),
+        opcode_word!("SWAP", Swap16),
+        opcode_word!("2SWAP", Swap32),
+        opcode_word!("DUP", Dup16),
+        opcode_word!("2DUP", Dup32),
+        opcode_word!("DROP", Drop16),
+        opcode_word!("ROT", Rot16),
+        opcode_word!("+", Add16),
+        opcode_word!("-", Sub16),
+        opcode_word!("*", Mul16),
+        opcode_word!("/", Div16),
+        opcode_word!("@", Load16),
+        opcode_word!("!", Store16),
+        opcode_word!("C@", Load8),
+        opcode_word!("C!", Store8),
+        opcode_word!("2@", Load32),
+        opcode_word!("2!", Store32),
+        opcode_word!("<", Lt16),
+        opcode_word!(">", Gt16),
+        opcode_word!("=", Eq16),
+        opcode_word!("INVERT", Invert16),
+        opcode_word!("AND", And16),
+        opcode_word!("OR", Or16),
+        opcode_word!("XOR", Xor16),
+        opcode_word!("S>D", I16ToI32),
+        compile_only_opcode_word!("R@", CallRead16),
+        compile_only_opcode_word!("2R@", CallRead32),
+        compile_only_opcode_word!(">R", CallPush16),
+        compile_only_opcode_word!("R>", CallPop16),
+        compile_only_opcode_word!("2>R", CallPush32),
+        compile_only_opcode_word!("2R>", CallPop32),
+        compile_only_opcode_word!("N>R", CallPushN),
+        compile_only_opcode_word!("NR>", CallPopN),
+        BuiltinWord::new(
+            "T{", BuiltinFlags::NONE, |machine, name_address| interpret_or_compile(machine, name_address, Machine::begin_test),
+        ),
+        BuiltinWord::new(
+            "->", BuiltinFlags::NONE, |machine, name_address| interpret_or_compile(machine, name_address, Machine::take_test_results),
+        ),
+        BuiltinWord::new(
+            "}T", BuiltinFlags::NONE, |machine, name_address| interpret_or_compile(machine, name_address, Machine::end_test),
+        ),
+        opcode_word!("ABS", Abs16),
+        BuiltinWord::new("S\"", BuiltinFlags::IMMEDIATE_COMPILE_ONLY, |machine, _| compile_string_literal(machine)),
+        BuiltinWord::new("LITERAL", BuiltinFlags::IMMEDIATE_COMPILE_ONLY, compile_literal),
+        BuiltinWord::new("2LITERAL", BuiltinFlags::IMMEDIATE_COMPILE_ONLY, compile_2literal),
+        opcode_word!("ALIGN", Align),
+        opcode_word!("ALIGNED", Aligned),
+        opcode_word!(",", Comma),
+        opcode_word!("C,", CommaByte),
+        opcode_word!("EMIT", Emit),
+        opcode_word!("XEMIT", XEmit),
+        opcode_word!("FLUSH", Flush),
+        opcode_word!("MS", Ms),
+        opcode_word!("TIME&DATE", TimeAndDate),
+        opcode_word!("BYE", Bye),
+        opcode_word!("CR", Cr),
+        opcode_word!("SPACE", Space),
+        opcode_word!("SPACES", Spaces),
+        constant_word!("BL", |machine| b' ' as Cell),
+        BuiltinWord::new("WORDS", BuiltinFlags::NONE, print_words),
+        BuiltinWord::new("SEE", BuiltinFlags::IMMEDIATE, see),
+        BuiltinWord::new(".S", BuiltinFlags::NONE, print_data_stack),
+        BuiltinWord::new("DUMP", BuiltinFlags::NONE, dump_memory),
+        BuiltinWord::new(".MEM", BuiltinFlags::NONE, print_memory_map),
+        opcode_word!("UTIME", UTime),
+        opcode_word!("COUNTER", Counter),
+        compile_only_opcode_word!("BREAK", Break),
+        BuiltinWord::new("BREAK-ON", BuiltinFlags::IMMEDIATE, break_on),
+        opcode_word!("SP@", SpFetch),
+        opcode_word!("SP!", SpStore),
+        compile_only_opcode_word!("RP@", RpFetch),
+        compile_only_opcode_word!("RP!", RpStore),
+        opcode_word!("ALLOCATE", Allocate),
+        opcode_word!("FREE", Free),
+        opcode_word!("RESIZE", Resize),
+        constant_word!("R/O", |machine| 0),
+        constant_word!("W/O", |machine| 1),
+        constant_word!("R/W", |machine| 2),
+        // Files are always accessed in binary mode
+        BuiltinWord::new("BIN", BuiltinFlags::NONE, |_, _| Ok(())),
+        opcode_word!("OPEN-FILE", OpenFile),
+        opcode_word!("CREATE-FILE", CreateFile),
+        opcode_word!("CLOSE-FILE", CloseFile),
+        opcode_word!("READ-FILE", ReadFile),
+        opcode_word!("READ-LINE", ReadLine),
+        opcode_word!("WRITE-FILE", WriteFile),
+        opcode_word!("WRITE-LINE", WriteLine),
+        opcode_word!("FILE-POSITION", FilePosition),
+        opcode_word!("REPOSITION-FILE", RepositionFile),
+        opcode_word!("FILE-SIZE", FileSize),
+        opcode_word!("DELETE-FILE", DeleteFile),
+        opcode_word!("SAVE-IMAGE", SaveImage),
+        BuiltinWord::new("INCLUDED", BuiltinFlags::NONE, |machine, name| include_named_file(machine, name, false)),
+        BuiltinWord::new("REQUIRED", BuiltinFlags::NONE, |machine, name| include_named_file(machine, name, true)),
+        BuiltinWord::new("INCLUDE", BuiltinFlags::NONE, |machine, _| include_parsed_file(machine, false)),
+        BuiltinWord::new("REQUIRE", BuiltinFlags::NONE, |machine, _| include_parsed_file(machine, true)),
+        BuiltinWord::new("LOAD-IMAGE", BuiltinFlags::NONE, load_image),
+        opcode_word!("TYPE", EmitString),
+        opcode_word!("<#", PnoInit),
+        opcode_word!("HOLD", PnoPut),
+        opcode_word!("#>", PnoFinish),
+        opcode_word!("#", PnoPutDigit),
+        BuiltinWord::new(".\"", BuiltinFlags::IMMEDIATE, print_string),
+    ];
+}
+
+/// Builtin words in the order `WORDS` lists them, not including words registered by host.
+pub fn builtin_word_table<'a, TExt: MachineExtensions + 'a>() -> &'a [BuiltinWord<TExt>] {
+    BuiltinWordTable::<'a, TExt>::WORDS
+}
+
+/// Find a word of `builtin_word_table` by binary search over positions of the words sorted by name.
+fn find_table_word<'a, TExt: MachineExtensions + 'a>(name: &[u8]) -> Option<&'a BuiltinWord<TExt>> {
+    // Names don't depend on machine extensions, so the order is shared by tables of all machines
+    static SORTED_POSITIONS: OnceLock<Vec<u16>> = OnceLock::new();
+
+    let table = builtin_word_table::<'a, TExt>();
+    let positions = SORTED_POSITIONS.get_or_init(|| {
+        let mut positions: Vec<u16> = (0..table.len() as u16).collect();
+        positions.sort_unstable_by_key(|&position| table[position as usize].name.as_bytes());
+
+        positions
+    });
+
+    positions.binary_search_by(|&position| table[position as usize].name.as_bytes().cmp(name))
+        .ok()
+        .map(|index| &table[positions[index] as usize])
+}
+
+pub fn process_builtin_word<TExt: MachineExtensions>(machine: &mut Machine<TExt>, name_address: Address) -> Result<(), MachineError> {
+    let mut name_buffer = [0u8; u8::MAX as usize];
+    let name = ReadableSizedString::new(&machine.memory.raw_memory, name_address, machine.memory.raw_memory.address_range())?
+        .copy_to(&mut name_buffer);
+
+    if let Some(word) = machine.find_builtin_word(name) {
+        let state = machine.memory.get_state();
+
+        if word.flags.compile_only && state == MachineState::Interpreter {
+            return Err(MachineError::CompileOnlyWord { name: word.name.to_string() });
         }
-        b"(" => {
-            loop {
-                match machine.extensions.get_input().read()? {
-                    None => { return Err(MachineError::UnexpectedInputEOF); }
-                    Some(b')') => { return Ok(()); }
-                    Some(_) => { continue; }
-                }
-            }
+
+        // Words of the table handle compiler state themselves, words added by host are compiled unless immediate
+        if !word.flags.immediate && state == MachineState::Compiler && machine.builtin_words.find(name).is_some() {
+            return match machine.custom_op_word(name) {
+                Some(op_code) => Ok(machine.memory.dict_write_u8(op_code)?),
+                None => compile_exec_builtin(machine, name_address),
+            };
         }
-        b"[" => {
-            machine.expect_state(MachineState::Compiler)?;
-            machine.memory.set_state(MachineState::Interpreter);
+
+        return (word.handler)(machine, name_address);
+    }
+
+    match machine.process_unrecognized_word(name_address) {
+        Err(MachineError::IllegalWord(_)) => machine.process_recognized_word(name_address),
+        res => res
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashSet;
+
+    use crate::input::StaticStringInput;
+    use crate::machine_testing::*;
+
+    use super::*;
+
+    #[test]
+    fn test_builtin_word_table() {
+        let table = builtin_word_table::<TestMachineExtensions>();
+        let names: HashSet<&str> = table.iter().map(|word| word.name).collect();
+
+        assert_eq!(names.len(), table.len(), "duplicate names in builtin word table");
+
+        for word in table {
+            let found = find_table_word::<TestMachineExtensions>(word.name.as_bytes()).unwrap();
+
+            assert_eq!(found.name, word.name);
         }
-        b"]" => {
-            machine.expect_state(MachineState::Interpreter)?;
+
+        assert!(find_table_word::<TestMachineExtensions>(b"dup").is_none());
+        assert!(find_table_word::<TestMachineExtensions>(b"").is_none());
+    }
+
+    #[test]
+    fn test_opcode_words() {
+        for (op_code, name, _) in OPCODE_WORDS {
+            let mut machine = TestMachine::default();
             machine.memory.set_state(MachineState::Compiler);
+            let start = machine.memory.get_dict_ptr();
+            let name_address = machine.memory.set_input_word(name.as_bytes()).unwrap();
+
+            process_builtin_word(&mut machine, name_address).unwrap();
+
+            assert_eq!(machine.memory.raw_memory.read_u8(start), op_code.int_value(), "{}", name);
+            assert_eq!(machine.memory.get_dict_ptr(), start + 1, "{}", name);
         }
-        b"TRUE" => { process_constant(machine, TRUE)?; }
-        b"FALSE" => { process_constant(machine, FALSE)?; }
-        b"BASE" => { process_constant(machine, machine.memory.get_reserved_address(ReservedAddresses::BaseVar))?; }
-        b"HERE" => { process_constant(machine, machine.memory.get_reserved_address(ReservedAddresses::HereVar))?; }
-        b"STATE" => { process_constant(machine, machine.memory.get_reserved_address(ReservedAddresses::StateVar))?; }
-        b"PAD" => { process_literal(machine, machine.memory.get_reserved_address(ReservedAddresses::PadBuffer))?; }
-        b"OVER" => { process_trivial_opcode(machine, OpCode::Over16)?; }
-        b"2OVER" => { process_trivial_opcode(machine, OpCode::Over32)?; }
-        b"SWAP" => { process_trivial_opcode(machine, OpCode::Swap16)?; }
-        b"2SWAP" => { process_trivial_opcode(machine, OpCode::Swap32)?; }
-        b"DUP" => { process_trivial_opcode(machine, OpCode::Dup16)?; }
-        b"2DUP" => { process_trivial_opcode(machine, OpCode::Dup32)?; }
-        b"DROP" => { process_trivial_opcode(machine, OpCode::Drop16)?; }
-        b"2DROP" => {
-            process_trivial_opcode(machine, OpCode::Drop16)?;
-            process_trivial_opcode(machine, OpCode::Drop16)?;
+    }
+
+    #[test]
+    fn test_builtin_words_dispatch() {
+        for word in builtin_word_table::<TestMachineExtensions>() {
+            let mut machine = TestMachine::default();
+            let name_address = machine.memory.set_input_word(word.name.as_bytes()).unwrap();
+
+            let result = process_builtin_word(&mut machine, name_address);
+
+            assert!(!matches!(result, Err(MachineError::IllegalWord(_))), "{} is not dispatched", word.name);
+
+            if word.flags.compile_only {
+                assert!(matches!(result, Err(MachineError::CompileOnlyWord { .. })), "{} runs in interpreter state", word.name);
+            }
         }
-        b"ROT" => { process_trivial_opcode(machine, OpCode::Rot16)?; }
-        b"+" => { process_trivial_opcode(machine, OpCode::Add16)?; }
-        b"-" => { process_trivial_opcode(machine, OpCode::Sub16)?; }
-        b"*" => { process_trivial_opcode(machine, OpCode::Mul16)?; }
-        b"/" => { process_trivial_opcode(machine, OpCode::Div16)?; }
-        b"@" => { process_trivial_opcode(machine, OpCode::Load16)?; }
-        b"!" => { process_trivial_opcode(machine, OpCode::Store16)?; }
-        b"C@" => { process_trivial_opcode(machine, OpCode::Load8)?; }
-        b"C!" => { process_trivial_opcode(machine, OpCode::Store8)?; }
-        b"2@" => { process_trivial_opcode(machine, OpCode::Load32)?; }
-        b"2!" => { process_trivial_opcode(machine, OpCode::Store32)?; }
-        b"<" => { process_trivial_opcode(machine, OpCode::Lt16)?; }
-        b">" => { process_trivial_opcode(machine, OpCode::Gt16)?; }
-        b"=" => { process_trivial_opcode(machine, OpCode::Eq16)?; }
-        b"INVERT" => { process_trivial_opcode(machine, OpCode::Invert16)?; }
-        b"AND" => { process_trivial_opcode(machine, OpCode::And16)?; }
-        b"OR" => { process_trivial_opcode(machine, OpCode::Or16)?; }
-        b"XOR" => { process_trivial_opcode(machine, OpCode::Xor16)?; }
-        b"S>D" => { process_trivial_opcode(machine, OpCode::I16ToI32)?; }
-        b"R@" => { process_compile_only_opcode(machine, OpCode::CallRead16)?; }
-        b"2R@" => { process_compile_only_opcode(machine, OpCode::CallRead32)?; }
-        b">R" => { process_compile_only_opcode(machine, OpCode::CallPush16)?; }
-        b"R>" => { process_compile_only_opcode(machine, OpCode::CallPop16)?; }
-        b"2>R" => { process_compile_only_opcode(machine, OpCode::CallPush32)?; }
-        b"2R>" => { process_compile_only_opcode(machine, OpCode::CallPop32)?; }
-        b"ABS" => { process_trivial_opcode(machine, OpCode::Abs16)?; }
-        b"S\"" => {
-            machine.expect_state(MachineState::Compiler)?;
-
-            compile_string_literal(machine)?;
+
+        let mut machine = TestMachine::default();
+        let name_address = machine.memory.set_input_word(b"NO-SUCH-BUILTIN").unwrap();
+
+        assert!(matches!(process_builtin_word(&mut machine, name_address), Err(MachineError::IllegalWord(_))));
+    }
+
+    #[test]
+    fn test_compiling_words_in_interpreter_state() {
+        let names = ["IF", "ELSE", "THEN", "BEGIN", "WHILE", "REPEAT", "EXIT", "RECURSE", "LITERAL", "POSTPONE", "S\"", ";", "["];
+
+        for name in names {
+            let mut machine = TestMachine::default();
+            let dict_ptr = machine.memory.get_dict_ptr();
+
+            assert!(matches!(
+                machine.interpret_str(&format!("1 2 3 {} DUP", name)),
+                Err(MachineError::CompileOnlyWord { .. }),
+            ), "{}", name);
+
+            assert_eq!(machine.memory.get_dict_ptr(), dict_ptr, "{}", name);
+            assert_eq!(machine.memory.get_state(), MachineState::Interpreter, "{}", name);
+            machine.assert_data_stack_state(&[StackElement::Cell(1), StackElement::Cell(2), StackElement::Cell(3)]);
         }
-        b"LITERAL" => {
-            machine.expect_state(MachineState::Compiler)?;
+    }
+
+    #[test]
+    fn test_compile_only_words() {
+        let mut machine = TestMachine::default();
+        machine.extensions.input = StaticStringInput::new("5 >R");
 
-            let value = machine.memory.data_pop_u16()?;
-            compile_u16_literal(machine, value)?;
+        match machine.interpret_input() {
+            Err(MachineError::CompileOnlyWord { name }) => assert_eq!(name, ">R"),
+            res => panic!("unexpected result {:?}", res),
         }
-        b"EMIT" => { process_trivial_opcode(machine, OpCode::Emit)?; }
-        b"TYPE" => { process_trivial_opcode(machine, OpCode::EmitString)?; }
-        b"<#" => { process_trivial_opcode(machine, OpCode::PnoInit)?; }
-        b"HOLD" => { process_trivial_opcode(machine, OpCode::PnoPut)?; }
-        b"#>" => { process_trivial_opcode(machine, OpCode::PnoFinish)?; }
-        b"#" => { process_trivial_opcode(machine, OpCode::PnoPutDigit)?; }
-        b".\"" => {
-            match machine.memory.get_state() {
-                MachineState::Compiler => {
-                    compile_string_literal(machine)?;
-                    machine.memory.dict_write_opcode(OpCode::EmitString)?;
-                }
-                MachineState::Interpreter => {
-                    loop {
-                        let c = machine.extensions.get_input().read()?.ok_or(MachineError::UnexpectedInputEOF)?;
-
-                        if c == b'"' {
-                            break
-                        }
-
-                        machine.extensions.get_output().putc(c as u16)?;
-                    }
-                }
-            }
+
+        machine.memory.data_pop_cell().unwrap();
+        machine.extensions.input = StaticStringInput::new("
+            : double DUP + ; COMPILE-ONLY
+            : endif POSTPONE THEN ; IMMEDIATE COMPILE-ONLY
+            : quad >R R> double double ;
+            : check 0 IF 3 endif 4 ;
+            5 quad check
+        ");
+        machine.interpret_input().unwrap();
+        machine.assert_data_stack_state(&[StackElement::Cell(20), StackElement::Cell(4)]);
+
+        let double = machine.memory.lookup_article(b"double").unwrap().unwrap();
+        assert!(double.is_compile_only() && !double.is_immediate());
+        let endif = machine.memory.lookup_article(b"endif").unwrap().unwrap();
+        assert!(endif.is_compile_only() && endif.is_immediate());
+
+        for word in ["double", "endif"] {
+            machine.extensions.input = StaticStringInput::new(word);
+            let err = machine.interpret_input().unwrap_err();
+
+            let mut message = Vec::new();
+            err.pretty_print(&mut message, &machine).unwrap();
+            assert_eq!(
+                String::from_utf8(message).unwrap(),
+                format!("Word {} is compile-only, it may only be used inside a definition", word),
+            );
         }
-        _ => {
-            return match TExt::process_unrecognized_word(machine, name_address) {
-                Err(MachineError::IllegalWord(_)) => {
-                    let base_address = machine.memory.get_reserved_address(ReservedAddresses::BaseVar);
-                    let base = unsafe { machine.memory.raw_memory.read_u16(base_address) };
-
-                    if let Some(parsed_literal) = parse_literal(
-                        ReadableSizedString::new(
-                            &machine.memory.raw_memory,
-                            name_address,
-                            machine.memory.raw_memory.address_range(),
-                        )?
-                            .as_bytes(),
-                        base as u32,
-                    ) {
-                        Ok(process_literal(machine, parsed_literal)?)
-                    } else {
-                        Err(MachineError::IllegalWord(Some(name_address)))
-                    }
-                }
-                res => res
-            };
+
+    }
+
+    #[test]
+    fn test_structure_words() {
+        let mut machine = TestMachine::default();
+        machine.interpret_str("
+            BEGIN-STRUCTURE point
+                CFIELD: point.tag
+                FIELD: point.x
+                FIELD: point.y
+                3 +FIELD point.name
+            END-STRUCTURE
+            point BUFFER: pt
+            VARIABLE p  pt p !
+            : move-x ( n -- ) p @ point.x ! ;
+            : get-y ( -- n ) p @ point.y @ ;
+            7 p @ point.tag C!  100 move-x  200 p @ point.y !
+            point  p @ point.tag C@  p @ point.x @  get-y  p @ point.name p @ -
+        ").unwrap();
+
+        machine.assert_data_stack_state(&[
+            StackElement::Cell(3 * CELL_BYTES as Cell + 3),
+            StackElement::Cell(7),
+            StackElement::Cell(100),
+            StackElement::Cell(200),
+            StackElement::Cell(3 * CELL_BYTES as Cell),
+        ]);
+
+        // Field words compile to the addition itself
+        machine.interpret_str(": y-of point.y ;").unwrap();
+        let y_of = machine.memory.lookup_article(b"y-of").unwrap().unwrap();
+        assert_eq!(machine.memory.raw_memory.address_slice(y_of.body_address(), 5).as_ref(), [
+            OpCode::DefaultArticleStart.int_value(),
+            OpCode::Literal8.int_value(),
+            2 * CELL_BYTES as u8,
+            OpCode::Add16.int_value(),
+            OpCode::Return.int_value(),
+        ]);
+
+        assert!(matches!(machine.interpret_str("END-STRUCTURE"), Err(MachineError::DataStackUnderflow { .. })));
+        assert!(matches!(machine.interpret_str("1 +FIELD"), Err(MachineError::DataStackUnderflow { .. })));
+        machine.assert_data_stack_state(&[StackElement::Cell(1)]);
+
+        // A field without a name leaves the offset on stack
+        assert!(matches!(machine.interpret_str("4 FIELD:"), Err(MachineError::UnexpectedInputEOF)));
+        machine.assert_data_stack_state(&[StackElement::Cell(4)]);
+    }
+
+    #[test]
+    fn test_synonym() {
+        let mut machine = TestMachine::default();
+        machine.interpret_str("
+            : square DUP * ;
+            SYNONYM sq square
+            : square 0 ;
+            : endif POSTPONE THEN ; IMMEDIATE
+            SYNONYM fi endif
+            SYNONYM when IF
+            : check 1 when 3 fi 4 ;
+            SYNONYM copy DUP
+            : twice copy + ;
+            3 sq 5 twice 7 copy check
+        ").unwrap();
+
+        machine.assert_data_stack_state(&[
+            StackElement::Cell(9), StackElement::Cell(10), StackElement::Cell(7), StackElement::Cell(7),
+            StackElement::Cell(3), StackElement::Cell(4),
+        ]);
+        assert!(machine.memory.lookup_article(b"fi").unwrap().unwrap().is_immediate());
+        assert!(!machine.memory.lookup_article(b"sq").unwrap().unwrap().is_immediate());
+        let when = machine.memory.lookup_article(b"when").unwrap().unwrap();
+        assert!(when.is_immediate() && when.is_compile_only());
+
+        for inline_threshold in [None, Some(16)] {
+            let mut machine = TestMachine::default();
+            machine.inline_threshold = inline_threshold;
+            machine.interpret_str("
+                SYNONYM my>r >R  SYNONYM myr> R>  SYNONYM my2>r 2>R  SYNONYM my2r> 2R>  SYNONYM our>r my>r
+                : x 5 my>r myr> ;
+                : y 6 7 my2>r 8 my2r> ;
+                : z 9 our>r myr> ;
+                x y z
+            ").unwrap();
+
+            machine.assert_data_stack_state(&[
+                StackElement::Cell(5), StackElement::Cell(8), StackElement::Cell(6), StackElement::Cell(7),
+                StackElement::Cell(9),
+            ]);
+            assert!(matches!(machine.interpret_str("1 my>r"), Err(MachineError::CompileOnlyWord { .. })));
         }
-    };
 
-    Ok(())
+        let dict_ptr = machine.memory.get_dict_ptr();
+        machine.extensions.input = StaticStringInput::new("SYNONYM new missing");
+        assert!(matches!(machine.interpret_input(), Err(MachineError::IllegalWord(_))));
+        assert_eq!(machine.memory.get_dict_ptr(), dict_ptr);
+        assert!(machine.memory.lookup_article(b"new").unwrap().is_none());
+    }
+
+    #[test]
+    fn test_values() {
+        let mut machine = TestMachine::default();
+        machine.interpret_str("
+            7 VALUE seven
+            100000. 2VALUE big
+            : set-big TO big ;
+            : bump seven 1 + TO seven ;
+            seven big
+            200000. set-big bump 3. TO big
+            seven big 2DUP
+        ").unwrap();
+
+        machine.assert_data_stack_state(&[
+            StackElement::Cell(7), StackElement::DoubleCell(100000),
+            StackElement::Cell(8), StackElement::DoubleCell(3), StackElement::DoubleCell(3),
+        ]);
+
+        machine.interpret_str("-1 TO seven seven").unwrap();
+        machine.assert_data_stack_state(&[StackElement::Cell(TRUE)]);
+
+        // Write protection doesn't apply to data of values
+        machine.memory.write_protection = true;
+        machine.interpret_str("5. TO big big").unwrap();
+        machine.assert_data_stack_state(&[StackElement::DoubleCell(5)]);
+
+        machine.extensions.input = StaticStringInput::new("TO big");
+        assert!(matches!(machine.interpret_input(), Err(MachineError::DataStackUnderflow { requested: 2, .. })));
+
+        for source in ["VARIABLE v 1 TO v", ": w 7 ; 1 TO w", "7 VALUE s : x s DROP ; 1 TO x"] {
+            let mut machine = TestMachine::default();
+            machine.extensions.input = StaticStringInput::new(source);
+
+            assert!(matches!(machine.interpret_input(), Err(MachineError::NotAValue { .. })), "{}", source);
+        }
+
+        machine.extensions.input = StaticStringInput::new(": z 1 TO DUP ;");
+        assert!(matches!(machine.interpret_input(), Err(MachineError::IllegalWord(_))));
+    }
+
+    #[test]
+    fn test_values_after_snapshot_restore() {
+        let mut machine = TestMachine::default();
+        machine.interpret_str("7 VALUE seven 100000. 2VALUE big").unwrap();
+
+        let mut snapshot = Vec::new();
+        machine.snapshot(&mut snapshot).unwrap();
+
+        let mut restored = TestMachine::default();
+        restored.restore(&mut snapshot.as_slice()).unwrap();
+        restored.interpret_str("8 TO seven 5. TO big : bump seven 1 + TO seven ; bump seven big").unwrap();
+        restored.assert_data_stack_state(&[StackElement::Cell(9), StackElement::DoubleCell(5)]);
+    }
+
+    #[test]
+    fn test_execute() {
+        let mut machine = TestMachine::default();
+        machine.interpret_str("
+            : square DUP * ;
+            : apply EXECUTE ;
+            : square-xt ['] square ;
+            3 ' square EXECUTE
+            4 square-xt apply
+        ").unwrap();
+        machine.assert_data_stack_state(&[StackElement::Cell(9), StackElement::Cell(16)]);
+
+        let nt = machine.memory.lookup_article(b"square").unwrap().unwrap().get_header_address();
+        machine.push(5u8).unwrap();
+        machine.push(nt).unwrap();
+        machine.interpret_str("NAME>INTERPRET EXECUTE").unwrap();
+        machine.assert_data_stack_state(&[StackElement::Cell(25)]);
+
+        machine.extensions.input = StaticStringInput::new("' DUP");
+        assert!(matches!(machine.interpret_input(), Err(MachineError::IllegalWord(_))));
+        machine.extensions.input = StaticStringInput::new("65000 EXECUTE");
+        assert!(matches!(machine.interpret_input(), Err(MachineError::InvalidExecutionToken { xt: 65000 })));
+        machine.extensions.input = StaticStringInput::new("1 NAME>STRING");
+        assert!(matches!(machine.interpret_input(), Err(MachineError::InvalidNameToken { nt: 1 })));
+    }
+
+    #[test]
+    fn test_compile_xt() {
+        let mut machine = TestMachine::default();
+        machine.interpret_str("
+            : square DUP * ;
+            : postpone-square ['] square COMPILE, ; IMMEDIATE
+            : fourth-power postpone-square [ ' square COMPILE, ] ;
+            3 fourth-power
+        ").unwrap();
+        machine.assert_data_stack_state(&[StackElement::Cell(81)]);
+
+        for (name, immediate) in [(b"square".as_slice(), FALSE), (b"postpone-square".as_slice(), TRUE)] {
+            let nt = machine.memory.lookup_article(name).unwrap().unwrap().get_header_address();
+            machine.push(nt).unwrap();
+            machine.interpret_str("IMMEDIATE?").unwrap();
+            machine.assert_data_stack_state(&[StackElement::Cell(immediate)]);
+        }
+
+        machine.extensions.input = StaticStringInput::new("65000 COMPILE,");
+        assert!(matches!(machine.interpret_input(), Err(MachineError::InvalidExecutionToken { xt: 65000 })));
+        machine.assert_data_stack_state(&[StackElement::Cell(65000)]);
+    }
+
+    #[test]
+    fn test_traverse_wordlist() {
+        let mut machine = TestMachine::default();
+        machine.interpret_str("
+            VARIABLE counter
+            : count-word DROP counter @ 1 + counter ! -1 ;
+            : count-words 0 counter ! ['] count-word FORTH-WORDLIST TRAVERSE-WORDLIST counter @ ;
+            : print-name NAME>STRING TYPE SPACE -1 ;
+            : first-two DROP counter @ 1 + DUP counter ! 2 < ;
+            count-words
+            0 counter ! ' first-two FORTH-WORDLIST TRAVERSE-WORDLIST counter @
+            ' print-name FORTH-WORDLIST TRAVERSE-WORDLIST
+        ").unwrap();
+
+        machine.assert_data_stack_state(&[StackElement::Cell(5), StackElement::Cell(2)]);
+
+        let names: Vec<String> = machine.memory.articles().map(|article| article.name().to_string() + " ").collect();
+        assert_eq!(String::from_utf8(machine.extensions.output.content.take()).unwrap(), names.concat());
+    }
+
+    #[test]
+    fn test_conditional_compilation() {
+        let mut machine = TestMachine::default();
+        machine.interpret_str("
+            -1 [IF] 1 [ELSE] 2 [THEN]
+            0 [IF] 3 [ELSE] 4 [THEN]
+            0 [IF] 5 [THEN]
+            0 [IF]
+                -1 [IF] 6 [ELSE] 7 [THEN]
+                .\" [THEN] \" ( [ELSE] )
+            [ELSE]
+                -1 [IF]
+                    8 0 [IF] 9 [THEN]
+                [THEN]
+            [THEN]
+            : pick-one [ -1 ] [IF] 10 [ELSE] 11 [THEN] ;
+            pick-one
+            [DEFINED] pick-one [DEFINED] DUP [DEFINED] missing [UNDEFINED] missing
+        ").unwrap();
+
+        machine.assert_data_stack_state(&[
+            StackElement::Cell(1), StackElement::Cell(4), StackElement::Cell(8), StackElement::Cell(10),
+            StackElement::Cell(TRUE), StackElement::Cell(TRUE), StackElement::Cell(FALSE), StackElement::Cell(TRUE),
+        ]);
+        assert_eq!(machine.extensions.output.content.take(), b"");
+
+        machine.extensions.input = StaticStringInput::new("0 [IF] 1");
+        assert!(matches!(machine.interpret_input(), Err(MachineError::UnexpectedInputEOF)));
+    }
+
+    #[test]
+    fn test_register_builtin_word() {
+        let mut machine = TestMachine::default();
+        machine.register_builtin_word(BuiltinWord::new("ANSWER", BuiltinFlags::NONE, |machine, _| process_constant(machine, 42)));
+        machine.register_builtin_word(BuiltinWord::new(
+            "INNER-ONLY", BuiltinFlags::IMMEDIATE_COMPILE_ONLY, |machine, _| process_compile_only_opcode(machine, OpCode::Push1),
+        ));
+
+        machine.extensions.input = StaticStringInput::new(": answers ANSWER INNER-ONLY ; ANSWER answers");
+        machine.interpret_input().unwrap();
+        machine.assert_data_stack_state(&[StackElement::Cell(42), StackElement::Cell(42), StackElement::Cell(1)]);
+
+        machine.extensions.input = StaticStringInput::new("INNER-ONLY");
+        assert!(matches!(machine.interpret_input(), Err(MachineError::CompileOnlyWord { .. })));
+
+        assert!(machine.unregister_builtin_word("ANSWER"));
+        assert!(!machine.unregister_builtin_word("ANSWER"));
+        assert!(machine.find_builtin_word(b"ANSWER").is_none());
+    }
+
+    #[test]
+    fn test_override_emit() {
+        let mut machine = TestMachine::default();
+        machine.register_builtin_word(BuiltinWord::new("EMIT", BuiltinFlags::NONE, |machine, _| {
+            let chr = machine.pop::<Cell>()?;
+            machine.output().puts(&[b'<', chr as u8, b'>'])?;
+
+            Ok(())
+        }));
+
+        machine.extensions.input = StaticStringInput::new("65 EMIT");
+        machine.interpret_input().unwrap();
+        assert_eq!(machine.extensions.output.content.take(), b"<A>");
+
+        assert!(machine.unregister_builtin_word("EMIT"));
+
+        machine.extensions.input = StaticStringInput::new("66 EMIT");
+        machine.interpret_input().unwrap();
+        assert_eq!(machine.extensions.output.content.take(), b"B");
+    }
+
+    #[test]
+    fn test_compile_host_words() {
+        let mut machine = TestMachine::default();
+        machine.register_builtin_word(BuiltinWord::new("EMIT", BuiltinFlags::NONE, |machine, _| {
+            let chr = machine.pop::<Cell>()?;
+            machine.output().puts(&[b'<', chr as u8, b'>'])?;
+
+            Ok(())
+        }));
+        machine.register_builtin_word(BuiltinWord::new("[MARK]", BuiltinFlags::IMMEDIATE, |machine, _| {
+            machine.output().puts(b"mark")?;
+
+            Ok(())
+        }));
+
+        machine.interpret_str(": x [MARK] 65 EMIT ;").unwrap();
+        assert_eq!(machine.extensions.output.content.take(), b"mark");
+        machine.assert_data_stack_state(&[]);
+
+        machine.interpret_str("x x").unwrap();
+        assert_eq!(machine.extensions.output.content.take(), b"<A><A>");
+    }
+
+    #[test]
+    fn test_enumerate_builtin_words() {
+        let mut machine = TestMachine::default();
+        machine.register_builtin_word(BuiltinWord::new("NOTHING", BuiltinFlags::IMMEDIATE, |_, _| Ok(())));
+        machine.register_builtin_word(BuiltinWord::new("DUP", BuiltinFlags::IMMEDIATE, |_, _| Ok(())));
+
+        let words = machine.all_builtin_words();
+        let table = builtin_word_table::<TestMachineExtensions>();
+
+        assert_eq!(words.len(), table.len() + 1);
+        assert_eq!(words.last().unwrap().name, "NOTHING");
+
+        let dup = words.iter().position(|word| word.name == "DUP").unwrap();
+        assert_eq!(table[dup].name, "DUP");
+        assert_eq!(words[dup].flags, BuiltinFlags::IMMEDIATE);
+
+        assert_eq!(machine.find_builtin_word(b"IF").unwrap().flags, BuiltinFlags::IMMEDIATE_COMPILE_ONLY);
+        assert_eq!(machine.find_builtin_word(b"R>").unwrap().flags, BuiltinFlags::COMPILE_ONLY);
+        assert_eq!(machine.find_builtin_word(b"SWAP").unwrap().flags, BuiltinFlags::NONE);
+        assert!(machine.find_builtin_word(b"NOTHING").unwrap().flags.immediate);
+    }
 }
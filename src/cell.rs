@@ -0,0 +1,39 @@
+/// Types of values stored in single and double data stack cells.
+///
+/// Cells are 16 bits wide by default, `cell32` feature makes them 32 bits wide (and double cells 64 bits wide).
+/// Addresses are 16-bit in both configurations.
+#[cfg(not(feature = "cell32"))]
+mod width {
+    pub type Cell = u16;
+    pub type SignedCell = i16;
+    pub type DoubleCell = u32;
+    pub type SignedDoubleCell = i32;
+}
+
+#[cfg(feature = "cell32")]
+mod width {
+    pub type Cell = u32;
+    pub type SignedCell = i32;
+    pub type DoubleCell = u64;
+    pub type SignedDoubleCell = i64;
+}
+
+pub use width::*;
+
+use crate::mem::Address;
+
+/// Size of a single cell in bytes.
+pub const CELL_BYTES: u16 = std::mem::size_of::<Cell>() as u16;
+
+/// Size of a double cell in bytes.
+pub const DOUBLE_CELL_BYTES: u16 = 2 * CELL_BYTES;
+
+/// Smallest cell-aligned address not less than given one.
+pub fn aligned(address: Address) -> Address {
+    address.wrapping_add(CELL_BYTES - 1) & !(CELL_BYTES - 1)
+}
+
+/// Well-formed flag with all bits set.
+pub const TRUE: Cell = Cell::MAX;
+
+pub const FALSE: Cell = 0;
@@ -0,0 +1,398 @@
+use std::fs;
+use std::io;
+
+use crate::debugger::{read_input_line, run_debugger};
+use crate::machine::{Machine, MachineExtensions};
+use crate::machine_error::MachineError;
+use crate::machine_memory::{MachineMemory, MemoryLayoutConfig};
+use crate::mem::{Mem, MEM_SIZE};
+use crate::output::Output;
+
+pub const USAGE: &str = "Usage: rs4 [-q | --no-repl] [--no-prelude] [--dump-on-error[=PATH]] [--post-mortem] [--memory SIZE] \
+    [--max-call-depth N] [--heap-size BYTES] [-e EXPRESSION | --eval EXPRESSION | FILE]...";
+
+/// Memory dump path used by `--dump-on-error` without explicit path.
+pub const DEFAULT_DUMP_PATH: &str = "./dump.bin";
+
+/// Source code interpreted before starting interactive session.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum CliSource {
+    File(String),
+    Expression(String),
+}
+
+/// What the command line interpreter should do.
+#[derive(Clone, Debug, PartialEq)]
+pub struct CliOptions {
+    /// Sources to interpret, in order.
+    pub sources: Vec<CliSource>,
+
+    /// Start interactive session after all sources are interpreted.
+    pub repl: bool,
+
+    /// Define words of `PRELUDE` before interpreting sources.
+    pub prelude: bool,
+
+    /// File memory of the machine is dumped to after an error in interactive session.
+    pub dump_path: Option<String>,
+
+    /// Start post-mortem debugger after an error in interactive session.
+    pub post_mortem: bool,
+
+    /// Size of machine memory in bytes.
+    pub memory_size: usize,
+
+    pub layout: MemoryLayoutConfig,
+}
+
+impl Default for CliOptions {
+    fn default() -> Self {
+        CliOptions {
+            sources: Vec::new(),
+            repl: true,
+            prelude: true,
+            dump_path: None,
+            post_mortem: false,
+            memory_size: MEM_SIZE,
+            layout: MemoryLayoutConfig::default(),
+        }
+    }
+}
+
+fn parse_number<T: std::str::FromStr>(option: &str, value: Option<String>) -> Result<T, String> {
+    let value = value.ok_or_else(|| format!("{} requires a number", option))?;
+
+    value.parse().map_err(|_| format!("Invalid value for {}: {}", option, value))
+}
+
+impl CliOptions {
+    /// Parse command line arguments, not including program name.
+    pub fn parse(args: impl IntoIterator<Item = String>) -> Result<CliOptions, String> {
+        let mut options = CliOptions::default();
+        let mut args = args.into_iter();
+
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "-q" | "--no-repl" => options.repl = false,
+                "--no-prelude" => options.prelude = false,
+                "-e" | "--eval" => {
+                    let expression = args.next().ok_or_else(|| format!("{} requires an expression", arg))?;
+                    options.sources.push(CliSource::Expression(expression));
+                }
+                "--dump-on-error" => options.dump_path = Some(DEFAULT_DUMP_PATH.to_string()),
+                "--post-mortem" => options.post_mortem = true,
+                "--memory" => options.memory_size = parse_number(&arg, args.next())?,
+                "--max-call-depth" => options.layout.max_call_stack_depth = parse_number(&arg, args.next())?,
+                "--heap-size" => options.layout.heap_size = parse_number(&arg, args.next())?,
+                _ if arg.starts_with("--dump-on-error=") => {
+                    options.dump_path = Some(arg["--dump-on-error=".len()..].to_string());
+                }
+                _ if arg.starts_with('-') && arg.len() > 1 => return Err(format!("Unknown option: {}", arg)),
+                _ => options.sources.push(CliSource::File(arg)),
+            }
+        }
+
+        if options.memory_size != MEM_SIZE {
+            return Err(format!("Unsupported memory size: {} (only {} bytes are supported)", options.memory_size, MEM_SIZE));
+        }
+
+        if !options.layout.fits_memory_size(options.memory_size as u32) {
+            return Err(format!("Memory layout does not fit into {} bytes of memory", options.memory_size));
+        }
+
+        Ok(options)
+    }
+
+    /// Create machine memory of size and layout given by options.
+    pub fn machine_memory(&self) -> MachineMemory {
+        MachineMemory::new(Mem::default(), self.layout)
+    }
+}
+
+fn report_error<TExt: MachineExtensions>(
+    machine: &mut Machine<TExt>,
+    err: &MachineError,
+    report: &mut impl io::Write,
+) -> io::Result<()> {
+    write!(report, "Error: ")?;
+    err.pretty_print(report, machine)?;
+    writeln!(report)
+}
+
+/// Load prelude and interpret sources given by options, then run interactive session on machine's input if requested.
+///
+/// Errors are written to `report`. A breakpoint reached in interactive session starts post-mortem debugger, the
+/// suspended code is resumed when it exits. Returns process exit code: non-zero if any of the sources failed, zero when
+/// interactive session ends or `BYE` is executed.
+pub fn run<TExt: MachineExtensions>(
+    machine: &mut Machine<TExt>,
+    options: &CliOptions,
+    report: &mut impl io::Write,
+) -> io::Result<i32> {
+    if options.prelude {
+        if let Err(err) = machine.load_prelude() {
+            report_error(machine, &err, report)?;
+
+            return Ok(1);
+        }
+    }
+
+    for source in &options.sources {
+        let result = match source {
+            CliSource::File(path) => machine.interpret_file(path),
+            CliSource::Expression(expression) => machine.interpret_str(expression),
+        };
+        let _ = machine.output().flush();
+
+        match result {
+            Ok(_) => {}
+            Err(MachineError::Bye) => return Ok(0),
+            Err(err) => {
+                report_error(machine, &err, report)?;
+
+                return Ok(1);
+            }
+        }
+    }
+
+    if !options.repl {
+        return Ok(0);
+    }
+
+    loop {
+        let mut result = machine.interpret_input();
+        let _ = machine.output().flush();
+
+        // Suspended code is resumed when debugger exits, then interpretation goes on. Debugger reads commands from
+        // the following lines, so the rest of current line is put aside until then.
+        while let Err(err @ MachineError::Breakpoint { .. }) = &result {
+            let rest_of_line = read_input_line(machine.input())?.unwrap_or_default();
+
+            err.pretty_print(report, machine)?;
+            writeln!(report)?;
+            run_debugger(machine, report)?;
+
+            result = machine.resume()
+                .and_then(|_| machine.interpret_str(&rest_of_line))
+                .and_then(|_| machine.interpret_input());
+            let _ = machine.output().flush();
+        }
+
+        match result {
+            Ok(_) | Err(MachineError::Bye) => return Ok(0),
+            Err(err) => {
+                report_error(machine, &err, report)?;
+                write!(report, "-----\nMachine state:\n")?;
+                machine.print_state(report)?;
+                machine.print_disassembly(report)?;
+                report.flush()?;
+
+                if let Some(path) = &options.dump_path {
+                    let dumped = fs::File::create(path)
+                        .and_then(|mut file| machine.memory.raw_memory.dump_to(&mut file));
+
+                    if let Err(err) = dumped {
+                        writeln!(report, "Could not write memory dump to {}: {}", path, err)?;
+                    }
+                }
+
+                if options.post_mortem {
+                    run_debugger(machine, report)?;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::str::from_utf8;
+
+    use crate::input::StaticStringInput;
+    use crate::machine_testing::*;
+
+    use super::*;
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    #[test]
+    fn test_parse_options() {
+        assert_eq!(CliOptions::parse(args(&["lib.fs", "-e", "1 2 +", "-q", "main.fs"])).unwrap(), CliOptions {
+            sources: vec![
+                CliSource::File("lib.fs".to_string()),
+                CliSource::Expression("1 2 +".to_string()),
+                CliSource::File("main.fs".to_string()),
+            ],
+            repl: false,
+            ..CliOptions::default()
+        });
+        assert_eq!(CliOptions::parse(args(&[])).unwrap(), CliOptions::default());
+        assert!(!CliOptions::parse(args(&["--no-prelude"])).unwrap().prelude);
+        assert!(CliOptions::parse(args(&["-e"])).is_err());
+        assert!(CliOptions::parse(args(&["--verbose"])).is_err());
+    }
+
+    #[test]
+    fn test_parse_machine_options() {
+        let options = CliOptions::parse(args(&["--dump-on-error", "--max-call-depth", "64", "--memory", "65536"])).unwrap();
+
+        assert_eq!(options.dump_path.as_deref(), Some(DEFAULT_DUMP_PATH));
+        assert_eq!(options.memory_size, MEM_SIZE);
+        assert_eq!(options.layout, MemoryLayoutConfig { max_call_stack_depth: 64, ..MemoryLayoutConfig::default() });
+        assert_eq!(options.machine_memory().layout_config().max_call_stack_depth, 64);
+
+        let options = CliOptions::parse(args(&["--dump-on-error=/tmp/rs4.bin"])).unwrap();
+
+        assert_eq!(options.dump_path.as_deref(), Some("/tmp/rs4.bin"));
+        assert_eq!(CliOptions::default().dump_path, None);
+
+        assert!(CliOptions::parse(args(&["--max-call-depth"])).is_err());
+        assert!(CliOptions::parse(args(&["--max-call-depth", "deep"])).is_err());
+        assert!(CliOptions::parse(args(&["--max-call-depth", "60000"])).is_err());
+        assert_eq!(CliOptions::parse(args(&["--heap-size", "0"])).unwrap().layout.heap_size, 0);
+        assert!(CliOptions::parse(args(&["--heap-size", "65000"])).is_err());
+        assert!(CliOptions::parse(args(&["--memory", "1024"])).is_err());
+    }
+
+    #[cfg(feature = "std-fs")]
+    #[test]
+    fn test_file_then_repl() {
+        let path = write_temp_file("cli-lib.fs", ": square DUP * ;\n");
+        let mut machine = TestMachine::with_std_file_system();
+        machine.extensions.input = StaticStringInput::new("3 square");
+        let mut report = Vec::new();
+
+        let options = CliOptions::parse(vec![path, "-e".to_string(), "2 square".to_string()]).unwrap();
+
+        assert_eq!(run(&mut machine, &options, &mut report).unwrap(), 0);
+        assert!(report.is_empty(), "{}", from_utf8(&report).unwrap());
+        machine.assert_data_stack_state(&[StackElement::Cell(4), StackElement::Cell(9)]);
+    }
+
+    #[cfg(feature = "std-fs")]
+    #[test]
+    fn test_file_with_error() {
+        let path = write_temp_file("cli-broken.fs", "1\n2 oops 3\n");
+        let mut machine = TestMachine::with_std_file_system();
+        machine.extensions.input = StaticStringInput::new("4");
+        let mut report = Vec::new();
+
+        let options = CliOptions::parse(vec![path.clone(), "-e".to_string(), "5".to_string()]).unwrap();
+
+        assert_eq!(run(&mut machine, &options, &mut report).unwrap(), 1);
+        assert_eq!(from_utf8(&report).unwrap(), format!("Error: {}:2:7: Illegal word: oops\n", path));
+        machine.assert_data_stack_state(&[StackElement::Cell(1), StackElement::Cell(2)]);
+    }
+
+    #[test]
+    fn test_expressions_without_repl() {
+        let mut machine = TestMachine::default();
+        machine.extensions.input = StaticStringInput::new("100");
+        let mut report = Vec::new();
+
+        let options = CliOptions::parse(args(&["-e", ": twice DUP + ;", "--eval", "21 twice", "-q"])).unwrap();
+
+        assert_eq!(run(&mut machine, &options, &mut report).unwrap(), 0);
+        machine.assert_data_stack_state(&[StackElement::Cell(42)]);
+
+        let options = CliOptions::parse(args(&["-e", "1 BYE 2", "-e", "3"])).unwrap();
+
+        assert_eq!(run(&mut machine, &options, &mut report).unwrap(), 0);
+        assert!(report.is_empty(), "{}", from_utf8(&report).unwrap());
+        machine.assert_data_stack_state(&[StackElement::Cell(1)]);
+    }
+
+    #[test]
+    fn test_prelude_option() {
+        let mut machine = TestMachine::default();
+        let mut report = Vec::new();
+
+        let options = CliOptions::parse(args(&["--no-prelude", "-e", "1 2 NIP", "-q"])).unwrap();
+
+        assert_eq!(run(&mut machine, &options, &mut report).unwrap(), 1);
+        assert_eq!(from_utf8(&report).unwrap(), "Error: Illegal word: NIP\n");
+
+        let options = CliOptions::parse(args(&["-e", "3 4 NIP", "-q"])).unwrap();
+
+        assert_eq!(run(&mut machine, &options, &mut report).unwrap(), 0);
+        machine.assert_data_stack_state(&[StackElement::Cell(1), StackElement::Cell(2), StackElement::Cell(4)]);
+    }
+
+    #[test]
+    fn test_repl_continues_after_error() {
+        let mut machine = TestMachine::default();
+        machine.extensions.input = StaticStringInput::new("1 oops 2");
+        let mut report = Vec::new();
+
+        assert_eq!(run(&mut machine, &CliOptions::default(), &mut report).unwrap(), 0);
+
+        let report = from_utf8(&report).unwrap();
+        assert!(report.starts_with("Error: Illegal word: oops\n-----\nMachine state:\n"), "{}", report);
+        machine.assert_data_stack_state(&[StackElement::Cell(1), StackElement::Cell(2)]);
+    }
+
+    #[test]
+    fn test_post_mortem_debugger() {
+        let mut machine = TestMachine::default();
+        machine.extensions.input = StaticStringInput::new(": broken DROP DROP DROP ; 1 2 + oops\nds\nbt\nc\n4 broken\nc\n5");
+        let mut report = Vec::new();
+
+        let options = CliOptions::parse(args(&["--post-mortem"])).unwrap();
+        assert!(options.post_mortem);
+
+        assert_eq!(run(&mut machine, &options, &mut report).unwrap(), 0);
+
+        let report = from_utf8(&report).unwrap();
+        assert!(report.contains("Post-mortem debugger, type help for list of commands or c to continue.\n\t"), "{}", report);
+        assert!(report.contains("No code is being executed\n"), "{}", report);
+        assert_eq!(report.matches("Post-mortem debugger").count(), 2, "{}", report);
+        machine.assert_data_stack_state(&[StackElement::Cell(5)]);
+    }
+
+    #[test]
+    fn test_repl_resumes_after_breakpoint() {
+        let mut machine = TestMachine::default();
+        machine.extensions.input = StaticStringInput::new(": w 1 BREAK 2 ; w 3\nds\nc\n4");
+        let mut report = Vec::new();
+
+        assert_eq!(run(&mut machine, &CliOptions::default(), &mut report).unwrap(), 0);
+
+        let report = from_utf8(&report).unwrap();
+        assert!(report.starts_with("Breakpoint at "), "{}", report);
+        assert!(report.contains(" (in article w)\nBacktrace:\n#0 w +0x"), "{}", report);
+        assert!(report.ends_with("or c to continue.\n\t0001 (    1,      1)\n"), "{}", report);
+        machine.assert_data_stack_state(&[
+            StackElement::Cell(1), StackElement::Cell(2), StackElement::Cell(3), StackElement::Cell(4),
+        ]);
+    }
+
+    #[cfg(feature = "std-fs")]
+    #[test]
+    fn test_dump_on_error() {
+        let path = write_temp_file("cli-dump.bin", "");
+        std::fs::remove_file(&path).unwrap();
+        let mut machine = TestMachine::default();
+        machine.extensions.input = StaticStringInput::new("1 oops");
+        let mut report = Vec::new();
+
+        let options = CliOptions::parse(vec![format!("--dump-on-error={}", path)]).unwrap();
+
+        assert_eq!(run(&mut machine, &options, &mut report).unwrap(), 0);
+        assert_eq!(std::fs::metadata(&path).unwrap().len(), MEM_SIZE as u64);
+    }
+
+    #[test]
+    fn test_dump_failure_reported() {
+        let mut machine = TestMachine::default();
+        machine.extensions.input = StaticStringInput::new("oops 1");
+        let mut report = Vec::new();
+
+        let options = CliOptions::parse(args(&["--dump-on-error=/nonexistent-directory/dump.bin"])).unwrap();
+
+        assert_eq!(run(&mut machine, &options, &mut report).unwrap(), 0);
+        assert!(from_utf8(&report).unwrap().contains("Could not write memory dump to /nonexistent-directory/dump.bin"));
+        machine.assert_data_stack_state(&[StackElement::Cell(1)]);
+    }
+}
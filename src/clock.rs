@@ -0,0 +1,206 @@
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::cell::Cell;
+use crate::machine::{Machine, MachineExtensions};
+use crate::machine_error::MachineError;
+
+/// Source of current time and a way to wait used by time-related words.
+pub trait Clock {
+    /// Time elapsed since Unix epoch, in UTC.
+    fn now(&mut self) -> Duration;
+
+    fn sleep(&mut self, duration: Duration);
+}
+
+/// Clock using system time and putting current thread to sleep.
+#[derive(Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&mut self) -> Duration {
+        SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default()
+    }
+
+    fn sleep(&mut self, duration: Duration) {
+        thread::sleep(duration)
+    }
+}
+
+/// Longest single sleep of `MS`, interrupt flag is checked between sleeps.
+const SLEEP_CHUNK: Duration = Duration::from_millis(10);
+
+/// Calendar date and time of day, in UTC.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct DateTime {
+    pub second: u8,
+    pub minute: u8,
+    pub hour: u8,
+    pub day: u8,
+    pub month: u8,
+    pub year: i32,
+}
+
+impl DateTime {
+    /// Convert time elapsed since Unix epoch to date and time in proleptic Gregorian calendar.
+    pub fn from_unix_time(time: Duration) -> Self {
+        let seconds = time.as_secs();
+        let days = (seconds / 86400) as i64;
+        let second_of_day = seconds % 86400;
+
+        // Days since 0000-03-01, so that leap day ends a year
+        let days = days + 719468;
+        let era = days.div_euclid(146097);
+        let day_of_era = days.rem_euclid(146097);
+        let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+        let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+        let shifted_month = (5 * day_of_year + 2) / 153;
+        let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+        let month = if shifted_month < 10 { shifted_month + 3 } else { shifted_month - 9 };
+        let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+
+        DateTime {
+            second: (second_of_day % 60) as u8,
+            minute: (second_of_day / 60 % 60) as u8,
+            hour: (second_of_day / 3600) as u8,
+            day: day as u8,
+            month: month as u8,
+            year: year as i32,
+        }
+    }
+}
+
+impl<TExt: MachineExtensions> Machine<TExt> {
+    /// Current date and time according to the machine's clock.
+    pub fn date_time(&mut self) -> DateTime {
+        DateTime::from_unix_time(self.clock().now())
+    }
+
+    /// Wait for given number of milliseconds using the machine's clock.
+    ///
+    /// Fails with `MachineError::Interrupted` if interrupt flag is set while waiting.
+    pub fn sleep_ms(&mut self, milliseconds: u64) -> Result<(), MachineError> {
+        let mut remaining = Duration::from_millis(milliseconds);
+
+        while !remaining.is_zero() {
+            self.check_interrupt()?;
+
+            let chunk = remaining.min(SLEEP_CHUNK);
+            self.clock().sleep(chunk);
+            remaining -= chunk;
+        }
+
+        Ok(())
+    }
+
+    /// Push components of current date and time to data stack in `TIME&DATE` order.
+    pub(crate) fn push_date_time(&mut self) -> Result<(), MachineError> {
+        let date_time = self.date_time();
+
+        for value in [
+            date_time.second as Cell,
+            date_time.minute as Cell,
+            date_time.hour as Cell,
+            date_time.day as Cell,
+            date_time.month as Cell,
+            date_time.year as Cell,
+        ] {
+            self.memory.data_push_cell(value)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    use crate::input::StaticStringInput;
+    use crate::machine_testing::*;
+
+    use super::*;
+
+    #[test]
+    fn test_from_unix_time() {
+        assert_eq!(DateTime::from_unix_time(Duration::ZERO), DateTime {
+            second: 0, minute: 0, hour: 0, day: 1, month: 1, year: 1970,
+        });
+        assert_eq!(DateTime::from_unix_time(Duration::from_secs(951_827_696)), DateTime {
+            second: 56, minute: 34, hour: 12, day: 29, month: 2, year: 2000,
+        });
+        assert_eq!(DateTime::from_unix_time(Duration::from_secs(1_792_022_399)), DateTime {
+            second: 59, minute: 59, hour: 23, day: 14, month: 10, year: 2026,
+        });
+    }
+
+    #[test]
+    fn test_ms_advances_clock() {
+        let clock = FakeClock::default();
+        let mut machine = TestMachine::default();
+        machine.set_clock(Box::new(clock.clone()));
+
+        machine.extensions.input = StaticStringInput::new("25 MS : pause 1000 MS ; pause");
+        machine.interpret_input().unwrap();
+
+        assert_eq!(clock.time(), Duration::from_millis(1025));
+        assert!(clock.longest_sleep() <= SLEEP_CHUNK);
+        machine.assert_data_stack_state(&[]);
+    }
+
+    #[test]
+    fn test_ms_interrupted() {
+        let clock = FakeClock::default();
+        let flag = Arc::new(AtomicBool::new(false));
+        let mut machine = TestMachine::default();
+        machine.set_clock(Box::new(clock.clone()));
+        machine.set_interrupt_flag(flag.clone());
+        machine.register_native_word("interrupt", move |_| {
+            flag.store(true, Ordering::Relaxed);
+
+            Ok(())
+        });
+
+        machine.extensions.input = StaticStringInput::new("interrupt 60000 MS");
+
+        assert!(matches!(machine.interpret_input(), Err(MachineError::Interrupted)));
+        assert_eq!(clock.time(), Duration::ZERO);
+    }
+
+    #[test]
+    fn test_time_and_date() {
+        let clock = FakeClock::default();
+        clock.set_now(Duration::from_secs(951_827_696));
+        let mut machine = TestMachine::default();
+        machine.set_clock(Box::new(clock));
+
+        machine.extensions.input = StaticStringInput::new("TIME&DATE");
+        machine.interpret_input().unwrap();
+
+        machine.assert_data_stack_state(&[
+            StackElement::Cell(56),
+            StackElement::Cell(34),
+            StackElement::Cell(12),
+            StackElement::Cell(29),
+            StackElement::Cell(2),
+            StackElement::Cell(2000),
+        ]);
+    }
+
+    #[test]
+    fn test_utime() {
+        let clock = FakeClock::default();
+        clock.set_now(Duration::from_micros(1_000_123));
+        let mut machine = TestMachine::default();
+        machine.set_clock(Box::new(clock.clone()));
+
+        machine.extensions.input = StaticStringInput::new(": elapsed UTIME 250 MS UTIME ; elapsed");
+        machine.interpret_input().unwrap();
+
+        machine.assert_data_stack_state(&[
+            StackElement::DoubleCell(1_000_123),
+            StackElement::DoubleCell(1_250_123),
+        ]);
+    }
+}
@@ -0,0 +1,184 @@
+use std::io;
+
+use crate::machine::{Machine, MachineExtensions};
+use crate::mem::Address;
+use crate::opcodes::OpCode;
+
+const BITS_PER_WORD: usize = u64::BITS as usize;
+
+/// Records addresses instructions were executed at.
+#[derive(Clone, Default, Debug)]
+pub struct Coverage {
+    /// Bit `n % 64` of word `n / 64` is set if an instruction at address `n` was executed. Grows up to the highest
+    /// executed address.
+    executed: Vec<u64>,
+}
+
+impl Coverage {
+    /// Note that an instruction at given address is about to be executed.
+    #[inline]
+    pub fn record(&mut self, address: Address) {
+        let (index, bit) = (address as usize / BITS_PER_WORD, address as usize % BITS_PER_WORD);
+
+        if index >= self.executed.len() {
+            self.executed.resize(index + 1, 0);
+        }
+
+        self.executed[index] |= 1 << bit;
+    }
+
+    pub fn is_executed(&self, address: Address) -> bool {
+        let (index, bit) = (address as usize / BITS_PER_WORD, address as usize % BITS_PER_WORD);
+
+        self.executed.get(index).is_some_and(|word| word & (1 << bit) != 0)
+    }
+
+    /// Raw bitset of executed addresses: bit `n % 64` of word `n / 64` stands for address `n`.
+    pub fn executed(&self) -> &[u64] {
+        &self.executed
+    }
+
+    pub fn reset(&mut self) {
+        self.executed.clear();
+    }
+}
+
+/// Numbers of instructions of an article.
+#[derive(Clone, Copy, Default, Debug, PartialEq, Eq)]
+pub struct ArticleCoverage {
+    pub executed: usize,
+    pub total: usize,
+}
+
+impl<TExt: MachineExtensions> Machine<TExt> {
+    /// Addresses of instructions in body of article with given header address, data ranges are skipped.
+    fn article_instructions(&self, header_address: Address) -> Vec<Address> {
+        let Some(article) = self.memory.articles().find(|article| article.get_header_address() == header_address) else {
+            return Vec::new();
+        };
+
+        let end = self.memory.article_end(&article);
+        let mut address = article.body_address();
+        let mut instructions = Vec::new();
+
+        while address < end {
+            let next = match self.memory.data_range_containing(address) {
+                Some(range) => range.end().wrapping_add(1),
+                None => {
+                    instructions.push(address);
+
+                    OpCode::format_at(&mut io::sink(), self, address).unwrap_or(address)
+                }
+            };
+
+            if next <= address {
+                break;
+            }
+
+            address = next;
+        }
+
+        instructions
+    }
+
+    /// Count executed and total instructions of every article, the oldest articles first.
+    ///
+    /// All instructions are counted as not executed if coverage is not being recorded.
+    pub fn article_coverage(&self) -> Vec<(Address, ArticleCoverage)> {
+        let mut headers: Vec<Address> = self.memory.articles().map(|article| article.get_header_address()).collect();
+        headers.reverse();
+
+        headers.into_iter().map(|header_address| {
+            let instructions = self.article_instructions(header_address);
+            let executed = self.coverage().map_or(0, |coverage| {
+                instructions.iter().filter(|&&address| coverage.is_executed(address)).count()
+            });
+
+            (header_address, ArticleCoverage { executed, total: instructions.len() })
+        }).collect()
+    }
+
+    /// Print numbers of executed and total instructions of every article, flagging articles never executed.
+    pub fn coverage_report(&self, writer: &mut impl io::Write) -> io::Result<()> {
+        if self.coverage().is_none() {
+            return writeln!(writer, "Coverage is not recorded");
+        }
+
+        writeln!(writer, "{:>8} {:>8}  word", "executed", "total")?;
+
+        for (header_address, stats) in self.article_coverage() {
+            write!(writer, "{:>8} {:>8}  ", stats.executed, stats.total)?;
+
+            match self.memory.article_containing(header_address) {
+                Some(article) => write!(writer, "{}", article.name())?,
+                None => write!(writer, "<{:04X}>", header_address)?,
+            }
+
+            writeln!(writer, "{}", if stats.executed == 0 { " (never executed)" } else { "" })?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::str::from_utf8;
+
+    use crate::input::StaticStringInput;
+    use crate::machine_testing::*;
+
+    use super::*;
+
+    #[test]
+    fn test_record() {
+        let mut coverage = Coverage::default();
+        coverage.record(3);
+        coverage.record(130);
+
+        assert!(coverage.is_executed(3));
+        assert!(coverage.is_executed(130));
+        assert!(!coverage.is_executed(4));
+        assert!(!coverage.is_executed(Address::MAX));
+        assert_eq!(coverage.executed(), [1 << 3, 0, 1 << 2]);
+
+        coverage.reset();
+        assert!(!coverage.is_executed(3));
+    }
+
+    #[test]
+    fn test_branch_coverage() {
+        let mut machine = TestMachine::default();
+        machine.extensions.input = StaticStringInput::new("
+            : classify 0 < IF 1 ELSE 2 3 + THEN ;
+            : unused 42 ;
+        ");
+        machine.interpret_input().unwrap();
+        machine.enable_coverage();
+
+        machine.extensions.input = StaticStringInput::new("5 classify");
+        machine.interpret_input().unwrap();
+        machine.assert_data_stack_state(&[StackElement::Cell(5)]);
+
+        let header_address = machine.memory.lookup_article(b"classify").unwrap().unwrap().get_header_address();
+        let instructions = machine.article_instructions(header_address);
+        let executed: Vec<bool> = instructions.iter()
+            .map(|&address| machine.coverage().unwrap().is_executed(address))
+            .collect();
+
+        // start, literal 0, `<`, branch over `1`, `1`, jump over the `ELSE` part, `2`, `3`, `+`, return
+        assert_eq!(executed, [true, true, true, true, false, false, true, true, true, true]);
+
+        let mut report = Vec::new();
+        machine.coverage_report(&mut report).unwrap();
+
+        assert_eq!(from_utf8(&report).unwrap(), "\
+executed    total  word
+       8       10  classify
+       0        3  unused (never executed)
+");
+
+        machine.disable_coverage();
+        assert!(machine.coverage().is_none());
+    }
+}
@@ -0,0 +1,263 @@
+use std::io;
+
+use crate::cell::Cell;
+use crate::input::{Input, PromptContext};
+use crate::literal::parse_literal;
+use crate::machine::{Machine, MachineExtensions};
+use crate::mem::{Address, MEM_SIZE};
+use crate::print_debug_info::StackFormatOptions;
+
+/// Text printed by `help` command.
+pub const DEBUGGER_HELP: &str = "\
+bt           print backtrace
+ds           print data stack
+rs           print call (return) stack
+dis [ADDR]   disassemble code around ADDR, the failed instruction by default
+x ADDR LEN   print hex dump of LEN bytes of memory starting at ADDR
+c            leave debugger, resume code stopped at a breakpoint and continue interactive session
+help         print this list
+Numbers are hexadecimal unless prefixed by # (decimal) or % (binary).
+";
+
+/// Command of post-mortem debugger.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DebuggerCommand {
+    Backtrace,
+    DataStack,
+    CallStack,
+
+    /// Disassemble code around given address or around program counter if `None`.
+    Disassemble(Option<Address>),
+
+    Examine { address: Address, length: u16 },
+    Continue,
+    Help,
+}
+
+fn parse_number(argument: Option<&str>, name: &str) -> Result<u16, String> {
+    let argument = argument.ok_or_else(|| format!("Missing {}", name))?;
+    let value: Cell = parse_literal(argument.as_bytes(), 16).ok_or_else(|| format!("Invalid {}: {}", name, argument))?;
+
+    u16::try_from(value as u32).map_err(|_| format!("Invalid {}: {}", name, argument))
+}
+
+impl DebuggerCommand {
+    /// Parse a line typed by user, returns `Ok(None)` for an empty line.
+    pub fn parse(line: &str) -> Result<Option<DebuggerCommand>, String> {
+        let mut words = line.split_whitespace();
+        let Some(name) = words.next() else { return Ok(None) };
+
+        let command = match name {
+            "bt" => DebuggerCommand::Backtrace,
+            "ds" => DebuggerCommand::DataStack,
+            "rs" => DebuggerCommand::CallStack,
+            "dis" => match words.next() {
+                None => DebuggerCommand::Disassemble(None),
+                address => DebuggerCommand::Disassemble(Some(parse_number(address, "address")?)),
+            },
+            "x" => DebuggerCommand::Examine {
+                address: parse_number(words.next(), "address")?,
+                length: parse_number(words.next(), "length")?,
+            },
+            "c" => DebuggerCommand::Continue,
+            "help" | "h" | "?" => DebuggerCommand::Help,
+            _ => return Err(format!("Unknown command: {} (type help for list of commands)", name)),
+        };
+
+        match words.next() {
+            Some(extra) => Err(format!("Unexpected argument: {}", extra)),
+            None => Ok(Some(command)),
+        }
+    }
+
+    /// Print information requested by the command.
+    ///
+    /// Nothing is printed for `Continue`, it's up to the caller to leave the debugger.
+    pub fn execute<TExt: MachineExtensions>(&self, machine: &Machine<TExt>, f: &mut impl io::Write) -> io::Result<()> {
+        match *self {
+            DebuggerCommand::Backtrace => {
+                let mut backtrace = Vec::new();
+                machine.format_backtrace(&mut backtrace)?;
+
+                if backtrace.is_empty() {
+                    writeln!(f, "No code is being executed")
+                } else {
+                    f.write_all(&backtrace)
+                }
+            }
+            DebuggerCommand::DataStack => machine.memory.format_stack(f, StackFormatOptions::default()),
+            DebuggerCommand::CallStack => machine.memory.format_call_stack(f),
+            DebuggerCommand::Disassemble(address) => match address.or(machine.program_counter()) {
+                Some(address) => machine.print_code_context(f, address),
+                None => writeln!(f, "No code is being executed, specify an address"),
+            },
+            DebuggerCommand::Examine { address, length } => {
+                let (start, end) = (address as usize, address as usize + length as usize);
+
+                if end > MEM_SIZE {
+                    writeln!(f, "Range {:04X}+{:04X} does not fit into memory", address, length)
+                } else {
+                    machine.memory.dump_range(f, start..end)
+                }
+            }
+            DebuggerCommand::Continue => Ok(()),
+            DebuggerCommand::Help => f.write_all(DEBUGGER_HELP.as_bytes()),
+        }
+    }
+}
+
+/// Read the rest of current line from given input, returns `None` at the end of input.
+pub fn read_input_line(input: &mut dyn Input) -> io::Result<Option<String>> {
+    let mut line = Vec::new();
+
+    loop {
+        match input.read().map_err(|err| io::Error::other(format!("{:?}", err)))? {
+            None if line.is_empty() => return Ok(None),
+            None | Some(b'\n') => break,
+            Some(chr) => line.push(chr),
+        }
+    }
+
+    // The line is consumed, so interactive input should not report it's end as interpreted
+    input.discard_line();
+
+    Ok(Some(String::from_utf8_lossy(&line).into_owned()))
+}
+
+/// Run post-mortem debugger reading commands from machine input and writing results to `report`, until `c` command
+/// or the end of input.
+///
+/// The debugger only inspects the machine, so state left by the failed code stays intact.
+pub fn run_debugger<TExt: MachineExtensions>(machine: &mut Machine<TExt>, report: &mut impl io::Write) -> io::Result<()> {
+    writeln!(report, "Post-mortem debugger, type help for list of commands or c to continue.")?;
+
+    loop {
+        report.flush()?;
+
+        // Commands are typed by user even if the code was loaded from a file
+        let input = machine.extensions.get_input();
+        input.set_prompt_context(PromptContext::Debugger);
+
+        let Some(line) = read_input_line(input)? else { break };
+
+        match DebuggerCommand::parse(&line) {
+            Ok(None) => {}
+            Ok(Some(DebuggerCommand::Continue)) => break,
+            Ok(Some(command)) => command.execute(machine, report)?,
+            Err(message) => writeln!(report, "{}", message)?,
+        }
+    }
+
+    machine.extensions.get_input().set_prompt_context(PromptContext::Interpreter);
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use std::str::from_utf8;
+
+    use crate::input::StaticStringInput;
+    use crate::machine_error::MachineError;
+    use crate::machine_testing::*;
+
+    use super::*;
+
+    /// Machine failed with data stack underflow in `inner` called by `outer`.
+    fn faulted_machine() -> TestMachine {
+        let mut machine = TestMachine::default();
+        machine.extensions.input = StaticStringInput::new("
+            : inner DROP DROP DROP ;
+            : outer inner ;
+            VARIABLE buf 1 buf ! 5 7 outer
+        ");
+
+        assert!(matches!(machine.interpret_input(), Err(MachineError::DataStackUnderflow { .. })));
+
+        machine
+    }
+
+    fn execute(machine: &TestMachine, line: &str) -> String {
+        let mut output = Vec::new();
+        DebuggerCommand::parse(line).unwrap().unwrap().execute(machine, &mut output).unwrap();
+
+        String::from_utf8(output).unwrap()
+    }
+
+    #[test]
+    fn test_parse_commands() {
+        assert_eq!(DebuggerCommand::parse("  "), Ok(None));
+        assert_eq!(DebuggerCommand::parse("bt"), Ok(Some(DebuggerCommand::Backtrace)));
+        assert_eq!(DebuggerCommand::parse(" ds "), Ok(Some(DebuggerCommand::DataStack)));
+        assert_eq!(DebuggerCommand::parse("rs"), Ok(Some(DebuggerCommand::CallStack)));
+        assert_eq!(DebuggerCommand::parse("dis"), Ok(Some(DebuggerCommand::Disassemble(None))));
+        assert_eq!(DebuggerCommand::parse("dis 1A0"), Ok(Some(DebuggerCommand::Disassemble(Some(0x1A0)))));
+        assert_eq!(
+            DebuggerCommand::parse("x $FF00 #32"),
+            Ok(Some(DebuggerCommand::Examine { address: 0xFF00, length: 32 })),
+        );
+        assert_eq!(DebuggerCommand::parse("c"), Ok(Some(DebuggerCommand::Continue)));
+        assert_eq!(DebuggerCommand::parse("help"), Ok(Some(DebuggerCommand::Help)));
+
+        assert!(DebuggerCommand::parse("step").unwrap_err().starts_with("Unknown command: step"));
+        assert_eq!(DebuggerCommand::parse("x 100"), Err("Missing length".to_string()));
+        assert_eq!(DebuggerCommand::parse("dis foo"), Err("Invalid address: foo".to_string()));
+        assert_eq!(DebuggerCommand::parse("bt full"), Err("Unexpected argument: full".to_string()));
+    }
+
+    #[test]
+    fn test_commands_output() {
+        let machine = faulted_machine();
+
+        assert!(execute(&machine, "bt").starts_with("#0 inner +0x03\n#1 outer +0x"), "{}", execute(&machine, "bt"));
+        assert_eq!(execute(&machine, "ds"), "\t(empty)\n");
+
+        // The only return address is right after the call of `inner`
+        let outer = machine.memory.lookup_article(b"outer").unwrap().unwrap().body_address();
+        assert_eq!(execute(&machine, "rs"), format!("\t{0:04X} ({0:>5})\n", outer + 4));
+
+        let disassembly = execute(&machine, "dis");
+        assert!(disassembly.starts_with("In article inner:\n"), "{}", disassembly);
+        assert!(disassembly.contains("=> "), "{}", disassembly);
+
+        assert!(execute(&machine, &format!("dis {:X}", outer)).starts_with("In article outer:\n=> "));
+
+        let buf = machine.memory.lookup_article(b"buf").unwrap().unwrap().body_address();
+        assert!(execute(&machine, &format!("x {:X} 2", buf)).starts_with(&format!("{:04X}: ", buf)));
+        assert_eq!(execute(&machine, "x FFFF 2"), "Range FFFF+0002 does not fit into memory\n");
+        assert!(execute(&machine, "help").contains("x ADDR LEN"));
+    }
+
+    #[test]
+    fn test_scripted_session() {
+        let mut machine = faulted_machine();
+        let call_stack_depth = machine.memory.call_stack_depth();
+        machine.extensions.input = StaticStringInput::new("\nbt\nfrobnicate\nc\n42");
+        let mut report = Vec::new();
+
+        run_debugger(&mut machine, &mut report).unwrap();
+
+        let report = from_utf8(&report).unwrap();
+        let lines: Vec<&str> = report.lines().collect();
+
+        assert_eq!(lines[1], "#0 inner +0x03");
+        assert!(lines[3].starts_with("Unknown command: frobnicate"), "{}", report);
+        assert_eq!(lines.len(), 4, "{}", report);
+        assert_eq!(machine.memory.call_stack_depth(), call_stack_depth);
+
+        // Commands after `c` are left for the interpreter
+        machine.interpret_input().unwrap();
+        machine.assert_data_stack_state(&[StackElement::Cell(42)]);
+    }
+
+    #[test]
+    fn test_session_ends_with_input() {
+        let mut machine = faulted_machine();
+        machine.extensions.input = StaticStringInput::new("ds");
+        let mut report = Vec::new();
+
+        run_debugger(&mut machine, &mut report).unwrap();
+
+        assert!(from_utf8(&report).unwrap().ends_with("\t(empty)\n"));
+    }
+}
@@ -0,0 +1,406 @@
+use std::io;
+
+use int_enum::IntEnum;
+
+use crate::builtin_words::opcode_word;
+use crate::cell::{Cell, SignedCell, SignedDoubleCell, TRUE};
+use crate::literal::format_literal;
+use crate::machine::{Machine, MachineExtensions};
+use crate::mem::Address;
+use crate::opcodes::OpCode;
+use crate::readable_article::ReadableArticle;
+use crate::sized_string::ReadableSizedString;
+
+enum Item {
+    /// Source text of the instruction, e.g. a word name, a number or `." text"`.
+    Text(String),
+
+    /// Jump to given address, taken only when the flag on top of data stack is zero if `conditional`.
+    Branch { target: Address, conditional: bool },
+
+    Return,
+}
+
+struct Instruction {
+    address: Address,
+
+    /// Address of the next instruction.
+    next: Address,
+
+    item: Item,
+}
+
+/// Control flow of the code does not match any of control structures known to decompiler.
+struct Unstructured;
+
+struct Decompiler<'a, 'm, TExt: MachineExtensions> {
+    machine: &'a Machine<TExt>,
+    article: &'a ReadableArticle<'m>,
+    base: u32,
+}
+
+impl<TExt: MachineExtensions> Decompiler<'_, '_, TExt> {
+    fn number(&self, value: Cell) -> String {
+        format_literal(value as SignedCell, self.base)
+    }
+
+    fn sized_string(&self, address: Address) -> Option<(String, Address)> {
+        let string = ReadableSizedString::new(
+            &self.machine.memory.raw_memory,
+            address,
+            self.machine.memory.get_used_dict_segment(),
+        ).ok()?;
+
+        Some((String::from_utf8_lossy(&string.as_bytes()).into_owned(), string.full_range().end().wrapping_add(1)))
+    }
+
+    /// Name of the word called by a call of given address.
+    fn call_target_name(&self, target: Address) -> String {
+        if target == self.article.body_address() || target == self.article.call_address() {
+            return "RECURSE".to_string();
+        }
+
+        self.machine.memory.articles()
+            .find(|article| article.call_address() == target || article.body_address() == target)
+            .map_or_else(|| format!("( call {:04X} )", target), |article| article.name().to_string())
+    }
+
+    /// Decode instruction at given address, returns `None` if it doesn't fit before `end`.
+    fn decode(&self, address: Address, end: Address) -> Option<Instruction> {
+        let memory = &self.machine.memory.raw_memory;
+        let operand = address.checked_add(1)?;
+        let relative_target = || operand.wrapping_add(2).wrapping_add(memory.read_u16(operand));
+        let op_code = memory.read_u8(address);
+        let (item, next) = match OpCode::from_int(op_code) {
+            Err(_) => (Item::Text(format!("( illegal op-code {} )", op_code)), operand),
+            Ok(op_code @ (OpCode::Push0 | OpCode::Push1 | OpCode::PushTrue)) => {
+                let value = match op_code {
+                    OpCode::Push0 => 0,
+                    OpCode::Push1 => 1,
+                    _ => TRUE,
+                };
+
+                (Item::Text(self.number(value)), operand)
+            }
+            Ok(OpCode::Literal8) => (Item::Text(self.number(memory.read_u8(operand) as Cell)), operand.checked_add(1)?),
+            Ok(OpCode::Literal16) => {
+                (Item::Text(self.number(memory.read_cell(operand))), operand.checked_add(OpCode::Literal16.operand_size()?)?)
+            }
+            Ok(OpCode::Literal32) => {
+                let value = memory.read_double_cell(operand) as SignedDoubleCell;
+
+                (Item::Text(format!("{}.", format_literal(value, self.base))), operand.checked_add(OpCode::Literal32.operand_size()?)?)
+            }
+            Ok(OpCode::LiteralString) => {
+                let (content, next) = self.sized_string(operand)?;
+
+                if next < end && memory.read_u8(next) == OpCode::EmitString.int_value() {
+                    (Item::Text(format!(".\" {}\"", content)), next + 1)
+                } else {
+                    (Item::Text(format!("S\" {}\"", content)), next)
+                }
+            }
+            Ok(OpCode::ExecBuiltin) => {
+                let (name, next) = self.sized_string(operand)?;
+
+                (Item::Text(name), next)
+            }
+            Ok(OpCode::ExecNative) => {
+                let index = memory.read_u16(operand);
+                let name = self.machine.native_words.name(index)
+                    .map_or_else(|| format!("( native {} )", index), |name| String::from_utf8_lossy(name).into_owned());
+
+                (Item::Text(name), operand.checked_add(2)?)
+            }
+            Ok(OpCode::Call) => (Item::Text(self.call_target_name(memory.read_u16(operand))), operand.checked_add(2)?),
+            Ok(OpCode::CallRel) => (Item::Text(self.call_target_name(relative_target())), operand.checked_add(2)?),
+            Ok(OpCode::CompileCall) => {
+                let name = self.call_target_name(memory.read_u16(operand));
+
+                (Item::Text(format!("POSTPONE {}", name)), operand.checked_add(2)?)
+            }
+            Ok(op_code @ (OpCode::GoTo | OpCode::GoToIfZ)) => {
+                let target = memory.read_u16(operand);
+
+                (Item::Branch { target, conditional: op_code == OpCode::GoToIfZ }, operand.checked_add(2)?)
+            }
+            Ok(op_code @ (OpCode::BranchRel | OpCode::BranchRelIfZ)) => {
+                let target = relative_target();
+
+                (Item::Branch { target, conditional: op_code == OpCode::BranchRelIfZ }, operand.checked_add(2)?)
+            }
+            Ok(OpCode::Return) => (Item::Return, operand),
+            Ok(op_code) => {
+                let text = opcode_word(op_code).map_or_else(|| format!("( {} )", op_code.mnemonic()), str::to_string);
+
+                (Item::Text(text), operand.checked_add(op_code.operand_size()?)?)
+            }
+        };
+
+        (next <= end).then_some(Instruction { address, next, item })
+    }
+
+    /// Decode instructions of the article body, up to the end of the article or the first data range.
+    fn decode_body(&self, end: Address) -> Vec<Instruction> {
+        let mut instructions = Vec::new();
+        let mut address = self.article.call_address();
+
+        while address < end && self.machine.memory.data_range_containing(address).is_none() {
+            match self.decode(address, end) {
+                Some(instruction) => {
+                    address = instruction.next;
+
+                    // Noops are used as padding and as a mark of immediate words in older images
+                    if self.machine.memory.raw_memory.read_u8(instruction.address) != OpCode::Noop.int_value() {
+                        instructions.push(instruction);
+                    }
+                }
+                None => break,
+            }
+        }
+
+        instructions
+    }
+}
+
+/// Instructions of a word along with the address following the last of them.
+struct Code {
+    instructions: Vec<Instruction>,
+    end: Address,
+}
+
+impl Code {
+    /// Address of instruction with given index, or the end address for index past the last instruction.
+    fn address(&self, index: usize) -> Address {
+        self.instructions.get(index).map_or(self.end, |instruction| instruction.address)
+    }
+
+    /// Index of instruction at given address within `lo..=hi`.
+    fn index_of(&self, address: Address, lo: usize, hi: usize) -> Option<usize> {
+        (lo..=hi).find(|&index| self.address(index) == address)
+    }
+
+    fn is_branch(&self, index: usize, to: Address, is_conditional: bool) -> bool {
+        matches!(
+            self.instructions[index].item,
+            Item::Branch { target, conditional } if target == to && conditional == is_conditional
+        )
+    }
+
+    /// Render instructions `lo..hi` as a sequence of words with `IF ... ELSE ... THEN` and
+    /// `BEGIN ... WHILE ... REPEAT` structures.
+    fn structured(&self, lo: usize, hi: usize, words: &mut Vec<String>) -> Result<(), Unstructured> {
+        let mut index = lo;
+
+        while index < hi {
+            let address = self.address(index);
+
+            if let Some(repeat) = (index + 1..hi).find(|&repeat| self.is_branch(repeat, address, false)) {
+                let exit = self.instructions[repeat].next;
+                let condition = (index..repeat).find(|&branch| self.is_branch(branch, exit, true)).ok_or(Unstructured)?;
+
+                words.push("BEGIN".to_string());
+                self.structured(index, condition, words)?;
+                words.push("WHILE".to_string());
+                self.structured(condition + 1, repeat, words)?;
+                words.push("REPEAT".to_string());
+                index = repeat + 1;
+
+                continue;
+            }
+
+            match &self.instructions[index].item {
+                Item::Text(text) => words.push(text.clone()),
+                Item::Return => words.push("EXIT".to_string()),
+                Item::Branch { target, conditional: true } => {
+                    let then = self.index_of(*target, index + 1, hi).ok_or(Unstructured)?;
+                    let else_end = match self.instructions.get(then.wrapping_sub(1)) {
+                        Some(Instruction { item: Item::Branch { target, conditional: false }, address, .. })
+                        if then - 1 > index && target > address => Some(self.index_of(*target, then, hi).ok_or(Unstructured)?),
+                        _ => None,
+                    };
+
+                    words.push("IF".to_string());
+
+                    match else_end {
+                        Some(else_end) => {
+                            self.structured(index + 1, then - 1, words)?;
+                            words.push("ELSE".to_string());
+                            self.structured(then, else_end, words)?;
+                            index = else_end;
+                        }
+                        None => {
+                            self.structured(index + 1, then, words)?;
+                            index = then;
+                        }
+                    }
+
+                    words.push("THEN".to_string());
+
+                    continue;
+                }
+                Item::Branch { .. } => return Err(Unstructured),
+            }
+
+            index += 1;
+        }
+
+        Ok(())
+    }
+
+    /// Render instructions `lo..hi` with branches as `BRANCH`/`?BRANCH` to explicit labels.
+    fn labeled(&self, lo: usize, hi: usize, words: &mut Vec<String>) {
+        let mut targets: Vec<Address> = self.instructions[lo..hi].iter()
+            .filter_map(|instruction| match instruction.item {
+                Item::Branch { target, .. } => self.index_of(target, lo, hi).map(|_| target),
+                _ => None,
+            })
+            .collect();
+        targets.sort_unstable();
+        targets.dedup();
+
+        let label = |address: Address| targets.binary_search(&address).ok().map(|index| format!("L{}", index + 1));
+
+        for index in lo..=hi {
+            if let Some(label) = label(self.address(index)) {
+                words.push(format!("{}:", label));
+            }
+
+            if index == hi {
+                break;
+            }
+
+            words.push(match &self.instructions[index].item {
+                Item::Text(text) => text.clone(),
+                Item::Return => "EXIT".to_string(),
+                Item::Branch { target, conditional } => format!(
+                    "{} {}",
+                    if *conditional { "?BRANCH" } else { "BRANCH" },
+                    label(*target).unwrap_or_else(|| format!("{:04X}", target)),
+                ),
+            });
+        }
+    }
+}
+
+/// Write Forth-like source of the word defined by given article, reconstructed from it's compiled code.
+///
+/// Calls are shown as names of called words, literals as numbers in current base, branches as `IF ... ELSE ... THEN`
+/// and `BEGIN ... WHILE ... REPEAT` when they match code compiled by these words. Otherwise branches are shown as
+/// `BRANCH` and `?BRANCH` to labels written before their targets. Code following the first data range of the article
+/// (e.g. value of a variable) is not shown.
+pub fn decompile<TExt: MachineExtensions>(
+    writer: &mut impl io::Write,
+    machine: &Machine<TExt>,
+    article: &ReadableArticle,
+) -> io::Result<()> {
+    let base: Cell = machine.memory.get_base();
+    let decompiler = Decompiler { machine, article, base: base as u32 };
+    let instructions = decompiler.decode_body(machine.memory.article_end(article));
+    let end = instructions.last().map_or(article.call_address(), |instruction| instruction.next);
+    let code = Code { instructions, end };
+
+    // The final `Return` ends the definition rather than being an `EXIT`
+    let hi = match code.instructions.last() {
+        Some(Instruction { item: Item::Return, .. }) => code.instructions.len() - 1,
+        _ => code.instructions.len(),
+    };
+    let mut words = Vec::new();
+
+    if code.structured(0, hi, &mut words).is_err() {
+        words.clear();
+        code.labeled(0, hi, &mut words);
+    }
+
+    write!(writer, ": {}", article.name())?;
+
+    for word in words {
+        write!(writer, " {}", word)?;
+    }
+
+    writeln!(
+        writer, " ;{}{}",
+        if article.is_immediate() { " IMMEDIATE" } else { "" },
+        if article.is_compile_only() { " COMPILE-ONLY" } else { "" },
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use std::str::from_utf8;
+
+    use crate::input::StaticStringInput;
+    use crate::machine_testing::*;
+
+    use super::*;
+
+    fn decompiled(machine: &TestMachine, name: &str) -> String {
+        let article = machine.memory.lookup_article(name.as_bytes()).unwrap().unwrap();
+        let mut source = Vec::new();
+
+        decompile(&mut source, machine, &article).unwrap();
+
+        from_utf8(&source).unwrap().to_string()
+    }
+
+    #[test]
+    fn test_decompile_factorial() {
+        let mut machine = TestMachine::default();
+        machine.extensions.input = StaticStringInput::new("
+            : 1- 1 - ;
+            : FACTORIAL DUP 2 < IF DROP 1 EXIT THEN DUP 1- RECURSE * ;
+            : greet .\" Hello, world!\" CR S\" bye\" DROP DROP ;
+            : skip 300 SWAP ; IMMEDIATE
+        ");
+        machine.interpret_input().unwrap();
+
+        assert_eq!(decompiled(&machine, "FACTORIAL"), ": FACTORIAL DUP 2 < IF DROP 1 EXIT THEN DUP 1- RECURSE * ;\n");
+        assert_eq!(decompiled(&machine, "greet"), ": greet .\" Hello, world!\" CR S\" bye\" DROP DROP ;\n");
+
+        machine.extensions.input = StaticStringInput::new("16 BASE !");
+        machine.interpret_input().unwrap();
+
+        assert_eq!(decompiled(&machine, "skip"), ": skip 12C SWAP ; IMMEDIATE\n");
+    }
+
+    #[test]
+    fn test_decompile_conditions() {
+        let mut machine = TestMachine::default();
+        machine.extensions.input = StaticStringInput::new("
+            : sum-to ( n -- sum ) 0 SWAP BEGIN DUP 0 > WHILE SWAP OVER + SWAP 1 - REPEAT DROP ;
+            : sign ( n -- n ) DUP 0 < IF DROP -1 ELSE 0 > IF 1 ELSE 0 THEN THEN ;
+            : nested BEGIN DUP WHILE DUP 2 > IF BEGIN DUP 5 > WHILE 1 - REPEAT THEN 1 - REPEAT ;
+        ");
+        machine.interpret_input().unwrap();
+
+        assert_eq!(
+            decompiled(&machine, "sum-to"),
+            ": sum-to 0 SWAP BEGIN DUP 0 > WHILE SWAP OVER + SWAP 1 - REPEAT DROP ;\n",
+        );
+        assert_eq!(
+            decompiled(&machine, "sign"),
+            ": sign DUP 0 < IF DROP -1 ELSE 0 > IF 1 ELSE 0 THEN THEN ;\n",
+        );
+        assert_eq!(
+            decompiled(&machine, "nested"),
+            ": nested BEGIN DUP WHILE DUP 2 > IF BEGIN DUP 5 > WHILE 1 - REPEAT THEN 1 - REPEAT ;\n",
+        );
+    }
+
+    #[test]
+    fn test_decompile_irreducible_jumps() {
+        let mut machine = TestMachine::default();
+        machine.extensions.input = StaticStringInput::new(": weird DUP IF 1 + THEN 2 * ;");
+        machine.interpret_input().unwrap();
+
+        // Make the conditional jump go back to the start of the word
+        let article = machine.memory.lookup_article(b"weird").unwrap().unwrap();
+        let start = article.call_address();
+        let branch = start + 1;
+
+        assert_eq!(machine.memory.raw_memory.read_u8(branch), OpCode::BranchRelIfZ.int_value());
+        machine.memory.raw_memory.write_u16(branch + 1, start.wrapping_sub(branch + 3));
+
+        assert_eq!(decompiled(&machine, "weird"), ": weird L1: DUP ?BRANCH L1 1 + 2 * ;\n");
+    }
+}
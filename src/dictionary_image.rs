@@ -0,0 +1,565 @@
+use std::collections::HashMap;
+use std::fmt::{Display, Formatter};
+use std::io;
+use std::ops::Range;
+
+use int_enum::IntEnum;
+
+use crate::cell::{aligned, CELL_BYTES};
+use crate::machine::{Machine, MachineExtensions};
+use crate::machine_error::MachineError;
+use crate::mem::Address;
+use crate::opcodes::OpCode;
+
+/// First bytes of every dictionary image.
+pub const DICTIONARY_IMAGE_MAGIC: [u8; 4] = *b"RS4D";
+
+/// Version of dictionary image format written by `Machine::save_dictionary`.
+pub const DICTIONARY_IMAGE_VERSION: u16 = 2;
+
+/// Dictionary image header consists of:
+///
+/// - magic (4 bytes)
+/// - format version (u16)
+/// - cell size in bytes (u8)
+/// - address the dictionary started at (u16)
+/// - dictionary size in bytes (u16)
+/// - last article presence flag (u8) followed by its offset (u16)
+/// - number of data ranges (u16)
+/// - number of relocations (u16)
+/// - number of exported entry points (u16)
+///
+/// Header is followed by data ranges (pairs of u16 offsets of first and last byte of each range), relocations
+/// (u16 offsets of absolute address operands), exported entry points (u16 offset followed by name as a sized
+/// string) and dictionary content.
+/// All offsets are relative to the start of dictionary. All values are little-endian.
+const DICTIONARY_IMAGE_HEADER_SIZE: usize = 4 + 2 + 1 + 2 + 2 + 1 + 2 + 2 + 2 + 2;
+
+#[derive(Debug)]
+pub enum DictionaryImageError {
+    IOError(io::Error),
+    BadMagic,
+    UnsupportedVersion(u16),
+    /// Image was made by a machine with different cell width.
+    CellSizeMismatch {
+        expected: u8,
+        actual: u8,
+    },
+    /// Article chain, data ranges or relocations point outside of the image.
+    InvalidImage,
+    /// A word is being compiled, loaded dictionary would overlap with its body.
+    DefinitionInProgress,
+    OutOfDataSpace {
+        needed: u16,
+        available: u16,
+    },
+}
+
+/// Content of a dictionary image with all addresses stored as offsets from the start of its code.
+struct DictionaryImage<'a> {
+    base: Address,
+    code: &'a [u8],
+    last_article_offset: Option<u16>,
+    data_ranges: Vec<(u16, u16)>,
+    relocations: Vec<u16>,
+    exports: Vec<(String, u16)>,
+}
+
+impl<'a> DictionaryImage<'a> {
+    fn write(&self, dst: &mut impl io::Write) -> io::Result<()> {
+        let mut header = Vec::with_capacity(DICTIONARY_IMAGE_HEADER_SIZE);
+        header.extend_from_slice(&DICTIONARY_IMAGE_MAGIC);
+        header.extend_from_slice(&DICTIONARY_IMAGE_VERSION.to_le_bytes());
+        header.push(CELL_BYTES as u8);
+        header.extend_from_slice(&self.base.to_le_bytes());
+        header.extend_from_slice(&(self.code.len() as u16).to_le_bytes());
+        header.push(self.last_article_offset.is_some() as u8);
+        header.extend_from_slice(&self.last_article_offset.unwrap_or(0).to_le_bytes());
+        header.extend_from_slice(&(self.data_ranges.len() as u16).to_le_bytes());
+        header.extend_from_slice(&(self.relocations.len() as u16).to_le_bytes());
+        header.extend_from_slice(&(self.exports.len() as u16).to_le_bytes());
+
+        for (start, end) in &self.data_ranges {
+            header.extend_from_slice(&start.to_le_bytes());
+            header.extend_from_slice(&end.to_le_bytes());
+        }
+
+        for offset in &self.relocations {
+            header.extend_from_slice(&offset.to_le_bytes());
+        }
+
+        for (name, offset) in &self.exports {
+            header.extend_from_slice(&offset.to_le_bytes());
+            header.push(name.len() as u8);
+            header.extend_from_slice(name.as_bytes());
+        }
+
+        dst.write_all(&header)?;
+        dst.write_all(self.code)
+    }
+}
+
+impl From<io::Error> for DictionaryImageError {
+    fn from(err: io::Error) -> Self {
+        DictionaryImageError::IOError(err)
+    }
+}
+
+impl Display for DictionaryImageError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DictionaryImageError::IOError(err) => write!(f, "IO error: {}", err),
+            DictionaryImageError::BadMagic => write!(f, "not a dictionary image"),
+            DictionaryImageError::UnsupportedVersion(version) => write!(
+                f, "unsupported dictionary image version {}", version,
+            ),
+            DictionaryImageError::CellSizeMismatch { expected, actual } => write!(
+                f, "dictionary image cells are {} bytes wide, expected {} bytes", actual, expected,
+            ),
+            DictionaryImageError::InvalidImage => write!(f, "dictionary image is corrupted"),
+            DictionaryImageError::DefinitionInProgress => write!(
+                f, "cannot load dictionary image while a word is being compiled",
+            ),
+            DictionaryImageError::OutOfDataSpace { needed, available } => write!(
+                f, "dictionary image needs {} bytes, only {} bytes available", needed, available,
+            ),
+        }
+    }
+}
+
+fn read_u16(src: &mut impl io::Read) -> io::Result<u16> {
+    let mut bytes = [0u8; 2];
+    src.read_exact(&mut bytes)?;
+
+    Ok(u16::from_le_bytes(bytes))
+}
+
+fn read_u16_at(code: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([code[offset], code[offset + 1]])
+}
+
+fn write_u16_at(code: &mut [u8], offset: usize, value: u16) {
+    code[offset..offset + 2].copy_from_slice(&value.to_le_bytes());
+}
+
+impl<TExt: MachineExtensions> Machine<TExt> {
+    /// Write compiled dictionary to given writer in a form that can be loaded by `load_dictionary` on top of
+    /// dictionary of another machine.
+    ///
+    /// Code compiled by the standard compiler is saved correctly. Absolute addresses compiled by user code
+    /// (e.g. with `,`) are not tracked and will point to wrong locations once the image is loaded at different
+    /// address.
+    pub fn save_dictionary(&self, dst: &mut impl io::Write) -> io::Result<()> {
+        let segment = self.memory.get_used_dict_segment();
+        let base = *segment.start();
+        let size = self.memory.dictionary_size();
+        let in_image = |address: Address| address >= base && address - base < size;
+
+        let code = self.memory.raw_memory.address_slice(base, size as usize);
+
+        DictionaryImage {
+            base,
+            code: &code,
+            last_article_offset: self.memory.last_article_ptr.map(|address| address - base),
+            data_ranges: self.memory.data_ranges().iter()
+                .filter(|range| in_image(*range.start()) && in_image(*range.end()))
+                .map(|range| (range.start() - base, range.end() - base))
+                .collect(),
+            relocations: self.memory.relocations().iter()
+                .filter(|&&address| in_image(address) && in_image(address.wrapping_add(1)))
+                .map(|address| address - base)
+                .collect(),
+            exports: Vec::new(),
+        }.write(dst)
+    }
+
+    /// Build a dictionary image containing only code of articles, without their names and article chain.
+    ///
+    /// Code is re-arranged so absolute and relative references between articles are rewritten.
+    /// Addresses of given exported words are stored in the image and returned by `load_dictionary`,
+    /// other words can not be found after the image is loaded.
+    ///
+    /// Fails if an article contains anything but code and marked data fields.
+    pub fn strip_dictionary(&self, exports: &[&str]) -> Result<Vec<u8>, MachineError> {
+        let mut articles: Vec<(Address, Address)> = self.memory.articles()
+            .map(|article| (article.get_header_address(), article.body_address()))
+            .collect();
+        articles.sort_unstable();
+
+        let dict_ptr = self.memory.get_dict_ptr();
+        let mut code = Vec::new();
+        // Ranges of original bodies along with offsets they are copied to
+        let mut bodies: Vec<(Range<Address>, u16)> = Vec::new();
+
+        for (i, &(_, body_address)) in articles.iter().enumerate() {
+            let body = body_address..articles.get(i + 1).map_or(dict_ptr, |(header_address, _)| *header_address);
+
+            bodies.push((body.clone(), code.len() as u16));
+            code.extend_from_slice(&self.memory.raw_memory.slice(body.start as usize..body.end as usize));
+        }
+
+        let map = |address: Address| bodies.iter()
+            .find(|(body, _)| body.contains(&address))
+            .map(|(body, offset)| offset + (address - body.start));
+
+        let data_ranges: Vec<_> = self.memory.data_ranges().iter()
+            .filter_map(|range| Some((map(*range.start())?, map(*range.end())?)))
+            .collect();
+        let is_data = |address: Address| self.memory.data_ranges().iter().find(|range| range.contains(&address));
+
+        let mut relocations = Vec::new();
+
+        for &address in self.memory.relocations() {
+            if let Some(offset) = map(address) {
+                let target = self.memory.raw_memory.read_u16(address);
+                let target_offset = map(target).ok_or(MachineError::InvalidJumpTarget { from: address, to: target })?;
+
+                write_u16_at(&mut code, offset as usize, target_offset);
+                relocations.push(offset);
+            }
+        }
+
+        for (body, _) in &bodies {
+            let mut address = body.start;
+
+            while address < body.end {
+                if let Some(range) = is_data(address) {
+                    address = range.end().wrapping_add(1);
+                    continue;
+                }
+
+                let op_code = self.memory.raw_memory.read_u8(address);
+                let op = OpCode::from_int(op_code)
+                    .map_err(|_| MachineError::IllegalOpCodeError { address, op_code })?;
+                let offset = map(address).unwrap();
+
+                match op {
+                    OpCode::Call | OpCode::GoTo | OpCode::GoToIfZ => {
+                        let target = self.memory.raw_memory.read_u16(address + 1);
+                        let target_offset = map(target)
+                            .ok_or(MachineError::InvalidJumpTarget { from: address, to: target })?;
+
+                        write_u16_at(&mut code, offset as usize + 1, target_offset);
+                        relocations.push(offset + 1);
+                    }
+                    OpCode::CallRel | OpCode::BranchRel | OpCode::BranchRelIfZ => {
+                        let target = address.wrapping_add(3).wrapping_add(self.memory.raw_memory.read_u16(address + 1));
+                        let target_offset = map(target)
+                            .ok_or(MachineError::InvalidJumpTarget { from: address, to: target })?;
+
+                        write_u16_at(&mut code, offset as usize + 1, target_offset.wrapping_sub(offset + 3));
+                    }
+                    _ => {}
+                }
+
+                address = op.format(&mut io::sink(), self, address)
+                    .expect("writing to io::sink never fails");
+            }
+        }
+
+        let exports = exports.iter()
+            .map(|&name| {
+                let article = self.memory.lookup_article(name.as_bytes())?.ok_or(MachineError::NoArticle)?;
+
+                Ok((name.to_string(), map(article.call_address()).ok_or(MachineError::NoArticle)?))
+            })
+            .collect::<Result<Vec<_>, MachineError>>()?;
+
+        let mut image = Vec::new();
+        DictionaryImage {
+            base: 0,
+            code: &code,
+            last_article_offset: None,
+            data_ranges,
+            relocations,
+            exports,
+        }.write(&mut image).expect("writing to a vector never fails");
+
+        Ok(image)
+    }
+
+    /// Append dictionary saved by `save_dictionary` to dictionary of this machine.
+    ///
+    /// Articles of loaded dictionary are linked on top of existing ones, so they take precedence over existing
+    /// articles with the same names.
+    ///
+    /// Returns addresses of entry points exported by the image (see `strip_dictionary`).
+    ///
+    /// Machine is not modified if the image is invalid or does not fit into free data space.
+    pub fn load_dictionary(&mut self, src: &mut impl io::Read) -> Result<HashMap<String, Address>, DictionaryImageError> {
+        let mut magic = [0u8; 4];
+        src.read_exact(&mut magic)?;
+
+        if magic != DICTIONARY_IMAGE_MAGIC {
+            return Err(DictionaryImageError::BadMagic);
+        }
+
+        let version = read_u16(src)?;
+        if version != DICTIONARY_IMAGE_VERSION {
+            return Err(DictionaryImageError::UnsupportedVersion(version));
+        }
+
+        let mut cell_size = [0u8; 1];
+        src.read_exact(&mut cell_size)?;
+        if cell_size[0] != CELL_BYTES as u8 {
+            return Err(DictionaryImageError::CellSizeMismatch { expected: CELL_BYTES as u8, actual: cell_size[0] });
+        }
+
+        let old_base = read_u16(src)?;
+        let size = read_u16(src)?;
+        let mut has_last_article = [0u8; 1];
+        src.read_exact(&mut has_last_article)?;
+        let last_article_offset = read_u16(src)?;
+        let data_ranges_count = read_u16(src)?;
+        let relocations_count = read_u16(src)?;
+        let exports_count = read_u16(src)?;
+
+        let mut data_ranges = Vec::with_capacity(data_ranges_count as usize);
+        for _ in 0..data_ranges_count {
+            let start = read_u16(src)?;
+            let end = read_u16(src)?;
+
+            if start > end || end >= size {
+                return Err(DictionaryImageError::InvalidImage);
+            }
+
+            data_ranges.push((start, end));
+        }
+
+        let mut relocations = Vec::with_capacity(relocations_count as usize);
+        for _ in 0..relocations_count {
+            let offset = read_u16(src)?;
+
+            if offset as u32 + 2 > size as u32 {
+                return Err(DictionaryImageError::InvalidImage);
+            }
+
+            relocations.push(offset);
+        }
+
+        let mut exports = Vec::with_capacity(exports_count as usize);
+        for _ in 0..exports_count {
+            let offset = read_u16(src)?;
+            let mut name_length = [0u8; 1];
+            src.read_exact(&mut name_length)?;
+            let mut name = vec![0u8; name_length[0] as usize];
+            src.read_exact(&mut name)?;
+
+            if offset >= size {
+                return Err(DictionaryImageError::InvalidImage);
+            }
+
+            exports.push((String::from_utf8(name).map_err(|_| DictionaryImageError::InvalidImage)?, offset));
+        }
+
+        let mut code = vec![0u8; size as usize];
+        src.read_exact(&mut code)?;
+
+        if self.memory.get_current_word().is_some() {
+            return Err(DictionaryImageError::DefinitionInProgress);
+        }
+
+        let dict_ptr = self.memory.get_dict_ptr();
+        let new_base = aligned(dict_ptr);
+        let needed = (new_base - dict_ptr) as u32 + size as u32;
+        let available = self.memory.free_data_space();
+
+        if needed > available as u32 {
+            return Err(DictionaryImageError::OutOfDataSpace { needed: needed.min(u16::MAX as u32) as u16, available });
+        }
+
+        let delta = new_base.wrapping_sub(old_base);
+
+        // Re-link article chain on top of existing articles
+        let last_article_ptr = if has_last_article[0] != 0 {
+            let mut header_offset = last_article_offset;
+
+            loop {
+                if header_offset as u32 + 2 > size as u32 {
+                    return Err(DictionaryImageError::InvalidImage);
+                }
+
+                let previous = read_u16_at(&code, header_offset as usize);
+                let previous_offset = previous.wrapping_sub(old_base);
+
+                if previous >= old_base && previous_offset < header_offset {
+                    write_u16_at(&mut code, header_offset as usize, previous.wrapping_add(delta));
+                    header_offset = previous_offset;
+                } else {
+                    write_u16_at(
+                        &mut code,
+                        header_offset as usize,
+                        self.memory.last_article_ptr.unwrap_or(Address::MAX),
+                    );
+                    break;
+                }
+            }
+
+            Some(new_base.wrapping_add(last_article_offset))
+        } else {
+            self.memory.last_article_ptr
+        };
+
+        for &offset in &relocations {
+            let value = read_u16_at(&code, offset as usize);
+            write_u16_at(&mut code, offset as usize, value.wrapping_add(delta));
+        }
+
+        self.memory.dict_align().expect("free space is checked above");
+        self.memory.raw_memory.write_slice(new_base, &code);
+        self.memory.set_dict_ptr(new_base.wrapping_add(size));
+        self.memory.last_article_ptr = last_article_ptr;
+
+        for (start, end) in data_ranges {
+            self.memory.mark_data_space(new_base.wrapping_add(start)..=new_base.wrapping_add(end));
+        }
+
+        for offset in relocations {
+            self.memory.mark_relocation(new_base.wrapping_add(offset));
+        }
+
+        Ok(exports.into_iter().map(|(name, offset)| (name, new_base.wrapping_add(offset))).collect())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::input::StaticStringInput;
+    use crate::machine_testing::*;
+
+    use super::*;
+
+    fn make_library_image() -> Vec<u8> {
+        let mut machine = TestMachine::default();
+        machine.extensions.input = StaticStringInput::new("
+            VARIABLE counter
+            : bump counter @ 1 + counter ! ;
+            : bump-twice bump bump ;
+            : compile-bump POSTPONE bump ; IMMEDIATE
+        ");
+        machine.interpret_input().unwrap();
+
+        let mut image = Vec::new();
+        machine.save_dictionary(&mut image).unwrap();
+
+        image
+    }
+
+    #[test]
+    fn test_load_dictionary_on_top_of_existing_words() {
+        let image = make_library_image();
+
+        let mut machine = TestMachine::default();
+        machine.extensions.input = StaticStringInput::new(": double 2 * ; : sq DUP * ;");
+        machine.interpret_input().unwrap();
+        let here_before = machine.memory.get_dict_ptr();
+
+        machine.load_dictionary(&mut image.as_slice()).unwrap();
+        assert!(machine.memory.get_dict_ptr() > here_before);
+
+        machine.extensions.input = StaticStringInput::new("
+            : bump-thrice bump-twice compile-bump ;
+            bump-thrice counter @ double sq
+        ");
+        machine.interpret_input().unwrap();
+        machine.assert_data_stack_state(&[StackElement::Cell(36)]);
+
+        let names: Vec<_> = machine.memory.articles().map(|article| article.name().to_string()).collect();
+        assert_eq!(names, vec!["bump-thrice", "compile-bump", "bump-twice", "bump", "counter", "sq", "double"]);
+    }
+
+    #[test]
+    fn test_loaded_dictionary_can_be_saved_again() {
+        let image = make_library_image();
+
+        let mut machine = TestMachine::default();
+        machine.extensions.input = StaticStringInput::new(": one 1 ;");
+        machine.interpret_input().unwrap();
+        machine.load_dictionary(&mut image.as_slice()).unwrap();
+
+        let mut second_image = Vec::new();
+        machine.save_dictionary(&mut second_image).unwrap();
+
+        let mut target = TestMachine::default();
+        target.extensions.input = StaticStringInput::new("VARIABLE x VARIABLE y");
+        target.interpret_input().unwrap();
+        target.load_dictionary(&mut second_image.as_slice()).unwrap();
+
+        target.extensions.input = StaticStringInput::new("5 x ! bump-twice one counter @ x @");
+        target.interpret_input().unwrap();
+        target.assert_data_stack_state(&[StackElement::Cell(1), StackElement::Cell(2), StackElement::Cell(5)]);
+    }
+
+    #[test]
+    fn test_load_dictionary_out_of_space() {
+        let image = make_library_image();
+
+        let mut machine = TestMachine::default();
+        let free_space = machine.memory.free_data_space();
+        machine.memory.set_dict_ptr(machine.memory.get_dict_ptr() + free_space - 4);
+        let here_before = machine.memory.get_dict_ptr();
+
+        assert!(matches!(
+            machine.load_dictionary(&mut image.as_slice()),
+            Err(DictionaryImageError::OutOfDataSpace { available: 4, .. })
+        ));
+        assert_eq!(machine.memory.get_dict_ptr(), here_before);
+        assert!(machine.memory.last_article_ptr.is_none());
+    }
+
+    #[test]
+    fn test_load_dictionary_during_definition() {
+        let image = make_library_image();
+
+        let mut machine = TestMachine::default();
+        machine.extensions.input = StaticStringInput::new(": unfinished 1 2");
+        machine.interpret_input().unwrap();
+
+        assert!(matches!(
+            machine.load_dictionary(&mut image.as_slice()),
+            Err(DictionaryImageError::DefinitionInProgress)
+        ));
+    }
+
+    #[test]
+    fn test_load_dictionary_bad_magic() {
+        let mut image = make_library_image();
+        image[0] = b'X';
+
+        let mut machine = TestMachine::default();
+        assert!(matches!(machine.load_dictionary(&mut image.as_slice()), Err(DictionaryImageError::BadMagic)));
+    }
+
+    #[test]
+    fn test_stripped_factorial() {
+        let mut machine = TestMachine::default();
+        machine.extensions.input = StaticStringInput::new("
+            : 1- 1 - ;
+            VARIABLE calls
+            : FACTORIAL calls @ 1 + calls ! DUP 2 < IF DROP 1 EXIT THEN DUP 1- RECURSE * ;
+            : FACTORIAL-CALLS calls @ ;
+        ");
+        machine.interpret_input().unwrap();
+
+        let image = machine.strip_dictionary(&["FACTORIAL", "FACTORIAL-CALLS"]).unwrap();
+
+        let mut target = TestMachine::default();
+        let entries = target.load_dictionary(&mut image.as_slice()).unwrap();
+        assert_eq!(target.memory.articles().count(), 0);
+        assert!(target.memory.dictionary_size() < machine.memory.dictionary_size());
+
+        target.memory.data_push_cell(6).unwrap();
+        target.run_until_exit(entries["FACTORIAL"]).unwrap();
+        target.run_until_exit(entries["FACTORIAL-CALLS"]).unwrap();
+        target.assert_data_stack_state(&[StackElement::Cell(720), StackElement::Cell(6)]);
+    }
+
+    #[test]
+    fn test_strip_missing_export() {
+        let mut machine = TestMachine::default();
+        machine.extensions.input = StaticStringInput::new(": sq DUP * ;");
+        machine.interpret_input().unwrap();
+
+        assert!(matches!(machine.strip_dictionary(&["cube"]), Err(MachineError::NoArticle)));
+    }
+}
@@ -0,0 +1,332 @@
+use std::io::{self, SeekFrom, Write};
+
+use crate::cell::Cell;
+use crate::file_system::{FileAccessMode, FileHandle, FileSystem};
+use crate::machine::{Machine, MachineExtensions};
+
+/// `ior` of a successful file access word.
+pub const IOR_SUCCESS: Cell = 0;
+
+/// `ior` of a file access word that failed because the file does not exist.
+pub const IOR_NOT_FOUND: Cell = 1;
+
+/// `ior` of a file access word that failed because access to the file is denied.
+pub const IOR_PERMISSION_DENIED: Cell = 2;
+
+/// `ior` of a file access word given an unknown file id, access method or otherwise invalid argument.
+pub const IOR_INVALID_ARGUMENT: Cell = 3;
+
+/// `ior` of a file access word that failed because machine has no file system.
+pub const IOR_UNSUPPORTED: Cell = 4;
+
+/// `ior` of a file access word that failed for any other reason.
+pub const IOR_IO_ERROR: Cell = 5;
+
+/// Get `ior` value reported by file access words for given error.
+pub fn io_result_code(err: &io::Error) -> Cell {
+    match err.kind() {
+        io::ErrorKind::NotFound => IOR_NOT_FOUND,
+        io::ErrorKind::PermissionDenied => IOR_PERMISSION_DENIED,
+        io::ErrorKind::InvalidInput => IOR_INVALID_ARGUMENT,
+        io::ErrorKind::Unsupported => IOR_UNSUPPORTED,
+        _ => IOR_IO_ERROR,
+    }
+}
+
+/// Get file access mode corresponding to given file access method value (`R/O`, `W/O` or `R/W`).
+pub fn file_access_mode(fam: Cell) -> io::Result<FileAccessMode> {
+    match fam {
+        0 => Ok(FileAccessMode::ReadOnly),
+        1 => Ok(FileAccessMode::WriteOnly),
+        2 => Ok(FileAccessMode::ReadWrite),
+        _ => Err(io::Error::new(io::ErrorKind::InvalidInput, "invalid file access method")),
+    }
+}
+
+/// Files opened by file access words.
+///
+/// Files are identified by small positive integers, ids of closed files are reused.
+#[derive(Default)]
+pub struct FileTable {
+    files: Vec<Option<Box<dyn FileHandle>>>,
+}
+
+impl FileTable {
+    pub fn insert(&mut self, file: Box<dyn FileHandle>) -> Cell {
+        let index = match self.files.iter().position(Option::is_none) {
+            Some(index) => index,
+            None => {
+                self.files.push(None);
+                self.files.len() - 1
+            }
+        };
+
+        self.files[index] = Some(file);
+
+        (index + 1) as Cell
+    }
+
+    pub fn get(&mut self, id: Cell) -> io::Result<&mut dyn FileHandle> {
+        match self.slot(id).and_then(Option::as_mut) {
+            Some(file) => Ok(file.as_mut()),
+            None => Err(Self::invalid_id()),
+        }
+    }
+
+    pub fn remove(&mut self, id: Cell) -> io::Result<Box<dyn FileHandle>> {
+        self.slot(id).and_then(Option::take).ok_or_else(Self::invalid_id)
+    }
+
+    /// Number of files currently open.
+    pub fn len(&self) -> usize {
+        self.files.iter().filter(|file| file.is_some()).count()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn slot(&mut self, id: Cell) -> Option<&mut Option<Box<dyn FileHandle>>> {
+        (id as usize).checked_sub(1).and_then(|index| self.files.get_mut(index))
+    }
+
+    fn invalid_id() -> io::Error {
+        io::Error::new(io::ErrorKind::InvalidInput, "invalid file id")
+    }
+}
+
+impl<TExt: MachineExtensions> Machine<TExt> {
+    /// Open an existing file in machine's file system, returning id of the open file.
+    pub fn open_file(&mut self, path: &str, mode: FileAccessMode) -> io::Result<Cell> {
+        let file = self.file_system()?.open_file(path, mode)?;
+
+        Ok(self.files.insert(file))
+    }
+
+    /// Create a file in machine's file system, truncating an existing one, and return id of the open file.
+    pub fn create_file(&mut self, path: &str, mode: FileAccessMode) -> io::Result<Cell> {
+        let file = self.file_system()?.create_file(path, mode)?;
+
+        Ok(self.files.insert(file))
+    }
+
+    pub fn close_file(&mut self, id: Cell) -> io::Result<()> {
+        self.files.remove(id)?.flush()
+    }
+
+    pub fn delete_file(&mut self, path: &str) -> io::Result<()> {
+        self.file_system()?.delete_file(path)
+    }
+
+    /// Read as many bytes as possible to fill given buffer, returning number of bytes read.
+    ///
+    /// Returns less than buffer size only when the file ends.
+    pub fn read_file(&mut self, id: Cell, buffer: &mut [u8]) -> io::Result<usize> {
+        let file = self.files.get(id)?;
+        let mut total = 0;
+
+        while total < buffer.len() {
+            match file.read(&mut buffer[total..]) {
+                Ok(0) => break,
+                Ok(count) => total += count,
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => {}
+                Err(err) => return Err(err),
+            }
+        }
+
+        Ok(total)
+    }
+
+    /// Read a line of at most `max_length` bytes, without the line terminator.
+    ///
+    /// A longer line is returned in parts, `None` is returned at the end of the file. The file position is left
+    /// right after the returned line and its terminator.
+    pub fn read_line(&mut self, id: Cell, max_length: usize) -> io::Result<Option<Vec<u8>>> {
+        // Extra bytes tell if a line of exactly `max_length` bytes is terminated
+        let mut buffer = vec![0u8; max_length + 2];
+        let read = self.read_file(id, &mut buffer)?;
+
+        if read == 0 {
+            return Ok(None);
+        }
+
+        buffer.truncate(read);
+
+        let (length, consumed) = match buffer.iter().position(|&byte| byte == b'\n') {
+            Some(position) => {
+                let length = if position > 0 && buffer[position - 1] == b'\r' { position - 1 } else { position };
+
+                if length <= max_length { (length, position + 1) } else { (max_length, max_length) }
+            }
+            None => {
+                let length = read.min(max_length);
+
+                (length, length)
+            }
+        };
+
+        if consumed < read {
+            self.files.get(id)?.seek(SeekFrom::Current(-((read - consumed) as i64)))?;
+        }
+
+        buffer.truncate(length);
+
+        Ok(Some(buffer))
+    }
+
+    pub fn write_file(&mut self, id: Cell, data: &[u8]) -> io::Result<()> {
+        self.files.get(id)?.write_all(data)
+    }
+
+    pub fn file_position(&mut self, id: Cell) -> io::Result<u64> {
+        self.files.get(id)?.stream_position()
+    }
+
+    pub fn reposition_file(&mut self, id: Cell, position: u64) -> io::Result<()> {
+        self.files.get(id)?.seek(SeekFrom::Start(position)).map(|_| ())
+    }
+
+    pub fn file_size(&mut self, id: Cell) -> io::Result<u64> {
+        let file = self.files.get(id)?;
+        let position = file.stream_position()?;
+        let size = file.seek(SeekFrom::End(0))?;
+        file.seek(SeekFrom::Start(position))?;
+
+        Ok(size)
+    }
+
+    fn file_system(&mut self) -> io::Result<&mut dyn FileSystem> {
+        self.extensions.get_file_system()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::Unsupported, "file system is not available"))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::cell::{FALSE, TRUE};
+    use crate::input::StaticStringInput;
+    use crate::machine_error::MachineError;
+    use crate::machine_testing::*;
+
+    use super::*;
+
+    fn run(machine: &mut TestMachine, source: String, expected: &[StackElement]) {
+        machine.interpret_str(&source).unwrap();
+        machine.assert_data_stack_state(expected);
+    }
+
+    /// Create a file at given path, write to it, then read it back, reposition and delete it.
+    fn run_file_scenario(machine: &mut TestMachine, path: &str) {
+        run(machine, format!("
+            VARIABLE fid
+            : path S\" {}\" ;
+            : line1 S\" first line\" ;
+            : line2 S\" second\" ;
+            : read-line PAD 100 fid @ READ-LINE ROT PAD SWAP TYPE ;
+            path R/W BIN CREATE-FILE SWAP fid !
+        ", path), &[StackElement::Cell(IOR_SUCCESS)]);
+
+        run(machine, "line1 fid @ WRITE-LINE line2 fid @ WRITE-FILE fid @ CLOSE-FILE".to_string(), &[
+            StackElement::Cell(IOR_SUCCESS), StackElement::Cell(IOR_SUCCESS), StackElement::Cell(IOR_SUCCESS),
+        ]);
+
+        run(machine, "path R/O OPEN-FILE SWAP fid ! read-line read-line read-line".to_string(), &[
+            StackElement::Cell(IOR_SUCCESS),
+            StackElement::Cell(TRUE), StackElement::Cell(IOR_SUCCESS),
+            StackElement::Cell(TRUE), StackElement::Cell(IOR_SUCCESS),
+            StackElement::Cell(FALSE), StackElement::Cell(IOR_SUCCESS),
+        ]);
+        assert_eq!(machine.extensions.output.content.take(), b"first linesecond");
+
+        run(machine, "fid @ FILE-SIZE fid @ FILE-POSITION 6 S>D fid @ REPOSITION-FILE read-line".to_string(), &[
+            StackElement::DoubleCell(17), StackElement::Cell(IOR_SUCCESS),
+            StackElement::DoubleCell(17), StackElement::Cell(IOR_SUCCESS),
+            StackElement::Cell(IOR_SUCCESS),
+            StackElement::Cell(TRUE), StackElement::Cell(IOR_SUCCESS),
+        ]);
+        assert_eq!(machine.extensions.output.content.take(), b"line");
+
+        run(machine, "fid @ CLOSE-FILE fid @ CLOSE-FILE path DELETE-FILE path R/O OPEN-FILE".to_string(), &[
+            StackElement::Cell(IOR_SUCCESS),
+            StackElement::Cell(IOR_INVALID_ARGUMENT),
+            StackElement::Cell(IOR_SUCCESS),
+            StackElement::Cell(0), StackElement::Cell(IOR_NOT_FOUND),
+        ]);
+        assert!(machine.files.is_empty());
+    }
+
+    #[test]
+    fn test_memory_file_system() {
+        let (mut machine, file_system) = TestMachine::with_memory_file_system();
+
+        run_file_scenario(&mut machine, "notes.txt");
+
+        assert_eq!(file_system.content("notes.txt"), None);
+    }
+
+    #[cfg(feature = "std-fs")]
+    #[test]
+    fn test_std_file_system() {
+        let path = write_temp_file("file-access.txt", "");
+        let mut machine = TestMachine::with_std_file_system();
+
+        run_file_scenario(&mut machine, &path);
+
+        assert!(!std::path::Path::new(&path).exists());
+    }
+
+    #[test]
+    fn test_written_content() {
+        let (mut machine, file_system) = TestMachine::with_memory_file_system();
+
+        run(&mut machine, ": path S\" out.txt\" ; : text S\" abc\" ;
+            path W/O CREATE-FILE DROP DUP text ROT WRITE-LINE SWAP DUP text ROT WRITE-FILE SWAP CLOSE-FILE
+        ".to_string(), &[
+            StackElement::Cell(IOR_SUCCESS), StackElement::Cell(IOR_SUCCESS), StackElement::Cell(IOR_SUCCESS),
+        ]);
+
+        assert_eq!(file_system.content("out.txt").unwrap(), b"abc\nabc");
+    }
+
+    #[test]
+    fn test_read_to_write_protected_memory() {
+        let (mut machine, _) = TestMachine::with_memory_file_system();
+        run(&mut machine, ": path S\" in.txt\" ; : text S\" abcd\" ; VARIABLE fid VARIABLE buffer
+            path W/O CREATE-FILE DROP DUP text ROT WRITE-FILE DROP CLOSE-FILE DROP
+            path R/O OPEN-FILE DROP fid !
+        ".to_string(), &[]);
+        machine.memory.write_protection = true;
+        let header = machine.memory.raw_memory.read_u16(0);
+
+        for source in ["0 2 fid @ READ-FILE", "0 2 fid @ READ-LINE"] {
+            machine.extensions.input = StaticStringInput::new(source);
+
+            assert!(matches!(machine.interpret_input(), Err(MachineError::WriteProtected { address: 0 })), "{}", source);
+            assert_eq!(machine.memory.raw_memory.read_u16(0), header);
+        }
+
+        run(&mut machine, "buffer 2 fid @ READ-FILE buffer C@".to_string(), &[
+            StackElement::Cell(2), StackElement::Cell(IOR_SUCCESS), StackElement::Cell(b'a' as Cell),
+        ]);
+    }
+
+    #[test]
+    fn test_error_codes() {
+        let (mut machine, _) = TestMachine::with_memory_file_system();
+
+        run(&mut machine, ": path S\" missing.txt\" ;
+            path R/O OPEN-FILE path 7 OPEN-FILE path DELETE-FILE PAD 10 3 READ-FILE
+        ".to_string(), &[
+            StackElement::Cell(0), StackElement::Cell(IOR_NOT_FOUND),
+            StackElement::Cell(0), StackElement::Cell(IOR_INVALID_ARGUMENT),
+            StackElement::Cell(IOR_NOT_FOUND),
+            StackElement::Cell(0), StackElement::Cell(IOR_INVALID_ARGUMENT),
+        ]);
+
+        let mut machine = TestMachine::default();
+
+        run(&mut machine, ": path S\" missing.txt\" ; path R/O OPEN-FILE".to_string(), &[
+            StackElement::Cell(0), StackElement::Cell(IOR_UNSUPPORTED),
+        ]);
+    }
+}
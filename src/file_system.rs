@@ -0,0 +1,78 @@
+use std::io;
+use std::path::PathBuf;
+
+/// An open file provided by a `FileSystem`.
+pub trait FileHandle: io::Read + io::Write + io::Seek {}
+
+impl<T: io::Read + io::Write + io::Seek> FileHandle for T {}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FileAccessMode {
+    ReadOnly,
+    WriteOnly,
+    ReadWrite,
+}
+
+/// Host file system available to the machine.
+///
+/// Embedders may implement it to sandbox or virtualize file access.
+pub trait FileSystem {
+    /// Open an existing file.
+    fn open_file(&mut self, path: &str, mode: FileAccessMode) -> io::Result<Box<dyn FileHandle>>;
+
+    /// Create a new file, truncating it if it already exists.
+    fn create_file(&mut self, path: &str, mode: FileAccessMode) -> io::Result<Box<dyn FileHandle>>;
+
+    /// Delete a file.
+    fn delete_file(&mut self, path: &str) -> io::Result<()> {
+        Err(io::Error::new(io::ErrorKind::Unsupported, format!("cannot delete {}", path)))
+    }
+
+    /// Get a path identifying the same file as given one regardless of how it's spelled.
+    ///
+    /// Used to tell if a file has been loaded already.
+    fn canonicalize(&mut self, path: &str) -> io::Result<PathBuf> {
+        Ok(PathBuf::from(path))
+    }
+}
+
+/// File system backed by `std::fs`.
+#[cfg(feature = "std-fs")]
+#[derive(Default)]
+pub struct StdFileSystem {}
+
+#[cfg(feature = "std-fs")]
+impl StdFileSystem {
+    fn open_options(mode: FileAccessMode) -> std::fs::OpenOptions {
+        let mut options = std::fs::OpenOptions::new();
+
+        options
+            .read(mode != FileAccessMode::WriteOnly)
+            .write(mode != FileAccessMode::ReadOnly);
+
+        options
+    }
+}
+
+#[cfg(feature = "std-fs")]
+impl FileSystem for StdFileSystem {
+    fn open_file(&mut self, path: &str, mode: FileAccessMode) -> io::Result<Box<dyn FileHandle>> {
+        Ok(Box::new(Self::open_options(mode).open(path)?))
+    }
+
+    fn create_file(&mut self, path: &str, mode: FileAccessMode) -> io::Result<Box<dyn FileHandle>> {
+        let mut options = Self::open_options(mode);
+
+        options.write(true).create(true).truncate(true);
+
+        Ok(Box::new(options.open(path)?))
+    }
+
+    fn delete_file(&mut self, path: &str) -> io::Result<()> {
+        std::fs::remove_file(path)
+    }
+
+    fn canonicalize(&mut self, path: &str) -> io::Result<PathBuf> {
+        std::fs::canonicalize(path)
+    }
+}
@@ -0,0 +1,292 @@
+//! Allocator behind `ALLOCATE`, `FREE` and `RESIZE`.
+//!
+//! Heap is a fixed region of memory (see `MemoryLayoutConfig::heap_size`) split into blocks laid out back to back.
+//! Every block starts with a cell-sized header holding block size in bytes, header included, with the lowest bit set
+//! if the block is allocated. As the state is kept in machine memory, it's saved and restored along with the memory.
+//!
+//! Allocation takes the first free block large enough, adjacent free blocks are merged when a block is freed.
+
+use crate::cell::{aligned, Cell, CELL_BYTES};
+use crate::machine_memory::MachineMemory;
+use crate::mem::Address;
+
+/// `ior` of a successful memory allocation word.
+pub const IOR_SUCCESS: Cell = 0;
+
+/// `ior` of `ALLOCATE` or `RESIZE` that failed because there is no free block large enough.
+pub const IOR_OUT_OF_MEMORY: Cell = 1;
+
+/// `ior` of `FREE` or `RESIZE` given an address not returned by `ALLOCATE` or `RESIZE`, or already freed.
+pub const IOR_INVALID_ADDRESS: Cell = 2;
+
+/// Bit of block header set when the block is allocated.
+const USED_FLAG: u16 = 1;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum HeapError {
+    OutOfMemory,
+    InvalidAddress,
+}
+
+impl HeapError {
+    /// Get `ior` value reported by memory allocation words for this error.
+    pub fn ior(self) -> Cell {
+        match self {
+            HeapError::OutOfMemory => IOR_OUT_OF_MEMORY,
+            HeapError::InvalidAddress => IOR_INVALID_ADDRESS,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+struct Block {
+    header_address: Address,
+    size: u16,
+    used: bool,
+}
+
+impl Block {
+    fn data_address(&self) -> Address {
+        self.header_address + CELL_BYTES
+    }
+
+    fn end(&self) -> u32 {
+        self.header_address as u32 + self.size as u32
+    }
+}
+
+/// Size of a block able to hold given number of bytes, if it may fit into memory at all.
+fn block_size(data_size: Cell) -> Option<u16> {
+    (data_size <= (u16::MAX - 2 * CELL_BYTES) as Cell).then(|| aligned(data_size as Address) + CELL_BYTES)
+}
+
+impl MachineMemory {
+    /// Make the whole heap a single free block.
+    pub(crate) fn reset_heap(&mut self) {
+        let heap = self.get_heap_segment();
+
+        if !heap.is_empty() {
+            self.write_block_header(*heap.start(), heap.len() as u16, false);
+        }
+    }
+
+    fn write_block_header(&mut self, header_address: Address, size: u16, used: bool) {
+        self.raw_memory.write_u16(header_address, size | if used { USED_FLAG } else { 0 });
+    }
+
+    fn read_block(&self, header_address: Address) -> Option<Block> {
+        let heap = self.get_heap_segment();
+
+        if heap.is_empty() || header_address < *heap.start() || header_address >= *heap.end() {
+            return None;
+        }
+
+        let header = self.raw_memory.read_u16(header_address);
+        let block = Block { header_address, size: header & !USED_FLAG, used: header & USED_FLAG != 0 };
+
+        // Headers overwritten by a program end the list
+        (block.size >= CELL_BYTES && block.size.is_multiple_of(CELL_BYTES) && block.end() <= *heap.end() as u32 + 1)
+            .then_some(block)
+    }
+
+    fn next_block(&self, block: &Block) -> Option<Block> {
+        u16::try_from(block.end()).ok().and_then(|address| self.read_block(address))
+    }
+
+    fn heap_blocks(&self) -> impl Iterator<Item = Block> + '_ {
+        std::iter::successors(self.read_block(*self.get_heap_segment().start()), |block| self.next_block(block))
+    }
+
+    /// Find allocated block with data at given address and the block preceding it.
+    fn find_used_block(&self, data_address: Address) -> Result<(Option<Block>, Block), HeapError> {
+        let mut previous = None;
+
+        for block in self.heap_blocks() {
+            if block.data_address() == data_address {
+                return if block.used { Ok((previous, block)) } else { Err(HeapError::InvalidAddress) };
+            }
+
+            previous = Some(block);
+        }
+
+        Err(HeapError::InvalidAddress)
+    }
+
+    /// Mark first `size` bytes of a block as allocated, leaving the rest as a free block if it's large enough.
+    fn occupy_block(&mut self, block: &Block, size: u16) {
+        if block.size - size >= CELL_BYTES {
+            self.write_block_header(block.header_address, size, true);
+            self.write_block_header(block.header_address + size, block.size - size, false);
+        } else {
+            self.write_block_header(block.header_address, block.size, true);
+        }
+    }
+
+    /// Allocate a block for given number of bytes and return address of it's first byte.
+    pub fn heap_allocate(&mut self, size: Cell) -> Result<Address, HeapError> {
+        let size = block_size(size).ok_or(HeapError::OutOfMemory)?;
+        let block = self.heap_blocks().find(|block| !block.used && block.size >= size).ok_or(HeapError::OutOfMemory)?;
+
+        self.occupy_block(&block, size);
+
+        Ok(block.data_address())
+    }
+
+    /// Free a block allocated by `heap_allocate` or `heap_resize`, merging it with adjacent free blocks.
+    pub fn heap_free(&mut self, address: Address) -> Result<(), HeapError> {
+        let (previous, block) = self.find_used_block(address)?;
+        let (mut start, mut size) = (block.header_address, block.size);
+
+        if let Some(next) = self.next_block(&block).filter(|next| !next.used) {
+            size += next.size;
+        }
+
+        if let Some(previous) = previous.filter(|previous| !previous.used) {
+            start = previous.header_address;
+            size += previous.size;
+        }
+
+        self.write_block_header(start, size, false);
+
+        Ok(())
+    }
+
+    /// Change size of an allocated block, moving it's content if the block can not be extended in place.
+    ///
+    /// The block stays intact if there is not enough free memory.
+    pub fn heap_resize(&mut self, address: Address, size: Cell) -> Result<Address, HeapError> {
+        let (_, block) = self.find_used_block(address)?;
+        let new_size = block_size(size).ok_or(HeapError::OutOfMemory)?;
+        let next_free = self.next_block(&block).filter(|next| !next.used);
+        let available = block.size + next_free.map_or(0, |next| next.size);
+
+        if new_size <= available {
+            self.occupy_block(&Block { size: available, ..block }, new_size);
+
+            return Ok(address);
+        }
+
+        let new_address = self.heap_allocate(size)?;
+        let content = self.raw_memory.address_slice(address, (block.size - CELL_BYTES) as usize).into_owned();
+        self.raw_memory.write_slice(new_address, &content);
+        self.heap_free(address)?;
+
+        Ok(new_address)
+    }
+
+    /// Total size of free heap blocks in bytes, headers included.
+    pub fn heap_free_space(&self) -> u16 {
+        self.heap_blocks().filter(|block| !block.used).map(|block| block.size).sum()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::machine_memory::MemoryLayoutConfig;
+    use crate::machine_testing::*;
+    use crate::mem::Mem;
+
+    use super::*;
+
+    fn make_mem(heap_size: u16) -> MachineMemory {
+        MachineMemory::new(Mem::default(), MemoryLayoutConfig { heap_size, ..MemoryLayoutConfig::default() })
+    }
+
+    #[test]
+    fn test_allocate_and_free() {
+        let mut mm = make_mem(64);
+        let heap = mm.get_heap_segment();
+
+        let a = mm.heap_allocate(3).unwrap();
+        let b = mm.heap_allocate(10).unwrap();
+        assert_eq!(a, *heap.start() + CELL_BYTES);
+        assert!(b > a + 3);
+        assert!(heap.contains(&(b + 9)));
+
+        mm.heap_free(a).unwrap();
+        assert_eq!(mm.heap_allocate(2).unwrap(), a);
+
+        mm.heap_free(a).unwrap();
+        mm.heap_free(b).unwrap();
+        assert_eq!(mm.heap_free_space(), 64);
+        assert_eq!(mm.heap_allocate(64 - CELL_BYTES as Cell).unwrap(), a);
+    }
+
+    #[test]
+    fn test_invalid_free() {
+        let mut mm = make_mem(64);
+        let a = mm.heap_allocate(4).unwrap();
+
+        assert_eq!(mm.heap_free(a + 1), Err(HeapError::InvalidAddress));
+        assert_eq!(mm.heap_free(0), Err(HeapError::InvalidAddress));
+        mm.heap_free(a).unwrap();
+        assert_eq!(mm.heap_free(a), Err(HeapError::InvalidAddress));
+        assert_eq!(mm.heap_resize(a, 8), Err(HeapError::InvalidAddress));
+    }
+
+    #[test]
+    fn test_exhaustion() {
+        let mut mm = make_mem(32);
+
+        assert_eq!(mm.heap_allocate(32), Err(HeapError::OutOfMemory));
+        assert_eq!(mm.heap_allocate(Cell::MAX), Err(HeapError::OutOfMemory));
+
+        let mut blocks = Vec::new();
+        while let Ok(address) = mm.heap_allocate(0) {
+            blocks.push(address);
+        }
+        assert_eq!(blocks.len(), 32 / CELL_BYTES as usize);
+        assert_eq!(mm.heap_free_space(), 0);
+
+        assert_eq!(make_mem(0).heap_allocate(0), Err(HeapError::OutOfMemory));
+    }
+
+    #[test]
+    fn test_resize() {
+        let mut mm = make_mem(64);
+        let a = mm.heap_allocate(4).unwrap();
+        mm.raw_memory.write_slice(a, b"abcd");
+
+        // Grows in place while the next block is free
+        assert_eq!(mm.heap_resize(a, 8).unwrap(), a);
+        let b = mm.heap_allocate(4).unwrap();
+
+        let moved = mm.heap_resize(a, 16).unwrap();
+        assert_ne!(moved, a);
+        assert_eq!(mm.raw_memory.address_slice(moved, 4).as_ref(), b"abcd");
+
+        // The freed block is reused
+        assert_eq!(mm.heap_allocate(8).unwrap(), a);
+
+        assert_eq!(mm.heap_resize(b, 64), Err(HeapError::OutOfMemory));
+        assert_eq!(mm.heap_resize(moved, 2).unwrap(), moved);
+        assert_eq!(mm.raw_memory.address_slice(moved, 2).as_ref(), b"ab");
+    }
+
+    #[test]
+    fn test_heap_words() {
+        let mut machine = TestMachine::default();
+        machine.interpret_str("
+            VARIABLE buf
+            100 ALLOCATE DROP buf !
+            42 buf @ 99 + C!
+            buf @ 2000 RESIZE SWAP DROP
+            : grow buf @ 200 RESIZE SWAP buf ! ;
+            8 ALLOCATE DROP DROP grow
+            buf @ 99 + C@
+            buf @ FREE buf @ FREE
+            50000 ALLOCATE SWAP DROP
+        ").unwrap();
+
+        machine.assert_data_stack_state(&[
+            StackElement::Cell(IOR_OUT_OF_MEMORY),
+            StackElement::Cell(IOR_SUCCESS),
+            StackElement::Cell(42),
+            StackElement::Cell(IOR_SUCCESS),
+            StackElement::Cell(IOR_INVALID_ADDRESS),
+            StackElement::Cell(IOR_OUT_OF_MEMORY),
+        ]);
+        assert!(machine.memory.lookup_article(b"grow").unwrap().is_some());
+        assert!(machine.memory.heap_free_space() < 1024);
+    }
+}
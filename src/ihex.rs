@@ -0,0 +1,235 @@
+use std::fmt::{Display, Formatter};
+use std::io;
+
+use crate::machine_memory::MachineMemory;
+use crate::mem::{AccessKind, Address, AddressRange, MemoryAccessError};
+use crate::memory_segment::WHOLE_MEMORY;
+
+/// Maximal number of data bytes in a single record written by `export_ihex`.
+const IHEX_RECORD_DATA_SIZE: usize = 16;
+
+const RECORD_TYPE_DATA: u8 = 0x00;
+const RECORD_TYPE_EOF: u8 = 0x01;
+
+#[derive(Debug)]
+pub enum IHexError {
+    IOError(io::Error),
+    /// Line is not a valid Intel HEX record.
+    Syntax { line: usize },
+    BadChecksum { line: usize },
+    UnsupportedRecordType { line: usize, record_type: u8 },
+    /// Data record targets addresses outside of allowed range.
+    MemoryAccessError { line: usize, err: MemoryAccessError },
+    MissingEof,
+}
+
+impl From<io::Error> for IHexError {
+    fn from(err: io::Error) -> Self {
+        IHexError::IOError(err)
+    }
+}
+
+impl Display for IHexError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            IHexError::IOError(err) => write!(f, "IO error: {}", err),
+            IHexError::Syntax { line } => write!(f, "line {}: malformed record", line),
+            IHexError::BadChecksum { line } => write!(f, "line {}: checksum mismatch", line),
+            IHexError::UnsupportedRecordType { line, record_type } => write!(
+                f, "line {}: unsupported record type {:02X}", line, record_type,
+            ),
+            IHexError::MemoryAccessError { line, err } => write!(f, "line {}: {}", line, err),
+            IHexError::MissingEof => write!(f, "missing end of file record"),
+        }
+    }
+}
+
+fn checksum(bytes: &[u8]) -> u8 {
+    bytes.iter().fold(0u8, |acc, b| acc.wrapping_add(*b)).wrapping_neg()
+}
+
+fn write_record(dst: &mut impl io::Write, address: Address, record_type: u8, data: &[u8]) -> io::Result<()> {
+    let mut bytes = vec![data.len() as u8];
+    bytes.extend_from_slice(&address.to_be_bytes());
+    bytes.push(record_type);
+    bytes.extend_from_slice(data);
+    bytes.push(checksum(&bytes));
+
+    write!(dst, ":")?;
+    for b in bytes {
+        write!(dst, "{:02X}", b)?;
+    }
+    writeln!(dst)
+}
+
+/// Parse a single record, returning it's address, type and data.
+fn parse_record(text: &str, line: usize) -> Result<(Address, u8, Vec<u8>), IHexError> {
+    let hex = text.strip_prefix(':').ok_or(IHexError::Syntax { line })?;
+
+    if hex.len() % 2 != 0 || hex.len() < 10 {
+        return Err(IHexError::Syntax { line });
+    }
+
+    let bytes = (0..hex.len()).step_by(2)
+        .map(|i| u8::from_str_radix(&hex[i..i + 2], 16))
+        .collect::<Result<Vec<u8>, _>>()
+        .map_err(|_| IHexError::Syntax { line })?;
+
+    if bytes.len() != (bytes[0] as usize) + 5 {
+        return Err(IHexError::Syntax { line });
+    }
+
+    let (content, sum) = bytes.split_at(bytes.len() - 1);
+    if checksum(content) != sum[0] {
+        return Err(IHexError::BadChecksum { line });
+    }
+
+    Ok((Address::from_be_bytes([content[1], content[2]]), content[3], content[4..].to_vec()))
+}
+
+impl MachineMemory {
+    /// Write content of given address range in Intel HEX format.
+    pub fn export_ihex(&self, dst: &mut impl io::Write, range: AddressRange) -> io::Result<()> {
+        if !range.is_empty() {
+            let data = self.raw_memory.slice((*range.start() as usize)..(*range.end() as usize + 1));
+
+            for (i, chunk) in data.chunks(IHEX_RECORD_DATA_SIZE).enumerate() {
+                let address = range.start().wrapping_add((i * IHEX_RECORD_DATA_SIZE) as u16);
+
+                write_record(dst, address, RECORD_TYPE_DATA, chunk)?;
+            }
+        }
+
+        write_record(dst, 0, RECORD_TYPE_EOF, &[])
+    }
+
+    /// Write used part of dictionary in Intel HEX format.
+    pub fn export_dictionary_ihex(&self, dst: &mut impl io::Write) -> io::Result<()> {
+        self.export_ihex(dst, self.get_used_dict_segment())
+    }
+
+    /// Load data from Intel HEX records into memory.
+    ///
+    /// Fails without modifying memory if any of the records is invalid or targets addresses outside of `allowed_range`.
+    pub fn import_ihex(&mut self, src: &mut impl io::BufRead, allowed_range: AddressRange) -> Result<(), IHexError> {
+        let mut records = Vec::new();
+        let mut eof = false;
+
+        for (index, text) in io::BufRead::lines(src).enumerate() {
+            let text = text?;
+            let text = text.trim();
+            let line = index + 1;
+
+            if text.is_empty() {
+                continue;
+            }
+
+            if eof {
+                return Err(IHexError::Syntax { line });
+            }
+
+            let (address, record_type, data) = parse_record(text, line)?;
+
+            match record_type {
+                RECORD_TYPE_DATA => {
+                    if !data.is_empty() {
+                        // Records crossing the end of memory produce a reversed range and are rejected
+                        let end = address.wrapping_add((data.len() - 1) as u16);
+
+                        self.raw_memory.validate_named_access(
+                            address..=end,
+                            allowed_range.clone(),
+                            WHOLE_MEMORY,
+                            AccessKind::Write,
+                        ).map_err(|err| IHexError::MemoryAccessError { line, err })?;
+
+                        records.push((address, data));
+                    }
+                }
+                RECORD_TYPE_EOF => { eof = true; }
+                record_type => { return Err(IHexError::UnsupportedRecordType { line, record_type }); }
+            }
+        }
+
+        if !eof {
+            return Err(IHexError::MissingEof);
+        }
+
+        for (address, data) in records {
+            self.raw_memory.write_slice(address, &data);
+        }
+
+        self.refresh_stack_bounds();
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::str::from_utf8;
+
+    use crate::input::StaticStringInput;
+    use crate::machine_testing::*;
+
+    use super::*;
+
+    #[test]
+    fn test_export_record_format() {
+        let mut mm = MachineMemory::default();
+        mm.raw_memory.write_slice(0x0100, &[0x21, 0x46, 0x01, 0x36]);
+
+        let mut out = Vec::new();
+        mm.export_ihex(&mut out, 0x0100..=0x0103).unwrap();
+
+        assert_eq!(from_utf8(&out).unwrap(), ":04010000214601365D\n:00000001FF\n");
+    }
+
+    #[test]
+    fn test_dictionary_round_trip() {
+        let mut machine = TestMachine::default();
+        machine.extensions.input = StaticStringInput::new(": sq DUP * ; : cube DUP sq * ;");
+        machine.interpret_input().unwrap();
+
+        let mut out = Vec::new();
+        machine.memory.export_dictionary_ihex(&mut out).unwrap();
+
+        let dict_segment = machine.memory.get_used_dict_segment();
+        let mut imported = MachineMemory::default();
+        imported.import_ihex(&mut out.as_slice(), dict_segment.clone()).unwrap();
+
+        let range = (*dict_segment.start() as usize)..(*dict_segment.end() as usize + 1);
+        assert_eq!(imported.raw_memory.slice(range.clone()), machine.memory.raw_memory.slice(range));
+    }
+
+    #[test]
+    fn test_import_bad_checksum() {
+        let mut mm = MachineMemory::default();
+
+        assert!(matches!(
+            mm.import_ihex(&mut ":04010000214601365E\n:00000001FF\n".as_bytes(), mm.raw_memory.address_range()),
+            Err(IHexError::BadChecksum { line: 1 })
+        ));
+        assert_eq!(mm.raw_memory.read_u8(0x0100), 0);
+    }
+
+    #[test]
+    fn test_import_out_of_range() {
+        let mut mm = MachineMemory::default();
+
+        assert!(matches!(
+            mm.import_ihex(&mut ":04010000214601365D\n:00000001FF\n".as_bytes(), 0x0000..=0x0101),
+            Err(IHexError::MemoryAccessError { line: 1, .. })
+        ));
+    }
+
+    #[test]
+    fn test_import_missing_eof() {
+        let mut mm = MachineMemory::default();
+
+        assert!(matches!(
+            mm.import_ihex(&mut ":04010000214601365D\n".as_bytes(), mm.raw_memory.address_range()),
+            Err(IHexError::MissingEof)
+        ));
+    }
+}
@@ -1,13 +1,70 @@
 use std::io;
-use std::io::{Error as IOError, Stdin, stdin, Write};
+use std::io::{BufRead, BufReader, Cursor, Error as IOError, Read, Seek, SeekFrom, stdin, Write};
 
-use crate::input::InputError::BufferOverflow;
+/// Maximal number of leading bytes of a too long word kept in `InputError::WordTooLong`.
+pub const LONG_WORD_PREFIX_LENGTH: usize = 32;
+
+/// Prompt printed by interactive inputs before reading a line in interpreter state, unless changed by `set_prompt`.
+pub const DEFAULT_PROMPT: &str = "\n> ";
+
+/// What the machine is in the middle of when interactive input reads a new line.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PromptContext {
+    Interpreter,
+
+    /// A definition is not finished yet.
+    Compiler,
+
+    /// A string literal (`S"` or `."`) is not terminated on previous line.
+    String,
+
+    /// A `(` comment is not terminated on previous line.
+    Comment,
+
+    /// Post-mortem debugger waits for a command.
+    Debugger,
+}
+
+/// Function choosing text printed before waiting for a new line, nothing is printed if it returns `None`.
+pub type PromptProvider = Box<dyn FnMut(PromptContext) -> Option<String>>;
+
+/// Prompt printed by interactive inputs unless changed by `set_prompt` or `set_prompt_provider`.
+pub fn default_prompt(context: PromptContext) -> Option<String> {
+    let prompt = match context {
+        PromptContext::Interpreter => DEFAULT_PROMPT,
+        PromptContext::Compiler => "\ncompile: ",
+        PromptContext::String => "\nstring: ",
+        PromptContext::Comment => "\ncomment: ",
+        PromptContext::Debugger => "\ndebug> ",
+    };
+
+    Some(prompt.to_string())
+}
 
 #[derive(Debug)]
 pub enum InputError {
     StdIOError(IOError),
     IllegalOffset,
-    BufferOverflow,
+    /// Word does not fit into word buffer.
+    ///
+    /// The whole word is consumed from input, the buffer contains as many of it's first bytes as it can hold.
+    WordTooLong {
+        prefix: Vec<u8>,
+
+        /// Input offset of the first byte of the word.
+        position: u32,
+    },
+}
+
+/// What to do with input words that don't fit into word buffer.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum LongWordPolicy {
+    /// Fail with `InputError::WordTooLong`.
+    #[default]
+    Error,
+
+    /// Cut the word to buffer size and print a warning.
+    Truncate,
 }
 
 impl From<IOError> for InputError {
@@ -27,6 +84,19 @@ pub trait Input {
 
     fn seek(&mut self, offset: u32) -> Result<(), InputError>;
 
+    /// Check if the current line of interactively typed input has been read completely, except for whitespace.
+    ///
+    /// Returns `true` at most once per line. Inputs that aren't read line by line always return `false`.
+    fn take_line_end(&mut self) -> bool {
+        false
+    }
+
+    /// Skip the rest of the current line of interactively typed input, e.g. after an error.
+    fn discard_line(&mut self) {}
+
+    /// Tell interactive input what the machine is in the middle of, so the prompt for the next line can reflect it.
+    fn set_prompt_context(&mut self, _context: PromptContext) {}
+
     fn read_word<'a, 'b>(&'a mut self, buffer: &'b mut [u8]) -> Result<&'b [u8], InputError> {
         let mut read_len: usize;
 
@@ -52,7 +122,18 @@ pub trait Input {
                 }
                 Some(chr) => {
                     if read_len >= buffer.len() {
-                        return Err(BufferOverflow);
+                        let position = self.tell()?.saturating_sub(read_len as u32 + 1);
+
+                        while let Some(chr) = self.read()? {
+                            if is_whitespace(chr) {
+                                break;
+                            }
+                        }
+
+                        return Err(InputError::WordTooLong {
+                            prefix: buffer[0..read_len.min(LONG_WORD_PREFIX_LENGTH)].to_vec(),
+                            position,
+                        });
                     }
 
                     buffer[read_len] = chr;
@@ -63,6 +144,62 @@ pub trait Input {
     }
 }
 
+/// Find line and column (both starting from 1) of character at given offset of an input.
+///
+/// Position of the input is preserved.
+pub fn line_and_column(input: &mut dyn Input, offset: u32) -> Result<(u32, u32), InputError> {
+    let saved_offset = input.tell()?;
+    let mut line = 1;
+    let mut column = 1;
+
+    input.seek(0)?;
+
+    for _ in 0..offset {
+        match input.read()? {
+            None => break,
+            Some(b'\n') => {
+                line += 1;
+                column = 1;
+            }
+            Some(_) => column += 1,
+        }
+    }
+
+    input.seek(saved_offset)?;
+
+    Ok((line, column))
+}
+
+impl<T: Input + ?Sized> Input for &mut T {
+    fn read(&mut self) -> Result<Option<u8>, InputError> {
+        (**self).read()
+    }
+
+    fn tell(&self) -> Result<u32, InputError> {
+        (**self).tell()
+    }
+
+    fn seek(&mut self, offset: u32) -> Result<(), InputError> {
+        (**self).seek(offset)
+    }
+
+    fn take_line_end(&mut self) -> bool {
+        (**self).take_line_end()
+    }
+
+    fn discard_line(&mut self) {
+        (**self).discard_line()
+    }
+
+    fn set_prompt_context(&mut self, context: PromptContext) {
+        (**self).set_prompt_context(context)
+    }
+
+    fn read_word<'a, 'b>(&'a mut self, buffer: &'b mut [u8]) -> Result<&'b [u8], InputError> {
+        (**self).read_word(buffer)
+    }
+}
+
 pub struct EmptyInput {}
 
 impl Input for EmptyInput {
@@ -132,22 +269,95 @@ impl Input for StaticStringInput {
     }
 }
 
+/// Same as `StaticStringInput` but owns the text.
+#[derive(Default)]
+pub struct StringInput {
+    text: String,
+    offset: u32,
+}
+
+impl StringInput {
+    pub fn new(text: String) -> StringInput {
+        StringInput {
+            text,
+            offset: 0,
+        }
+    }
+}
+
+impl Input for StringInput {
+    fn read(&mut self) -> Result<Option<u8>, InputError> {
+        let offset = self.offset as usize;
+
+        match self.text.as_bytes().get(offset) {
+            Some(&byte) => {
+                self.offset += 1;
+
+                Ok(Some(byte))
+            }
+            None => Ok(None),
+        }
+    }
+
+    fn tell(&self) -> Result<u32, InputError> {
+        Ok(self.offset)
+    }
+
+    fn seek(&mut self, offset: u32) -> Result<(), InputError> {
+        if (offset as usize) >= self.text.len() {
+            return Err(InputError::IllegalOffset);
+        }
+
+        self.offset = offset;
+
+        Ok(())
+    }
+}
+
 pub struct StdinInput {
-    stdin: Stdin,
+    reader: Box<dyn BufRead>,
     buffer: String,
     offset: u32,
-    prompt: Option<String>,
+    prompt_provider: PromptProvider,
+    prompt_context: PromptContext,
+    before_prompt: Option<Box<dyn FnMut()>>,
+
+    /// The last line read has not been reported by `take_line_end` or discarded yet.
+    line_pending: bool,
 }
 
 impl StdinInput {
     pub fn new() -> StdinInput {
+        Self::with_reader(Box::new(stdin().lock()))
+    }
+
+    /// Create an input reading lines from given reader instead of standard input, e.g. to script a session.
+    pub fn with_reader(reader: Box<dyn BufRead>) -> StdinInput {
         StdinInput {
-            stdin: stdin(),
+            reader,
             buffer: String::new(),
             offset: 0,
-            prompt: Some("\n> ".to_string()),
+            prompt_provider: Box::new(default_prompt),
+            prompt_context: PromptContext::Interpreter,
+            before_prompt: None,
+            line_pending: false,
         }
     }
+
+    /// Set text printed to standard output before waiting for every new line, nothing is printed if `None`.
+    pub fn set_prompt(&mut self, prompt: Option<String>) {
+        self.set_prompt_provider(Box::new(move |_| prompt.clone()));
+    }
+
+    /// Set a function choosing text printed to standard output before waiting for a new line.
+    pub fn set_prompt_provider(&mut self, provider: PromptProvider) {
+        self.prompt_provider = provider;
+    }
+
+    /// Set a function called every time before waiting for a new line, e.g. to flush buffered output.
+    pub fn set_before_prompt(&mut self, hook: Box<dyn FnMut()>) {
+        self.before_prompt = Some(hook);
+    }
 }
 
 impl Default for StdinInput {
@@ -161,16 +371,22 @@ impl Input for StdinInput {
         let offset = self.offset as usize;
 
         if self.buffer.as_bytes().len() <= offset {
-            if let Some(prompt) = self.prompt.as_ref() {
+            if let Some(hook) = self.before_prompt.as_mut() {
+                hook();
+            }
+
+            if let Some(prompt) = (self.prompt_provider)(self.prompt_context) {
                 print!("{}", prompt);
                 io::stdout().flush()?;
             }
 
-            self.stdin.read_line(&mut self.buffer)?;
+            self.reader.read_line(&mut self.buffer)?;
 
             if self.buffer.as_bytes().len() <= offset {
                 return Ok(None);
             }
+
+            self.line_pending = true;
         }
 
         self.offset += 1;
@@ -191,6 +407,169 @@ impl Input for StdinInput {
 
         Ok(())
     }
+
+    fn take_line_end(&mut self) -> bool {
+        let rest = &self.buffer.as_bytes()[(self.offset as usize).min(self.buffer.len())..];
+
+        if !self.line_pending || !rest.iter().copied().all(is_whitespace) {
+            return false;
+        }
+
+        self.line_pending = false;
+
+        true
+    }
+
+    fn discard_line(&mut self) {
+        self.offset = self.buffer.len() as u32;
+        self.line_pending = false;
+    }
+
+    fn set_prompt_context(&mut self, context: PromptContext) {
+        self.prompt_context = context;
+    }
+}
+
+/// Input reading from a seekable stream, e.g. a file.
+pub struct FileInput<R: Read + Seek> {
+    reader: BufReader<R>,
+    offset: u32,
+}
+
+impl<R: Read + Seek> FileInput<R> {
+    pub fn new(reader: R) -> FileInput<R> {
+        FileInput {
+            reader: BufReader::new(reader),
+            offset: 0,
+        }
+    }
+}
+
+impl<R: Read + Seek> Input for FileInput<R> {
+    fn read(&mut self) -> Result<Option<u8>, InputError> {
+        let mut byte = [0u8; 1];
+
+        if self.reader.read(&mut byte)? == 0 {
+            return Ok(None);
+        }
+
+        self.offset += 1;
+
+        Ok(Some(byte[0]))
+    }
+
+    fn tell(&self) -> Result<u32, InputError> {
+        Ok(self.offset)
+    }
+
+    fn seek(&mut self, offset: u32) -> Result<(), InputError> {
+        let length = self.reader.seek(SeekFrom::End(0))?;
+        let target = if (offset as u64) > length { self.offset } else { offset };
+
+        self.reader.seek(SeekFrom::Start(target as u64))?;
+
+        if target != offset {
+            return Err(InputError::IllegalOffset);
+        }
+
+        self.offset = offset;
+
+        Ok(())
+    }
+}
+
+/// Default number of already read bytes `ReaderInput` keeps available for `seek`.
+pub const DEFAULT_READER_WINDOW: usize = 4096;
+
+/// Input reading from any buffered reader.
+///
+/// Bytes read recently are retained so `seek` works within a window of `window` bytes before current position.
+pub struct ReaderInput<R: BufRead> {
+    reader: R,
+
+    /// Retained bytes, starting at input offset `buffer_start`.
+    buffer: Vec<u8>,
+    buffer_start: u32,
+    offset: u32,
+    window: usize,
+}
+
+impl<R: BufRead> ReaderInput<R> {
+    pub fn new(reader: R) -> ReaderInput<R> {
+        Self::with_window(reader, DEFAULT_READER_WINDOW)
+    }
+
+    pub fn with_window(reader: R, window: usize) -> ReaderInput<R> {
+        ReaderInput {
+            reader,
+            buffer: Vec::new(),
+            buffer_start: 0,
+            offset: 0,
+            window,
+        }
+    }
+
+    /// Read more data from the reader, dropping bytes that are out of the window.
+    ///
+    /// Returns `false` at the end of the reader.
+    fn refill(&mut self) -> Result<bool, InputError> {
+        let retained_from = (self.offset - self.buffer_start) as usize;
+        let dropped = retained_from.saturating_sub(self.window);
+
+        if dropped > 0 {
+            self.buffer.drain(..dropped);
+            self.buffer_start += dropped as u32;
+        }
+
+        let data = self.reader.fill_buf()?;
+        let length = data.len();
+
+        self.buffer.extend_from_slice(data);
+        self.reader.consume(length);
+
+        Ok(length > 0)
+    }
+}
+
+impl From<Vec<u8>> for ReaderInput<Cursor<Vec<u8>>> {
+    fn from(data: Vec<u8>) -> Self {
+        ReaderInput::new(Cursor::new(data))
+    }
+}
+
+impl From<String> for ReaderInput<Cursor<Vec<u8>>> {
+    fn from(text: String) -> Self {
+        text.into_bytes().into()
+    }
+}
+
+impl<R: BufRead> Input for ReaderInput<R> {
+    fn read(&mut self) -> Result<Option<u8>, InputError> {
+        while (self.offset - self.buffer_start) as usize >= self.buffer.len() {
+            if !self.refill()? {
+                return Ok(None);
+            }
+        }
+
+        let byte = self.buffer[(self.offset - self.buffer_start) as usize];
+        self.offset += 1;
+
+        Ok(Some(byte))
+    }
+
+    fn tell(&self) -> Result<u32, InputError> {
+        Ok(self.offset)
+    }
+
+    fn seek(&mut self, offset: u32) -> Result<(), InputError> {
+        if offset < self.buffer_start || offset > self.buffer_start + self.buffer.len() as u32 {
+            return Err(InputError::IllegalOffset);
+        }
+
+        self.offset = offset;
+
+        Ok(())
+    }
 }
 
 #[cfg(test)]
@@ -222,6 +601,20 @@ mod test {
         assert_eq!(input.read_word(&mut buf).unwrap(), "".as_bytes());
     }
 
+    #[test]
+    fn test_string_input_read_too_long_word() {
+        let mut buf = [0u8; 4];
+        let mut input = StaticStringInput::new("foo  barbazqux baz");
+
+        assert_eq!(input.read_word(&mut buf).unwrap(), "foo".as_bytes());
+        assert!(matches!(
+            input.read_word(&mut buf),
+            Err(InputError::WordTooLong { prefix, position: 5 }) if prefix == "barb".as_bytes()
+        ));
+        assert_eq!(&buf, "barb".as_bytes());
+        assert_eq!(input.read_word(&mut buf).unwrap(), "baz".as_bytes());
+    }
+
     #[test]
     fn test_string_input_tell() {
         let mut input = StaticStringInput::new("foo bar");
@@ -248,4 +641,110 @@ mod test {
         let bad_seek_result = input.seek(10);
         assert!(matches!(bad_seek_result, Err(InputError::IllegalOffset)))
     }
+
+    #[test]
+    fn test_owned_string_input() {
+        let mut buf = [0u8; 10];
+        let mut input = StringInput::new(format!("{} {}", "foo", 42));
+
+        assert_eq!(input.read_word(&mut buf).unwrap(), "foo".as_bytes());
+        assert_eq!(input.tell().unwrap(), 4);
+        assert_eq!(input.read_word(&mut buf).unwrap(), "42".as_bytes());
+        assert_eq!(input.read_word(&mut buf).unwrap(), "".as_bytes());
+
+        input.seek(1).unwrap();
+        assert_eq!(input.read().unwrap(), Some(b'o'));
+        assert!(matches!(input.seek(6), Err(InputError::IllegalOffset)));
+    }
+
+    #[test]
+    fn test_file_input_seek() {
+        let mut input = FileInput::new(Cursor::new(b"foo bar".to_vec()));
+        let mut buf = [0u8; 10];
+
+        assert_eq!(input.read_word(&mut buf).unwrap(), "foo".as_bytes());
+        assert_eq!(input.tell().unwrap(), 4);
+
+        input.seek(1).unwrap();
+        assert_eq!(input.read_word(&mut buf).unwrap(), "oo".as_bytes());
+
+        assert!(matches!(input.seek(10), Err(InputError::IllegalOffset)));
+        assert_eq!(input.tell().unwrap(), 4);
+        assert_eq!(input.read_word(&mut buf).unwrap(), "bar".as_bytes());
+        assert_eq!(input.read().unwrap(), None);
+    }
+
+    #[test]
+    fn test_line_and_column() {
+        let mut input = StaticStringInput::new("foo\nbar baz\n");
+        input.seek(6).unwrap();
+
+        assert_eq!(line_and_column(&mut input, 0).unwrap(), (1, 1));
+        assert_eq!(line_and_column(&mut input, 9).unwrap(), (2, 6));
+        assert_eq!(input.tell().unwrap(), 6);
+    }
+
+    #[test]
+    fn test_reader_input_lines() {
+        let mut input = ReaderInput::new(Cursor::new(b": sq DUP * ;\n5 sq\n".to_vec()));
+        let mut buf = [0u8; 10];
+        let mut words = Vec::new();
+
+        loop {
+            let word = input.read_word(&mut buf).unwrap();
+
+            if word.is_empty() {
+                break;
+            }
+
+            words.push(String::from_utf8(word.to_vec()).unwrap());
+        }
+
+        assert_eq!(words, vec![":", "sq", "DUP", "*", ";", "5", "sq"]);
+        assert_eq!(input.tell().unwrap(), 18);
+    }
+
+    #[test]
+    fn test_reader_input_words_across_refills() {
+        let reader = BufReader::with_capacity(3, "foobar  baz qux".as_bytes());
+        let mut input = ReaderInput::new(reader);
+        let mut buf = [0u8; 10];
+
+        assert_eq!(input.read_word(&mut buf).unwrap(), "foobar".as_bytes());
+        assert_eq!(input.read_word(&mut buf).unwrap(), "baz".as_bytes());
+
+        input.seek(3).unwrap();
+        assert_eq!(input.read_word(&mut buf).unwrap(), "bar".as_bytes());
+        assert_eq!(input.read_word(&mut buf).unwrap(), "baz".as_bytes());
+        assert_eq!(input.read_word(&mut buf).unwrap(), "qux".as_bytes());
+        assert_eq!(input.read_word(&mut buf).unwrap(), "".as_bytes());
+    }
+
+    #[test]
+    fn test_reader_input_seek_window() {
+        let reader = BufReader::with_capacity(2, "0123456789".as_bytes());
+        let mut input = ReaderInput::with_window(reader, 4);
+
+        for _ in 0..8 {
+            input.read().unwrap();
+        }
+
+        assert!(matches!(input.seek(9), Err(InputError::IllegalOffset)));
+        assert!(matches!(input.seek(1), Err(InputError::IllegalOffset)));
+
+        input.seek(4).unwrap();
+        assert_eq!(input.read().unwrap(), Some(b'4'));
+    }
+
+    #[test]
+    fn test_reader_input_from_string() {
+        let mut input = ReaderInput::from(format!("{} {}", "foo", 42));
+        let mut buf = [0u8; 10];
+
+        assert_eq!(input.read_word(&mut buf).unwrap(), "foo".as_bytes());
+        assert_eq!(input.read_word(&mut buf).unwrap(), "42".as_bytes());
+
+        input.seek(0).unwrap();
+        assert_eq!(input.read().unwrap(), Some(b'f'));
+    }
 }
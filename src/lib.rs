@@ -1,18 +1,41 @@
 extern crate core;
 
+pub mod cell;
 pub mod mem;
 pub mod machine;
 pub mod readable_article;
 pub mod opcodes;
+pub mod assembler;
+pub mod decompiler;
 pub mod input;
+pub mod line_editor;
 pub mod output;
 pub mod sized_string;
 pub mod builtin_words;
 pub mod literal;
+pub mod recognizer;
 pub mod machine_memory;
 pub mod print_debug_info;
 pub mod machine_error;
 pub mod machine_state;
+pub mod memory_segment;
+pub mod mmio;
+pub mod heap;
+pub mod snapshot;
+pub mod state_snapshot;
+pub mod dictionary_image;
+pub mod ihex;
+pub mod file_system;
+pub mod file_access;
+pub mod tracer;
+pub mod profiler;
+pub mod coverage;
+pub mod debugger;
+pub mod clock;
+pub mod native_words;
+pub mod operations;
+pub mod tester;
+pub mod cli;
 #[macro_use]
 pub mod stack_effect;
 
@@ -0,0 +1,315 @@
+use std::path::PathBuf;
+
+#[cfg(feature = "line-editor")]
+use std::io::{self, Write};
+
+#[cfg(feature = "line-editor")]
+use rustyline::DefaultEditor;
+#[cfg(feature = "line-editor")]
+use rustyline::error::ReadlineError;
+
+#[cfg(feature = "line-editor")]
+use crate::input::default_prompt;
+use crate::input::{Input, InputError, PromptContext, PromptProvider, StdinInput};
+
+/// Input reading lines typed by user in a line editor with history.
+///
+/// Like `StdinInput`, all lines read so far are kept, so `tell` and `seek` work over them.
+#[cfg(feature = "line-editor")]
+pub struct LineEditorInput {
+    editor: DefaultEditor,
+    buffer: String,
+    offset: u32,
+    prompt_provider: PromptProvider,
+    prompt_context: PromptContext,
+    before_prompt: Option<Box<dyn FnMut()>>,
+
+    /// File history is loaded from and new lines are appended to.
+    history_path: Option<PathBuf>,
+
+    /// The last line read has not been reported by `take_line_end` or discarded yet.
+    line_pending: bool,
+}
+
+#[cfg(feature = "line-editor")]
+impl LineEditorInput {
+    /// Create an input with history loaded from given file, the file is created when the first line is entered.
+    pub fn new(history_path: Option<PathBuf>) -> io::Result<LineEditorInput> {
+        let mut editor = DefaultEditor::new().map_err(readline_to_io_error)?;
+
+        if let Some(path) = &history_path {
+            match editor.load_history(path) {
+                Ok(()) => {}
+                Err(ReadlineError::Io(err)) if err.kind() == io::ErrorKind::NotFound => {}
+                Err(err) => return Err(readline_to_io_error(err)),
+            }
+        }
+
+        Ok(LineEditorInput {
+            editor,
+            buffer: String::new(),
+            offset: 0,
+            prompt_provider: Box::new(default_prompt),
+            prompt_context: PromptContext::Interpreter,
+            before_prompt: None,
+            history_path,
+            line_pending: false,
+        })
+    }
+
+    /// Set text printed before waiting for every new line, nothing is printed if `None`.
+    pub fn set_prompt(&mut self, prompt: Option<String>) {
+        self.set_prompt_provider(Box::new(move |_| prompt.clone()));
+    }
+
+    /// Set a function choosing text printed before waiting for a new line.
+    pub fn set_prompt_provider(&mut self, provider: PromptProvider) {
+        self.prompt_provider = provider;
+    }
+
+    /// Set a function called every time before waiting for a new line, e.g. to flush buffered output.
+    pub fn set_before_prompt(&mut self, hook: Box<dyn FnMut()>) {
+        self.before_prompt = Some(hook);
+    }
+
+    /// Lines in history, the oldest first.
+    pub fn history(&self) -> impl Iterator<Item = &String> {
+        self.editor.history().iter()
+    }
+
+    /// Read a line from the editor, returns `None` at the end of input.
+    fn read_line(&mut self) -> io::Result<Option<String>> {
+        let prompt = (self.prompt_provider)(self.prompt_context).unwrap_or_default();
+
+        // The editor handles only the last line of the prompt, preceding lines are printed as is
+        let (leading, prompt) = match prompt.rfind('\n') {
+            Some(position) => prompt.split_at(position + 1),
+            None => ("", prompt.as_str()),
+        };
+
+        if !leading.is_empty() {
+            print!("{}", leading);
+            io::stdout().flush()?;
+        }
+
+        loop {
+            match self.editor.readline(prompt) {
+                Ok(line) => return Ok(Some(line)),
+                Err(ReadlineError::Eof) => return Ok(None),
+                // Ctrl-C drops the line being edited
+                Err(ReadlineError::Interrupted) => {}
+                Err(err) => return Err(readline_to_io_error(err)),
+            }
+        }
+    }
+
+    /// Append a line read from the editor to the buffer and to history.
+    fn accept_line(&mut self, line: String) {
+        if !line.trim().is_empty() {
+            let _ = self.editor.add_history_entry(line.as_str());
+
+            if let Some(path) = &self.history_path {
+                let _ = self.editor.append_history(path);
+            }
+        }
+
+        self.buffer.push_str(&line);
+        self.buffer.push('\n');
+        self.line_pending = true;
+    }
+}
+
+#[cfg(feature = "line-editor")]
+fn readline_to_io_error(err: ReadlineError) -> io::Error {
+    match err {
+        ReadlineError::Io(err) => err,
+        err => io::Error::other(err.to_string()),
+    }
+}
+
+#[cfg(feature = "line-editor")]
+impl Input for LineEditorInput {
+    fn read(&mut self) -> Result<Option<u8>, InputError> {
+        let offset = self.offset as usize;
+
+        if self.buffer.len() <= offset {
+            if let Some(hook) = self.before_prompt.as_mut() {
+                hook();
+            }
+
+            match self.read_line()? {
+                Some(line) => self.accept_line(line),
+                None => return Ok(None),
+            }
+        }
+
+        self.offset += 1;
+
+        Ok(Some(self.buffer.as_bytes()[offset]))
+    }
+
+    fn tell(&self) -> Result<u32, InputError> {
+        Ok(self.offset)
+    }
+
+    fn seek(&mut self, offset: u32) -> Result<(), InputError> {
+        if (offset as usize) > self.buffer.len() {
+            return Err(InputError::IllegalOffset);
+        }
+
+        self.offset = offset;
+
+        Ok(())
+    }
+
+    fn take_line_end(&mut self) -> bool {
+        let rest = &self.buffer.as_bytes()[(self.offset as usize).min(self.buffer.len())..];
+
+        if !self.line_pending || !rest.iter().all(u8::is_ascii_whitespace) {
+            return false;
+        }
+
+        self.line_pending = false;
+
+        true
+    }
+
+    fn discard_line(&mut self) {
+        self.offset = self.buffer.len() as u32;
+        self.line_pending = false;
+    }
+
+    fn set_prompt_context(&mut self, context: PromptContext) {
+        self.prompt_context = context;
+    }
+}
+
+/// Input of interactive session: a line editor when it's available and standard input is a terminal,
+/// plain `StdinInput` otherwise.
+pub enum ReplInput {
+    Stdin(StdinInput),
+
+    #[cfg(feature = "line-editor")]
+    LineEditor(Box<LineEditorInput>),
+}
+
+impl ReplInput {
+    /// Choose input depending on whether standard input is a terminal.
+    ///
+    /// Falls back to `StdinInput` if line editor can not be created.
+    pub fn new(terminal: bool, history_path: Option<PathBuf>) -> ReplInput {
+        #[cfg(feature = "line-editor")]
+        if terminal {
+            if let Ok(input) = LineEditorInput::new(history_path) {
+                return ReplInput::LineEditor(Box::new(input));
+            }
+        }
+
+        #[cfg(not(feature = "line-editor"))]
+        let _ = (terminal, history_path);
+
+        ReplInput::Stdin(StdinInput::new())
+    }
+
+    pub fn is_line_editor(&self) -> bool {
+        !matches!(self, ReplInput::Stdin(_))
+    }
+
+    fn as_input(&mut self) -> &mut dyn Input {
+        match self {
+            ReplInput::Stdin(input) => input,
+            #[cfg(feature = "line-editor")]
+            ReplInput::LineEditor(input) => input.as_mut(),
+        }
+    }
+
+    pub fn set_prompt(&mut self, prompt: Option<String>) {
+        match self {
+            ReplInput::Stdin(input) => input.set_prompt(prompt),
+            #[cfg(feature = "line-editor")]
+            ReplInput::LineEditor(input) => input.set_prompt(prompt),
+        }
+    }
+
+    pub fn set_prompt_provider(&mut self, provider: PromptProvider) {
+        match self {
+            ReplInput::Stdin(input) => input.set_prompt_provider(provider),
+            #[cfg(feature = "line-editor")]
+            ReplInput::LineEditor(input) => input.set_prompt_provider(provider),
+        }
+    }
+
+    pub fn set_before_prompt(&mut self, hook: Box<dyn FnMut()>) {
+        match self {
+            ReplInput::Stdin(input) => input.set_before_prompt(hook),
+            #[cfg(feature = "line-editor")]
+            ReplInput::LineEditor(input) => input.set_before_prompt(hook),
+        }
+    }
+}
+
+impl Input for ReplInput {
+    fn read(&mut self) -> Result<Option<u8>, InputError> {
+        self.as_input().read()
+    }
+
+    fn tell(&self) -> Result<u32, InputError> {
+        match self {
+            ReplInput::Stdin(input) => input.tell(),
+            #[cfg(feature = "line-editor")]
+            ReplInput::LineEditor(input) => input.tell(),
+        }
+    }
+
+    fn seek(&mut self, offset: u32) -> Result<(), InputError> {
+        self.as_input().seek(offset)
+    }
+
+    fn take_line_end(&mut self) -> bool {
+        self.as_input().take_line_end()
+    }
+
+    fn discard_line(&mut self) {
+        self.as_input().discard_line()
+    }
+
+    fn set_prompt_context(&mut self, context: PromptContext) {
+        self.as_input().set_prompt_context(context)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_falls_back_to_stdin_without_terminal() {
+        let mut input = ReplInput::new(false, None);
+
+        assert!(!input.is_line_editor());
+        assert_eq!(input.tell().unwrap(), 0);
+        assert!(!input.take_line_end());
+    }
+
+    #[cfg(feature = "line-editor")]
+    #[test]
+    fn test_history_round_trip() {
+        let path = crate::machine_testing::write_temp_file("history.txt", "");
+        std::fs::remove_file(&path).unwrap();
+
+        let mut input = LineEditorInput::new(Some(PathBuf::from(&path))).unwrap();
+        input.accept_line("1 2 +".to_string());
+        input.accept_line("  ".to_string());
+        input.accept_line(": square DUP * ;".to_string());
+
+        assert_eq!(input.read().unwrap(), Some(b'1'));
+        input.seek(6).unwrap();
+        assert_eq!(input.read().unwrap(), Some(b' '));
+        input.discard_line();
+        assert_eq!(input.tell().unwrap(), 26);
+
+        let input = LineEditorInput::new(Some(PathBuf::from(&path))).unwrap();
+
+        assert_eq!(input.history().collect::<Vec<_>>(), vec!["1 2 +", ": square DUP * ;"]);
+    }
+}
@@ -1,33 +1,84 @@
 use std::ops::Neg;
 use std::str;
 
-fn try_parse(source: &[u8], radix: u32) -> Option<u16> {
+use crate::cell::{Cell, DoubleCell, SignedCell, SignedDoubleCell};
+
+fn try_parse(source: &[u8], radix: u32) -> Option<Cell> {
     match source[0] {
         b'-' => {
-            let absolute = u16::from_str_radix(str::from_utf8(&source[1..]).ok()?, radix).ok()?;
-            let signed = i16::try_from(absolute).ok()?.neg();
-            let unsigned_repr = signed as u16;
+            let absolute = Cell::from_str_radix(str::from_utf8(&source[1..]).ok()?, radix).ok()?;
+            let signed = SignedCell::try_from(absolute).ok()?.neg();
+            let unsigned_repr = signed as Cell;
 
             Some(unsigned_repr)
         }
-        _ => u16::from_str_radix(str::from_utf8(source).ok()?, radix).ok()
+        _ => Cell::from_str_radix(str::from_utf8(source).ok()?, radix).ok()
+    }
+}
+
+fn try_parse_double(source: &[u8], radix: u32) -> Option<DoubleCell> {
+    match source[0] {
+        b'-' => {
+            let absolute = DoubleCell::from_str_radix(str::from_utf8(&source[1..]).ok()?, radix).ok()?;
+            let signed = SignedDoubleCell::try_from(absolute).ok()?.neg();
+
+            Some(signed as DoubleCell)
+        }
+        _ => DoubleCell::from_str_radix(str::from_utf8(source).ok()?, radix).ok()
     }
 }
 
+/// Split radix prefix off a numeric literal, `None` if nothing is left.
+fn split_radix(source: &[u8], default_radix: u32) -> Option<(&[u8], u32)> {
+    let (digits, radix) = match source.first()? {
+        b'#' => (&source[1..], 10),
+        b'$' => (&source[1..], 16),
+        b'%' => (&source[1..], 2),
+        _ => (source, default_radix),
+    };
+
+    (!digits.is_empty()).then_some((digits, radix))
+}
+
 /// Try to parse a numeric literal.
 ///
 /// See: https://forth-standard.org/standard/usage#usage:numbers
-pub fn parse_literal(source: &[u8], default_radix: u32) -> Option<u16> {
-    if source.len() == 0 {
-        return None;
+pub fn parse_literal(source: &[u8], default_radix: u32) -> Option<Cell> {
+    let (digits, radix) = split_radix(source, default_radix)?;
+
+    try_parse(digits, radix)
+}
+
+/// Try to parse a double-cell numeric literal, a number followed by a dot, e.g. `100000.` or `$-1.`.
+pub fn parse_double_literal(source: &[u8], default_radix: u32) -> Option<DoubleCell> {
+    let (digits, radix) = split_radix(source.strip_suffix(b".")?, default_radix)?;
+
+    try_parse_double(digits, radix)
+}
+
+/// Format a signed number in given radix the way `.S` prints it, using upper-case letters for digits above 9.
+///
+/// Radix outside of 2..=36 is treated as 10.
+pub fn format_literal(value: impl Into<i64>, radix: u32) -> String {
+    let value = value.into();
+    let radix = if (2..=36).contains(&radix) { radix } else { 10 };
+    let mut magnitude = value.unsigned_abs();
+    let mut digits = Vec::new();
+
+    loop {
+        digits.push(char::from_digit((magnitude % radix as u64) as u32, radix).unwrap().to_ascii_uppercase());
+        magnitude /= radix as u64;
+
+        if magnitude == 0 {
+            break;
+        }
     }
 
-    match source[0] {
-        b'#' => try_parse(&source[1..], 10),
-        b'$' => try_parse(&source[1..], 16),
-        b'%' => try_parse(&source[1..], 2),
-        _ => try_parse(source, default_radix),
+    if value < 0 {
+        digits.push('-');
     }
+
+    digits.iter().rev().collect()
 }
 
 #[cfg(test)]
@@ -56,10 +107,10 @@ mod test {
         )
     }
 
-    fn assert_parse_negative(src: &[u8], default_radix: u32, expected_abs: u16) {
+    fn assert_parse_negative(src: &[u8], default_radix: u32, expected_abs: Cell) {
         assert_eq!(
             parse_literal(src, default_radix).unwrap(),
-            0u16.wrapping_sub(expected_abs)
+            (0 as Cell).wrapping_sub(expected_abs)
         )
     }
 
@@ -79,6 +130,7 @@ mod test {
     }
 
     #[test]
+    #[cfg(not(feature = "cell32"))]
     fn test_parse_overflow() {
         assert_eq!(
             parse_literal(b"100500", 10),
@@ -91,6 +143,28 @@ mod test {
         )
     }
 
+    #[test]
+    fn test_parse_double() {
+        assert_eq!(parse_double_literal(b"100000.", 10), Some(100000));
+        assert_eq!(parse_double_literal(b"$-1.", 10), Some(DoubleCell::MAX));
+        assert_eq!(parse_double_literal(b"#15.", 16), Some(15));
+        assert_eq!(parse_double_literal(b"100000", 10), None);
+        assert_eq!(parse_double_literal(b".", 10), None);
+        assert_eq!(parse_double_literal(b"$.", 10), None);
+        assert_eq!(parse_double_literal(b"-.", 10), None);
+    }
+
+    #[test]
+    fn test_format_literal() {
+        assert_eq!(format_literal(0, 10), "0");
+        assert_eq!(format_literal(12345, 10), "12345");
+        assert_eq!(format_literal(-255, 16), "-FF");
+        assert_eq!(format_literal(1295, 36), "ZZ");
+        assert_eq!(format_literal(5, 2), "101");
+        assert_eq!(format_literal(SignedCell::MIN, 10), SignedCell::MIN.to_string());
+        assert_eq!(format_literal(42, 1), "42");
+    }
+
     #[test]
     fn test_parse_bad_string() {
         assert_eq!(
@@ -1,13 +1,32 @@
+use std::cmp::min;
+use std::io;
+use std::collections::HashSet;
+use std::path::PathBuf;
 use std::result::Result as StdResult;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
 
-use crate::builtin_words::process_builtin_word;
-use crate::input::Input;
+use int_enum::IntEnum;
+
+use crate::builtin_words::{BuiltinFlags, BuiltinWord, BuiltinWords, process_builtin_word, process_custom_op_word};
+use crate::cell::{Cell, SignedCell};
+use crate::clock::{Clock, SystemClock};
+use crate::coverage::Coverage;
+use crate::file_access::FileTable;
+use crate::file_system::{FileAccessMode, FileSystem};
+use crate::input::{FileInput, Input, InputError, line_and_column, LongWordPolicy, PromptContext, StringInput};
 use crate::machine_error::MachineError;
 use crate::machine_memory::MachineMemory;
 use crate::machine_state::MachineState;
-use crate::mem::Address;
-use crate::opcodes::OpCode;
-use crate::output::Output;
+use crate::mem::{Address, AddressRange};
+use crate::mmio::{MmioHandler, MmioMap};
+use crate::native_words::NativeWords;
+use crate::opcodes::{compile_call, CUSTOM_OP_CODES, CustomOpHandler, find_inlinable_code, find_opcode_synonym, OpCode};
+use crate::output::{Output, OutputWriter, TeeOutput};
+use crate::profiler::Profiler;
+use crate::recognizer::{default_recognizers, Recognizer};
+use crate::tester::Tester;
+use crate::tracer::{Tracer, WriteTracer};
 
 pub trait MachineExtensions: Sized {
     type TInput: Input;
@@ -19,13 +38,201 @@ pub trait MachineExtensions: Sized {
     fn process_unrecognized_word(_machine: &mut Machine<Self>, name_address: Address) -> Result<()> {
         Err(MachineError::IllegalWord(Some(name_address)))
     }
+
+    /// File system words like `SAVE-IMAGE` have access to.
+    ///
+    /// File access is forbidden when `None`.
+    fn get_file_system(&mut self) -> Option<&mut dyn FileSystem> {
+        None
+    }
+}
+
+/// Extensions using input and output borrowed from host, so host can use them as soon as the machine is dropped.
+pub struct BorrowedExtensions<'io> {
+    pub input: &'io mut dyn Input,
+    pub output: &'io mut dyn Output,
+}
+
+impl<'io> MachineExtensions for BorrowedExtensions<'io> {
+    type TInput = &'io mut dyn Input;
+    type TOutput = &'io mut dyn Output;
+
+    fn get_input(&mut self) -> &mut Self::TInput {
+        &mut self.input
+    }
+
+    fn get_output(&mut self) -> &mut Self::TOutput {
+        &mut self.output
+    }
+}
+
+/// Handles words that are neither dictionary articles, native words nor builtin words.
+///
+/// Returning `MachineError::IllegalWord` means the word is not recognized by the handler either, the word is then
+/// offered to `Machine::recognizers` and reported as illegal only if none of them accepts it. Any other error is
+/// reported as is.
+pub trait FallbackHandler<TExt: MachineExtensions> {
+    fn process_word(&mut self, machine: &mut Machine<TExt>, name_address: Address) -> Result<()>;
+}
+
+impl<TExt: MachineExtensions, F: FnMut(&mut Machine<TExt>, Address) -> Result<()>> FallbackHandler<TExt> for F {
+    fn process_word(&mut self, machine: &mut Machine<TExt>, name_address: Address) -> Result<()> {
+        self(machine, name_address)
+    }
 }
 
 type Result<T> = StdResult<T, MachineError>;
 
+/// Number of instructions executed between checks of interrupt flag.
+const INTERRUPT_CHECK_INTERVAL: u64 = 1024;
+
+#[derive(Clone, Copy)]
+struct StepLimit {
+    /// Value of instruction counter when the limit was installed.
+    start: u64,
+
+    /// Value of instruction counter at which execution must stop.
+    end: u64,
+}
+
+/// Result of executing a single instruction with `Machine::step`.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum StepOutcome {
+    /// The instruction was executed, execution continues at given address.
+    Running(Address),
+
+    /// Execution returned to host, there is nothing left to step through.
+    Exited,
+}
+
+/// Result of running code for a limited number of instructions with `Machine::run_for`.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum RunOutcome {
+    /// Instruction limit was reached, execution may be resumed from given address.
+    Paused(Address),
+
+    /// Code returned to host.
+    Done,
+}
+
+/// Output of a machine: output provided by extensions and the one set by `Machine::tee_output`.
+pub type MachineOutput<'m, TExt> = TeeOutput<&'m mut <TExt as MachineExtensions>::TOutput, Option<&'m mut dyn Output>>;
+
+/// Combine output of extensions with the extra output set by `Machine::tee_output`.
+///
+/// Takes the fields rather than the machine, so callers can borrow other parts of the machine at the same time.
+fn machine_output<'m, TExt: MachineExtensions>(
+    extensions: &'m mut TExt,
+    tee_output: &'m mut Option<Box<dyn Output>>,
+) -> MachineOutput<'m, TExt> {
+    TeeOutput::new(extensions.get_output(), tee_output.as_deref_mut().map(|output| output as &mut dyn Output))
+}
+
 pub struct Machine<TExtensions: MachineExtensions> {
     pub memory: MachineMemory,
     pub extensions: TExtensions,
+
+    /// Memory-mapped I/O ranges used by memory access op-codes.
+    pub mmio: MmioMap,
+
+    /// Maximal number of instructions a single `interpret_input` call is allowed to execute.
+    ///
+    /// Execution is not limited when `None`.
+    pub instruction_budget: Option<u64>,
+
+    /// What to do with input words longer than word buffer.
+    pub long_word_policy: LongWordPolicy,
+
+    /// Maximal size in bytes of straight-line words that are copied into compiled words instead of being called.
+    ///
+    /// Words are never inlined when `None`.
+    pub inline_threshold: Option<u16>,
+
+    /// Input provided by extensions is typed by a user line by line.
+    ///
+    /// When set, ` ok <depth>` is printed after each successfully interpreted line and the rest of a line is skipped
+    /// after an error.
+    pub interactive: bool,
+
+    /// Files opened by file access words.
+    pub files: FileTable,
+
+    /// Words implemented by host functions.
+    pub native_words: NativeWords<TExtensions>,
+
+    /// Builtin words added or overridden by host.
+    pub(crate) builtin_words: BuiltinWords<TExtensions>,
+
+    /// Recognizers of words that are not names of words, tried in order after the fallback handler.
+    ///
+    /// Starts with `NumberRecognizer` and `DoubleNumberRecognizer`.
+    pub recognizers: Vec<Box<dyn Recognizer<TExtensions>>>,
+
+    /// State of tester words `T{`, `->` and `}T`.
+    pub tester: Tester,
+
+    /// Total number of instructions executed by this machine.
+    instructions_executed: u64,
+
+    step_limit: Option<StepLimit>,
+
+    /// Address of the next instruction `step` will execute.
+    program_counter: Option<Address>,
+
+    /// Addresses `run_until_breakpoint` stops at.
+    breakpoints: HashSet<Address>,
+
+    /// Receives every instruction before it is executed.
+    tracer: Option<Box<dyn Tracer<TExtensions>>>,
+
+    /// Collects per-article statistics when profiling is enabled.
+    profiler: Option<Profiler>,
+
+    /// Records addresses of executed instructions when coverage is enabled.
+    coverage: Option<Coverage>,
+
+    interrupt_flag: Option<Arc<AtomicBool>>,
+
+    /// Input sources replacing input provided by extensions, the last one is active.
+    input_sources: Vec<InputSource>,
+
+    /// Canonical paths of files interpreted or included so far.
+    included_files: HashSet<PathBuf>,
+
+    /// Output receiving a copy of everything written to output provided by extensions.
+    tee_output: Option<Box<dyn Output>>,
+
+    /// Handles unrecognized words instead of `MachineExtensions::process_unrecognized_word` when set.
+    fallback_handler: Option<Box<dyn FallbackHandler<TExtensions>>>,
+
+    /// Executes op-codes of `CUSTOM_OP_CODES` range, they are illegal when `None`.
+    custom_op_handler: Option<Box<dyn CustomOpHandler<TExtensions>>>,
+
+    /// Names of builtin words registered by `register_custom_op_word` and op-codes they compile to.
+    custom_op_words: Vec<(&'static str, u8)>,
+
+    /// Clock used by time-related words.
+    clock: Box<dyn Clock>,
+}
+
+/// Maximal number of input sources `include_file` may nest.
+pub const MAX_INCLUDE_DEPTH: usize = 16;
+
+/// Maximal length of a line printed by `WORDS`, unless a single name is longer.
+pub const WORDS_LINE_WIDTH: usize = 80;
+
+/// Forth source of standard words defined on top of builtin words, see `Machine::load_prelude`.
+pub const PRELUDE: &str = include_str!("prelude.fs");
+
+/// Path errors in `PRELUDE` are reported at.
+pub const PRELUDE_PATH: &str = "prelude.fs";
+
+struct InputSource {
+    input: Box<dyn Input>,
+    path: String,
+
+    /// Source was pushed by `include_file`, reading continues from the previous source when it ends.
+    included: bool,
 }
 
 impl<TExt: MachineExtensions + Default> Default for Machine<TExt> {
@@ -36,14 +243,68 @@ impl<TExt: MachineExtensions + Default> Default for Machine<TExt> {
 
 impl<TExt: MachineExtensions> Machine<TExt> {
     pub fn new(extensions: TExt) -> Self {
+        Self::with_memory(extensions, MachineMemory::default())
+    }
+
+    /// Create a machine and define words of `PRELUDE` in it, `new` creates a machine without them.
+    pub fn with_prelude(extensions: TExt) -> Result<Self> {
+        let mut machine = Self::new(extensions);
+        machine.load_prelude()?;
+
+        Ok(machine)
+    }
+
+    pub fn with_memory(extensions: TExt, memory: MachineMemory) -> Self {
         Self {
             extensions,
-            memory: MachineMemory::default(),
+            memory,
+            mmio: MmioMap::default(),
+            instruction_budget: None,
+            long_word_policy: LongWordPolicy::default(),
+            inline_threshold: None,
+            interactive: false,
+            files: FileTable::default(),
+            native_words: NativeWords::default(),
+            builtin_words: BuiltinWords::default(),
+            recognizers: default_recognizers(),
+            tester: Tester::default(),
+            instructions_executed: 0,
+            step_limit: None,
+            program_counter: None,
+            breakpoints: HashSet::new(),
+            tracer: None,
+            profiler: None,
+            coverage: None,
+            interrupt_flag: None,
+            input_sources: Vec::new(),
+            included_files: HashSet::new(),
+            tee_output: None,
+            fallback_handler: None,
+            custom_op_handler: None,
+            custom_op_words: Vec::new(),
+            clock: Box::new(SystemClock),
+        }
+    }
+
+    /// Create a machine with given extensions and memory sharing content with memory of this machine.
+    ///
+    /// Memory is copied page by page when either of machines writes to it, so forking a machine with a large
+    /// prepared dictionary is cheap. Memory-mapped devices, native words, recognizers added by host, tester state,
+    /// interrupt flag and executed instruction count are not inherited by the new machine.
+    pub fn fork(&self, extensions: TExt) -> Self {
+        Self {
+            instruction_budget: self.instruction_budget,
+            long_word_policy: self.long_word_policy,
+            inline_threshold: self.inline_threshold,
+            builtin_words: self.builtin_words.clone(),
+            custom_op_words: self.custom_op_words.clone(),
+            ..Self::with_memory(extensions, self.memory.clone())
         }
     }
 
     pub fn reset(&mut self) {
         self.memory.reset();
+        self.tester.abort();
     }
 
     pub fn expect_state(&self, expected: MachineState) -> Result<()> {
@@ -56,337 +317,3089 @@ impl<TExt: MachineExtensions> Machine<TExt> {
         Ok(())
     }
 
-    pub fn run_forever(&mut self, start_address: Address) -> Result<()> {
-        let mut address = start_address;
-
-        loop {
-            address = OpCode::execute_at(self, address)?;
-        }
+    /// Route memory access op-codes targeting given address range to given handler.
+    ///
+    /// Fails if the range overlaps with an already mapped one.
+    pub fn map_io(&mut self, range: AddressRange, handler: Box<dyn MmioHandler>) -> Result<()> {
+        self.mmio.map(range, handler)
     }
 
-    pub fn run_until_exit(&mut self, start_address: Address) -> Result<()> {
-        match self.run_forever(start_address) {
-            Err(MachineError::Exited) => Ok(()),
-            res => res
-        }
+    /// Install a flag that interrupts execution with `MachineError::Interrupted` when set.
+    ///
+    /// The flag is checked between instructions every few instructions and is cleared when the
+    /// interruption happens.
+    pub fn set_interrupt_flag(&mut self, flag: Arc<AtomicBool>) {
+        self.interrupt_flag = Some(flag);
     }
 
-    pub fn execute_word(&mut self, name_address: Address) -> Result<()> {
-        if let Some(article) = self.memory.lookup_article_name_buf(name_address)? {
-            self.run_until_exit(article.body_address())
-        } else {
-            process_builtin_word(self, name_address)
-        }
+    /// Replace clock used by time-related words like `MS` and `TIME&DATE`, system clock is used by default.
+    pub fn set_clock(&mut self, clock: Box<dyn Clock>) {
+        self.clock = clock;
     }
 
-    pub fn read_input_word(&mut self) -> Result<Option<Address>> {
-        Ok(self.memory.read_input_word(self.extensions.get_input())?)
+    pub fn clock(&mut self) -> &mut dyn Clock {
+        self.clock.as_mut()
     }
 
-    pub fn interpret_input(&mut self) -> Result<()> {
-        loop {
-            if let Some(name_address) = self.read_input_word()? {
-                self.execute_word(name_address)?;
-            } else {
-                return Ok(());
+    /// Fail with `MachineError::Interrupted` if interrupt flag is set, clearing the flag.
+    pub fn check_interrupt(&mut self) -> Result<()> {
+        if let Some(flag) = &self.interrupt_flag {
+            if flag.swap(false, Ordering::Relaxed) {
+                return Err(MachineError::Interrupted);
             }
         }
+
+        Ok(())
     }
-}
 
-#[cfg(test)]
-mod test {
-    use std::str::from_utf8;
-    use crate::machine_testing::*;
+    /// Clear interrupt flag, if any, dropping interruption requested before.
+    pub fn clear_interrupt(&mut self) {
+        if let Some(flag) = &self.interrupt_flag {
+            flag.store(false, Ordering::Relaxed);
+        }
+    }
 
-    use super::*;
+    /// Install a handler of unrecognized words replacing `MachineExtensions::process_unrecognized_word`.
+    ///
+    /// See `FallbackHandler` for how returned errors are treated.
+    pub fn set_fallback_handler(&mut self, handler: Box<dyn FallbackHandler<TExt>>) {
+        self.fallback_handler = Some(handler);
+    }
 
-    fn test_16_bit_results(input: &'static str, results: &[u16]) {
-        let mut r = Machine::run_with_test_input(input);
+    /// Restore use of `MachineExtensions::process_unrecognized_word` for unrecognized words.
+    pub fn clear_fallback_handler(&mut self) {
+        self.fallback_handler = None;
+    }
 
-        match r.result {
-            Ok(_) => {}
-            Err(err) => {
-                let mut buf = Vec::new();
-                err.pretty_print(&mut buf, &r.machine).unwrap();
+    /// Process a word that is neither a dictionary article, native word nor builtin word with installed fallback
+    /// handler, or with `MachineExtensions::process_unrecognized_word` if there is none.
+    pub fn process_unrecognized_word(&mut self, name_address: Address) -> Result<()> {
+        match self.fallback_handler.take() {
+            Some(mut handler) => {
+                let result = handler.process_word(self, name_address);
 
-                panic!("Machine error occurred: {}", from_utf8(buf.as_slice()).unwrap());
+                // The handler may have installed a different handler
+                if self.fallback_handler.is_none() {
+                    self.fallback_handler = Some(handler);
+                }
+
+                result
             }
+            None => TExt::process_unrecognized_word(self, name_address),
         }
-        r.machine.assert_data_stack_state(&results.iter().map(|r| StackElement::Cell(*r)).collect::<Vec<_>>())
     }
 
-    #[test]
-    fn test_push_literal() {
-        test_16_bit_results("1 2", &[1, 2]);
+    /// Install a handler of op-codes of `CUSTOM_OP_CODES` range, replacing the previous one.
+    pub fn set_custom_op_handler(&mut self, handler: Box<dyn CustomOpHandler<TExt>>) {
+        self.custom_op_handler = Some(handler);
     }
 
-    #[test]
-    fn test_arithmetic() {
-        test_16_bit_results("1 2 +", &[3]);
-        test_16_bit_results("1 -3 -", &[4]);
-        test_16_bit_results("42 2 *", &[84]);
-        test_16_bit_results("10 2 /", &[5]);
+    /// Make op-codes of `CUSTOM_OP_CODES` range illegal again.
+    pub fn clear_custom_op_handler(&mut self) {
+        self.custom_op_handler = None;
     }
 
-    #[test]
-    fn test_store_load_16() {
-        test_16_bit_results("42 101 !", &[]);
-        test_16_bit_results("42 101 ! 101 @", &[42]);
+    pub fn custom_op_handler(&self) -> Option<&dyn CustomOpHandler<TExt>> {
+        self.custom_op_handler.as_deref()
     }
 
-    #[test]
-    fn test_store_load_8() {
-        test_16_bit_results("$FFFF 101 C! $FEFE 102 C!", &[]);
-        test_16_bit_results("$FFFF 101 C! $FEFE 102 C! 101 C@ 102 C@", &[0xff, 0xfe]);
+    /// Add a builtin word compiling given custom op-code into definitions and executing it in interpreter state, so
+    /// that compiled code may call the handler without the overhead of `ExecBuiltin`.
+    ///
+    /// The op-code is compiled without an operand and is executed in interpreter state as if located at address 0, so
+    /// the handler must neither read an operand nor jump. Panics if the op-code is out of `CUSTOM_OP_CODES` range.
+    pub fn register_custom_op_word(&mut self, name: &'static str, op_code: u8) {
+        assert!(CUSTOM_OP_CODES.contains(&op_code), "op-code {} is not a custom op-code", op_code);
+
+        match self.custom_op_words.iter_mut().find(|(word_name, _)| *word_name == name) {
+            Some(word) => word.1 = op_code,
+            None => self.custom_op_words.push((name, op_code)),
+        }
+
+        self.register_builtin_word(BuiltinWord::new(name, BuiltinFlags::NONE, process_custom_op_word));
     }
 
-    #[test]
-    fn test_radix_change() {
-        test_16_bit_results("100 36 BASE ! zZz", &[100, 46655]);
+    /// Custom op-code compiled by a word registered with `register_custom_op_word`.
+    pub fn custom_op_word(&self, name: &[u8]) -> Option<u8> {
+        self.custom_op_words.iter().find(|(word_name, _)| word_name.as_bytes() == name).map(|(_, op_code)| *op_code)
     }
 
-    fn test_output(input: &'static str, expected_output: &'static [u8]) {
-        let result = Machine::run_with_test_input(input);
-        let out_vec = result.machine.extensions.output.content.borrow();
+    /// Execute a custom op-code located at given address with installed handler.
+    pub(crate) fn execute_custom_op(&mut self, op_code: u8, address: Address) -> Result<Address> {
+        let Some(mut handler) = self.custom_op_handler.take() else {
+            return Err(MachineError::IllegalOpCodeError { address, op_code });
+        };
 
-        assert_eq!(out_vec.as_slice(), expected_output)
-    }
+        let result = handler.execute(self, op_code, address);
 
-    #[test]
-    fn test_emit_single_characters() {
-        test_output(
-            "70 EMIT 79 DUP EMIT EMIT 66 EMIT 65 EMIT 82 EMIT",
-            b"FOOBAR",
-        )
+        // The handler may have installed a different handler
+        if self.custom_op_handler.is_none() {
+            self.custom_op_handler = Some(handler);
+        }
+
+        result
     }
 
-    #[test]
-    fn test_colon_definition() {
-        test_16_bit_results(
-            ": foo + ;",
-            &[],
-        );
-        test_16_bit_results(
-            ": foo + ; 100 1 foo",
-            &[101],
-        )
+    /// Install a tracer receiving every instruction before it is executed, replacing the previous one.
+    pub fn set_tracer(&mut self, tracer: Box<dyn Tracer<TExt>>) {
+        self.tracer = Some(tracer);
     }
 
-    #[test]
-    fn test_colon_definition_use() {
-        test_16_bit_results(
-            ": +3 3 + ; 2 +3 +3",
-            &[8],
-        )
+    /// Write disassembly of every executed instruction to given writer.
+    pub fn trace_to(&mut self, writer: impl io::Write + 'static) {
+        self.set_tracer(Box::new(WriteTracer::new(writer)));
     }
 
-    #[test]
-    fn test_comparison() {
-        test_16_bit_results(
-            "0 1 < -1 0 < 0 0 < 2 1 <",
-            &[0xffff, 0xffff, 0, 0],
-        );
-        test_16_bit_results(
-            "0 1 > -1 0 > 0 0 > 2 1 >",
-            &[0, 0, 0, 0xffff],
-        );
-        test_16_bit_results(
-            "0 1 = -1 0 = 0 0 = 2 1 =",
-            &[0, 0, 0xffff, 0],
-        );
+    pub fn clear_tracer(&mut self) {
+        self.tracer = None;
     }
 
-    #[test]
-    fn test_logic() {
-        test_16_bit_results(
-            "TRUE FALSE",
-            &[0xffff, 0],
-        );
-        test_16_bit_results(
-            "TRUE FALSE AND FALSE TRUE AND FALSE FALSE AND TRUE TRUE AND",
-            &[0, 0, 0, 0xffff],
-        );
-        test_16_bit_results(
-            "TRUE FALSE OR FALSE TRUE OR FALSE FALSE OR TRUE TRUE OR",
-            &[0xffff, 0xffff, 0, 0xffff],
-        );
-        test_16_bit_results(
-            "TRUE FALSE XOR FALSE TRUE XOR FALSE FALSE XOR TRUE TRUE XOR",
-            &[0xffff, 0xffff, 0, 0],
-        );
-        test_16_bit_results(
-            "TRUE INVERT FALSE INVERT",
-            &[0, 0xffff],
-        );
+    /// Start collecting per-article execution statistics, keeping already collected ones.
+    pub fn enable_profiling(&mut self) {
+        self.profiler.get_or_insert_with(Profiler::default);
     }
 
-    #[test]
-    fn test_dup() {
-        test_16_bit_results(
-            "1 2 DUP",
-            &[1, 2, 2],
-        );
+    pub fn disable_profiling(&mut self) {
+        self.profiler = None;
+    }
 
-        test_16_bit_results(
-            "3 4 2DUP",
-            &[3, 4, 3, 4],
-        );
+    pub fn profiler(&self) -> Option<&Profiler> {
+        self.profiler.as_ref()
     }
 
-    #[test]
-    fn test_drop() {
-        test_16_bit_results(
-            "1 2 3 DROP",
-            &[1, 2],
-        );
+    pub fn reset_profile(&mut self) {
+        if let Some(profiler) = &mut self.profiler {
+            profiler.reset();
+        }
+    }
 
-        test_16_bit_results(
-            "4 5 6 2DROP",
-            &[4],
-        );
+    /// Start recording addresses of executed instructions, keeping already recorded ones.
+    pub fn enable_coverage(&mut self) {
+        self.coverage.get_or_insert_with(Coverage::default);
     }
 
-    #[test]
-    fn test_rot() {
-        test_16_bit_results(
-            "1 2 3 ROT",
-            &[2, 3, 1],
-        )
+    pub fn disable_coverage(&mut self) {
+        self.coverage = None;
     }
 
-    #[test]
-    fn test_immediate() {
-        test_16_bit_results(
-            "
-            : C,, HERE @ C! HERE @ 1 + HERE ! ;
-            : ,, HERE @ ! HERE @ 2 + HERE ! ;
-            : iff    7 ( OpCode: GoToIfZ ) C,, HERE @ 0 ,, ; IMMEDIATE
-            : elsse  6 ( OpCode: GoTo    ) C,, HERE @ 0 ,, SWAP HERE @ SWAP ! ; IMMEDIATE
-            : endiff                                            HERE @ SWAP ! ; IMMEDIATE
-            : tst 0 < iff -1 elsse 1 endiff ;
+    pub fn coverage(&self) -> Option<&Coverage> {
+        self.coverage.as_ref()
+    }
 
-            0 tst -1 tst
-            ",
-            &[1, 0xffff],
-        )
+    pub fn reset_coverage(&mut self) {
+        if let Some(coverage) = &mut self.coverage {
+            coverage.reset();
+        }
     }
 
-    #[test]
-    fn test_conditions() {
-        test_16_bit_results(
-            "
-            : myabs 1 SWAP 0 < IF DROP -1 THEN ;
+    /// Report instruction at given address to the installed tracer, if any.
+    #[inline]
+    pub fn trace_instruction(&mut self, address: Address, op_code: u8) {
+        if let Some(mut tracer) = self.tracer.take() {
+            if let Ok(op_code) = OpCode::from_int(op_code) {
+                tracer.trace(self, address, op_code);
+            }
 
-            0 myabs -1 myabs
-            ",
-            &[1, 0xffff],
-        );
+            self.tracer = Some(tracer);
+        }
     }
 
-    #[test]
-    fn test_conditions_2() {
-        test_16_bit_results(
-            "
-            : myabs 0 < IF -1 ELSE 1 THEN ;
+    /// Account for one executed instruction, failing if current step limit is exhausted or
+    /// execution was interrupted.
+    pub fn count_step(&mut self) -> Result<()> {
+        if self.instructions_executed.is_multiple_of(INTERRUPT_CHECK_INTERVAL) {
+            self.check_interrupt()?;
+        }
 
-            0 myabs -1 myabs
-            ",
-            &[1, 0xffff],
-        );
+        if let Some(limit) = self.step_limit {
+            if self.instructions_executed >= limit.end {
+                return Err(MachineError::StepLimitExceeded {
+                    executed: self.instructions_executed - limit.start,
+                });
+            }
+        }
+
+        self.instructions_executed += 1;
+
+        Ok(())
     }
 
-    #[test]
-    fn test_while_loop() {
-        test_16_bit_results(
+    /// Run `f` allowing it to execute at most `max_steps` instructions.
+    ///
+    /// Limits installed by outer calls keep working, so nested limits can only be tighter.
+    fn with_step_limit<T>(&mut self, max_steps: u64, f: impl FnOnce(&mut Self) -> Result<T>) -> Result<T> {
+        let outer_limit = self.step_limit;
+        let end = self.instructions_executed.saturating_add(max_steps);
+
+        self.step_limit = Some(StepLimit {
+            start: self.instructions_executed,
+            end: outer_limit.map_or(end, |outer| min(outer.end, end)),
+        });
+
+        let result = f(self);
+
+        self.step_limit = outer_limit;
+
+        result
+    }
+
+    /// Prepare to execute code starting at given address instruction by instruction with `step`.
+    pub fn prepare(&mut self, start_address: Address) {
+        self.program_counter = Some(start_address);
+
+        if let Some(profiler) = &mut self.profiler {
+            profiler.start_run();
+        }
+    }
+
+    /// Address of the next instruction `step` will execute, `None` if there is nothing to execute.
+    pub fn program_counter(&self) -> Option<Address> {
+        self.program_counter
+    }
+
+    /// Execute exactly one instruction at the program counter set by `prepare`.
+    ///
+    /// When the instruction fails the program counter keeps pointing to it, except for `BREAK` which moves it to the
+    /// next instruction, so execution can be resumed after it.
+    pub fn step(&mut self) -> Result<StepOutcome> {
+        let Some(address) = self.program_counter else {
+            return Ok(StepOutcome::Exited);
+        };
+
+        self.count_step()?;
+
+        if let Some(profiler) = &mut self.profiler {
+            profiler.record(&self.memory, address);
+        }
+
+        if let Some(coverage) = &mut self.coverage {
+            coverage.record(address);
+        }
+
+        match OpCode::execute_at(self, address) {
+            Ok(next_address) => {
+                self.program_counter = Some(next_address);
+
+                Ok(StepOutcome::Running(next_address))
+            }
+            Err(MachineError::Exited) => {
+                self.program_counter = None;
+
+                Ok(StepOutcome::Exited)
+            }
+            Err(MachineError::Breakpoint { address }) => {
+                self.program_counter = Some(address);
+
+                Err(MachineError::Breakpoint { address })
+            }
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Execute at most `max_steps` instructions starting at given address.
+    ///
+    /// When `start_address` is the address returned in `RunOutcome::Paused` by previous call, execution continues
+    /// where it was paused, with call stack left by that call.
+    pub fn run_for(&mut self, start_address: Address, max_steps: u64) -> Result<RunOutcome> {
+        if self.program_counter != Some(start_address) {
+            self.prepare(start_address);
+        }
+
+        for _ in 0..max_steps {
+            if let StepOutcome::Exited = self.step()? {
+                return Ok(RunOutcome::Done);
+            }
+        }
+
+        Ok(match self.program_counter {
+            Some(address) => RunOutcome::Paused(address),
+            None => RunOutcome::Done,
+        })
+    }
+
+    pub fn set_breakpoint(&mut self, address: Address) {
+        self.breakpoints.insert(address);
+    }
+
+    pub fn clear_breakpoint(&mut self, address: Address) {
+        self.breakpoints.remove(&address);
+    }
+
+    /// Set a breakpoint at the address compiled calls of article with given name go to, returning address of the
+    /// breakpoint.
+    pub fn set_word_breakpoint(&mut self, name: &[u8]) -> Result<Address> {
+        let address = self.memory.lookup_article(name)?
+            .ok_or(MachineError::NoArticle)?
+            .call_address();
+
+        self.set_breakpoint(address);
+
+        Ok(address)
+    }
+
+    /// Run code starting at given address until it returns to host or reaches a breakpoint.
+    ///
+    /// Returns address of the reached breakpoint, the instruction at it is not executed.
+    /// Instruction at `start_address` is always executed even if there is a breakpoint at it.
+    pub fn run_until_breakpoint(&mut self, start_address: Address) -> Result<Option<Address>> {
+        self.prepare(start_address);
+
+        self.resume_until_breakpoint()
+    }
+
+    /// Continue execution from current program counter (e.g. from a reached breakpoint) until code returns to host
+    /// or reaches a breakpoint or `BREAK`.
+    pub fn resume_until_breakpoint(&mut self) -> Result<Option<Address>> {
+        loop {
+            match self.step() {
+                Ok(StepOutcome::Exited) => return Ok(None),
+                Ok(StepOutcome::Running(address)) if self.breakpoints.contains(&address) => return Ok(Some(address)),
+                Ok(StepOutcome::Running(_)) => {}
+                Err(MachineError::Breakpoint { address }) => return Ok(Some(address)),
+                Err(err) => return Err(err),
+            }
+        }
+    }
+
+    /// Run code from current program counter until it returns to host, failing with `MachineError::Exited` when it
+    /// does.
+    ///
+    /// Fails with `MachineError::Breakpoint` before executing an instruction with a breakpoint set, unless it's the
+    /// first one and `check_first` is false.
+    fn run_prepared(&mut self, check_first: bool) -> Result<()> {
+        let mut check_breakpoint = check_first;
+
+        loop {
+            if check_breakpoint && !self.breakpoints.is_empty() {
+                if let Some(address) = self.program_counter.filter(|address| self.breakpoints.contains(address)) {
+                    return Err(MachineError::Breakpoint { address });
+                }
+            }
+
+            check_breakpoint = true;
+
+            if self.step()? == StepOutcome::Exited {
+                return Err(MachineError::Exited);
+            }
+        }
+    }
+
+    pub fn run_forever(&mut self, start_address: Address) -> Result<()> {
+        self.prepare(start_address);
+
+        self.run_prepared(true)
+    }
+
+    /// Continue code suspended by `MachineError::Breakpoint` until it returns to host.
+    ///
+    /// Only the code that was running when the breakpoint was reached is continued, not the interpretation of input
+    /// that called it. Does nothing if there is no code to continue.
+    pub fn resume(&mut self) -> Result<()> {
+        match self.run_prepared(false) {
+            Err(MachineError::Exited) => Ok(()),
+            res => res,
+        }
+    }
+
+    /// Same as `run_until_exit` but fails with `StepLimitExceeded` after executing `max_steps` instructions.
+    pub fn run_with_limit(&mut self, start_address: Address, max_steps: u64) -> Result<()> {
+        self.with_step_limit(max_steps, |machine| machine.run_until_exit(start_address))
+    }
+
+    pub fn run_until_exit(&mut self, start_address: Address) -> Result<()> {
+        match self.run_forever(start_address) {
+            Err(MachineError::Exited) => Ok(()),
+            res => res
+        }
+    }
+
+    pub fn execute_word(&mut self, name_address: Address) -> Result<()> {
+        if let Some(article) = self.memory.lookup_article_name_buf(name_address)? {
+            if self.memory.get_state() == MachineState::Interpreter && article.is_compile_only() {
+                return Err(MachineError::CompileOnlyWord { name: article.name().to_string() });
+            }
+
+            if self.memory.get_state() == MachineState::Compiler && !article.is_immediate() {
+                let call_address = article.call_address();
+
+                self.compile_reference(call_address)
+            } else {
+                self.run_until_exit(article.body_address())
+            }
+        } else if let Some(index) = self.find_native_word(name_address)? {
+            self.process_native_word(index)
+        } else {
+            process_builtin_word(self, name_address)
+        }
+    }
+
+    /// Names of all words available to programs: dictionary articles, most recent first, then native and builtin
+    /// words.
+    ///
+    /// Each name is listed once even if it's defined several times.
+    pub fn all_word_names(&self) -> Vec<String> {
+        let mut seen = HashSet::new();
+
+        self.memory.articles()
+            .map(|article| article.name().to_string())
+            .chain((0..self.native_words.len() as u16).filter_map(|index| {
+                self.native_words.name(index).map(|name| String::from_utf8_lossy(name).into_owned())
+            }))
+            .chain(self.all_builtin_words().iter().map(|word| word.name.to_string()))
+            .filter(|name| seen.insert(name.clone()))
+            .collect()
+    }
+
+    /// Print names returned by `all_word_names` separated by spaces and wrapped at `WORDS_LINE_WIDTH`.
+    pub fn print_words(&mut self) -> Result<()> {
+        let mut listing = String::new();
+        let mut line_length = 0;
+
+        for name in self.all_word_names() {
+            if line_length > 0 && line_length + 1 + name.len() > WORDS_LINE_WIDTH {
+                listing.push('\n');
+                line_length = 0;
+            } else if line_length > 0 {
+                listing.push(' ');
+                line_length += 1;
+            }
+
+            listing.push_str(&name);
+            line_length += name.len();
+        }
+
+        listing.push('\n');
+
+        Ok(self.output().puts(listing.as_bytes())?)
+    }
+
+    /// Run a word with given name taking given arguments from host and return exactly `result_count` cells it leaves
+    /// on data stack.
+    ///
+    /// The word is looked up the same way as words read from input. Arguments are pushed in order, so the last one
+    /// ends up on top of the stack, and results are returned in the same order. On failure, including the word
+    /// leaving a different number of cells, data and call stacks are restored to the state before the call.
+    pub fn call_word(&mut self, name: &str, args: &[Cell], result_count: usize) -> Result<Vec<Cell>> {
+        self.expect_state(MachineState::Interpreter)?;
+
+        let data_stack_ptr = self.memory.data_stack_ptr;
+        let call_stack_ptr = self.memory.call_stack_ptr;
+        let result = self.call_word_unchecked(name, args, result_count);
+
+        if result.is_err() {
+            self.memory.data_stack_ptr = data_stack_ptr;
+            self.memory.call_stack_ptr = call_stack_ptr;
+        }
+
+        result
+    }
+
+    /// Same as `call_word` but takes and returns signed values.
+    pub fn call_word_signed(&mut self, name: &str, args: &[SignedCell], result_count: usize) -> Result<Vec<SignedCell>> {
+        let args: Vec<Cell> = args.iter().map(|&arg| arg as Cell).collect();
+
+        Ok(self.call_word(name, &args, result_count)?.into_iter().map(|result| result as SignedCell).collect())
+    }
+
+    fn call_word_unchecked(&mut self, name: &str, args: &[Cell], result_count: usize) -> Result<Vec<Cell>> {
+        let initial_depth = self.memory.data_stack_depth() as usize;
+
+        for &arg in args {
+            self.push(arg)?;
+        }
+
+        self.run_word(name)?;
+
+        let depth = self.memory.data_stack_depth() as usize;
+
+        if depth != initial_depth + result_count {
+            return Err(MachineError::ResultCountMismatch {
+                expected: result_count,
+                actual: depth as isize - initial_depth as isize,
+            });
+        }
+
+        let mut results = (0..result_count)
+            .map(|_| self.pop::<Cell>())
+            .collect::<Result<Vec<_>>>()?;
+        results.reverse();
+
+        Ok(results)
+    }
+
+    /// Compile a call to given address or a copy of code at it if it's small enough to be inlined.
+    ///
+    /// References to synonyms of op-code words are compiled as the op-code.
+    pub(crate) fn compile_reference(&mut self, call_address: Address) -> Result<()> {
+        if let Some(op_code) = find_opcode_synonym(self, call_address) {
+            return self.memory.dict_write_opcode(op_code);
+        }
+
+        let inlinable_code = self.inline_threshold
+            .and_then(|threshold| find_inlinable_code(self, call_address, threshold));
+
+        match inlinable_code {
+            Some(code_range) => {
+                let code = self.memory.raw_memory.slice(code_range.start as usize..code_range.end as usize).into_owned();
+                let destination = self.memory.get_dict_ptr();
+                let relocations: Vec<Address> = self.memory.relocations().iter()
+                    .filter(|address| code_range.contains(address))
+                    .map(|address| destination.wrapping_add(address - code_range.start))
+                    .collect();
+
+                for byte in code {
+                    self.memory.dict_write_u8(byte)?;
+                }
+
+                for address in relocations {
+                    self.memory.mark_relocation(address);
+                }
+
+                Ok(())
+            }
+            None => compile_call(self, call_address),
+        }
+    }
+
+    /// Total number of instructions executed by this machine.
+    pub fn instructions_executed(&self) -> u64 {
+        self.instructions_executed
+    }
+
+    /// Output the machine writes to.
+    pub fn output(&mut self) -> MachineOutput<'_, TExt> {
+        machine_output(&mut self.extensions, &mut self.tee_output)
+    }
+
+    /// Memory of the machine along with a writer writing to machine output, to print reports about memory directly
+    /// to the output.
+    pub fn memory_and_output_writer(&mut self) -> (&MachineMemory, OutputWriter<MachineOutput<'_, TExt>>) {
+        let output = machine_output(&mut self.extensions, &mut self.tee_output);
+
+        (&self.memory, OutputWriter::new(output))
+    }
+
+    /// Copy everything written to machine output to given output as well.
+    ///
+    /// Replaces the output set by previous call.
+    pub fn tee_output(&mut self, extra: Box<dyn Output>) {
+        self.tee_output = Some(extra);
+    }
+
+    /// Stop copying machine output, returning the output set by `tee_output`.
+    pub fn remove_tee_output(&mut self) -> Option<Box<dyn Output>> {
+        self.tee_output.take()
+    }
+
+    /// Input source words are currently read from.
+    pub fn input(&mut self) -> &mut dyn Input {
+        match self.input_sources.last_mut() {
+            Some(source) => source.input.as_mut(),
+            None => self.extensions.get_input(),
+        }
+    }
+
+    fn canonical_path(&mut self, path: &str) -> Result<PathBuf> {
+        self.extensions.get_file_system()
+            .ok_or(MachineError::FileSystemUnavailable)?
+            .canonicalize(path)
+            .map_err(|err| MachineError::FileError { path: path.to_string(), err })
+    }
+
+    /// Remember that a file has been loaded, so `require_file` will not load it again.
+    pub fn mark_included(&mut self, path: &str) -> Result<()> {
+        let canonical_path = self.canonical_path(path)?;
+        self.included_files.insert(canonical_path);
+
+        Ok(())
+    }
+
+    fn open_input_file(&mut self, path: &str) -> Result<Box<dyn Input>> {
+        let canonical_path = self.canonical_path(path)?;
+        let file = self.extensions.get_file_system()
+            .ok_or(MachineError::FileSystemUnavailable)?
+            .open_file(path, FileAccessMode::ReadOnly)
+            .map_err(|err| MachineError::FileError { path: path.to_string(), err })?;
+
+        self.included_files.insert(canonical_path);
+
+        Ok(Box::new(FileInput::new(file)))
+    }
+
+    /// Annotate an error with path and position of given input source.
+    ///
+    /// `MachineError::Bye` is not an error in the source, so it is returned as is.
+    fn error_in_source(mut source: InputSource, err: MachineError) -> MachineError {
+        if let MachineError::Bye = err {
+            return err;
+        }
+
+        let (line, column) = source.input.tell()
+            .and_then(|offset| line_and_column(source.input.as_mut(), offset.saturating_sub(1)))
+            .unwrap_or((0, 0));
+
+        MachineError::InFile { path: source.path, line, column, err: Box::new(err) }
+    }
+
+    /// Continue reading input from a file in machine's file system until it ends.
+    ///
+    /// Fails if `MAX_INCLUDE_DEPTH` files are already being read.
+    pub fn include_file(&mut self, path: &str) -> Result<()> {
+        if self.input_sources.len() >= MAX_INCLUDE_DEPTH {
+            return Err(MachineError::IncludeDepthExceeded { depth: self.input_sources.len() });
+        }
+
+        let input = self.open_input_file(path)?;
+        self.input_sources.push(InputSource { input, path: path.to_string(), included: true });
+
+        Ok(())
+    }
+
+    /// Same as `include_file` but does nothing if the file has already been interpreted or included.
+    pub fn require_file(&mut self, path: &str) -> Result<()> {
+        let canonical_path = self.canonical_path(path)?;
+
+        if self.included_files.contains(&canonical_path) {
+            return Ok(());
+        }
+
+        self.include_file(path)
+    }
+
+    /// Remove included input sources after an error, annotating the error with their positions.
+    fn abort_included_sources(&mut self, mut err: MachineError) -> MachineError {
+        while self.input_sources.last().is_some_and(|source| source.included) {
+            let source = self.input_sources.pop().unwrap();
+            err = Self::error_in_source(source, err);
+        }
+
+        err
+    }
+
+    /// Interpret a file from machine's file system.
+    ///
+    /// Input is read from the file until it ends, then the previous input is restored.
+    /// Errors are annotated with file path and position of the last character read.
+    pub fn interpret_file(&mut self, path: &str) -> Result<()> {
+        let input = self.open_input_file(path)?;
+        let (result, source) = self.interpret_source(InputSource { input, path: path.to_string(), included: false });
+
+        result.map_err(|err| Self::error_in_source(source, err))
+    }
+
+    /// Interpret given source code, then restore the previous input.
+    pub fn interpret_str(&mut self, source: &str) -> Result<()> {
+        let input = Box::new(StringInput::new(source.to_string()));
+
+        self.interpret_source(InputSource { input, path: "<string>".to_string(), included: false }).0
+    }
+
+    /// Define words of `PRELUDE`.
+    ///
+    /// Errors are annotated with `PRELUDE_PATH` and position in the prelude, as errors of `interpret_file` are.
+    pub fn load_prelude(&mut self) -> Result<()> {
+        self.interpret_named_str(PRELUDE_PATH, PRELUDE)
+    }
+
+    fn interpret_named_str(&mut self, path: &str, source: &str) -> Result<()> {
+        let input = Box::new(StringInput::new(source.to_string()));
+        let (result, source) = self.interpret_source(InputSource { input, path: path.to_string(), included: false });
+
+        result.map_err(|err| Self::error_in_source(source, err))
+    }
+
+    fn interpret_source(&mut self, source: InputSource) -> (Result<()>, InputSource) {
+        self.input_sources.push(source);
+        let result = self.interpret_input();
+        let source = self.input_sources.pop().expect("interpreted source is still on the stack");
+
+        (result, source)
+    }
+
+    /// Read next word from the active input source to word buffer.
+    ///
+    /// When an included source ends, reading continues from the source that included it.
+    pub fn read_input_word(&mut self) -> Result<Option<Address>> {
+        let context = match self.memory.get_state() {
+            MachineState::Interpreter => PromptContext::Interpreter,
+            MachineState::Compiler => PromptContext::Compiler,
+        };
+
+        loop {
+            let (input, included): (&mut dyn Input, bool) = match self.input_sources.last_mut() {
+                Some(source) => (source.input.as_mut(), source.included),
+                None => (self.extensions.get_input(), false),
+            };
+
+            input.set_prompt_context(context);
+
+            match self.memory.read_input_word(input) {
+                Ok(None) if included => {
+                    self.input_sources.pop();
+                }
+                result => return self.handle_long_word(result),
+            }
+        }
+    }
+
+    fn handle_long_word(&mut self, result: StdResult<Option<Address>, InputError>) -> Result<Option<Address>> {
+        match result {
+            Err(InputError::WordTooLong { prefix, position }) if self.long_word_policy == LongWordPolicy::Truncate => {
+                let warning = format!(
+                    "Warning: word at input offset {} (\"{}...\") truncated to {} bytes\n",
+                    position, String::from_utf8_lossy(&prefix), self.memory.layout_config().max_word_length,
+                );
+                self.output().puts(warning.as_bytes())?;
+
+                Ok(Some(self.memory.truncate_input_word()))
+            }
+            result => Ok(result?),
+        }
+    }
+
+    pub fn interpret_input(&mut self) -> Result<()> {
+        // Interruption requested while the machine was idle must not abort the new input
+        if self.input_sources.is_empty() {
+            self.clear_interrupt();
+        }
+
+        match self.instruction_budget {
+            None => self.interpret_input_words(),
+            Some(budget) => self.with_step_limit(budget, Self::interpret_input_words),
+        }
+    }
+
+    fn interpret_input_words(&mut self) -> Result<()> {
+        self.interpret_input_sources().map_err(|err| {
+            // Input is left as is, so interpretation can go on after suspended code is resumed
+            if let MachineError::Breakpoint { .. } = err {
+                return err;
+            }
+
+            let err = self.abort_included_sources(err);
+            self.tester.abort();
+
+            if self.interactive && self.input_sources.is_empty() {
+                self.extensions.get_input().discard_line();
+            }
+
+            err
+        })
+    }
+
+    /// Print ` ok <depth>` (or ` compiled` in the middle of a definition) if a line of interactive input has just
+    /// been interpreted.
+    fn report_line_end(&mut self) -> Result<()> {
+        if !self.interactive || !self.input_sources.is_empty() || !self.extensions.get_input().take_line_end() {
+            return Ok(());
+        }
+
+        let feedback = match self.memory.get_state() {
+            MachineState::Interpreter => format!(" ok <{}>", self.memory.data_stack_depth()),
+            MachineState::Compiler => " compiled".to_string(),
+        };
+
+        Ok(self.output().puts(feedback.as_bytes())?)
+    }
+
+    fn interpret_input_sources(&mut self) -> Result<()> {
+        loop {
+            self.report_line_end()?;
+
+            if let Some(name_address) = self.read_input_word()? {
+                self.execute_word(name_address)?;
+            } else {
+                return Ok(());
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+    use std::str::from_utf8;
+    use int_enum::IntEnum;
+    use crate::builtin_words::builtin_word_table;
+    use crate::cell::{Cell, CELL_BYTES, DoubleCell, FALSE, TRUE};
+    use crate::input::StaticStringInput;
+    use crate::machine_memory::MemoryLayoutConfig;
+    use crate::mem::{AccessKind, Mem, MEM_SIZE, MemoryAccessError, PAGE_SIZE};
+    use crate::machine_testing::*;
+    use crate::output::WriterOutput;
+
+    use super::*;
+
+    fn test_16_bit_results(input: &'static str, results: &[Cell]) {
+        let mut r = Machine::run_with_test_input(input);
+
+        match r.result {
+            Ok(_) => {}
+            Err(err) => {
+                let mut buf = Vec::new();
+                err.pretty_print(&mut buf, &r.machine).unwrap();
+
+                panic!("Machine error occurred: {}", from_utf8(buf.as_slice()).unwrap());
+            }
+        }
+        r.machine.assert_data_stack_state(&results.iter().map(|r| StackElement::Cell(*r)).collect::<Vec<_>>())
+    }
+
+    #[test]
+    fn test_push_literal() {
+        test_16_bit_results("1 2", &[1, 2]);
+    }
+
+    #[test]
+    fn test_cell_width_wraparound() {
+        test_16_bit_results("-1 1 + -1 -1 =", &[0, TRUE]);
+    }
+
+    #[test]
+    #[cfg(feature = "cell32")]
+    fn test_32_bit_cells() {
+        test_16_bit_results("70000 1 + -70000 ABS", &[70001, 70000]);
+    }
+
+    #[test]
+    fn test_arithmetic() {
+        test_16_bit_results("1 2 +", &[3]);
+        test_16_bit_results("1 -3 -", &[4]);
+        test_16_bit_results("42 2 *", &[84]);
+        test_16_bit_results("10 2 /", &[5]);
+    }
+
+    #[test]
+    fn test_store_load_16() {
+        test_16_bit_results("42 101 !", &[]);
+        test_16_bit_results("42 101 ! 101 @", &[42]);
+    }
+
+    #[test]
+    fn test_store_load_8() {
+        test_16_bit_results("$FFFF 101 C! $FEFE 102 C!", &[]);
+        test_16_bit_results("$FFFF 101 C! $FEFE 102 C! 101 C@ 102 C@", &[0xff, 0xfe]);
+    }
+
+    #[test]
+    fn test_radix_change() {
+        test_16_bit_results("100 36 BASE ! zZz", &[100, 46655]);
+    }
+
+    fn test_output(input: &'static str, expected_output: &'static [u8]) {
+        let result = Machine::run_with_test_input(input);
+        let out_vec = result.machine.extensions.output.content.borrow();
+
+        assert_eq!(out_vec.as_slice(), expected_output)
+    }
+
+    #[test]
+    fn test_print_data_stack() {
+        test_output(".S", b"<0> ");
+        test_output("1 -2 300 .S", b"<3> 1 -2 300 ");
+        test_output("255 -2 16 BASE ! .S", b"<2> FF -2 ");
+        test_output(": show .S ; 7 show", b"<1> 7 ");
+
+        let mut result = Machine::run_with_test_input("1 2 3 .S .S");
+        result.machine.assert_data_stack_state(&[StackElement::Cell(1), StackElement::Cell(2), StackElement::Cell(3)]);
+    }
+
+    #[test]
+    fn test_print_deep_data_stack() {
+        let result = Machine::run_with_test_input(": push20 20 BEGIN DUP WHILE DUP 1 - REPEAT ; push20 .S");
+        let expected = format!("<21> {}", (0..=20).rev().map(|value| format!("{} ", value)).collect::<String>());
+
+        assert_eq!(from_utf8(&result.machine.extensions.output.content.borrow()).unwrap(), expected);
+    }
+
+    #[test]
+    fn test_dump() {
+        let mut machine = TestMachine::default();
+        machine.memory.raw_memory.write_slice(0x2000, b"\tForth\n");
+        machine.extensions.input = StaticStringInput::new("$2000 8 DUMP $2000 0 DUMP");
+        machine.interpret_input().unwrap();
+
+        assert_eq!(
+            from_utf8(&machine.extensions.output.content.take()).unwrap(),
+            "2000: 09 46 6F 72 74 68 0A 00                          .Forth..\n",
+        );
+        machine.assert_data_stack_state(&[]);
+
+        machine.extensions.input = StaticStringInput::new("$FFF0 $20 DUMP");
+
+        assert!(matches!(
+            machine.interpret_input(),
+            Err(MachineError::MemoryAccessError(MemoryAccessError { kind: AccessKind::Read, .. })),
+        ));
+        assert!(machine.extensions.output.content.borrow().is_empty());
+        machine.assert_data_stack_state(&[StackElement::Cell(0xFFF0), StackElement::Cell(0x20)]);
+    }
+
+    #[test]
+    fn test_emit_single_characters() {
+        test_output(
+            "70 EMIT 79 DUP EMIT EMIT 66 EMIT 65 EMIT 82 EMIT",
+            b"FOOBAR",
+        )
+    }
+
+    #[test]
+    fn test_xemit() {
+        test_output("233 XEMIT 65 XEMIT : euro 8364 XEMIT ; euro", "éA€".as_bytes());
+    }
+
+    #[test]
+    fn test_spaces_and_line_breaks() {
+        test_output("42 EMIT 3 SPACES 42 EMIT 0 SPACES -2 SPACES CR 42 EMIT SPACE BL EMIT 42 EMIT", b"*   *\n*  *");
+        test_output(": indent 2 SPACES ; : line indent 42 EMIT CR ; line line", b"  *\n  *\n");
+        test_output("150 SPACES", &[b' '; 150]);
+    }
+
+    #[test]
+    fn test_colon_definition() {
+        test_16_bit_results(
+            ": foo + ;",
+            &[],
+        );
+        test_16_bit_results(
+            ": foo + ; 100 1 foo",
+            &[101],
+        )
+    }
+
+    #[test]
+    fn test_colon_definition_use() {
+        test_16_bit_results(
+            ": +3 3 + ; 2 +3 +3",
+            &[8],
+        )
+    }
+
+    #[test]
+    fn test_comparison() {
+        test_16_bit_results(
+            "0 1 < -1 0 < 0 0 < 2 1 <",
+            &[TRUE, TRUE, 0, 0],
+        );
+        test_16_bit_results(
+            "0 1 > -1 0 > 0 0 > 2 1 >",
+            &[0, 0, 0, TRUE],
+        );
+        test_16_bit_results(
+            "0 1 = -1 0 = 0 0 = 2 1 =",
+            &[0, 0, TRUE, 0],
+        );
+    }
+
+    #[test]
+    fn test_logic() {
+        test_16_bit_results(
+            "-1 0 AND 0 -1 AND 0 0 AND -1 -1 AND",
+            &[0, 0, 0, TRUE],
+        );
+        test_16_bit_results(
+            "-1 0 OR 0 -1 OR 0 0 OR -1 -1 OR",
+            &[TRUE, TRUE, 0, TRUE],
+        );
+        test_16_bit_results(
+            "-1 0 XOR 0 -1 XOR 0 0 XOR -1 -1 XOR",
+            &[TRUE, TRUE, 0, 0],
+        );
+        test_16_bit_results(
+            "-1 INVERT 0 INVERT",
+            &[0, TRUE],
+        );
+    }
+
+    #[test]
+    fn test_dup() {
+        test_16_bit_results(
+            "1 2 DUP",
+            &[1, 2, 2],
+        );
+
+        test_16_bit_results(
+            "3 4 2DUP",
+            &[3, 4, 3, 4],
+        );
+    }
+
+    #[test]
+    fn test_drop() {
+        test_16_bit_results(
+            "1 2 3 DROP",
+            &[1, 2],
+        );
+    }
+
+    #[test]
+    fn test_rot() {
+        test_16_bit_results(
+            "1 2 3 ROT",
+            &[2, 3, 1],
+        )
+    }
+
+    #[test]
+    #[cfg(not(feature = "cell32"))] // `!` stores a whole cell but compiled addresses are 16-bit
+    fn test_immediate() {
+        test_16_bit_results(
+            "
+            : C,, HERE @ C! HERE @ 1 + HERE ! ;
+            : ,, HERE @ ! HERE @ 2 + HERE ! ;
+            : iff    7 ( OpCode: GoToIfZ ) C,, HERE @ 0 ,, ; IMMEDIATE
+            : elsse  6 ( OpCode: GoTo    ) C,, HERE @ 0 ,, SWAP HERE @ SWAP ! ; IMMEDIATE
+            : endiff                                            HERE @ SWAP ! ; IMMEDIATE
+            : tst 0 < iff -1 elsse 1 endiff ;
+
+            0 tst -1 tst
+            ",
+            &[1, TRUE],
+        )
+    }
+
+    #[test]
+    fn test_immediate_after_compile_only() {
+        let mut machine = TestMachine::default();
+        machine.interpret_str(": e 7 ; COMPILE-ONLY IMMEDIATE : f e LITERAL ; f").unwrap();
+        machine.assert_data_stack_state(&[StackElement::Cell(7)]);
+
+        let e = machine.memory.lookup_article(b"e").unwrap().unwrap();
+        assert!(e.is_immediate() && e.is_compile_only());
+
+        machine.extensions.input = StaticStringInput::new("e");
+        assert!(matches!(machine.interpret_input(), Err(MachineError::CompileOnlyWord { .. })));
+    }
+
+    #[test]
+    fn test_see_immediate() {
+        let mut machine = TestMachine::default();
+        machine.interpret_str(": a 1 ; IMMEDIATE SEE a").unwrap();
+
+        let listing = String::from_utf8(machine.extensions.output.content.take()).unwrap();
+
+        assert!(listing.contains("start_immediate"), "{}", listing);
+        assert!(!listing.contains("noop"), "{}", listing);
+    }
+
+    #[test]
+    fn test_single_opcode_word_compiled_as_call() {
+        let mut machine = TestMachine::default();
+        machine.interpret_str(": foo DUP ; : bar foo ; : peek R@ ; : frame 1 >R peek R> DROP ; frame").unwrap();
+
+        let bar = machine.memory.lookup_article(b"bar").unwrap().unwrap().call_address();
+        assert_eq!(machine.memory.raw_memory.read_u8(bar), OpCode::CallRel.int_value());
+
+        // `R@` reads return address of `peek`, not the value pushed by `frame`
+        assert_ne!(machine.pop::<Cell>().unwrap(), 1);
+    }
+
+    #[test]
+    fn test_compiling_reference_has_no_side_effects() {
+        let mut machine = TestMachine::default();
+        machine.extensions.input = StaticStringInput::new("
+            : noisy 42 EMIT 1 ;
+            : user noisy noisy + ;
+        ");
+        machine.interpret_input().unwrap();
+
+        assert!(machine.extensions.output.content.borrow().is_empty());
+        assert_eq!(machine.memory.call_stack_depth(), 0);
+        assert_eq!(machine.memory.data_stack_depth(), 0);
+
+        machine.extensions.input = StaticStringInput::new("user");
+        machine.interpret_input().unwrap();
+
+        assert_eq!(machine.extensions.output.content.borrow().as_slice(), b"**");
+        machine.assert_data_stack_state(&[StackElement::Cell(2)]);
+    }
+
+    #[test]
+    fn test_compile_reference_to_variable() {
+        let mut machine = TestMachine::default();
+        machine.extensions.input = StaticStringInput::new("
+            VARIABLE x
+            : set-x x ! ;
+            : get-x x @ ;
+            42 set-x get-x
+        ");
+        machine.interpret_input().unwrap();
+
+        machine.assert_data_stack_state(&[StackElement::Cell(42)]);
+    }
+
+    #[test]
+    fn test_postpone_non_immediate_word() {
+        let mut machine = TestMachine::default();
+        machine.extensions.input = StaticStringInput::new("
+            : star 42 EMIT ;
+            : compile-star POSTPONE star ; IMMEDIATE
+            : stars compile-star compile-star ;
+        ");
+        machine.interpret_input().unwrap();
+
+        assert!(machine.extensions.output.content.borrow().is_empty());
+
+        machine.extensions.input = StaticStringInput::new("stars");
+        machine.interpret_input().unwrap();
+
+        assert_eq!(machine.extensions.output.content.borrow().as_slice(), b"**");
+    }
+
+    #[test]
+    fn test_instruction_counter() {
+        let mut machine = TestMachine::default();
+        machine.register_native_word("nested", |machine| machine.call_word("countdown", &[], 0).map(|_| ()));
+        machine.extensions.input = StaticStringInput::new("
+            : countdown 5 BEGIN DUP WHILE 1 - REPEAT DROP ;
+            : measure COUNTER countdown COUNTER ;
+            : measure-nested COUNTER nested COUNTER ;
+        ");
+        machine.interpret_input().unwrap();
+
+        // Article start, literal, 5 iterations of `DUP ?BRANCH 1 - BRANCH`, the last `DUP ?BRANCH`, `DROP`, return
+        let countdown_steps = 1 + 1 + 5 * 5 + 2 + 1 + 1;
+
+        let executed_before = machine.instructions_executed();
+        machine.extensions.input = StaticStringInput::new("countdown");
+        machine.interpret_input().unwrap();
+        assert_eq!(machine.instructions_executed() - executed_before, countdown_steps);
+
+        // The call and the second `COUNTER` are counted too, native word runs `countdown` including the article start
+        for (word, expected) in [("measure", countdown_steps + 1), ("measure-nested", countdown_steps + 2)] {
+            machine.extensions.input = StaticStringInput::new(word);
+            machine.interpret_input().unwrap();
+
+            let end = machine.memory.data_pop_double_cell().unwrap();
+            let start = machine.memory.data_pop_double_cell().unwrap();
+            let elapsed: DoubleCell = end - start;
+
+            assert_eq!(elapsed as u64, expected, "{}", word);
+        }
+    }
+
+    #[test]
+    fn test_data_stack_pointer_words() {
+        let mut machine = TestMachine::default();
+        machine.interpret_str(&format!(
+            "10 20 30 SP@ @ SP@ {cell} + @ : drop3 SP@ {three} + SP! ; 40 50 60 drop3",
+            cell = CELL_BYTES, three = CELL_BYTES * 3,
+        )).unwrap();
+
+        // `SP@` points to the top cell as it was before the pointer was pushed
+        machine.assert_data_stack_state(&[
+            StackElement::Cell(10), StackElement::Cell(20), StackElement::Cell(30),
+            StackElement::Cell(30), StackElement::Cell(30),
+        ]);
+
+        // The stack is empty, so its pointer can not move up
+        for input in ["0 SP!".to_string(), "SP@ 1 + SP!".to_string(), format!("SP@ {} + SP!", CELL_BYTES)] {
+            assert!(matches!(
+                machine.interpret_str(&input),
+                Err(MachineError::InvalidStackPointer { segment_name: "data stack", .. }),
+            ), "{}", input);
+
+            // The rejected pointer stays on the stack
+            assert_eq!(machine.memory.data_stack_depth(), 1, "{}", input);
+            machine.interpret_str("DROP").unwrap();
+        }
+    }
+
+    #[test]
+    fn test_call_stack_pointer_words() {
+        let mut machine = TestMachine::default();
+        machine.interpret_str(&format!(
+            ": leave-caller RP@ {cell} + RP! ; : outer leave-caller 99 ; : main outer 7 ; main",
+            cell = CELL_BYTES,
+        )).unwrap();
+
+        // Return address to `outer` is dropped, so `leave-caller` returns right to `main`
+        machine.assert_data_stack_state(&[StackElement::Cell(7)]);
+
+        machine.extensions.input = StaticStringInput::new("RP@");
+        assert!(matches!(machine.interpret_input(), Err(MachineError::CompileOnlyWord { .. })));
+
+        machine.extensions.input = StaticStringInput::new(": bad 0 RP! ; bad");
+        assert!(matches!(
+            machine.interpret_input(),
+            Err(MachineError::InvalidStackPointer { segment_name: "call stack", .. }),
+        ));
+        machine.assert_data_stack_state(&[StackElement::Cell(0)]);
+    }
+
+    #[test]
+    fn test_n_to_r() {
+        let mut machine = TestMachine::default();
+        machine.extensions.input = StaticStringInput::new("
+            : round-trip N>R 100 NR> ;
+            0 round-trip
+            11 1 round-trip
+            1 2 3 4 5 5 round-trip
+        ");
+        machine.interpret_input().unwrap();
+
+        machine.assert_data_stack_state(&[
+            StackElement::Cell(100), StackElement::Cell(0),
+            StackElement::Cell(100), StackElement::Cell(11), StackElement::Cell(1),
+            StackElement::Cell(100), StackElement::Cell(1), StackElement::Cell(2), StackElement::Cell(3),
+            StackElement::Cell(4), StackElement::Cell(5), StackElement::Cell(5),
+        ]);
+
+        machine.extensions.input = StaticStringInput::new("0 N>R");
+        assert!(matches!(machine.interpret_input(), Err(MachineError::CompileOnlyWord { .. })));
+    }
+
+    #[test]
+    fn test_n_to_r_overflow() {
+        let mut machine = TestMachine::with_memory(
+            TestMachineExtensions::default(),
+            MachineMemory::new(Mem::default(), MemoryLayoutConfig { max_call_stack_depth: 4, ..MemoryLayoutConfig::default() }),
+        );
+        machine.extensions.input = StaticStringInput::new(": save N>R NR> ; : outer save ; 1 2 3 3 outer");
+
+        // Return address to `outer` leaves room for only 3 cells
+        assert!(matches!(machine.interpret_input(), Err(MachineError::CallStackOverflow { .. })));
+        machine.assert_data_stack_state(&[
+            StackElement::Cell(1), StackElement::Cell(2), StackElement::Cell(3), StackElement::Cell(3),
+        ]);
+    }
+
+    #[test]
+    fn test_inlining() {
+        fn run_factorial(inline_threshold: Option<u16>) -> (TestMachine, u64) {
+            let mut machine = TestMachine::default();
+            machine.inline_threshold = inline_threshold;
+            machine.extensions.input = StaticStringInput::new("
+                : 1- 1 - ;
+                : FACTORIAL DUP 2 < IF DROP 1 EXIT THEN DUP 1- RECURSE * ;
+            ");
+            machine.interpret_input().unwrap();
+
+            let executed_before = machine.instructions_executed();
+            machine.extensions.input = StaticStringInput::new("8 FACTORIAL");
+            machine.interpret_input().unwrap();
+
+            let executed = machine.instructions_executed() - executed_before;
+
+            (machine, executed)
+        }
+
+        let (mut called, called_steps) = run_factorial(None);
+        let (mut inlined, inlined_steps) = run_factorial(Some(8));
+
+        called.assert_data_stack_state(&[StackElement::Cell(40320)]);
+        inlined.assert_data_stack_state(&[StackElement::Cell(40320)]);
+
+        // Each of 7 calls of `1-` saves a `Call` and a `Return`
+        assert_eq!(inlined_steps, called_steps - 7 * 2);
+    }
+
+    #[test]
+    fn test_inlining_keeps_call_stack_words() {
+        fn run(inline_threshold: Option<u16>) -> TestMachine {
+            let mut machine = TestMachine::default();
+            machine.inline_threshold = inline_threshold;
+            machine.interpret_str(&format!("
+                : under >R DUP R> ;
+                : leave-caller RP@ {} + RP! ;
+                : outer leave-caller 99 ;
+                : main 1 2 under outer 7 ;
+                main
+            ", CELL_BYTES)).unwrap();
+
+            machine
+        }
+
+        let expected = [StackElement::Cell(1), StackElement::Cell(1), StackElement::Cell(2), StackElement::Cell(7)];
+
+        run(None).assert_data_stack_state(&expected);
+
+        let mut inlined = run(Some(16));
+        inlined.assert_data_stack_state(&expected);
+
+        let outer = inlined.memory.lookup_article(b"outer").unwrap().unwrap().body_address();
+        assert_eq!(inlined.memory.raw_memory.read_u8(outer + 1), OpCode::CallRel.int_value());
+    }
+
+    #[test]
+    fn test_stack_high_water_marks() {
+        let mut machine = TestMachine::default();
+        machine.extensions.input = StaticStringInput::new("
+            : 1- 1 - ;
+            : FACTORIAL DUP 2 < IF DROP 1 EXIT THEN DUP 1- RECURSE * ;
+        ");
+        machine.interpret_input().unwrap();
+
+        machine.extensions.input = StaticStringInput::new("8 FACTORIAL");
+        machine.interpret_input().unwrap();
+
+        assert_eq!((machine.memory.max_data_depth(), machine.memory.max_call_depth()), (0, 0));
+
+        machine.memory.track_stack_usage = true;
+        machine.memory.reset_stats();
+        machine.extensions.input = StaticStringInput::new("DROP 1 FACTORIAL DROP");
+        machine.interpret_input().unwrap();
+
+        // Call stack used by a single, non-recursive, call of the word
+        let overhead = machine.memory.max_call_depth();
+
+        machine.memory.reset_stats();
+        machine.extensions.input = StaticStringInput::new("8 FACTORIAL");
+        machine.interpret_input().unwrap();
+
+        machine.assert_data_stack_state(&[StackElement::Cell(40320)]);
+        // 7 nested calls of `FACTORIAL`, `1-` called from the 6th one is not deeper than the 7th
+        assert_eq!(machine.memory.max_call_depth(), 7 + overhead);
+        // 8 numbers, 1 duplicated and compared to 2
+        assert_eq!(machine.memory.max_data_depth(), 10);
+
+        machine.memory.reset_stats();
+        machine.extensions.input = StaticStringInput::new("1 2 3 4 + + + DROP");
+        machine.interpret_input().unwrap();
+
+        assert_eq!(machine.memory.max_data_depth(), 4);
+
+        let mut state = Vec::new();
+        machine.memory.print_memory_state(&mut state).unwrap();
+
+        assert!(from_utf8(&state).unwrap().contains("Data stack (depth: 0, max: 4):"), "{}", from_utf8(&state).unwrap());
+    }
+
+    #[test]
+    fn test_inlining_skips_large_and_branching_words() {
+        let mut machine = TestMachine::default();
+        machine.inline_threshold = Some(4);
+        machine.extensions.input = StaticStringInput::new("
+            : long 1 2 + 3 + ;
+            : branchy IF 1 THEN ;
+            : small DUP + ;
+            : user long branchy small ;
+        ");
+        machine.interpret_input().unwrap();
+
+        let user = machine.memory.lookup_article(b"user").unwrap().unwrap();
+        let body_address = user.body_address();
+        let long_address = machine.memory.lookup_article(b"long").unwrap().unwrap().call_address();
+        let branchy_address = machine.memory.lookup_article(b"branchy").unwrap().unwrap().call_address();
+        let mem = &machine.memory.raw_memory;
+
+        assert_eq!(mem.read_u8(body_address + 1), OpCode::CallRel.int_value());
+        assert_eq!(mem.read_u16(body_address + 2), long_address.wrapping_sub(body_address + 4));
+        assert_eq!(mem.read_u8(body_address + 4), OpCode::CallRel.int_value());
+        assert_eq!(mem.read_u16(body_address + 5), branchy_address.wrapping_sub(body_address + 7));
+        assert_eq!(mem.read_u8(body_address + 7), OpCode::Dup16.int_value());
+        assert_eq!(mem.read_u8(body_address + 8), OpCode::Add16.int_value());
+        assert_eq!(mem.read_u8(body_address + 9), OpCode::Return.int_value());
+
+        // `branchy` takes 6 pushed by `long` and pushes 1 that is doubled by inlined `small`
+        machine.extensions.input = StaticStringInput::new("user");
+        machine.interpret_input().unwrap();
+        machine.assert_data_stack_state(&[StackElement::Cell(2)]);
+    }
+
+    #[test]
+    fn test_compact_literals() {
+        let mut machine = TestMachine::default();
+        machine.extensions.input = StaticStringInput::new(": lits 0 1 -1 200 1000 [ 5 ] LITERAL [ -1 ] LITERAL ;");
+        machine.interpret_input().unwrap();
+
+        let body_address = machine.memory.lookup_article(b"lits").unwrap().unwrap().body_address();
+
+        // start_article, push0, push1, push_true, push8, push16, push8, push_true, ret
+        let expected_size = 1 + 1 + 1 + 1 + 2 + (1 + CELL_BYTES) + 2 + 1 + 1;
+        assert_eq!(machine.memory.get_dict_ptr() - body_address, expected_size);
+
+        machine.extensions.input = StaticStringInput::new("lits");
+        machine.interpret_input().unwrap();
+
+        machine.assert_data_stack_state(&[
+            StackElement::Cell(0),
+            StackElement::Cell(1),
+            StackElement::Cell(TRUE),
+            StackElement::Cell(200),
+            StackElement::Cell(1000),
+            StackElement::Cell(5),
+            StackElement::Cell(TRUE),
+        ]);
+    }
+
+    #[test]
+    fn test_double_literals() {
+        let mut machine = TestMachine::default();
+        machine.extensions.input = StaticStringInput::new(": k [ 100000. ] 2LITERAL ; : m -5. ; k m $10.");
+        machine.interpret_input().unwrap();
+
+        machine.assert_data_stack_state(&[
+            StackElement::DoubleCell(100000),
+            StackElement::DoubleCell(-5i8 as DoubleCell),
+            StackElement::DoubleCell(16),
+        ]);
+
+        for (name, text) in [(&b"k"[..], "(100000, 100000)"), (b"m", ", -5)")] {
+            let article = machine.memory.lookup_article(name).unwrap().unwrap();
+            let mut listing = Vec::new();
+            OpCode::format_at(&mut listing, &machine, article.call_address()).unwrap();
+            let listing = String::from_utf8(listing).unwrap();
+
+            assert!(listing.contains("push32 "), "{}", listing);
+            assert!(listing.contains(text), "{}", listing);
+        }
+
+        // The operand is not entirely in the dictionary
+        let address = machine.memory.get_dict_ptr();
+        machine.memory.dict_write_opcode(OpCode::Literal32).unwrap();
+        machine.memory.dict_write_cell(1).unwrap();
+
+        assert!(matches!(
+            OpCode::Literal32.execute(&mut machine, address),
+            Err(MachineError::MemoryAccessError(MemoryAccessError { kind: AccessKind::Read, .. })),
+        ));
+        machine.assert_data_stack_state(&[]);
+
+        machine.extensions.input = StaticStringInput::new("2LITERAL");
+        assert!(matches!(machine.interpret_input(), Err(MachineError::CompileOnlyWord { .. })));
+    }
+
+    #[test]
+    fn test_compact_literals_reduce_dictionary_size() {
+        let mut machine = TestMachine::default();
+        machine.extensions.input = StaticStringInput::new("
+            : 1- 1 - ;
+            : FACTORIAL DUP 2 < IF DROP 1 EXIT THEN DUP 1- RECURSE * ;
+        ");
+        machine.interpret_input().unwrap();
+
+        // Headers take 5 and 12 bytes, bodies take 4 and 20 bytes with literals `1`, `2` and `1` taking 1, 2 and 1
+        // bytes instead of `1 + CELL_BYTES` bytes each
+        assert_eq!(machine.memory.dictionary_size(), 5 + 4 + 12 + 20);
+
+        machine.extensions.input = StaticStringInput::new("8 FACTORIAL");
+        machine.interpret_input().unwrap();
+        machine.assert_data_stack_state(&[StackElement::Cell(40320)]);
+    }
+
+    #[test]
+    fn test_relocated_word() {
+        let mut machine = TestMachine::default();
+        machine.extensions.input = StaticStringInput::new("
+            : sum-to ( n -- sum ) 0 SWAP BEGIN DUP 0 > WHILE SWAP OVER + SWAP 1 - REPEAT DROP ;
+            : sign ( n -- n ) DUP 0 < IF DROP -1 ELSE 0 > IF 1 ELSE 0 THEN THEN ;
+        ");
+        machine.interpret_input().unwrap();
+
+        // Copy bodies of both words to the end of dictionary, shifted by a few bytes
+        let sum_to_address = machine.memory.lookup_article(b"sum-to").unwrap().unwrap().body_address();
+        let sign_address = machine.memory.lookup_article(b"sign").unwrap().unwrap().body_address();
+        let dict_end = machine.memory.get_dict_ptr();
+        let code = machine.memory.raw_memory.slice(sum_to_address as usize..dict_end as usize).into_owned();
+
+        for _ in 0..3 {
+            machine.memory.dict_write_u8(OpCode::Noop.int_value()).unwrap();
+        }
+
+        let new_sum_to_address = machine.memory.get_dict_ptr();
+        let new_sign_address = new_sum_to_address + (sign_address - sum_to_address);
+
+        for byte in code {
+            machine.memory.dict_write_u8(byte).unwrap();
+        }
+
+        machine.memory.data_push_cell(10).unwrap();
+        machine.run_until_exit(new_sum_to_address).unwrap();
+        machine.assert_data_stack_state(&[StackElement::Cell(55)]);
+
+        for (value, sign) in [(5, 1), (0, 0), (TRUE, TRUE)] {
+            machine.memory.data_push_cell(value).unwrap();
+            machine.run_until_exit(new_sign_address).unwrap();
+            machine.assert_data_stack_state(&[StackElement::Cell(sign)]);
+        }
+    }
+
+    #[test]
+    fn test_relative_branch_disassembly() {
+        let mut machine = TestMachine::default();
+        machine.extensions.input = StaticStringInput::new(": loop BEGIN DUP WHILE 1 - REPEAT ;");
+        machine.interpret_input().unwrap();
+
+        let mut buf = Vec::new();
+        machine.print_disassembly(&mut buf).unwrap();
+        let disassembly = from_utf8(&buf).unwrap();
+        let body_address = machine.memory.lookup_article(b"loop").unwrap().unwrap().body_address();
+
+        assert!(disassembly.contains(&format!("rjumpz +5 ({:04X})", body_address + 1 + 1 + 3 + 1 + 1 + 3)), "{}", disassembly);
+        assert!(disassembly.contains(&format!("rjump -9 ({:04X})", body_address + 1)), "{}", disassembly);
+    }
+
+    #[test]
+    fn test_conditions() {
+        test_16_bit_results(
+            "
+            : myabs 1 SWAP 0 < IF DROP -1 THEN ;
+
+            0 myabs -1 myabs
+            ",
+            &[1, TRUE],
+        );
+    }
+
+    #[test]
+    fn test_conditions_2() {
+        test_16_bit_results(
+            "
+            : myabs 0 < IF -1 ELSE 1 THEN ;
+
+            0 myabs -1 myabs
+            ",
+            &[1, TRUE],
+        );
+    }
+
+    #[test]
+    fn test_while_loop() {
+        test_16_bit_results(
+            "
+            : 1- 1 - ;
+            : FACTORIAL ( +n1 -- +n2 )
+               DUP 2 < IF DROP 1 EXIT THEN
+               DUP
+               BEGIN DUP 2 > WHILE
+               1- SWAP OVER * SWAP
+               REPEAT DROP
+            ;
+            8 FACTORIAL
+            ",
+            &[40320],
+        );
+    }
+
+    #[test]
+    fn test_postpone() {
+        test_16_bit_results(
+            "
+            : endif POSTPONE THEN ; IMMEDIATE
+            : myabs 1 SWAP 0 < IF DROP -1 endif ;
+
+            0 myabs -1 myabs
+            ",
+            &[1, TRUE],
+        )
+    }
+
+    #[test]
+    fn test_state_variable() {
+        let mut machine = TestMachine::default();
+        machine.extensions.input = StaticStringInput::new("
+            : sum [ 1 STATE ! 2 3 + ;
+            : interpret 0 STATE ! ; IMMEDIATE
+            : one 1 interpret 7 1 STATE ! ;
+            sum one
+        ");
+        machine.interpret_input().unwrap();
+
+        // 7 is pushed while `one` is being defined
+        machine.assert_data_stack_state(&[StackElement::Cell(7), StackElement::Cell(5), StackElement::Cell(1)]);
+
+        machine.extensions.input = StaticStringInput::new("1 STATE !");
+        machine.interpret_input().unwrap();
+        assert_eq!(machine.memory.get_state(), MachineState::Compiler);
+
+        machine.reset();
+        assert_eq!(machine.memory.get_state(), MachineState::Interpreter);
+    }
+
+    #[test]
+    fn test_postpone_builtin_words() {
+        test_16_bit_results(
+            "
+            : ENDIF POSTPONE THEN ; IMMEDIATE
+            : UNLESS POSTPONE INVERT POSTPONE IF ; IMMEDIATE
+            : choose UNLESS 1 ELSE 2 ENDIF ;
+
+            -1 choose 0 choose
+            ",
+            &[2, 1],
+        )
+    }
+
+    #[test]
+    fn test_postpone_compiles_non_immediate_builtin() {
+        let mut machine = TestMachine::default();
+        machine.extensions.input = StaticStringInput::new(": compile-dup POSTPONE DUP ;");
+        machine.interpret_input().unwrap();
+
+        // Compilation of `DUP` is appended even if the postponing word runs in interpreter state
+        let start = machine.memory.get_dict_ptr();
+        machine.extensions.input = StaticStringInput::new("7 compile-dup");
+        machine.interpret_input().unwrap();
+
+        machine.assert_data_stack_state(&[StackElement::Cell(7)]);
+        assert_eq!(machine.memory.get_dict_ptr(), start + 1);
+        assert_eq!(machine.memory.raw_memory.read_u8(start), OpCode::Dup16.int_value());
+    }
+
+    #[test]
+    fn test_recurse() {
+        test_16_bit_results(
+            "
+            : 1- 1 - ;
+            : FACTORIAL ( +n1 -- +n2)
+               DUP 2 < IF DROP 1 EXIT THEN
+               DUP 1- RECURSE *
+            ;
+            8 FACTORIAL
+            ",
+            &[40320],
+        )
+    }
+
+    #[test]
+    fn test_print_string() {
+        test_output(
+            "
+            : say-bye .\" Goodbye world\" ;
+            .\" Hello world\" 10 EMIT
+            say-bye
+            ",
+            b"Hello world\nGoodbye world",
+        )
+    }
+
+    #[test]
+    fn test_pictured_number_output() {
+        test_output(
+            "
+            666 S>D <# # # # # #>
+            ",
+            b"",
+        );
+        test_output(
+            "
+            666 S>D <# # # # # #>
+            TYPE
+            ",
+            b"0666",
+        );
+        test_output(
             "
+            1638 16 BASE ! S>D <# # # # # #>
+            TYPE
+            ",
+            b"0666",
+        );
+    }
+
+    #[test]
+    fn test_type_wraps_around() {
+        let mut machine = TestMachine::default();
+        machine.memory.raw_memory.write_slice(0xFFFE, b"abcd");
+
+        machine.interpret_str("65534 4 TYPE").unwrap();
+        assert_eq!(machine.extensions.output.content.take(), b"abcd");
+    }
+
+    #[test]
+    fn test_mode_switch_and_literals() {
+        test_16_bit_results(
+            ": foo [ 1 2 + ] LITERAL + ;",
+            &[],
+        );
+        test_16_bit_results(
+            ": foo [ 1 2 + ] LITERAL + ; 3 foo",
+            &[6],
+        );
+    }
+
+    #[test]
+    fn test_step_limit_stops_infinite_loop() {
+        let mut machine = TestMachine::default();
+        machine.extensions.input = StaticStringInput::new(": forever BEGIN -1 WHILE REPEAT ;");
+        machine.interpret_input().unwrap();
+
+        let body_address = machine.memory.lookup_article(b"forever").unwrap().unwrap().body_address();
+
+        assert!(matches!(
+            machine.run_with_limit(body_address, 10_000),
+            Err(MachineError::StepLimitExceeded { executed: 10_000 })
+        ));
+    }
+
+    #[test]
+    fn test_instruction_budget_limits_interpret_input() {
+        let mut machine = TestMachine::default();
+        machine.instruction_budget = Some(10_000);
+        machine.extensions.input = StaticStringInput::new(": forever BEGIN -1 WHILE REPEAT ; 1 forever 2");
+
+        assert!(matches!(
+            machine.interpret_input(),
+            Err(MachineError::StepLimitExceeded { .. })
+        ));
+        machine.assert_data_stack_state(&[StackElement::Cell(1)]);
+    }
+
+    #[test]
+    fn test_step_limit_does_not_affect_terminating_program() {
+        let mut machine = TestMachine::default();
+        machine.instruction_budget = Some(10_000);
+        machine.extensions.input = StaticStringInput::new("
             : 1- 1 - ;
-            : FACTORIAL ( +n1 -- +n2 )
-               DUP 2 < IF DROP 1 EXIT THEN
-               DUP
-               BEGIN DUP 2 > WHILE
-               1- SWAP OVER * SWAP
-               REPEAT DROP
-            ;
+            : FACTORIAL DUP 2 < IF DROP 1 EXIT THEN DUP 1- RECURSE * ;
             8 FACTORIAL
-            ",
-            &[40320],
+        ");
+
+        machine.interpret_input().unwrap();
+        machine.assert_data_stack_state(&[StackElement::Cell(40320)]);
+
+        let body_address = machine.memory.lookup_article(b"FACTORIAL").unwrap().unwrap().body_address();
+        machine.memory.data_push_cell(5).unwrap();
+        machine.run_with_limit(body_address, 10_000).unwrap();
+        machine.assert_data_stack_state(&[StackElement::Cell(120)]);
+    }
+
+    #[test]
+    fn test_interrupt_flag_stops_infinite_loop() {
+        let mut machine = TestMachine::default();
+        machine.extensions.input = StaticStringInput::new(": forever BEGIN -1 WHILE REPEAT ;");
+        machine.interpret_input().unwrap();
+
+        let body_address = machine.memory.lookup_article(b"forever").unwrap().unwrap().body_address();
+
+        let flag = Arc::new(AtomicBool::new(false));
+        machine.set_interrupt_flag(flag.clone());
+
+        let setter = std::thread::spawn(move || {
+            std::thread::sleep(std::time::Duration::from_millis(50));
+            flag.store(true, Ordering::Relaxed);
+        });
+
+        let started = std::time::Instant::now();
+        let result = machine.run_until_exit(body_address);
+        setter.join().unwrap();
+
+        assert!(matches!(result, Err(MachineError::Interrupted)));
+        assert!(started.elapsed() < std::time::Duration::from_secs(5));
+
+        // The flag is cleared so the machine keeps working afterwards
+        machine.reset();
+        machine.extensions.input = StaticStringInput::new("1 2 +");
+        machine.interpret_input().unwrap();
+        machine.assert_data_stack_state(&[StackElement::Cell(3)]);
+    }
+
+    #[test]
+    fn test_interrupt_flag_cleared_before_input() {
+        let mut machine = TestMachine::default();
+        let flag = Arc::new(AtomicBool::new(true));
+        machine.set_interrupt_flag(flag.clone());
+
+        machine.extensions.input = StaticStringInput::new(": count 0 BEGIN DUP 3000 < WHILE 1 + REPEAT ; count");
+        machine.interpret_input().unwrap();
+        machine.assert_data_stack_state(&[StackElement::Cell(3000)]);
+        assert!(!flag.load(Ordering::Relaxed));
+    }
+
+    #[test]
+    fn test_stack_underflow_report() {
+        let r = Machine::run_with_test_input("1 ROT");
+
+        let err = r.result.unwrap_err();
+        assert!(matches!(err, MachineError::DataStackUnderflow { requested: 3, .. }));
+
+        let mut buf = Vec::new();
+        err.pretty_print(&mut buf, &r.machine).unwrap();
+        assert_eq!(
+            from_utf8(buf.as_slice()).unwrap(),
+            "Data stack underflow in ROT ( x1 x2 x3 -- x2 x3 x1 ): tried to take 3 cell(s), stack depth is 1"
+        );
+    }
+
+    #[test]
+    fn test_call_stack_underflow_report() {
+        let r = Machine::run_with_test_input(": take-all R> R> ; take-all");
+
+        let err = r.result.unwrap_err();
+        assert!(matches!(err, MachineError::CallStackUnderflow { requested: 1, .. }));
+
+        let mut buf = Vec::new();
+        err.pretty_print(&mut buf, &r.machine).unwrap();
+        assert!(
+            from_utf8(buf.as_slice()).unwrap().starts_with(
+                "Call stack underflow in R> ( -- x ) ( R: x -- ): tried to take 1 cell(s)"
+            ),
+            "{}", from_utf8(buf.as_slice()).unwrap(),
+        );
+    }
+
+    #[test]
+    fn test_stack_overflow_report() {
+        let mut machine = TestMachine::default();
+        let guard_size = machine.memory.layout_config().guard_size;
+        machine.memory.set_dict_ptr(machine.memory.data_stack_ptr - 4 * CELL_BYTES - guard_size);
+
+        machine.extensions.input = StaticStringInput::new("1 2 3 4 DUP");
+        let err = machine.interpret_input().unwrap_err();
+        assert!(matches!(err, MachineError::DataStackOverflow { requested: 1, .. }));
+
+        let mut buf = Vec::new();
+        err.pretty_print(&mut buf, &machine).unwrap();
+        assert!(
+            from_utf8(buf.as_slice()).unwrap().starts_with(
+                "Data stack overflow in DUP ( x -- x x ): tried to add 1 cell(s), stack depth is 4"
+            ),
+            "{}", from_utf8(buf.as_slice()).unwrap(),
+        );
+    }
+
+    #[test]
+    fn test_illegal_opcode_report() {
+        let mut machine = TestMachine::default();
+        machine.extensions.input = StaticStringInput::new(": broken 1 2 + ;");
+        machine.interpret_input().unwrap();
+
+        // Replace `+` (after `start_article`, `push1` and `push8`) with an unknown op-code
+        let body_address = machine.memory.lookup_article(b"broken").unwrap().unwrap().body_address();
+        let add_address = body_address + 1 + 1 + 2;
+        assert_eq!(machine.memory.raw_memory.read_u8(add_address), OpCode::Add16.int_value());
+        machine.memory.raw_memory.write_u8(add_address, 0xff);
+
+        machine.extensions.input = StaticStringInput::new("broken");
+        let err = machine.interpret_input().unwrap_err();
+        assert!(matches!(err, MachineError::IllegalOpCodeError { op_code: 0xff, .. }));
+
+        let mut buf = Vec::new();
+        err.pretty_print(&mut buf, &machine).unwrap();
+        let report = from_utf8(buf.as_slice()).unwrap();
+
+        assert!(report.contains("In article broken:"), "{}", report);
+        assert!(report.contains("push8 02"), "{}", report);
+        assert!(report.contains(&format!("=> {:04X}: (illegal op-code = 255)", add_address)), "{}", report);
+        assert!(report.contains("ret"), "{}", report);
+    }
+
+    #[test]
+    fn test_jump_to_data_stack() {
+        let mut machine = TestMachine::default();
+        let start_address = machine.memory.get_dict_ptr();
+        let data_stack_address = machine.memory.data_stack_ptr - 2;
+
+        machine.memory.dict_write_opcode(OpCode::GoTo).unwrap();
+        machine.memory.dict_write_u16(data_stack_address).unwrap();
+
+        assert!(matches!(
+            machine.run_until_exit(start_address),
+            Err(MachineError::InvalidJumpTarget { from, to }) if from == start_address && to == data_stack_address
+        ));
+    }
+
+    #[test]
+    fn test_call_outside_of_dictionary() {
+        let mut machine = TestMachine::default();
+        let start_address = machine.memory.get_dict_ptr();
+
+        machine.memory.dict_write_opcode(OpCode::Call).unwrap();
+        machine.memory.dict_write_u16(0x8000).unwrap();
+
+        assert!(matches!(
+            machine.run_until_exit(start_address),
+            Err(MachineError::InvalidJumpTarget { to: 0x8000, .. })
+        ));
+        assert_eq!(machine.memory.call_stack_depth(), 0);
+    }
+
+    #[test]
+    fn test_extra_executable_segment() {
+        let mut machine = TestMachine::default();
+        let start_address = machine.memory.get_dict_ptr();
+
+        machine.memory.dict_write_opcode(OpCode::GoTo).unwrap();
+        machine.memory.dict_write_u16(0x8000).unwrap();
+
+        machine.memory.raw_memory.write_u8(0x8000, OpCode::Return.int_value());
+        machine.memory.extra_executable_segment = Some(0x8000..=0x80FF);
+
+        machine.run_until_exit(start_address).unwrap();
+    }
+
+    #[test]
+    fn test_out_of_data_space() {
+        let mut machine = TestMachine::default();
+
+        let err = loop {
+            machine.extensions.input = StaticStringInput::new(
+                ": big 1 2 3 4 5 6 7 8 9 10 11 12 13 14 15 16 17 18 19 20 21 22 23 24 25 26 27 28 29 30 ;"
+            );
+
+            if let Err(err) = machine.interpret_input() {
+                break err;
+            }
+        };
+
+        assert!(matches!(err, MachineError::OutOfDataSpace { .. }), "{:?}", err);
+
+        let mut buf = Vec::new();
+        err.pretty_print(&mut buf, &machine).unwrap();
+        let report = from_utf8(buf.as_slice()).unwrap();
+        assert!(report.starts_with("Out of data space: needed "), "{}", report);
+        assert!(report.contains("(definition of big takes "), "{}", report);
+
+        machine.reset();
+        machine.extensions.input = StaticStringInput::new("1 2 +");
+        machine.interpret_input().unwrap();
+        machine.assert_data_stack_state(&[StackElement::Cell(3)]);
+    }
+
+    fn long_word_input(prefix: &str, word_length: usize, suffix: &str) -> String {
+        format!("{}{}{}", prefix, "a".repeat(word_length), suffix)
+    }
+
+    #[test]
+    fn test_too_long_word_error() {
+        let mut machine = TestMachine::default();
+
+        let err = machine.interpret_str(&long_word_input("1 ", 300, " 2")).unwrap_err();
+        assert!(matches!(&err, MachineError::InputError(InputError::WordTooLong { position: 2, .. })));
+
+        let mut buf = Vec::new();
+        err.pretty_print(&mut buf, &machine).unwrap();
+        assert_eq!(
+            from_utf8(buf.as_slice()).unwrap(),
+            format!("Word at input offset 2 is too long: \"{}...\"", "a".repeat(32))
+        );
+    }
+
+    #[test]
+    fn test_too_long_word_truncate() {
+        let mut machine = TestMachine::default();
+        machine.long_word_policy = LongWordPolicy::Truncate;
+
+        machine.interpret_str(&long_word_input(": ", 300, " 42 ;")).unwrap();
+        machine.interpret_str(&long_word_input("", 255, "")).unwrap();
+        machine.assert_data_stack_state(&[StackElement::Cell(42)]);
+
+        let output = machine.extensions.output.content.borrow();
+        assert!(from_utf8(output.as_slice()).unwrap().starts_with("Warning: word at input offset 2 "));
+    }
+
+    #[test]
+    fn test_restore_memory_dump() {
+        let mut machine = TestMachine::default();
+        machine.extensions.input = StaticStringInput::new(": sq DUP * ; : cube DUP sq * ;");
+        machine.interpret_input().unwrap();
+
+        let mut image = Vec::new();
+        machine.memory.raw_memory.dump_to(&mut image).unwrap();
+
+        let mut raw_memory = Mem::default();
+        raw_memory.load_from(&mut image.as_slice()).unwrap();
+        let memory = MachineMemory::attach_memory(raw_memory, MemoryLayoutConfig::default(), machine.memory.last_article_ptr);
+        let mut restored = TestMachine::with_memory(TestMachineExtensions::default(), memory);
+
+        assert!(restored.memory.lookup_article(b"sq").unwrap().is_some());
+        assert!(restored.memory.lookup_article(b"cube").unwrap().is_some());
+        assert_eq!(restored.memory.get_dict_ptr(), machine.memory.get_dict_ptr());
+
+        restored.extensions.input = StaticStringInput::new("3 cube");
+        restored.interpret_input().unwrap();
+        restored.assert_data_stack_state(&[StackElement::Cell(27)]);
+    }
+
+    #[test]
+    fn test_data_stack_overflow_keeps_dictionary_intact() {
+        let mut machine = TestMachine::default();
+        let guard_size = machine.memory.layout_config().guard_size;
+
+        machine.memory.set_dict_ptr(machine.memory.data_stack_ptr - 64 - guard_size);
+        let dict_end = machine.memory.get_dict_ptr();
+        for i in 0..guard_size {
+            machine.memory.raw_memory.write_u8(dict_end + i, 0xAA);
+        }
+
+        let err = loop {
+            machine.extensions.input = StaticStringInput::new("-1");
+
+            if let Err(err) = machine.interpret_input() {
+                break err;
+            }
+        };
+
+        assert!(matches!(err, MachineError::DataStackOverflow { requested: 1, .. }));
+        assert_eq!(machine.memory.data_stack_depth(), 64 / CELL_BYTES);
+        for i in 0..guard_size {
+            assert_eq!(machine.memory.raw_memory.read_u8(dict_end + i), 0xAA);
+        }
+
+        let mut buf = Vec::new();
+        err.pretty_print(&mut buf, &machine).unwrap();
+        assert!(from_utf8(buf.as_slice()).unwrap().ends_with("data stack has reached the guard region above dictionary"));
+    }
+
+    #[test]
+    fn test_zero_guard_size() {
+        let config = MemoryLayoutConfig { guard_size: 0, ..MemoryLayoutConfig::default() };
+        let mut machine = TestMachine::with_memory(
+            TestMachineExtensions::default(),
+            MachineMemory::new(Mem::default(), config),
+        );
+
+        machine.memory.set_dict_ptr(machine.memory.data_stack_ptr - 2 * CELL_BYTES);
+        machine.memory.data_push_cell(1).unwrap();
+        machine.memory.data_push_cell(2).unwrap();
+        assert!(matches!(machine.memory.data_push_cell(3), Err(MachineError::DataStackOverflow { .. })));
+
+        machine.memory.data_pop_cell().unwrap();
+        machine.memory.dict_write_cell(0xBEEF).unwrap();
+        assert_eq!(machine.memory.free_data_space(), 0);
+    }
+
+    #[test]
+    fn test_fork() {
+        let mut parent = TestMachine::default();
+        parent.extensions.input = StaticStringInput::new(": sq DUP * ; : cube DUP sq * ;");
+        parent.interpret_input().unwrap();
+        let parent_dict_ptr = parent.memory.get_dict_ptr();
+
+        let mut child = parent.fork(TestMachineExtensions::default());
+        child.extensions.input = StaticStringInput::new(": quad sq sq ; 3 quad 2 cube");
+        child.interpret_input().unwrap();
+        child.assert_data_stack_state(&[StackElement::Cell(81), StackElement::Cell(8)]);
+
+        assert_eq!(parent.memory.get_dict_ptr(), parent_dict_ptr);
+        assert!(parent.memory.lookup_article(b"quad").unwrap().is_none());
+        parent.assert_data_stack_state(&[]);
+
+        parent.extensions.input = StaticStringInput::new("2 sq");
+        parent.interpret_input().unwrap();
+        parent.assert_data_stack_state(&[StackElement::Cell(4)]);
+    }
+
+    #[test]
+    fn test_fork_does_not_copy_memory() {
+        let mut parent = TestMachine::default();
+        parent.extensions.input = StaticStringInput::new(": sq DUP * ;");
+        parent.interpret_input().unwrap();
+
+        for _ in 0..100 {
+            let mut child = parent.fork(TestMachineExtensions::default());
+            assert_eq!(child.memory.raw_memory.shared_bytes(&parent.memory.raw_memory), MEM_SIZE);
+
+            child.extensions.input = StaticStringInput::new(": quad sq sq ; 3 quad");
+            child.interpret_input().unwrap();
+
+            // Only pages containing dictionary end, built-in variables, word buffer and data stack get copied
+            assert!(child.memory.raw_memory.shared_bytes(&parent.memory.raw_memory) >= MEM_SIZE - 8 * PAGE_SIZE);
+        }
+    }
+
+    #[test]
+    fn test_variable() {
+        test_16_bit_results("VARIABLE x : inc-x x @ 1 + x ! ; 41 x ! inc-x x @", &[42]);
+    }
+
+    #[test]
+    fn test_write_protection() {
+        let mut machine = TestMachine::default();
+        machine.memory.write_protection = true;
+
+        machine.extensions.input = StaticStringInput::new("VARIABLE x 42 x ! x @");
+        machine.interpret_input().unwrap();
+        machine.assert_data_stack_state(&[StackElement::Cell(42)]);
+
+        let header = machine.memory.raw_memory.read_u16(0);
+        machine.extensions.input = StaticStringInput::new("5 0 !");
+        let err = machine.interpret_input().unwrap_err();
+        assert!(matches!(err, MachineError::WriteProtected { address: 0 }));
+        assert_eq!(machine.memory.raw_memory.read_u16(0), header);
+
+        let mut buf = Vec::new();
+        err.pretty_print(&mut buf, &machine).unwrap();
+        assert_eq!(from_utf8(buf.as_slice()).unwrap(), "Write to protected dictionary address 0000 (in article x)");
+    }
+
+    #[test]
+    fn test_write_protection_disabled() {
+        let mut machine = TestMachine::default();
+
+        machine.extensions.input = StaticStringInput::new("VARIABLE x 5 0 !");
+        machine.interpret_input().unwrap();
+
+        assert_eq!(machine.memory.raw_memory.read_u16(0), 5);
+    }
+
+    #[test]
+    fn test_aligned() {
+        test_16_bit_results("0 ALIGNED 1 ALIGNED", &[0, CELL_BYTES as Cell]);
+    }
+
+    #[test]
+    fn test_variable_data_is_aligned() {
+        let mut machine = TestMachine::default();
+
+        machine.extensions.input = StaticStringInput::new(": odd ; VARIABLE x : ab ; VARIABLE y x y");
+        machine.interpret_input().unwrap();
+
+        let y = machine.memory.data_pop_cell().unwrap() as Address;
+        let x = machine.memory.data_pop_cell().unwrap() as Address;
+        assert_ne!(x, y);
+        assert_eq!(x % CELL_BYTES, 0);
+        assert_eq!(y % CELL_BYTES, 0);
+    }
+
+    #[test]
+    fn test_comma_aligns() {
+        let mut machine = TestMachine::default();
+        let start = machine.memory.get_dict_ptr();
+
+        machine.extensions.input = StaticStringInput::new("7 C, 1234 ,");
+        machine.interpret_input().unwrap();
+
+        let end = machine.memory.get_dict_ptr();
+        assert_eq!(end, start + 2 * CELL_BYTES);
+        assert_eq!(machine.memory.raw_memory.read_u8(start), 7);
+        assert_eq!(machine.memory.raw_memory.read_cell(end - CELL_BYTES), 1234);
+    }
+
+    #[test]
+    fn test_step() {
+        let mut machine = TestMachine::default();
+        machine.extensions.input = StaticStringInput::new(": calc 1 2 + 3 * ;");
+        machine.interpret_input().unwrap();
+
+        let body_address = machine.memory.lookup_article(b"calc").unwrap().unwrap().body_address();
+        let mut expected_addresses = vec![body_address];
+        let mut address = body_address;
+
+        while address < machine.memory.get_dict_ptr() {
+            address = OpCode::format_at(&mut std::io::sink(), &machine, address).unwrap();
+            expected_addresses.push(address);
+        }
+
+        machine.prepare(body_address);
+
+        // Step through everything but multiplication and return
+        for i in 1..(expected_addresses.len() - 2) {
+            assert_eq!(machine.step().unwrap(), StepOutcome::Running(expected_addresses[i]));
+            assert_eq!(machine.program_counter(), Some(expected_addresses[i]));
+        }
+
+        assert_eq!(machine.memory.data_stack_depth(), 2);
+
+        machine.run_until_exit(machine.program_counter().unwrap()).unwrap();
+
+        machine.assert_data_stack_state(&[StackElement::Cell(9)]);
+        assert_eq!(machine.program_counter(), None);
+        assert_eq!(machine.step().unwrap(), StepOutcome::Exited);
+    }
+
+    #[test]
+    fn test_step_keeps_program_counter_at_failed_instruction() {
+        let mut machine = TestMachine::default();
+        machine.extensions.input = StaticStringInput::new(": broken DROP ;");
+        machine.interpret_input().unwrap();
+
+        let body_address = machine.memory.lookup_article(b"broken").unwrap().unwrap().body_address();
+        machine.prepare(body_address);
+
+        while machine.memory.raw_memory.read_u8(machine.program_counter().unwrap()) != OpCode::Drop16.int_value() {
+            machine.step().unwrap();
+        }
+
+        let drop_address = machine.program_counter();
+
+        assert!(matches!(machine.step(), Err(MachineError::DataStackUnderflow { requested: 1, .. })));
+        assert_eq!(machine.program_counter(), drop_address);
+    }
+
+    #[test]
+    fn test_breakpoint_in_loop() {
+        let mut machine = TestMachine::default();
+        machine.extensions.input = StaticStringInput::new(": count 0 BEGIN DUP 5 < WHILE 1 + REPEAT ;");
+        machine.interpret_input().unwrap();
+
+        let body_address = machine.memory.lookup_article(b"count").unwrap().unwrap().body_address();
+        let add_address = (body_address..machine.memory.get_dict_ptr())
+            .find(|&address| machine.memory.raw_memory.read_u8(address) == OpCode::Add16.int_value())
+            .unwrap();
+
+        machine.set_breakpoint(add_address);
+
+        assert_eq!(machine.run_until_breakpoint(body_address).unwrap(), Some(add_address));
+
+        for i in 0..2 {
+            // Counter and the literal `1` are on the stack right before the addition
+            assert_eq!(machine.memory.data_stack_depth(), 2);
+            assert_eq!(machine.memory.raw_memory.read_cell(machine.memory.data_stack_ptr + CELL_BYTES), i);
+
+            assert_eq!(machine.resume_until_breakpoint().unwrap(), Some(add_address));
+        }
+
+        machine.clear_breakpoint(add_address);
+
+        assert_eq!(machine.resume_until_breakpoint().unwrap(), None);
+        machine.assert_data_stack_state(&[StackElement::Cell(5)]);
+    }
+
+    #[test]
+    fn test_word_breakpoint() {
+        let mut machine = TestMachine::default();
+        machine.extensions.input = StaticStringInput::new(": inc 1 + ; : main 5 inc inc ;");
+        machine.interpret_input().unwrap();
+
+        let inc_address = machine.set_word_breakpoint(b"inc").unwrap();
+        let main_address = machine.memory.lookup_article(b"main").unwrap().unwrap().body_address();
+
+        assert_eq!(machine.run_until_breakpoint(main_address).unwrap(), Some(inc_address));
+        assert_eq!(machine.memory.call_stack_depth(), 1);
+        assert_eq!(machine.resume_until_breakpoint().unwrap(), Some(inc_address));
+        assert_eq!(machine.resume_until_breakpoint().unwrap(), None);
+        machine.assert_data_stack_state(&[StackElement::Cell(7)]);
+
+        assert!(matches!(machine.set_word_breakpoint(b"missing"), Err(MachineError::NoArticle)));
+    }
+
+    #[test]
+    fn test_break_word() {
+        let mut machine = TestMachine::default();
+        machine.extensions.input = StaticStringInput::new(": work 1 2 BREAK + 10 * ; : outer work 3 ; outer 4");
+
+        let err = machine.interpret_input().unwrap_err();
+        let work = instruction_addresses(&machine, b"work");
+
+        // Suspended right after `BREAK` with partial results on the stack
+        assert!(matches!(err, MachineError::Breakpoint { address } if address == work[4]), "{:?}", err);
+        assert_eq!(machine.program_counter(), Some(work[4]));
+        assert_eq!(machine.memory.call_stack_depth(), 1);
+        assert_eq!(machine.memory.data_stack_depth(), 2);
+        assert_eq!(machine.memory.raw_memory.read_cell(machine.memory.data_stack_ptr), 2);
+
+        machine.resume().unwrap();
+        assert_eq!(machine.program_counter(), None);
+        machine.interpret_input().unwrap();
+
+        machine.assert_data_stack_state(&[StackElement::Cell(30), StackElement::Cell(3), StackElement::Cell(4)]);
+
+        machine.extensions.input = StaticStringInput::new("BREAK");
+        assert!(matches!(machine.interpret_input(), Err(MachineError::CompileOnlyWord { .. })));
+    }
+
+    #[test]
+    fn test_break_on_word() {
+        let mut machine = TestMachine::default();
+        machine.extensions.input = StaticStringInput::new(": inc 1 + ; : main 5 inc inc ; BREAK-ON inc main inc");
+        let inc_address = |machine: &TestMachine| machine.memory.lookup_article(b"inc").unwrap().unwrap().call_address();
+
+        // Each call of `inc` made by `main` stops at the breakpoint
+        let mut result = machine.interpret_input();
+
+        for _ in 0..2 {
+            let err = result.unwrap_err();
+
+            assert!(matches!(err, MachineError::Breakpoint { address } if address == inc_address(&machine)), "{:?}", err);
+            assert_eq!(machine.memory.call_stack_depth(), 1);
+
+            result = machine.resume();
+        }
+
+        result.unwrap();
+
+        // Word called directly from the interpreter stops at the breakpoint too
+        assert!(matches!(machine.interpret_input(), Err(MachineError::Breakpoint { .. })));
+        assert_eq!(machine.memory.call_stack_depth(), 0);
+        machine.resume().unwrap();
+        machine.interpret_input().unwrap();
+
+        machine.assert_data_stack_state(&[StackElement::Cell(8)]);
+
+        machine.extensions.input = StaticStringInput::new("BREAK-ON missing");
+        assert!(matches!(machine.interpret_input(), Err(MachineError::IllegalWord(_))));
+    }
+
+    #[test]
+    fn test_run_until_breakpoint_stops_at_break() {
+        let mut machine = TestMachine::default();
+        machine.extensions.input = StaticStringInput::new(": work 1 BREAK 2 ;");
+        machine.interpret_input().unwrap();
+
+        let work = instruction_addresses(&machine, b"work");
+
+        assert_eq!(machine.run_until_breakpoint(work[0]).unwrap(), Some(work[3]));
+        assert_eq!(machine.resume_until_breakpoint().unwrap(), None);
+        machine.assert_data_stack_state(&[StackElement::Cell(1), StackElement::Cell(2)]);
+    }
+
+    #[test]
+    fn test_run_for_slices() {
+        fn make_machine() -> (TestMachine, Address) {
+            let mut machine = TestMachine::default();
+            machine.extensions.input = StaticStringInput::new("
+                : letters 0 BEGIN DUP 1000 < WHILE DUP 15 AND 65 + EMIT 1 + REPEAT ;
+            ");
+            machine.interpret_input().unwrap();
+
+            let body_address = machine.memory.lookup_article(b"letters").unwrap().unwrap().body_address();
+
+            (machine, body_address)
+        }
+
+        let (mut whole_machine, body_address) = make_machine();
+        whole_machine.run_until_exit(body_address).unwrap();
+
+        let (mut machine, body_address) = make_machine();
+        let mut address = body_address;
+        let mut slices = 0;
+
+        while let RunOutcome::Paused(next_address) = machine.run_for(address, 1000).unwrap() {
+            address = next_address;
+            slices += 1;
+        }
+
+        assert!(slices >= 10, "{}", slices);
+        assert_eq!(machine.program_counter(), None);
+        assert_eq!(machine.memory.call_stack_depth(), 0);
+        assert_eq!(*machine.extensions.output.content.borrow(), *whole_machine.extensions.output.content.borrow());
+        assert_eq!(&machine.extensions.output.content.borrow()[..18], b"ABCDEFGHIJKLMNOPAB");
+        assert_eq!(whole_machine.memory.data_stack_depth(), 1);
+        machine.assert_data_stack_state(&[StackElement::Cell(1000)]);
+    }
+
+    #[test]
+    fn test_run_for_pauses_inside_call() {
+        let mut machine = TestMachine::default();
+        machine.extensions.input = StaticStringInput::new(": inc 1 + ; : main 5 inc inc ;");
+        machine.interpret_input().unwrap();
+
+        let body_address = machine.memory.lookup_article(b"main").unwrap().unwrap().body_address();
+        let mut address = body_address;
+        let mut max_call_depth = 0;
+
+        // Pause after every instruction, including right after calls
+        while let RunOutcome::Paused(next_address) = machine.run_for(address, 1).unwrap() {
+            max_call_depth = max_call_depth.max(machine.memory.call_stack_depth());
+            address = next_address;
+        }
+
+        assert_eq!(max_call_depth, 1);
+        machine.assert_data_stack_state(&[StackElement::Cell(7)]);
+    }
+
+    #[test]
+    fn test_million_iterations() {
+        let mut machine = TestMachine::default();
+        machine.extensions.input = StaticStringInput::new("
+            : inner 0 BEGIN DUP 1000 < WHILE 1 + REPEAT DROP ;
+            : outer 0 BEGIN DUP 1000 < WHILE inner 1 + REPEAT ;
+        ");
+        machine.interpret_input().unwrap();
+
+        let executed_before = machine.instructions_executed();
+        machine.extensions.input = StaticStringInput::new("outer");
+        machine.interpret_input().unwrap();
+        let executed = machine.instructions_executed() - executed_before;
+
+        machine.assert_data_stack_state(&[StackElement::Cell(1000)]);
+        // Each iteration of the inner loop executes at least DUP, literal, <, branch, literal, + and jump
+        assert!(executed >= 7_000_000, "{}", executed);
+    }
+
+    #[test]
+    fn test_stack_heavy_loop() {
+        let mut machine = TestMachine::default();
+        machine.extensions.input = StaticStringInput::new("
+            : inner 0 BEGIN DUP 1000 < WHILE DUP DUP * OVER SWAP DROP DROP 1 + REPEAT DROP ;
+            : outer 0 BEGIN DUP 100 < WHILE inner 1 + REPEAT ;
+        ");
+        machine.interpret_input().unwrap();
+
+        let executed_before = machine.instructions_executed();
+        machine.extensions.input = StaticStringInput::new("outer");
+        machine.interpret_input().unwrap();
+        let executed = machine.instructions_executed() - executed_before;
+
+        machine.assert_data_stack_state(&[StackElement::Cell(100)]);
+        assert!(executed >= 1_300_000, "{}", executed);
+    }
+
+    #[test]
+    fn test_data_stack_limited_after_here_store() {
+        let mut machine = TestMachine::default();
+        let near_stack = machine.memory.data_stack_ptr - MemoryLayoutConfig::default().guard_size - CELL_BYTES;
+        machine.memory.data_push_cell(near_stack as Cell).unwrap();
+        machine.extensions.input = StaticStringInput::new("HERE ! 1 2");
+
+        assert!(matches!(machine.interpret_input(), Err(MachineError::DataStackOverflow { .. })));
+    }
+
+    #[cfg(feature = "std-fs")]
+    #[test]
+    fn test_interpret_file() {
+        let path = write_temp_file("interpret-file.fs", ": sq DUP * ;\n: cube DUP sq * ;\n3 cube\nfoo-bar 1 2\n: late 1 ;\n");
+        let mut machine = TestMachine::with_std_file_system();
+        machine.extensions.input = StaticStringInput::new("5 sq");
+
+        let err = machine.interpret_file(&path).unwrap_err();
+        assert!(
+            matches!(&err, MachineError::InFile { path: error_path, line: 4, column: 8, err }
+                if *error_path == path && matches!(**err, MachineError::IllegalWord(_))),
+            "{:?}", err,
         );
+
+        let mut buf = Vec::new();
+        err.pretty_print(&mut buf, &machine).unwrap();
+        assert_eq!(from_utf8(&buf).unwrap(), format!("{}:4:8: Illegal word: foo-bar", path));
+
+        assert!(machine.memory.lookup_article(b"cube").unwrap().is_some());
+        assert!(machine.memory.lookup_article(b"late").unwrap().is_none());
+
+        machine.interpret_input().unwrap();
+        machine.assert_data_stack_state(&[StackElement::Cell(27), StackElement::Cell(25)]);
     }
 
+    #[cfg(feature = "std-fs")]
     #[test]
-    fn test_postpone() {
-        test_16_bit_results(
-            "
-            : endif POSTPONE THEN ; IMMEDIATE
-            : myabs 1 SWAP 0 < IF DROP -1 endif ;
+    fn test_interpret_missing_file() {
+        let mut machine = TestMachine::with_std_file_system();
 
-            0 myabs -1 myabs
-            ",
-            &[1, 0xffff],
-        )
+        assert!(matches!(
+            machine.interpret_file("/nonexistent/file.fs"),
+            Err(MachineError::FileError { path, .. }) if path == "/nonexistent/file.fs"
+        ));
     }
 
     #[test]
-    fn test_recurse() {
-        test_16_bit_results(
-            "
-            : 1- 1 - ;
-            : FACTORIAL ( +n1 -- +n2)
-               DUP 2 < IF DROP 1 EXIT THEN
-               DUP 1- RECURSE *
-            ;
-            8 FACTORIAL
-            ",
-            &[40320],
-        )
+    fn test_borrowed_io() {
+        let mut input = StaticStringInput::new(": greet 104 EMIT 105 EMIT ; greet 2 3 + DUP 48 + EMIT");
+        let mut output = Vec::new();
+
+        let mut machine = Machine::new(BorrowedExtensions { input: &mut input, output: &mut output });
+        machine.interpret_input().unwrap();
+        assert_eq!(machine.memory.data_pop_cell().unwrap(), 5);
+        assert_eq!(machine.memory.data_stack_depth(), 0);
+        drop(machine);
+
+        // Both are available to host right away, nothing is shared or cloned
+        assert_eq!(from_utf8(&output).unwrap(), "hi5");
+        assert_eq!(input.read().unwrap(), None);
+
+        // The same buffer may be passed to another machine
+        let mut input = StaticStringInput::new("42 EMIT");
+        Machine::new(BorrowedExtensions { input: &mut input, output: &mut output }).interpret_input().unwrap();
+        assert_eq!(from_utf8(&output).unwrap(), "hi5*");
     }
 
     #[test]
-    fn test_print_string() {
-        test_output(
-            "
-            : say-bye .\" Goodbye world\" ;
-            .\" Hello world\" 10 EMIT
-            say-bye
-            ",
-            b"Hello world\nGoodbye world",
-        )
+    fn test_interactive_line_feedback() {
+        let mut extensions = ScriptedExtensions::new("1 2\n: sq DUP *\n;\n\n3 sq  \nfoo 5\n+\n");
+        extensions.input.set_prompt(None);
+
+        let mut machine = Machine::new(extensions);
+        machine.interactive = true;
+
+        let mut errors = Vec::new();
+
+        while let Err(err) = machine.interpret_input() {
+            errors.push(err);
+        }
+
+        assert!(matches!(errors.as_slice(), [MachineError::IllegalWord(_)]), "{:?}", errors);
+        assert_eq!(
+            from_utf8(&machine.extensions.output.content.borrow()).unwrap(),
+            " ok <2> compiled ok <2> ok <3> ok <2>",
+        );
+        assert_eq!(machine.memory.data_pop_cell().unwrap(), 11);
     }
 
     #[test]
-    fn test_pictured_number_output() {
-        test_output(
-            "
-            666 S>D <# # # # # #>
-            ",
-            b"",
+    fn test_prompt_context() {
+        use crate::input::PromptContext;
+
+        let contexts = Rc::new(RefCell::new(Vec::new()));
+        let mut extensions = ScriptedExtensions::new("1 2\n: sq ( n --\n n2 ) DUP\n .\" a\nb\" *\n;\n3 sq\n");
+        let provider_contexts = contexts.clone();
+        extensions.input.set_prompt_provider(Box::new(move |context| {
+            provider_contexts.borrow_mut().push(context);
+
+            None
+        }));
+
+        let mut machine = Machine::new(extensions);
+        machine.interpret_input().unwrap();
+
+        assert_eq!(contexts.borrow().as_slice(), [
+            PromptContext::Interpreter,
+            PromptContext::Interpreter,
+            PromptContext::Comment,
+            PromptContext::Compiler,
+            PromptContext::String,
+            PromptContext::Compiler,
+            PromptContext::Interpreter,
+            PromptContext::Interpreter,
+        ]);
+        assert_eq!(from_utf8(&machine.extensions.output.content.borrow()).unwrap(), "a\nb");
+        assert_eq!(machine.memory.data_pop_cell().unwrap(), 9);
+    }
+
+    #[test]
+    fn test_all_word_names() {
+        let mut machine = TestMachine::default();
+        machine.register_native_word("native", |_| Ok(()));
+        machine.extensions.input = StaticStringInput::new(": square DUP * ; : DUP OVER OVER DROP ; : square 1 ;");
+        machine.interpret_input().unwrap();
+
+        let names = machine.all_word_names();
+
+        assert_eq!(&names[..3], ["square", "DUP", "native"]);
+        assert_eq!(names.iter().filter(|name| *name == "DUP").count(), 1);
+        assert_eq!(names.iter().filter(|name| *name == "square").count(), 1);
+        assert!(names.iter().any(|name| name == "SWAP"));
+        assert_eq!(names.len(), builtin_word_table::<TestMachineExtensions>().len() + 2);
+
+        machine.extensions.input = StaticStringInput::new("WORDS");
+        machine.interpret_input().unwrap();
+
+        let listing = String::from_utf8(machine.extensions.output.content.take()).unwrap();
+        assert!(listing.starts_with("square DUP native : ; VARIABLE "), "{}", listing);
+        assert!(listing.ends_with(" .\"\n"), "{}", listing);
+    }
+
+    #[test]
+    fn test_words_listing() {
+        let mut machine = TestMachine::default();
+        machine.extensions.input = StaticStringInput::new(": first 1 ; : second 2 ; : third 3 ; WORDS");
+        machine.interpret_input().unwrap();
+
+        let listing = String::from_utf8(machine.extensions.output.content.take()).unwrap();
+        let names: Vec<&str> = listing.split_whitespace().collect();
+
+        assert_eq!(&names[..3], ["third", "second", "first"]);
+        assert!(names.contains(&"DUP"));
+        assert!(listing.lines().count() > 1);
+        assert!(listing.lines().all(|line| line.len() <= WORDS_LINE_WIDTH), "{}", listing);
+    }
+
+    #[test]
+    fn test_see() {
+        let mut machine = TestMachine::default();
+        machine.register_native_word("native", |_| Ok(()));
+        machine.extensions.input = StaticStringInput::new("
+            : greet 0 < IF S\" negative\" TYPE ELSE 42 THEN ;
+            : after 1 ;
+            SEE greet SEE DUP SEE native
+        ");
+        machine.interpret_input().unwrap();
+
+        let listing = String::from_utf8(machine.extensions.output.content.take()).unwrap();
+
+        assert!(listing.starts_with("---- Define article greet\n"), "{}", listing);
+        assert!(listing.contains("negative"), "{}", listing);
+        assert!(listing.contains("rjumpz"), "{}", listing);
+        assert!(!listing.contains("after"), "{}", listing);
+        assert!(listing.ends_with("DUP is a built-in word\nnative is a native word\n"), "{}", listing);
+
+        machine.extensions.input = StaticStringInput::new("SEE no-such-word");
+
+        assert!(matches!(machine.interpret_input(), Err(MachineError::IllegalWord(Some(_)))));
+    }
+
+    #[test]
+    fn test_see_variable_data() {
+        let mut machine = TestMachine::default();
+        machine.extensions.input = StaticStringInput::new("
+            VARIABLE buf 1 , 2 , 3 C,
+            SEE buf
+        ");
+        machine.interpret_input().unwrap();
+
+        let listing = String::from_utf8(machine.extensions.output.content.take()).unwrap();
+        let expected_bytes = [0, 1, 2].iter()
+            .flat_map(|value: &Cell| value.to_le_bytes())
+            .chain([3])
+            .map(|byte| format!(" {:02X}", byte))
+            .collect::<String>();
+
+        assert!(listing.contains(&format!(": db{}\n", expected_bytes)), "{}", listing);
+        assert!(!listing.contains("illegal"), "{}", listing);
+    }
+
+    /// Addresses of instructions in body of given article as found by `OpCode::format_at`.
+    fn instruction_addresses(machine: &TestMachine, name: &[u8]) -> Vec<Address> {
+        let article = machine.memory.lookup_article(name).unwrap().unwrap();
+        let end = machine.memory.article_end(&article);
+        let mut addresses = vec![article.body_address()];
+
+        while *addresses.last().unwrap() < end {
+            addresses.push(OpCode::format_at(&mut io::sink(), machine, *addresses.last().unwrap()).unwrap());
+        }
+
+        addresses
+    }
+
+    #[test]
+    fn test_disassemble_range() {
+        let mut machine = TestMachine::default();
+        machine.extensions.input = StaticStringInput::new(": calc 1 2 + 300 * ;");
+        machine.interpret_input().unwrap();
+
+        let addresses = instruction_addresses(&machine, b"calc");
+        let mut listing = Vec::new();
+
+        assert_eq!(machine.disassemble_range(addresses[1], addresses[3], &mut listing).unwrap(), addresses[3]);
+
+        let listing = String::from_utf8(listing).unwrap();
+        let lines: Vec<&str> = listing.lines().collect();
+
+        assert_eq!(lines.len(), 2, "{}", listing);
+        assert!(lines[0].starts_with(&format!("{:04X}: ", addresses[1])), "{}", listing);
+        assert!(lines[1].starts_with(&format!("{:04X}: ", addresses[2])), "{}", listing);
+    }
+
+    #[test]
+    fn test_disassemble_range_ending_mid_instruction() {
+        let mut machine = TestMachine::default();
+        machine.extensions.input = StaticStringInput::new(": calc 1 2 + 300 * ;");
+        machine.interpret_input().unwrap();
+
+        let addresses = instruction_addresses(&machine, b"calc");
+        let push = addresses.iter().position(|&address| {
+            machine.memory.raw_memory.read_u8(address) == OpCode::Literal16 as u8
+        }).unwrap();
+        let mut listing = Vec::new();
+
+        assert_eq!(
+            machine.disassemble_range(addresses[push - 1], addresses[push + 1] - 1, &mut listing).unwrap(),
+            addresses[push],
         );
-        test_output(
-            "
-            666 S>D <# # # # # #>
-            TYPE
-            ",
-            b"0666",
+        assert_eq!(String::from_utf8(listing).unwrap().lines().count(), 1);
+
+        // Operand of the call would be past the end of memory
+        machine.memory.raw_memory.write_u8(0xFFFE, OpCode::Call as u8);
+        let mut listing = Vec::new();
+
+        assert_eq!(machine.disassemble_range(0xFFFE, 0xFFFF, &mut listing).unwrap(), 0xFFFE);
+        assert!(listing.is_empty());
+    }
+
+    #[test]
+    fn test_disassemble_range_with_illegal_op_code() {
+        let mut machine = TestMachine::default();
+        let illegal = (0..=u8::MAX).find(|&op_code| OpCode::from_int(op_code).is_err()).unwrap();
+        let start = machine.memory.get_dict_ptr();
+
+        machine.memory.raw_memory.write_slice(start, &[illegal, OpCode::Dup16 as u8]);
+
+        let mut listing = Vec::new();
+
+        assert_eq!(machine.disassemble_range(start, start + 2, &mut listing).unwrap(), start + 2);
+        assert_eq!(
+            String::from_utf8(listing).unwrap(),
+            format!("{:04X}: (illegal op-code = {})\n{:04X}: dup\n", start, illegal, start + 1),
         );
-        test_output(
-            "
-            1638 16 BASE ! S>D <# # # # # #>
-            TYPE
-            ",
-            b"0666",
+    }
+
+    #[test]
+    fn test_backtrace_of_nested_calls() {
+        let mut machine = TestMachine::default();
+        machine.extensions.input = StaticStringInput::new(": inner DROP DROP ; : middle inner ; : outer 1 middle ; outer");
+
+        let err = machine.interpret_input().unwrap_err();
+        assert!(matches!(err, MachineError::DataStackUnderflow { .. }), "{:?}", err);
+
+        let inner = instruction_addresses(&machine, b"inner");
+        let middle = instruction_addresses(&machine, b"middle");
+        let outer = instruction_addresses(&machine, b"outer");
+        let mut backtrace = Vec::new();
+        machine.format_backtrace(&mut backtrace).unwrap();
+
+        assert_eq!(String::from_utf8(backtrace).unwrap(), format!(
+            "#0 inner +0x{:02X}\n#1 middle +0x{:02X}\n#2 outer +0x{:02X}\n",
+            inner[2] - inner[0], middle[2] - middle[0], outer[3] - outer[0],
+        ));
+
+        let mut report = Vec::new();
+        err.pretty_print(&mut report, &machine).unwrap();
+        let report = String::from_utf8(report).unwrap();
+
+        assert!(report.contains("\nBacktrace:\n#0 inner +0x"), "{}", report);
+        assert!(report.ends_with(&format!("#2 outer +0x{:02X}", outer[3] - outer[0])), "{}", report);
+    }
+
+    /// Sizes of memory regions printed by `.MEM`, by region name.
+    fn memory_map(machine: &mut TestMachine) -> Vec<(String, usize)> {
+        machine.extensions.input = StaticStringInput::new(".MEM");
+        machine.interpret_input().unwrap();
+
+        let listing = String::from_utf8(machine.extensions.output.content.take()).unwrap();
+
+        assert!(listing.starts_with(&format!("Memory map ({} bytes):\n", MEM_SIZE)), "{}", listing);
+
+        listing.lines().skip(1).map(|line| {
+            let mut fields = line.split_whitespace();
+            let _address = fields.next().unwrap();
+            let size = fields.next().unwrap().parse().unwrap();
+
+            (fields.collect::<Vec<_>>().join(" "), size)
+        }).collect()
+    }
+
+    #[test]
+    fn test_memory_map() {
+        let mut machine = TestMachine::default();
+        let initial = memory_map(&mut machine);
+
+        assert_eq!(initial.iter().map(|(_, size)| size).sum::<usize>(), MEM_SIZE);
+        assert_eq!(initial[0], ("dictionary".to_string(), 0));
+        assert!(initial[2].0.starts_with("data stack, 0 of"), "{:?}", initial);
+        assert_eq!(initial[3].0, "call stack, 0 of 128 cell(s) used");
+        assert_eq!(initial[4], ("heap, 1024 byte(s) free".to_string(), 1024));
+        assert_eq!(initial[5], ("built-in variables".to_string(), 256));
+        assert_eq!(initial[7], ("PAD".to_string(), 128));
+
+        machine.extensions.input = StaticStringInput::new(": square DUP * ; 1 2 3");
+        machine.interpret_input().unwrap();
+
+        let updated = memory_map(&mut machine);
+        let dictionary_size = machine.memory.dictionary_size() as usize;
+
+        assert_eq!(updated.iter().map(|(_, size)| size).sum::<usize>(), MEM_SIZE);
+        assert_eq!(updated[0].1, dictionary_size);
+        assert_eq!(updated[1].1, initial[1].1 - dictionary_size - 3 * CELL_BYTES as usize);
+        assert!(updated[2].0.starts_with("data stack, 3 of"), "{:?}", updated);
+        assert_eq!(updated[2].1, 3 * CELL_BYTES as usize);
+        assert_eq!(updated[3..], initial[3..]);
+    }
+
+    #[test]
+    fn test_call_word() {
+        let mut machine = TestMachine::default();
+        machine.extensions.input = StaticStringInput::new("
+            : FACTORIAL DUP 2 < IF DROP 1 EXIT THEN DUP 1 - RECURSE * ;
+            : DIVMOD 2DUP / ROT ROT OVER OVER / * - ;
+            100
+        ");
+        machine.interpret_input().unwrap();
+
+        assert_eq!(machine.call_word("FACTORIAL", &[5], 1).unwrap(), vec![120]);
+        assert_eq!(machine.call_word("FACTORIAL", &[7], 1).unwrap(), vec![5040]);
+        assert_eq!(machine.call_word("DIVMOD", &[17, 5], 2).unwrap(), vec![3, 2]);
+        assert_eq!(machine.call_word("+", &[2, 3], 1).unwrap(), vec![5]);
+        assert_eq!(machine.call_word_signed("-", &[2, 3], 1).unwrap(), vec![-1]);
+
+        machine.assert_data_stack_state(&[StackElement::Cell(100)]);
+    }
+
+    #[test]
+    fn test_call_word_errors_keep_stack_balanced() {
+        let mut machine = TestMachine::default();
+        machine.register_native_word("fail", |_| Err(MachineError::Interrupted));
+        machine.extensions.input = StaticStringInput::new(": inner 1 2 fail ; : outer 5 inner ; 100");
+        machine.interpret_input().unwrap();
+
+        assert!(matches!(machine.call_word("outer", &[9], 1), Err(MachineError::Interrupted)));
+        assert!(matches!(
+            machine.call_word("DUP", &[7], 1),
+            Err(MachineError::ResultCountMismatch { expected: 1, actual: 2 })
+        ));
+        assert!(matches!(
+            machine.call_word("DROP", &[], 0),
+            Err(MachineError::ResultCountMismatch { expected: 0, actual: -1 })
+        ));
+        assert!(matches!(machine.call_word("no-such-word", &[1, 2], 0), Err(MachineError::IllegalWord(_))));
+        assert!(matches!(machine.call_word("DROP", &[], 0), Err(MachineError::ResultCountMismatch { .. })));
+
+        machine.assert_data_stack_state(&[StackElement::Cell(100)]);
+        assert_eq!(machine.memory.call_stack_depth(), 0);
+    }
+
+    #[test]
+    fn test_stateful_fallback_handler() {
+        use std::collections::HashMap;
+        use crate::sized_string::ReadableSizedString;
+
+        let symbols = HashMap::from([("width".to_string(), 80 as Cell), ("height".to_string(), 25 as Cell)]);
+        let lookups = Rc::new(RefCell::new(Vec::new()));
+        let lookups_log = lookups.clone();
+
+        let mut machine = TestMachine::default();
+        machine.set_fallback_handler(Box::new(move |machine: &mut TestMachine, name_address| {
+            let name = ReadableSizedString::new(&machine.memory.raw_memory, name_address, machine.memory.raw_memory.address_range())?
+                .to_string();
+            lookups_log.borrow_mut().push(name.clone());
+
+            match symbols.get(&name) {
+                Some(&value) => machine.memory.data_push_cell(value),
+                None => Err(MachineError::IllegalWord(Some(name_address))),
+            }
+        }));
+
+        machine.extensions.input = StaticStringInput::new("width height 7 width");
+        machine.interpret_input().unwrap();
+        machine.assert_data_stack_state(&[
+            StackElement::Cell(80), StackElement::Cell(25), StackElement::Cell(7), StackElement::Cell(80),
+        ]);
+        assert_eq!(lookups.borrow().as_slice(), ["width", "height", "7", "width"]);
+
+        machine.extensions.input = StaticStringInput::new("depth");
+        assert!(matches!(machine.interpret_input(), Err(MachineError::IllegalWord(Some(_)))));
+
+        machine.clear_fallback_handler();
+        machine.extensions.input = StaticStringInput::new("width");
+        assert!(matches!(machine.interpret_input(), Err(MachineError::IllegalWord(Some(_)))));
+        assert_eq!(lookups.borrow().len(), 5);
+    }
+
+    #[test]
+    fn test_bye_stops_interpretation() {
+        let mut machine = TestMachine::default();
+        machine.extensions.input = StaticStringInput::new("1 2 BYE 3");
+
+        assert!(matches!(machine.interpret_input(), Err(MachineError::Bye)));
+        machine.assert_data_stack_state(&[StackElement::Cell(1), StackElement::Cell(2)]);
+    }
+
+    #[test]
+    fn test_bye_in_compiled_word() {
+        let mut machine = TestMachine::default();
+        machine.extensions.input = StaticStringInput::new(": quit 5 BYE 6 ;");
+        machine.interpret_input().unwrap();
+
+        let body_address = machine.memory.lookup_article(b"quit").unwrap().unwrap().body_address();
+
+        assert!(matches!(machine.run_until_exit(body_address), Err(MachineError::Bye)));
+        machine.assert_data_stack_state(&[StackElement::Cell(5)]);
+    }
+
+    #[cfg(feature = "std-fs")]
+    #[test]
+    fn test_bye_in_included_file() {
+        let lib_path = write_temp_file("bye-lib.fs", "7 BYE 8\n");
+        let mut machine = TestMachine::with_std_file_system();
+        assert!(matches!(machine.interpret_str(&format!("INCLUDE {} 9", lib_path)), Err(MachineError::Bye)));
+        machine.assert_data_stack_state(&[StackElement::Cell(7)]);
+    }
+
+    #[cfg(feature = "std-fs")]
+    #[test]
+    fn test_include_nested_files() {
+        let words_path = write_temp_file("include-words.fs", ": sq DUP * ;\n");
+        let lib_path = write_temp_file("include-lib.fs", &format!("INCLUDE {}\n: cube DUP sq * ;\n", words_path));
+        let main_path = write_temp_file(
+            "include-main.fs",
+            &format!(": lib S\" {}\" ;\nlib INCLUDED 2 cube 3 sq\n", lib_path),
         );
+        let mut machine = TestMachine::with_std_file_system();
+        machine.extensions.input = StaticStringInput::new("4 cube");
+
+        machine.interpret_file(&main_path).unwrap();
+        machine.interpret_input().unwrap();
+
+        machine.assert_data_stack_state(&[StackElement::Cell(8), StackElement::Cell(9), StackElement::Cell(64)]);
     }
 
+    #[cfg(feature = "std-fs")]
     #[test]
-    fn test_mode_switch_and_literals() {
-        test_16_bit_results(
-            ": foo [ 1 2 + ] LITERAL + ;",
-            &[],
+    fn test_include_error_reports_include_chain() {
+        let lib_path = write_temp_file("include-broken-lib.fs", ": ok 1 ;\n\n  oops\n");
+        let main_path = write_temp_file("include-broken-main.fs", &format!("INCLUDE {}\nok\n", lib_path));
+        let mut machine = TestMachine::with_std_file_system();
+
+        let err = machine.interpret_file(&main_path).unwrap_err();
+
+        let mut buf = Vec::new();
+        err.pretty_print(&mut buf, &machine).unwrap();
+        assert_eq!(
+            from_utf8(&buf).unwrap(),
+            format!("{}:1:{}: {}:3:7: Illegal word: oops", main_path, lib_path.len() + 9, lib_path),
         );
-        test_16_bit_results(
-            ": foo [ 1 2 + ] LITERAL + ; 3 foo",
-            &[6],
+
+        // Reading continues from the original input after an error in included file
+        machine.extensions.input = StaticStringInput::new("ok");
+        machine.interpret_input().unwrap();
+        machine.assert_data_stack_state(&[StackElement::Cell(1)]);
+    }
+
+    #[cfg(feature = "std-fs")]
+    #[test]
+    fn test_include_missing_file() {
+        let mut machine = TestMachine::with_std_file_system();
+        machine.extensions.input = StaticStringInput::new("INCLUDE /nonexistent/file.fs");
+
+        assert!(matches!(
+            machine.interpret_input(),
+            Err(MachineError::FileError { path, .. }) if path == "/nonexistent/file.fs"
+        ));
+    }
+
+    #[cfg(feature = "std-fs")]
+    #[test]
+    fn test_include_depth_limit() {
+        let path = write_temp_file("include-self.fs", "");
+        std::fs::write(&path, format!("1 INCLUDE {}\n", path)).unwrap();
+        let mut machine = TestMachine::with_std_file_system();
+        let mut err = machine.interpret_str(&format!("INCLUDE {}", path)).unwrap_err();
+        let mut chain_length = 0;
+
+        while let MachineError::InFile { err: inner, .. } = err {
+            chain_length += 1;
+            err = *inner;
+        }
+
+        // The interpreted string takes one level of nesting
+        assert!(matches!(err, MachineError::IncludeDepthExceeded { depth: MAX_INCLUDE_DEPTH }));
+        assert_eq!(chain_length, MAX_INCLUDE_DEPTH - 1);
+        assert_eq!(machine.memory.data_stack_depth(), MAX_INCLUDE_DEPTH as u16 - 1);
+    }
+
+    #[cfg(feature = "std-fs")]
+    #[test]
+    fn test_require_loads_shared_file_once() {
+        let common_path = write_temp_file("require-common.fs", "65 EMIT : common 42 ;\n");
+        let left_path = write_temp_file("require-left.fs", &format!("REQUIRE {}\n: left common 1 + ;\n", common_path));
+        let right_path = write_temp_file(
+            "require-right.fs",
+            &format!(": common-path S\" {}\" ;\ncommon-path REQUIRED : right common 2 + ;\n", common_path),
         );
+        let mut machine = TestMachine::with_std_file_system();
+        machine.interpret_str(&format!("REQUIRE {} REQUIRE {} REQUIRE {} left right", left_path, right_path, left_path))
+            .unwrap();
+
+        machine.assert_data_stack_state(&[StackElement::Cell(43), StackElement::Cell(44)]);
+        assert_eq!(machine.extensions.output.content.borrow().as_slice(), b"A");
+        assert_eq!(machine.memory.articles().filter(|article| article.name().to_string() == "common").count(), 1);
+    }
+
+    #[cfg(feature = "std-fs")]
+    #[test]
+    fn test_mark_included() {
+        let path = write_temp_file("require-preloaded.fs", ": preloaded 1 ;\n");
+        let mut machine = TestMachine::with_std_file_system();
+        machine.mark_included(&path).unwrap();
+
+        machine.interpret_str(&format!("REQUIRE {}", path)).unwrap();
+        assert!(machine.memory.lookup_article(b"preloaded").unwrap().is_none());
+
+        machine.interpret_str(&format!("INCLUDE {}", path)).unwrap();
+        assert!(machine.memory.lookup_article(b"preloaded").unwrap().is_some());
+    }
+
+    #[test]
+    fn test_interpret_str() {
+        let mut machine = TestMachine::default();
+        machine.extensions.input = StaticStringInput::new("10 20");
+        let name_address = machine.read_input_word().unwrap().unwrap();
+        machine.execute_word(name_address).unwrap();
+
+        let factor = 3;
+        machine.interpret_str(&format!(": scale {} * ; 5 scale", factor)).unwrap();
+
+        machine.interpret_input().unwrap();
+        machine.assert_data_stack_state(&[StackElement::Cell(10), StackElement::Cell(15), StackElement::Cell(20)]);
+    }
+
+    #[test]
+    fn test_interpret_str_error_restores_input() {
+        let mut machine = TestMachine::default();
+        machine.extensions.input = StaticStringInput::new("1 2");
+
+        assert!(matches!(machine.interpret_str("3 oops 4"), Err(MachineError::IllegalWord(_))));
+
+        machine.interpret_input().unwrap();
+        machine.assert_data_stack_state(&[StackElement::Cell(3), StackElement::Cell(1), StackElement::Cell(2)]);
+    }
+
+    #[test]
+    fn test_prelude() {
+        assert!(TestMachine::default().memory.lookup_article(b"NIP").unwrap().is_none());
+
+        let mut machine = TestMachine::with_prelude(TestMachineExtensions::default()).unwrap();
+        let cells = |values: &[Cell]| values.iter().map(|&value| StackElement::Cell(value)).collect::<Vec<_>>();
+
+        for (source, expected) in [
+            ("TRUE FALSE", cells(&[TRUE, FALSE])),
+            ("4 5 6 2DROP", cells(&[4])),
+            ("1 2 NIP", cells(&[2])),
+            ("1 2 TUCK", cells(&[2, 1, 2])),
+            ("0 ?DUP 5 ?DUP", cells(&[0, 5, 5])),
+            ("7 NEGATE", cells(&[(-7i8) as Cell])),
+            ("5 1+ 5 1- 5 2*", cells(&[6, 4, 10])),
+            ("3 8 MAX 3 8 MIN -1 2 MAX", cells(&[8, 3, 2])),
+            ("VARIABLE v 5 v ! 3 v +! v @", cells(&[8])),
+            ("0 0= 1 0= -1 0< 1 0> 0 0<> 1 2 <>", cells(&[TRUE, FALSE, TRUE, TRUE, FALSE, TRUE])),
+            ("2 CELLS 10 CELL+ 3 CHARS 3 CHAR+", cells(&[2 * CELL_BYTES as Cell, 10 + CELL_BYTES as Cell, 3, 4])),
+            ("HEX BASE @ DECIMAL BASE @", cells(&[16, 10])),
+        ] {
+            machine.interpret_str(source).unwrap();
+            machine.assert_data_stack_state(&expected);
+        }
+    }
+
+    #[test]
+    fn test_name_to_compile() {
+        let mut machine = TestMachine::with_prelude(TestMachineExtensions::default()).unwrap();
+        machine.interpret_str(": five 5 ; : seven 7 ; IMMEDIATE").unwrap();
+
+        let article = |machine: &TestMachine, name: &[u8]| {
+            let article = machine.memory.lookup_article(name).unwrap().unwrap();
+            (article.get_header_address(), article.call_address() as Cell)
+        };
+        let (five, five_xt) = article(&machine, b"five");
+        let (seven, _) = article(&machine, b"seven");
+
+        // Compilation semantics of `seven` execute it while `both` is compiled
+        machine.interpret_str(&format!(
+            ": both [ {five} NAME>COMPILE EXECUTE {seven} NAME>COMPILE EXECUTE ] LITERAL ; both",
+        )).unwrap();
+        machine.assert_data_stack_state(&[StackElement::Cell(5), StackElement::Cell(7)]);
+
+        machine.interpret_str(&format!("{five} NAME>COMPILE")).unwrap();
+        let (_, compile_xt) = article(&machine, b"(compile,)");
+        machine.assert_data_stack_state(&[StackElement::Cell(five_xt), StackElement::Cell(compile_xt)]);
+    }
+
+    #[test]
+    fn test_prelude_error_reports_line() {
+        let mut machine = TestMachine::default();
+
+        let err = machine.interpret_named_str(PRELUDE_PATH, ": fine ;\n: broken oops ;\n").unwrap_err();
+
+        assert!(matches!(
+            &err,
+            MachineError::InFile { path, line: 2, err, .. } if path == PRELUDE_PATH && matches!(**err, MachineError::IllegalWord(_)),
+        ), "{:?}", err);
+    }
+
+    #[test]
+    fn test_tee_output() {
+        let mut machine = TestMachine::default();
+        let buffer = SharedBuffer::default();
+        machine.tee_output(Box::new(WriterOutput::new(buffer.clone())));
+
+        machine.interpret_str(": greet .\" hello\" ; greet 33 EMIT").unwrap();
+        assert!(machine.remove_tee_output().is_some());
+        machine.interpret_str("greet").unwrap();
+
+        assert_eq!(buffer.content(), b"hello!");
+        assert_eq!(machine.extensions.output.content.borrow().as_slice(), b"hello!hello");
+    }
+
+    #[test]
+    fn test_flush_word() {
+        let mut machine = TestMachine::default();
+        let writer = SharedBuffer::default();
+        machine.tee_output(Box::new(WriterOutput::new(writer.clone())));
+
+        machine.interpret_str("65 EMIT FLUSH : done 66 EMIT FLUSH ; done").unwrap();
+
+        assert_eq!(writer.flushes(), 2);
+        assert_eq!(machine.extensions.output.content.borrow().as_slice(), b"AB");
+    }
+
+    #[test]
+    fn test_buffered_output_throughput() {
+        let mut machine = TestMachine::default();
+        let writer = SharedBuffer::default();
+        machine.tee_output(Box::new(WriterOutput::new(io::BufWriter::new(writer.clone()))));
+
+        machine.interpret_str(": stars 0 BEGIN DUP 10000 < WHILE 42 EMIT 1 + REPEAT DROP ; stars stars stars stars stars stars stars stars stars stars FLUSH").unwrap();
+
+        assert_eq!(writer.content().len(), 100000);
+        assert!(writer.writes() < 100, "{} writes", writer.writes());
     }
 }
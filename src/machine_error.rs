@@ -1,12 +1,14 @@
-use std::io;
+use std::{fmt, io};
 use std::str::from_utf8;
 
 use crate::input::InputError;
 use crate::machine::{Machine, MachineExtensions};
 use crate::machine_state::MachineState;
-use crate::mem::{Address, MemoryAccessError};
+use crate::mem::{Address, AddressRange, MemoryAccessError};
 use crate::output::OutputError;
+use crate::readable_article::ReadableArticle;
 use crate::sized_string::ReadableSizedString;
+use crate::snapshot::SnapshotError;
 
 #[derive(Debug)]
 pub enum MachineError {
@@ -26,7 +28,155 @@ pub enum MachineError {
         expected: MachineState,
         actual: MachineState,
     },
+    /// Word that may only be used inside a definition was used in interpreter mode.
+    CompileOnlyWord {
+        name: String,
+    },
+    /// `->` or `}T` was used out of `T{ ... -> ... }T` order.
+    UnmatchedTestWord {
+        name: String,
+    },
+    /// `}T` found a mismatch while `Tester::fail_on_mismatch` is set, the message is the one printed to output.
+    TestFailed {
+        message: String,
+    },
+    /// `TO` was applied to a word not created by `VALUE` or `2VALUE`.
+    NotAValue {
+        name: String,
+    },
     Exited,
+    /// Program asked to stop with `BYE`.
+    Bye,
+    /// Execution reached `BREAK` or a breakpoint set by host, `Machine::resume` continues it at given address.
+    Breakpoint {
+        address: Address,
+    },
+    /// Compiled code refers to a native word that is not registered.
+    UnknownNativeWord {
+        index: u16,
+    },
+    /// Word called from host left a different number of cells on data stack than expected.
+    ResultCountMismatch {
+        expected: usize,
+        actual: isize,
+    },
+    /// Native word was called again while it was running.
+    NativeWordReentered {
+        index: u16,
+    },
+    StepLimitExceeded {
+        executed: u64,
+    },
+    Interrupted,
+    DataStackUnderflow {
+        /// Number of cells the failed operation tried to take from the stack
+        requested: u16,
+        operation: StackOperation,
+    },
+    DataStackOverflow {
+        /// Number of cells the failed operation tried to add to the stack
+        requested: u16,
+        operation: StackOperation,
+    },
+    CallStackUnderflow {
+        requested: u16,
+        operation: StackOperation,
+    },
+    CallStackOverflow {
+        requested: u16,
+        operation: StackOperation,
+    },
+    /// Dictionary has no space left for a write of `needed` bytes.
+    OutOfDataSpace {
+        needed: u16,
+        available: u16,
+    },
+    /// Memory-mapped I/O range overlaps with an already mapped one.
+    OverlappingMmioRange {
+        range: AddressRange,
+        existing: AddressRange,
+    },
+    /// Store to a write-protected part of dictionary.
+    WriteProtected {
+        address: Address,
+    },
+    /// File access requested but machine extensions provide no file system.
+    FileSystemUnavailable,
+    /// Machine image could not be saved to or loaded from given file.
+    ImageError {
+        path: String,
+        err: SnapshotError,
+    },
+    InvalidJumpTarget {
+        /// Address of the jump (or call) instruction
+        from: Address,
+        to: Address,
+    },
+    /// Execution token points outside of executable memory.
+    InvalidExecutionToken {
+        xt: Address,
+    },
+    /// Name token is not an address of an article header.
+    InvalidNameToken {
+        nt: Address,
+    },
+    /// `SP!` or `RP!` tried to move stack pointer outside of the stack or between cells.
+    InvalidStackPointer {
+        address: Address,
+
+        /// Addresses the pointer may take, the last one stands for an empty stack.
+        segment: AddressRange,
+        segment_name: &'static str,
+    },
+    /// File could not be opened.
+    FileError {
+        path: String,
+        err: io::Error,
+    },
+    /// Too many files are included into each other.
+    IncludeDepthExceeded {
+        depth: usize,
+    },
+    /// Error that happened while interpreting a file.
+    InFile {
+        path: String,
+
+        /// Line (starting from 1) of the last character read from the file before the error.
+        line: u32,
+        column: u32,
+        err: Box<MachineError>,
+    },
+}
+
+/// Operation that failed to take cells from a stack or to add cells to it, as far as it is known.
+#[derive(Clone, Copy, Default, Debug, PartialEq, Eq)]
+pub struct StackOperation {
+    /// Name of the word being executed, or mnemonic of the op-code if no word compiles to it.
+    pub word: Option<&'static str>,
+
+    /// Standard stack comment of the word, e.g. `x -- x x`.
+    pub effect: Option<&'static str>,
+}
+
+impl fmt::Display for StackOperation {
+    /// Formats as ` in NAME ( EFFECT )` or an empty string if nothing is known.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.word.is_none() && self.effect.is_none() {
+            return Ok(());
+        }
+
+        write!(f, " in")?;
+
+        if let Some(word) = self.word {
+            write!(f, " {}", word)?;
+        }
+
+        if let Some(effect) = self.effect {
+            write!(f, " ( {} )", effect.trim())?;
+        }
+
+        Ok(())
+    }
 }
 
 impl From<MemoryAccessError> for MachineError {
@@ -48,7 +198,43 @@ impl From<OutputError> for MachineError {
 }
 
 impl MachineError {
+    /// Attach name and stack comment of failed word to a stack underflow or overflow error, unless it already names
+    /// one.
+    pub fn in_word(mut self, word: &'static str, effect: Option<&'static str>) -> Self {
+        match &mut self {
+            MachineError::DataStackUnderflow { operation, .. }
+            | MachineError::DataStackOverflow { operation, .. }
+            | MachineError::CallStackUnderflow { operation, .. }
+            | MachineError::CallStackOverflow { operation, .. } if operation.word.is_none() => {
+                *operation = StackOperation { word: Some(word), effect };
+            }
+            _ => {}
+        }
+
+        self
+    }
+
+    /// Print description of the error followed by backtrace of code that was running when it happened, if any.
     pub fn pretty_print<TExt: MachineExtensions>(&self, f: &mut impl io::Write, machine: &Machine<TExt>) -> io::Result<()> {
+        self.print_message(f, machine)?;
+
+        // Nested error prints the backtrace itself
+        if matches!(self, MachineError::InFile { .. }) {
+            return Ok(());
+        }
+
+        let mut backtrace = Vec::new();
+        machine.format_backtrace(&mut backtrace)?;
+
+        if let Some(backtrace) = backtrace.strip_suffix(b"\n") {
+            write!(f, "\nBacktrace:\n")?;
+            f.write_all(backtrace)?;
+        }
+
+        Ok(())
+    }
+
+    fn print_message<TExt: MachineExtensions>(&self, f: &mut impl io::Write, machine: &Machine<TExt>) -> io::Result<()> {
         match self {
             MachineError::InputError(input_err) => {
                 match input_err {
@@ -58,8 +244,8 @@ impl MachineError {
                     InputError::IllegalOffset => {
                         write!(f, "Illegal input offset requested")
                     }
-                    InputError::BufferOverflow => {
-                        write!(f, "Input buffer overflow")
+                    InputError::WordTooLong { prefix, position } => {
+                        write!(f, "Word at input offset {} is too long: \"{}...\"", position, String::from_utf8_lossy(prefix))
                     }
                 }
             }
@@ -75,10 +261,147 @@ impl MachineError {
                     .unwrap()
                     .as_bytes();
 
-                write!(f, "Illegal word: {}", from_utf8(name_bytes).unwrap_or("(unprintable name)"))
+                write!(f, "Illegal word: {}", from_utf8(&name_bytes).unwrap_or("(unprintable name)"))
+            }
+            MachineError::CompileOnlyWord { name } => {
+                write!(f, "Word {} is compile-only, it may only be used inside a definition", name)
+            }
+            MachineError::UnmatchedTestWord { name } => {
+                write!(f, "Word {} is used out of T{{ ... -> ... }}T order", name)
+            }
+            MachineError::TestFailed { message } => {
+                write!(f, "Test failed: {}", message)
+            }
+            MachineError::NotAValue { name } => {
+                write!(f, "Word {} is not a value, TO may only change words created by VALUE or 2VALUE", name)
+            }
+            MachineError::IllegalOpCodeError { address, op_code } => {
+                writeln!(f, "Illegal op-code {} at {:04X}", op_code, address)?;
+                machine.print_code_context(f, *address)
+            }
+            MachineError::OutOfDataSpace { needed, available } => {
+                write!(f, "Out of data space: needed {} byte(s), {} byte(s) available", needed, available)?;
+
+                if machine.memory.is_dictionary_limited_by_data_stack() {
+                    write!(f, ", dictionary has reached the guard region below data stack")?;
+                } else {
+                    write!(f, ", dictionary has reached the space reserved for data stack")?;
+                }
+
+                if let Some(header_address) = machine.memory.get_current_word() {
+                    let definition_size = machine.memory.get_dict_ptr() - header_address;
+
+                    match ReadableArticle::new(&machine.memory.raw_memory, header_address, machine.memory.get_used_dict_segment()) {
+                        Ok(article) => write!(f, " (definition of {} takes {} byte(s) so far)", article.name(), definition_size)?,
+                        Err(_) => write!(f, " (current definition takes {} byte(s) so far)", definition_size)?,
+                    }
+                }
+
+                Ok(())
+            }
+            MachineError::OverlappingMmioRange { range, existing } => {
+                write!(f, "Memory-mapped I/O range {:04X?} overlaps already mapped range {:04X?}", range, existing)
+            }
+            MachineError::WriteProtected { address } => {
+                write!(f, "Write to protected dictionary address {:04X}", address)?;
+
+                if let Some(article) = machine.memory.article_containing(*address) {
+                    write!(f, " (in article {})", article.name())?;
+                }
+
+                Ok(())
+            }
+            MachineError::FileSystemUnavailable => {
+                write!(f, "File system is not available")
+            }
+            MachineError::ImageError { path, err } => {
+                write!(f, "Image {:?}: {}", path, err)
+            }
+            MachineError::FileError { path, err } => {
+                write!(f, "File {:?}: {}", path, err)
+            }
+            MachineError::IncludeDepthExceeded { depth } => {
+                write!(f, "Too many nested includes, {} file(s) are being read", depth)
+            }
+            MachineError::InFile { path, line, column, err } => {
+                write!(f, "{}:{}:{}: ", path, line, column)?;
+                err.pretty_print(f, machine)
+            }
+            MachineError::InvalidJumpTarget { from, to } => {
+                writeln!(f, "Invalid jump target {:04X} at {:04X}", to, from)?;
+                machine.print_code_context(f, *from)
+            }
+            MachineError::InvalidExecutionToken { xt } => {
+                write!(f, "Invalid execution token {:04X}", xt)
+            }
+            MachineError::InvalidNameToken { nt } => {
+                write!(f, "Invalid name token {:04X}", nt)
+            }
+            MachineError::InvalidStackPointer { address, segment, segment_name } => {
+                write!(f, "Invalid {} pointer {:04X}, must be a cell boundary in range {:04X?}", segment_name, address, segment)
+            }
+            MachineError::MemoryAccessError(err) => {
+                write!(f, "Illegal memory access: {}", err)
+            }
+            MachineError::StepLimitExceeded { executed } => {
+                write!(f, "Step limit exceeded after executing {} instruction(s)", executed)
+            }
+            MachineError::DataStackUnderflow { requested, operation } => {
+                write!(
+                    f, "Data stack underflow{}: tried to take {} cell(s), stack depth is {}",
+                    operation, requested, machine.memory.data_stack_depth(),
+                )
+            }
+            MachineError::DataStackOverflow { requested, operation } => {
+                write!(
+                    f, "Data stack overflow{}: tried to add {} cell(s), stack depth is {}",
+                    operation, requested, machine.memory.data_stack_depth(),
+                )?;
+
+                if machine.memory.is_data_stack_limited_by_dictionary() {
+                    write!(f, ", data stack has reached the guard region above dictionary")?;
+                }
+
+                Ok(())
+            }
+            MachineError::CallStackUnderflow { requested, operation } => {
+                write!(
+                    f, "Call stack underflow{}: tried to take {} cell(s), stack depth is {}",
+                    operation, requested, machine.memory.call_stack_depth(),
+                )
+            }
+            MachineError::CallStackOverflow { requested, operation } => {
+                write!(
+                    f, "Call stack overflow{}: tried to add {} cell(s), stack depth is {}",
+                    operation, requested, machine.memory.call_stack_depth(),
+                )
+            }
+            MachineError::Interrupted => {
+                write!(f, "Interrupted")
+            }
+            MachineError::Breakpoint { address } => {
+                write!(f, "Breakpoint at {:04X}", address)?;
+
+                if let Some(article) = machine.memory.article_containing(*address) {
+                    write!(f, " (in article {})", article.name())?;
+                }
+
+                Ok(())
+            }
+            MachineError::Bye => {
+                write!(f, "Bye")
             }
-            MachineError::MemoryAccessError(MemoryAccessError { access_range, segment }) => {
-                write!(f, "Illegal memory access attempt to {} byte(s) at {:X?} (allowed range is {:X?})", access_range.len(), access_range, segment)
+            MachineError::ResultCountMismatch { expected, actual } => {
+                write!(f, "Word left {} cell(s) on data stack, expected {}", actual, expected)
+            }
+            MachineError::UnknownNativeWord { index } => {
+                write!(f, "Unknown native word #{}", index)
+            }
+            MachineError::NativeWordReentered { index } => {
+                match machine.native_words.name(*index) {
+                    Some(name) => write!(f, "Native word {} called while running", String::from_utf8_lossy(name)),
+                    None => write!(f, "Native word #{} called while running", index),
+                }
             }
             _ => {
                 write!(f, "{:?}", self)
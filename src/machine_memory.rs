@@ -1,25 +1,104 @@
 use int_enum::IntEnum;
 
+use crate::cell::{aligned, Cell, CELL_BYTES, DOUBLE_CELL_BYTES, DoubleCell, TRUE};
 use crate::input::{Input, InputError};
+use crate::machine_error::{MachineError, StackOperation};
 use crate::machine_state::MachineState;
-use crate::mem::{Address, AddressRange, Mem, MemoryAccessError};
+use crate::mem::{AccessKind, Address, AddressRange, Mem, MemoryAccessError};
+use crate::memory_segment::{
+    CallStackSegment, DataStackSegment, DictionarySegment, MemorySegment, PnoBufferSegment, WholeMemorySegment,
+    WHOLE_MEMORY,
+};
 use crate::opcodes::OpCode;
 use crate::readable_article::{ReadableArticle, ReadableArticlesIterator};
 use crate::sized_string::ReadableSizedString;
 
-#[derive(Copy, Clone)]
+#[derive(Copy, Clone, Debug, PartialEq)]
 pub struct MemoryLayoutConfig {
     pub max_call_stack_depth: u16,
+
+    /// Maximal depth of data stack in cells.
+    ///
+    /// When set, the space reserved for data stack can not be used by dictionary.
+    /// Otherwise data stack may grow until it meets the dictionary.
+    pub max_data_stack_depth: Option<u16>,
+
+    /// Maximal length of a word read from input.
+    pub max_word_length: u8,
+
+    /// Size of buffer returned by `PAD` in bytes.
+    pub pad_size: u16,
+
+    /// Maximal length of pictured numeric output.
+    pub max_pno_length: u8,
+
+    /// Number of bytes kept unused between the end of dictionary and the data stack.
+    ///
+    /// Neither dictionary nor data stack may grow into the gap, so collisions are detected before any of them is
+    /// corrupted.
+    pub guard_size: u16,
+
+    /// Size of region used by `ALLOCATE`, `FREE` and `RESIZE`, in bytes, rounded down to whole cells.
+    ///
+    /// The region lies between call stack and reserved space, so neither dictionary nor stacks may grow into it.
+    pub heap_size: u16,
 }
 
 impl Default for MemoryLayoutConfig {
     fn default() -> Self {
         MemoryLayoutConfig {
             max_call_stack_depth: 128,
+            max_data_stack_depth: None,
+            max_word_length: 255,
+            pad_size: 128,
+            max_pno_length: 127,
+            guard_size: 8,
+            heap_size: 1024,
         }
     }
 }
 
+/// Size of area reserved for built-in variables (`ReservedAddresses`), in bytes.
+const RESERVED_VARIABLES_SIZE: u32 = 256;
+
+/// Minimal number of bytes left for dictionary by a valid `MemoryLayoutConfig`.
+const MIN_DICTIONARY_SIZE: u32 = 256;
+
+impl MemoryLayoutConfig {
+    fn word_buffer_offset(&self) -> u32 {
+        RESERVED_VARIABLES_SIZE
+    }
+
+    fn pad_offset(&self) -> u32 {
+        self.word_buffer_offset() + 1 + self.max_word_length as u32
+    }
+
+    fn pno_buffer_offset(&self) -> u32 {
+        self.pad_offset() + self.pad_size as u32
+    }
+
+    /// Size of reserved space (built-in variables and buffers) in bytes.
+    fn reserved_space_size(&self) -> u32 {
+        self.pno_buffer_offset() + 1 + self.max_pno_length as u32
+    }
+
+    /// Size of heap in bytes.
+    fn heap_length(&self) -> u16 {
+        self.heap_size & !(CELL_BYTES - 1)
+    }
+
+    /// Check if memory of given size is large enough for this layout.
+    pub fn fits_memory_size(&self, memory_size: u32) -> bool {
+        let stacks_size = (CELL_BYTES as u32) * (self.max_call_stack_depth as u32 + self.max_data_stack_depth.unwrap_or(0) as u32);
+
+        self.reserved_space_size() + self.heap_length() as u32 + stacks_size + self.guard_size as u32 + MIN_DICTIONARY_SIZE
+            <= memory_size
+    }
+}
+
+/// Offsets of built-in variables relative to the start of reserved space.
+///
+/// Buffers follow the variables, their offsets depend on `MemoryLayoutConfig`.
 #[repr(u16)]
 #[derive(Clone, Copy, PartialEq, Debug, IntEnum)]
 pub enum ReservedAddresses {
@@ -34,18 +113,6 @@ pub enum ReservedAddresses {
 
     /// Radix used when parsing and formatting numbers
     BaseVar = 10,
-
-    /// A buffer used to keep parsed words (as counted strings)
-    WordBuffer = 256,
-
-    PadBuffer = 512,
-
-    PnoBuffer = 640,
-
-    /// Maximal address available in reserved space.
-    ///
-    /// 256 + 128 + 128 bytes for buffers + 256 bytes for 128 built-in variables - 1 to get offset of last byte
-    Max = 767,
 }
 
 /// A virtual machine's memory along with "registers" representing current layout and usage of the
@@ -61,13 +128,47 @@ pub struct MachineMemory {
     /// Lowest address available for call stack.
     stacks_border: Address,
 
+    /// Lowest address data stack may currently use.
+    ///
+    /// Cached as it's checked on every data stack operation but depends on dictionary pointer stored in memory.
+    /// Kept up to date by `refresh_stack_bounds`.
+    data_stack_bottom: Address,
+
     /// Address of the most recent word on call stack
     /// or address immediately after call stack if call stack is empty.
     pub call_stack_ptr: Address,
 
+    /// Lowest address of heap, call stack lies right below it.
+    heap_start: Address,
+
     /// Lowest address reserved for built-in variables.
     reserved_space_start: Address,
 
+    config: MemoryLayoutConfig,
+
+    /// Range of addresses code may be executed from in addition to the used part of dictionary.
+    pub extra_executable_segment: Option<AddressRange>,
+
+    /// Reject stores into the used part of dictionary except for data fields marked by `mark_data_space`.
+    pub write_protection: bool,
+
+    /// Record the deepest both stacks got, see `max_data_depth` and `max_call_depth`.
+    pub track_stack_usage: bool,
+
+    /// Greatest data stack depth seen while `track_stack_usage` is set.
+    max_data_depth: u16,
+
+    /// Greatest call stack depth seen while `track_stack_usage` is set.
+    max_call_depth: u16,
+
+    /// Ranges of dictionary containing data (e.g. variable values) rather than code.
+    data_ranges: Vec<AddressRange>,
+
+    /// Addresses of absolute 16-bit address operands compiled into dictionary.
+    ///
+    /// Used to fix the operands up when the dictionary is moved to another address.
+    relocations: Vec<Address>,
+
     pub raw_memory: Mem,
 }
 
@@ -79,47 +180,77 @@ impl Default for MachineMemory {
 
 impl MachineMemory {
     pub fn new(memory: Mem, config: MemoryLayoutConfig) -> MachineMemory {
+        let mut mm = MachineMemory::attach_memory(memory, config, None);
+
+        mm.reset_builtin_vars();
+        mm.reset_heap();
+
+        mm
+    }
+
+    /// Create machine memory around memory that already contains a dictionary and reserved variables,
+    /// e.g. one restored by `Mem::load_from`.
+    ///
+    /// Both stacks are empty. Last article pointer is not stored in memory so it should be provided by caller.
+    ///
+    /// Panics if memory is too small for given layout config.
+    pub fn attach_memory(memory: Mem, config: MemoryLayoutConfig, last_article_ptr: Option<Address>) -> MachineMemory {
         let total_range = memory.address_range();
-        let reserved_space_start = *total_range.end() - ReservedAddresses::Max.int_value();
-        let stacks_border = reserved_space_start - 2 * config.max_call_stack_depth;
+        assert!(
+            config.fits_memory_size(total_range.len() as u32),
+            "memory layout {:?} does not fit into memory", config,
+        );
+
+        let reserved_space_start = *total_range.end() - (config.reserved_space_size() - 1) as Address;
+        let heap_start = reserved_space_start - config.heap_length();
+        let stacks_border = heap_start - CELL_BYTES * config.max_call_stack_depth;
 
         let mut mm = MachineMemory {
-            last_article_ptr: None,
+            last_article_ptr,
+            heap_start,
             reserved_space_start,
-            call_stack_ptr: reserved_space_start,
+            config,
+            extra_executable_segment: None,
+            write_protection: false,
+            track_stack_usage: false,
+            max_data_depth: 0,
+            max_call_depth: 0,
+            data_ranges: Vec::new(),
+            relocations: Vec::new(),
+            call_stack_ptr: heap_start,
             stacks_border,
+            data_stack_bottom: stacks_border,
             data_stack_ptr: stacks_border,
 
             raw_memory: memory,
         };
 
-        mm.reset_builtin_vars();
+        mm.refresh_stack_bounds();
 
         mm
     }
 
     fn reset_builtin_vars(&mut self) {
-        unsafe {
-            self.raw_memory.write_u16(
-                self.get_reserved_address(ReservedAddresses::BaseVar),
-                10,
-            );
-            self.raw_memory.write_u16(
-                self.get_reserved_address(ReservedAddresses::HereVar),
-                *self.raw_memory.address_range().start(),
-            );
-            self.raw_memory.write_u16(
-                self.get_reserved_address(ReservedAddresses::StateVar),
-                0,
-            );
-            self.raw_memory.write_u16(
-                self.get_reserved_address(ReservedAddresses::CurrentDefVar),
-                Address::MAX,
-            );
-        }
+        self.raw_memory.write_cell(
+            self.get_reserved_address(ReservedAddresses::BaseVar),
+            10,
+        );
+        self.raw_memory.write_cell(
+            self.get_reserved_address(ReservedAddresses::HereVar),
+            *self.raw_memory.address_range().start() as Cell,
+        );
+        self.raw_memory.write_cell(
+            self.get_reserved_address(ReservedAddresses::StateVar),
+            0,
+        );
+        self.raw_memory.write_cell(
+            self.get_reserved_address(ReservedAddresses::CurrentDefVar),
+            Address::MAX as Cell,
+        );
+        self.refresh_stack_bounds();
     }
 
-    pub fn create_forward_reference(&mut self) -> Result<Address, MemoryAccessError> {
+    pub fn create_forward_reference(&mut self) -> Result<Address, MachineError> {
         let addr = self.get_dict_ptr();
 
         self.dict_write_u16(0xDEAD)?;
@@ -128,50 +259,132 @@ impl MachineMemory {
     }
 
     pub fn resolve_forward_reference(&mut self, reference_address: Address) -> Result<(), MemoryAccessError> {
-        self.raw_memory.validate_access(
-            reference_address..=reference_address + 1,
-            self.get_used_dict_segment(),
-        )?;
+        self.validate_segment_access(&DictionarySegment, reference_address..=reference_address + 1, AccessKind::Write)?;
 
-        unsafe {
-            self.raw_memory.write_u16(
-                reference_address,
-                self.get_dict_ptr(),
-            )
-        }
+        self.raw_memory.write_u16(
+            reference_address,
+            self.get_dict_ptr(),
+        );
+
+        Ok(())
+    }
+
+    /// Same as `resolve_forward_reference` for references holding an offset relative to the address following them.
+    pub fn resolve_relative_forward_reference(&mut self, reference_address: Address) -> Result<(), MemoryAccessError> {
+        self.validate_segment_access(&DictionarySegment, reference_address..=reference_address + 1, AccessKind::Write)?;
+
+        self.raw_memory.write_u16(
+            reference_address,
+            self.get_dict_ptr().wrapping_sub(reference_address.wrapping_add(2)),
+        );
 
         Ok(())
     }
 
     pub fn get_dict_ptr(&self) -> Address {
-        unsafe {
-            self.raw_memory.read_u16(self.get_reserved_address(ReservedAddresses::HereVar))
-        }
+        self.raw_memory.read_cell(self.get_reserved_address(ReservedAddresses::HereVar)) as Address
     }
 
     pub fn set_dict_ptr(&mut self, address: Address) {
-        unsafe {
-            self.raw_memory.write_u16(self.get_reserved_address(ReservedAddresses::HereVar), address)
+        self.raw_memory.write_cell(self.get_reserved_address(ReservedAddresses::HereVar), address as Cell);
+        self.refresh_stack_bounds();
+    }
+
+    /// Recompute cached stack bounds from dictionary pointer.
+    ///
+    /// Should be called after dictionary pointer is modified by writing to `raw_memory` directly.
+    pub fn refresh_stack_bounds(&mut self) {
+        self.data_stack_bottom = self.get_data_stack_bottom();
+    }
+
+    /// Keep cached stack bounds up to date after a store (e.g. `HERE !`) to given range.
+    pub fn note_store(&mut self, range: AddressRange) {
+        let here_address = self.get_reserved_address(ReservedAddresses::HereVar);
+
+        if *range.start() <= here_address.wrapping_add(CELL_BYTES - 1) && here_address <= *range.end() {
+            self.refresh_stack_bounds();
         }
     }
 
     /// Reset mutable pointers and some reserved variables to initial values.
     pub fn reset(&mut self) {
         self.last_article_ptr = None;
-        self.call_stack_ptr = self.reserved_space_start;
+        self.data_ranges.clear();
+        self.relocations.clear();
+        self.call_stack_ptr = self.heap_start;
         self.data_stack_ptr = self.stacks_border;
+        self.reset_stats();
+        self.reset_heap();
 
         self.reset_builtin_vars()
     }
 
+    /// Greatest data stack depth (in cells) since tracking was enabled or `reset_stats` was called.
+    pub fn max_data_depth(&self) -> u16 {
+        self.max_data_depth
+    }
+
+    /// Greatest call stack depth (in cells) since tracking was enabled or `reset_stats` was called.
+    pub fn max_call_depth(&self) -> u16 {
+        self.max_call_depth
+    }
+
+    /// Restart tracking of stack usage from current depths.
+    pub fn reset_stats(&mut self) {
+        self.max_data_depth = self.data_stack_depth();
+        self.max_call_depth = self.call_stack_depth();
+    }
+
+    /// Update maximal stack depths after a push, if tracking is enabled.
+    #[inline]
+    pub(crate) fn update_stack_usage(&mut self) {
+        if self.track_stack_usage {
+            self.max_data_depth = self.max_data_depth.max(self.data_stack_depth());
+            self.max_call_depth = self.max_call_depth.max(self.call_stack_depth());
+        }
+    }
+
     /// Current depth of call stack in words.
     pub fn call_stack_depth(&self) -> u16 {
-        self.reserved_space_start.wrapping_sub(self.call_stack_ptr) >> 1
+        self.heap_start.wrapping_sub(self.call_stack_ptr) / CELL_BYTES
     }
 
     /// Current depth of data stack in words.
     pub fn data_stack_depth(&self) -> u16 {
-        self.stacks_border.wrapping_sub(self.data_stack_ptr) >> 1
+        self.stacks_border.wrapping_sub(self.data_stack_ptr) / CELL_BYTES
+    }
+
+    /// Check that a stack occupying `bottom..top` may have given stack pointer: it must point to a whole number of
+    /// cells below `top`.
+    fn validate_stack_ptr(ptr: Address, bottom: Address, top: Address, segment_name: &'static str) -> Result<(), MachineError> {
+        if ptr < bottom || ptr > top || !(top - ptr).is_multiple_of(CELL_BYTES) {
+            return Err(MachineError::InvalidStackPointer { address: ptr, segment: bottom..=top, segment_name });
+        }
+
+        Ok(())
+    }
+
+    /// Check that data stack pointer can be moved to given address.
+    pub fn validate_data_stack_ptr(&self, ptr: Address) -> Result<(), MachineError> {
+        MachineMemory::validate_stack_ptr(ptr, self.data_stack_bottom, self.stacks_border, DataStackSegment.name())
+    }
+
+    /// Move data stack pointer to given address, as `SP!` does, e.g. to drop several cells at once.
+    pub fn set_data_stack_ptr(&mut self, ptr: Address) -> Result<(), MachineError> {
+        self.validate_data_stack_ptr(ptr)?;
+        self.data_stack_ptr = ptr;
+        self.update_stack_usage();
+
+        Ok(())
+    }
+
+    /// Move call stack pointer to given address, as `RP!` does.
+    pub fn set_call_stack_ptr(&mut self, ptr: Address) -> Result<(), MachineError> {
+        MachineMemory::validate_stack_ptr(ptr, self.stacks_border, self.heap_start, CallStackSegment.name())?;
+        self.call_stack_ptr = ptr;
+        self.update_stack_usage();
+
+        Ok(())
     }
 
     /// Current size of a dictionary in bytes.
@@ -179,26 +392,97 @@ impl MachineMemory {
         self.get_dict_ptr().wrapping_sub(*self.raw_memory.address_range().start())
     }
 
+    /// Layout configuration this memory was created with.
+    pub fn layout_config(&self) -> MemoryLayoutConfig {
+        self.config
+    }
+
+    /// Address of counted string buffer used to keep words read from input.
+    pub fn get_word_buffer_address(&self) -> Address {
+        self.reserved_space_start + self.config.word_buffer_offset() as Address
+    }
+
+    /// Address of the buffer returned by `PAD`.
+    pub fn get_pad_address(&self) -> Address {
+        self.reserved_space_start + self.config.pad_offset() as Address
+    }
+
+    pub(crate) fn get_pno_buffer_address(&self) -> Address {
+        self.reserved_space_start + self.config.pno_buffer_offset() as Address
+    }
+
+    /// Lowest address data stack may use when it's depth is limited.
+    fn get_data_stack_floor(&self) -> Option<Address> {
+        self.config.max_data_stack_depth.map(|depth| self.stacks_border - CELL_BYTES * depth)
+    }
+
+    /// Lowest address data stack may currently use, taking the guard region above dictionary into account.
+    fn get_data_stack_bottom(&self) -> Address {
+        let guarded_dict_end = self.get_dict_ptr().saturating_add(self.config.guard_size);
+
+        self.get_data_stack_floor().map_or(guarded_dict_end, |floor| floor.max(guarded_dict_end))
+    }
+
+    /// Address dictionary may not grow past, taking the guard region below data stack into account.
+    fn get_dict_limit(&self) -> Address {
+        let stack_bottom = match self.get_data_stack_floor() {
+            Some(floor) => floor.min(self.data_stack_ptr),
+            None => self.data_stack_ptr,
+        };
+
+        stack_bottom.saturating_sub(self.config.guard_size)
+    }
+
+    /// Check if dictionary growth is currently limited by data stack (and the guard region below it) rather than by
+    /// space reserved for data stack.
+    pub fn is_dictionary_limited_by_data_stack(&self) -> bool {
+        self.get_data_stack_floor().is_none_or(|floor| self.data_stack_ptr < floor)
+    }
+
+    /// Check if data stack depth is currently limited by dictionary (and the guard region above it) rather than by
+    /// maximal data stack depth.
+    pub fn is_data_stack_limited_by_dictionary(&self) -> bool {
+        let guarded_dict_end = self.get_dict_ptr().saturating_add(self.config.guard_size);
+
+        self.get_data_stack_floor().is_none_or(|floor| guarded_dict_end > floor)
+    }
+
     /// Get address in reserved address space corresponding to given `ReservedAddress`.
+    ///
+    /// `ReservedAddresses` values are offsets for 16-bit cells, they are scaled when cells are wider.
     pub fn get_reserved_address(&self, address: ReservedAddresses) -> Address {
-        self.reserved_space_start + address.int_value()
+        self.reserved_space_start + (address.int_value() / 2) * CELL_BYTES
     }
 
     /// Range of addresses available for use by call stack.
     pub fn get_call_stack_segment(&self) -> AddressRange {
-        self.stacks_border..=(self.reserved_space_start - 1)
+        self.stacks_border..=(self.heap_start - 1)
+    }
+
+    /// Range of addresses used by heap, empty if there is no heap.
+    pub fn get_heap_segment(&self) -> AddressRange {
+        self.heap_start..=(self.reserved_space_start - 1)
     }
 
     /// Range of addresses currently available for use by data stack.
     ///
     /// May change with writes to dictionary.
     pub fn get_data_stack_segment(&self) -> AddressRange {
-        self.get_dict_ptr()..=(self.stacks_border - 1)
+        self.data_stack_bottom..=(self.stacks_border - 1)
     }
 
     /// Range of data space addresses that are not used by dict or data stack
     pub fn get_free_data_segment(&self) -> AddressRange {
-        self.get_dict_ptr()..=(self.data_stack_ptr - 1)
+        let dict_ptr = self.get_dict_ptr();
+        let dict_limit = self.get_dict_limit();
+
+        if dict_limit <= dict_ptr {
+            // Dictionary has met (or crossed) the data stack, no address is free
+            #[allow(clippy::reversed_empty_ranges)]
+            return 1..=0;
+        }
+
+        dict_ptr..=(dict_limit - 1)
     }
 
     /// Range of addresses currently used by dictionary.
@@ -206,125 +490,201 @@ impl MachineMemory {
         (*self.raw_memory.address_range().start())..=(self.get_dict_ptr().saturating_sub(1))
     }
 
-    fn push_u16(memory: &mut Mem, sp: &mut Address, safe_range: AddressRange, value: u16) -> Result<(), MemoryAccessError> {
-        let next_sp = (*sp).wrapping_sub(2);
+    /// Check that given range lies within given segment, reporting the segment by name on failure.
+    pub fn validate_segment_access(
+        &self,
+        segment: &impl MemorySegment,
+        address_range: AddressRange,
+        kind: AccessKind,
+    ) -> Result<(), MemoryAccessError> {
+        self.raw_memory.validate_named_access(address_range, segment.range(self), segment.name(), kind)
+    }
 
-        memory.validate_access(
-            next_sp..=next_sp.wrapping_add(1),
-            safe_range,
-        )?;
+    /// Check if code at given address may be executed (e.g. used as a target of call or jump).
+    pub fn is_executable(&self, address: Address) -> bool {
+        self.get_used_dict_segment().contains(&address)
+            || self.extra_executable_segment.as_ref().is_some_and(|segment| segment.contains(&address))
+    }
 
-        unsafe { memory.write_u16(next_sp, value) };
+    /// Mark given part of dictionary as data that stays writable when write protection is enabled.
+    pub fn mark_data_space(&mut self, range: AddressRange) {
+        if let Some(last) = self.data_ranges.last_mut() {
+            if last.end().wrapping_add(1) == *range.start() {
+                *last = *last.start()..=*range.end();
+                return;
+            }
+        }
 
-        *sp = next_sp;
+        self.data_ranges.push(range);
+    }
 
-        Ok(())
+    /// Ranges of dictionary marked by `mark_data_space`.
+    pub fn data_ranges(&self) -> &[AddressRange] {
+        &self.data_ranges
     }
 
-    fn get_u16(memory: &Mem, sp: Address, safe_range: AddressRange) -> Result<u16, MemoryAccessError> {
-        memory.validate_access(
-            sp..=sp.wrapping_add(1),
-            safe_range,
-        )?;
+    /// Find a range marked by `mark_data_space` containing given address.
+    pub fn data_range_containing(&self, address: Address) -> Option<AddressRange> {
+        self.data_ranges.iter().find(|range| range.contains(&address)).cloned()
+    }
 
-        Ok(unsafe { memory.read_u16(sp) })
+    /// Note that a 16-bit operand at given address holds an absolute address inside of dictionary.
+    pub fn mark_relocation(&mut self, address: Address) {
+        self.relocations.push(address);
     }
 
-    fn pop_u16(memory: &mut Mem, sp: &mut Address, safe_range: AddressRange) -> Result<u16, MemoryAccessError> {
-        let value = MachineMemory::get_u16(memory, *sp, safe_range)?;
-        *sp = sp.wrapping_add(2);
+    /// Addresses of operands registered by `mark_relocation`, in order they were registered.
+    pub fn relocations(&self) -> &[Address] {
+        &self.relocations
+    }
 
-        Ok(value)
+    /// Check if a store (as opposed to dictionary writes done by compiler) to given range is allowed.
+    pub fn validate_store(&self, range: AddressRange) -> Result<(), MachineError> {
+        if !self.write_protection {
+            return Ok(());
+        }
+
+        let used_dict_segment = self.get_used_dict_segment();
+        // A range wrapping past the end of address space covers both its end and its beginning
+        let length = range.end().wrapping_sub(*range.start()) as u32 + 1;
+
+        for address in (0..length).map(|offset| range.start().wrapping_add(offset as Address)) {
+            if used_dict_segment.contains(&address) && !self.data_ranges.iter().any(|data| data.contains(&address)) {
+                return Err(MachineError::WriteProtected { address });
+            }
+        }
+
+        Ok(())
     }
 
-    fn push_u32(memory: &mut Mem, sp: &mut Address, safe_range: AddressRange, value: u32) -> Result<(), MemoryAccessError> {
-        let next_sp = (*sp).wrapping_sub(4);
+    /// Stack pointer after pushing `size` bytes to a stack occupying `bottom..top`, if they fit.
+    #[inline]
+    fn push_ptr(sp: Address, size: u16, bottom: Address, top: Address) -> Option<Address> {
+        let next_sp = sp.wrapping_sub(size);
 
-        memory.validate_access(
-            next_sp..=next_sp.wrapping_add(3),
-            safe_range,
-        )?;
+        (next_sp >= bottom && next_sp as u32 + size as u32 <= top as u32).then_some(next_sp)
+    }
 
-        unsafe { memory.write_u32(next_sp, value) };
+    /// Stack pointer after popping `size` bytes from a stack occupying `bottom..top`, if there are enough of them.
+    #[inline]
+    fn pop_ptr(sp: Address, size: u16, bottom: Address, top: Address) -> Option<Address> {
+        (sp >= bottom && sp as u32 + size as u32 <= top as u32).then(|| sp.wrapping_add(size))
+    }
 
-        *sp = next_sp;
+    pub fn data_push_cell(&mut self, value: Cell) -> Result<(), MachineError> {
+        let next_sp = MachineMemory::push_ptr(self.data_stack_ptr, CELL_BYTES, self.data_stack_bottom, self.stacks_border)
+            .ok_or(MachineError::DataStackOverflow { requested: 1, operation: StackOperation::default() })?;
+        self.raw_memory.write_cell(next_sp, value);
+        self.data_stack_ptr = next_sp;
+        self.update_stack_usage();
 
         Ok(())
     }
 
-    fn get_u32(memory: &Mem, sp: Address, safe_range: AddressRange) -> Result<u32, MemoryAccessError> {
-        memory.validate_access(
-            sp..=sp.wrapping_add(3),
-            safe_range,
-        )?;
+    pub fn data_pop_cell(&mut self) -> Result<Cell, MachineError> {
+        let next_sp = MachineMemory::pop_ptr(self.data_stack_ptr, CELL_BYTES, self.data_stack_bottom, self.stacks_border)
+            .ok_or(MachineError::DataStackUnderflow { requested: 1, operation: StackOperation::default() })?;
+        let value = self.raw_memory.read_cell(self.data_stack_ptr);
+        self.data_stack_ptr = next_sp;
 
-        Ok(unsafe { memory.read_u32(sp) })
+        Ok(value)
     }
 
-    fn pop_u32(memory: &mut Mem, sp: &mut Address, safe_range: AddressRange) -> Result<u32, MemoryAccessError> {
-        let value = MachineMemory::get_u32(memory, *sp, safe_range)?;
-        *sp = sp.wrapping_add(4);
+    pub fn data_push_double_cell(&mut self, value: DoubleCell) -> Result<(), MachineError> {
+        let next_sp = MachineMemory::push_ptr(self.data_stack_ptr, DOUBLE_CELL_BYTES, self.data_stack_bottom, self.stacks_border)
+            .ok_or(MachineError::DataStackOverflow { requested: 2, operation: StackOperation::default() })?;
+        self.raw_memory.write_double_cell(next_sp, value);
+        self.data_stack_ptr = next_sp;
+        self.update_stack_usage();
 
-        Ok(value)
+        Ok(())
     }
 
-    pub fn data_push_u16(&mut self, value: u16) -> Result<(), MemoryAccessError> {
-        let segment = self.get_data_stack_segment();
-        MachineMemory::push_u16(&mut self.raw_memory, &mut self.data_stack_ptr, segment, value)
+    pub fn data_pop_double_cell(&mut self) -> Result<DoubleCell, MachineError> {
+        let next_sp = MachineMemory::pop_ptr(self.data_stack_ptr, DOUBLE_CELL_BYTES, self.data_stack_bottom, self.stacks_border)
+            .ok_or(MachineError::DataStackUnderflow { requested: 2, operation: StackOperation::default() })?;
+        let value = self.raw_memory.read_double_cell(self.data_stack_ptr);
+        self.data_stack_ptr = next_sp;
+
+        Ok(value)
     }
 
-    pub fn data_pop_u16(&mut self) -> Result<u16, MemoryAccessError> {
-        let segment = self.get_data_stack_segment();
-        MachineMemory::pop_u16(&mut self.raw_memory, &mut self.data_stack_ptr, segment)
+    pub fn call_push_cell(&mut self, value: Cell) -> Result<(), MachineError> {
+        let next_sp = MachineMemory::push_ptr(self.call_stack_ptr, CELL_BYTES, self.stacks_border, self.heap_start)
+            .ok_or(MachineError::CallStackOverflow { requested: 1, operation: StackOperation::default() })?;
+        self.raw_memory.write_cell(next_sp, value);
+        self.call_stack_ptr = next_sp;
+        self.update_stack_usage();
+
+        Ok(())
     }
 
-    pub fn data_push_u32(&mut self, value: u32) -> Result<(), MemoryAccessError> {
-        let segment = self.get_data_stack_segment();
-        MachineMemory::push_u32(&mut self.raw_memory, &mut self.data_stack_ptr, segment, value)
+    pub fn call_push_double_cell(&mut self, value: DoubleCell) -> Result<(), MachineError> {
+        let next_sp = MachineMemory::push_ptr(self.call_stack_ptr, DOUBLE_CELL_BYTES, self.stacks_border, self.heap_start)
+            .ok_or(MachineError::CallStackOverflow { requested: 2, operation: StackOperation::default() })?;
+        self.raw_memory.write_double_cell(next_sp, value);
+        self.call_stack_ptr = next_sp;
+        self.update_stack_usage();
+
+        Ok(())
     }
 
-    pub fn data_pop_u32(&mut self) -> Result<u32, MemoryAccessError> {
-        let segment = self.get_data_stack_segment();
-        MachineMemory::pop_u32(&mut self.raw_memory, &mut self.data_stack_ptr, segment)
+    pub fn call_pop_cell(&mut self) -> Result<Cell, MachineError> {
+        let value = self.call_get_cell()?;
+        self.call_stack_ptr = self.call_stack_ptr.wrapping_add(CELL_BYTES);
+
+        Ok(value)
     }
 
-    pub fn call_push_u16(&mut self, value: u16) -> Result<(), MemoryAccessError> {
-        let segment = self.get_call_stack_segment();
-        MachineMemory::push_u16(&mut self.raw_memory, &mut self.call_stack_ptr, segment, value)
+    pub fn call_get_cell(&self) -> Result<Cell, MachineError> {
+        MachineMemory::pop_ptr(self.call_stack_ptr, CELL_BYTES, self.stacks_border, self.heap_start)
+            .ok_or(MachineError::CallStackUnderflow { requested: 1, operation: StackOperation::default() })?;
+
+        Ok(self.raw_memory.read_cell(self.call_stack_ptr))
     }
 
-    pub fn call_push_u32(&mut self, value: u32) -> Result<(), MemoryAccessError> {
-        let segment = self.get_call_stack_segment();
-        MachineMemory::push_u32(&mut self.raw_memory, &mut self.call_stack_ptr, segment, value)
+    pub fn call_pop_double_cell(&mut self) -> Result<DoubleCell, MachineError> {
+        let value = self.call_get_double_cell()?;
+        self.call_stack_ptr = self.call_stack_ptr.wrapping_add(DOUBLE_CELL_BYTES);
+
+        Ok(value)
     }
 
-    pub fn call_pop_u16(&mut self) -> Result<u16, MemoryAccessError> {
-        let segment = self.get_call_stack_segment();
-        MachineMemory::pop_u16(&mut self.raw_memory, &mut self.call_stack_ptr, segment)
+    pub fn call_get_double_cell(&self) -> Result<DoubleCell, MachineError> {
+        MachineMemory::pop_ptr(self.call_stack_ptr, DOUBLE_CELL_BYTES, self.stacks_border, self.heap_start)
+            .ok_or(MachineError::CallStackUnderflow { requested: 2, operation: StackOperation::default() })?;
+
+        Ok(self.raw_memory.read_double_cell(self.call_stack_ptr))
     }
 
-    pub fn call_get_u16(&self) -> Result<u16, MemoryAccessError> {
-        let segment = self.get_call_stack_segment();
-        MachineMemory::get_u16(&self.raw_memory, self.call_stack_ptr, segment)
+    /// Push a return address to call stack.
+    pub fn call_push_address(&mut self, address: Address) -> Result<(), MachineError> {
+        self.call_push_cell(address as Cell)
     }
 
-    pub fn call_pop_u32(&mut self) -> Result<u32, MemoryAccessError> {
-        let segment = self.get_call_stack_segment();
-        MachineMemory::pop_u32(&mut self.raw_memory, &mut self.call_stack_ptr, segment)
+    /// Pop a return address from call stack.
+    pub fn call_pop_address(&mut self) -> Result<Address, MachineError> {
+        Ok(self.call_pop_cell()? as Address)
     }
 
-    pub fn call_get_u32(&self) -> Result<u32, MemoryAccessError> {
-        let segment = self.get_call_stack_segment();
-        MachineMemory::get_u32(&self.raw_memory, self.call_stack_ptr, segment)
+    /// Number of bytes dictionary may still grow by before it meets the data stack.
+    pub fn free_data_space(&self) -> u16 {
+        self.get_dict_limit().saturating_sub(self.get_dict_ptr())
     }
 
-    pub fn dict_write_u8(&mut self, value: u8) -> Result<(), MemoryAccessError> {
-        let dict_ptr = self.get_dict_ptr();
+    /// Check that `size` more bytes can be written to dictionary and return address to write them at.
+    fn reserve_dict_space(&self, size: u16) -> Result<Address, MachineError> {
+        let available = self.free_data_space();
 
-        self.raw_memory.validate_access(
-            dict_ptr..=dict_ptr,
-            self.get_free_data_segment(),
-        )?;
+        if size > available {
+            return Err(MachineError::OutOfDataSpace { needed: size, available });
+        }
+
+        Ok(self.get_dict_ptr())
+    }
+
+    pub fn dict_write_u8(&mut self, value: u8) -> Result<(), MachineError> {
+        let dict_ptr = self.reserve_dict_space(1)?;
 
         self.raw_memory.write_u8(dict_ptr, value);
         self.set_dict_ptr(dict_ptr.wrapping_add(1));
@@ -332,49 +692,61 @@ impl MachineMemory {
         Ok(())
     }
 
-    pub fn dict_write_opcode(&mut self, value: OpCode) -> Result<(), MemoryAccessError> {
+    pub fn dict_write_opcode(&mut self, value: OpCode) -> Result<(), MachineError> {
         self.dict_write_u8(value.int_value())
     }
 
-    pub fn dict_write_u16(&mut self, value: u16) -> Result<(), MemoryAccessError> {
-        let dict_ptr = self.get_dict_ptr();
-
-        self.raw_memory.validate_access(
-            dict_ptr..=(dict_ptr.wrapping_add(1)),
-            self.get_free_data_segment(),
-        )?;
+    pub fn dict_write_u16(&mut self, value: u16) -> Result<(), MachineError> {
+        let dict_ptr = self.reserve_dict_space(2)?;
 
-        unsafe { self.raw_memory.write_u16(dict_ptr, value) };
+        self.raw_memory.write_u16(dict_ptr, value);
         self.set_dict_ptr(dict_ptr.wrapping_add(2));
 
         Ok(())
     }
 
-    pub fn dict_write_u32(&mut self, value: u32) -> Result<(), MemoryAccessError> {
-        let dict_ptr = self.get_dict_ptr();
+    /// Pad dictionary with `Noop` op-codes up to the next cell boundary.
+    pub fn dict_align(&mut self) -> Result<(), MachineError> {
+        while self.get_dict_ptr() != aligned(self.get_dict_ptr()) {
+            self.dict_write_opcode(OpCode::Noop)?;
+        }
 
-        self.raw_memory.validate_access(
-            dict_ptr..=(dict_ptr.wrapping_add(3)),
-            self.get_free_data_segment(),
-        )?;
+        Ok(())
+    }
 
-        unsafe { self.raw_memory.write_u32(dict_ptr, value) };
-        self.set_dict_ptr(dict_ptr.wrapping_add(4));
+    pub fn dict_write_cell(&mut self, value: Cell) -> Result<(), MachineError> {
+        let dict_ptr = self.reserve_dict_space(CELL_BYTES)?;
+
+        self.raw_memory.write_cell(dict_ptr, value);
+        self.set_dict_ptr(dict_ptr.wrapping_add(CELL_BYTES));
 
         Ok(())
     }
 
-    pub fn dict_write_sized_string(&mut self, address: Address) -> Result<(), MemoryAccessError> {
-        let dict_ptr = self.get_dict_ptr();
+    pub fn dict_write_double_cell(&mut self, value: DoubleCell) -> Result<(), MachineError> {
+        let dict_ptr = self.reserve_dict_space(DOUBLE_CELL_BYTES)?;
 
+        self.raw_memory.write_double_cell(dict_ptr, value);
+        self.set_dict_ptr(dict_ptr.wrapping_add(DOUBLE_CELL_BYTES));
+
+        Ok(())
+    }
+
+    pub fn dict_write_u32(&mut self, value: u32) -> Result<(), MachineError> {
+        let dict_ptr = self.reserve_dict_space(4)?;
+
+        self.raw_memory.write_u32(dict_ptr, value);
+        self.set_dict_ptr(dict_ptr.wrapping_add(4));
+
+        Ok(())
+    }
+
+    pub fn dict_write_sized_string(&mut self, address: Address) -> Result<(), MachineError> {
         let s = ReadableSizedString::new(&self.raw_memory, address, self.raw_memory.address_range())?;
         let length = s.read_length();
         let content_address = s.content_address();
 
-        self.raw_memory.validate_access(
-            dict_ptr..=(dict_ptr.wrapping_add(1).wrapping_add(length as u16)),
-            self.get_free_data_segment(),
-        )?;
+        let dict_ptr = self.reserve_dict_space(1 + length as u16)?;
 
         self.raw_memory.write_u8(dict_ptr, length);
 
@@ -397,7 +769,7 @@ impl MachineMemory {
         };
 
         loop {
-            if current_article.name().as_bytes() == name {
+            if *current_article.name().as_bytes() == *name {
                 return Ok(Some(current_article));
             }
 
@@ -411,15 +783,29 @@ impl MachineMemory {
     pub fn lookup_article_name_buf(&self, name_address: Address) -> Result<Option<ReadableArticle>, MemoryAccessError> {
         let s = ReadableSizedString::new(&self.raw_memory, name_address, self.raw_memory.address_range())?;
 
-        self.lookup_article(s.as_bytes())
+        self.lookup_article(&s.as_bytes())
     }
 
     pub fn read_input_word(&mut self, input: &mut dyn Input) -> Result<Option<Address>, InputError> {
-        let buffer_address = self.get_reserved_address(ReservedAddresses::WordBuffer);
+        let buffer_address = self.get_word_buffer_address();
         let content_address = buffer_address + 1;
+        let max_length = self.config.max_word_length as usize;
+
+        let mut word_buffer = [0u8; u8::MAX as usize];
+        let word = match input.read_word(&mut word_buffer[..max_length]) {
+            Ok(word) => word,
+            Err(err) => {
+                // Keep beginning of a too long word in the buffer, so it can be accepted by `truncate_input_word`.
+                if let InputError::WordTooLong { .. } = err {
+                    self.raw_memory.write_slice(content_address, &word_buffer[..max_length]);
+                }
+
+                return Err(err);
+            }
+        };
+        let word_length = word.len();
 
-        let word_length = input.read_word(self.raw_memory.address_slice_mut(content_address, 255))?.len();
-
+        self.raw_memory.write_slice(content_address, word);
         self.raw_memory.write_u8(buffer_address, word_length as u8);
 
         if word_length > 0 {
@@ -429,12 +815,54 @@ impl MachineMemory {
         }
     }
 
+    /// Mark word buffer as containing a word of maximal length.
+    ///
+    /// Used to accept the beginning of a word after `read_input_word` failed with `InputError::WordTooLong`.
+    pub fn truncate_input_word(&mut self) -> Address {
+        let buffer_address = self.get_word_buffer_address();
+
+        self.raw_memory.write_u8(buffer_address, self.config.max_word_length);
+
+        buffer_address
+    }
+
+    /// Put given word to word buffer as if it was read from input.
+    ///
+    /// Returns `None` if the word is empty or too long for the buffer.
+    pub fn set_input_word(&mut self, word: &[u8]) -> Option<Address> {
+        if word.is_empty() || word.len() > self.config.max_word_length as usize {
+            return None;
+        }
+
+        let buffer_address = self.get_word_buffer_address();
+
+        self.raw_memory.write_slice(buffer_address + 1, word);
+        self.raw_memory.write_u8(buffer_address, word.len() as u8);
+
+        Some(buffer_address)
+    }
+
+    /// Read a string given by address and size (as left on data stack by `S"`).
+    ///
+    /// Invalid UTF-8 sequences are replaced by replacement characters.
+    pub fn read_string(&self, address: Address, size: u16) -> Result<String, MemoryAccessError> {
+        if size == 0 {
+            return Ok(String::new());
+        }
+
+        self.validate_segment_access(&WholeMemorySegment, address..=address.wrapping_add(size - 1), AccessKind::Read)?;
+
+        Ok(String::from_utf8_lossy(&self.raw_memory.address_slice(address, size as usize)).into_owned())
+    }
+
     pub fn copy_string(&mut self, src_address: Address, dst_address: Address, dst_segment: AddressRange) -> Result<(), MemoryAccessError> {
         let src_range = ReadableSizedString::new(&self.raw_memory, src_address, self.raw_memory.address_range())?.full_range();
 
-        self.raw_memory.validate_access(
+        self.raw_memory.validate_named_access(
             dst_address..=(dst_address.wrapping_add((src_range.len() - 1) as u16)),
             dst_segment,
+            WHOLE_MEMORY,
+            AccessKind::Write,
         )?;
 
         for src_byte_address in src_range {
@@ -451,10 +879,39 @@ impl MachineMemory {
         ReadableArticlesIterator::new(&self.raw_memory, self.last_article_ptr, self.get_used_dict_segment())
     }
 
+    /// Address right after the last byte of given article, i.e. header of the article defined after it or
+    /// dictionary pointer for the last article.
+    pub fn article_end(&self, article: &ReadableArticle) -> Address {
+        let mut limit = self.get_dict_ptr();
+
+        for other in self.articles() {
+            if other.get_header_address() == article.get_header_address() {
+                break;
+            }
+
+            limit = other.get_header_address();
+        }
+
+        limit
+    }
+
+    /// Find an article whose header or body contains given address.
+    pub fn article_containing(&self, address: Address) -> Option<ReadableArticle<'_>> {
+        let mut limit = self.get_dict_ptr();
+
+        for article in self.articles() {
+            if article.get_header_address() <= address && address < limit {
+                return Some(article);
+            }
+
+            limit = article.get_header_address();
+        }
+
+        None
+    }
+
     pub fn get_current_word(&self) -> Option<Address> {
-        let addr = unsafe {
-            self.raw_memory.read_u16(self.get_reserved_address(ReservedAddresses::CurrentDefVar))
-        };
+        let addr = self.raw_memory.read_cell(self.get_reserved_address(ReservedAddresses::CurrentDefVar)) as Address;
 
         if addr >= self.get_dict_ptr() {
             return None;
@@ -464,26 +921,22 @@ impl MachineMemory {
     }
 
     pub fn set_current_word(&mut self, header_address: Option<Address>) {
-        unsafe {
-            self.raw_memory.write_u16(
-                self.get_reserved_address(ReservedAddresses::CurrentDefVar),
-                match header_address {
-                    None => 0xffff,
-                    Some(addr) => addr
-                },
-            )
-        }
+        self.raw_memory.write_cell(
+            self.get_reserved_address(ReservedAddresses::CurrentDefVar),
+            match header_address {
+                None => Address::MAX as Cell,
+                Some(addr) => addr as Cell
+            },
+        )
     }
 
-    pub fn get_base(&self) -> u16 {
-        unsafe {
-            self.raw_memory.read_u16(self.get_reserved_address(ReservedAddresses::BaseVar))
-        }
+    pub fn get_base(&self) -> Cell {
+        self.raw_memory.read_cell(self.get_reserved_address(ReservedAddresses::BaseVar))
     }
 
     pub fn get_pno_buffer_range(&self) -> AddressRange {
-        let start_address = self.get_reserved_address(ReservedAddresses::PnoBuffer);
-        start_address..=(start_address.wrapping_add(127))
+        let start_address = self.get_pno_buffer_address();
+        start_address..=(start_address.wrapping_add(self.config.max_pno_length as u16))
     }
 
     pub fn get_pno_content_range(&self) -> AddressRange {
@@ -493,34 +946,31 @@ impl MachineMemory {
 
     pub fn clear_pno_buffer(&mut self) {
         self.raw_memory.write_u8(
-            self.get_reserved_address(ReservedAddresses::PnoBuffer),
+            self.get_pno_buffer_address(),
             0,
         );
     }
 
     pub fn pno_put(&mut self, ch: u8) -> Result<(), MemoryAccessError> {
-        let current_size = self.raw_memory.read_u8(self.get_reserved_address(ReservedAddresses::PnoBuffer));
+        let current_size = self.raw_memory.read_u8(self.get_pno_buffer_address());
         let content_range = self.get_pno_content_range();
         let write_address = content_range.end().wrapping_sub(current_size as u16);
-        self.raw_memory.validate_access(
-            write_address..=write_address,
-            content_range,
-        )?;
+        self.validate_segment_access(&PnoBufferSegment, write_address..=write_address, AccessKind::Write)?;
 
         self.raw_memory.write_u8(write_address, ch);
-        self.raw_memory.write_u8(self.get_reserved_address(ReservedAddresses::PnoBuffer), current_size.wrapping_add(1));
+        self.raw_memory.write_u8(self.get_pno_buffer_address(), current_size.wrapping_add(1));
 
         Ok(())
     }
 
     pub fn pno_finish(&self) -> (Address, u8) {
-        let size = self.raw_memory.read_u8(self.get_reserved_address(ReservedAddresses::PnoBuffer));
+        let size = self.raw_memory.read_u8(self.get_pno_buffer_address());
         let address = self.get_pno_content_range().end().wrapping_sub(size as u16).wrapping_add(1);
         (address, size)
     }
 
     pub fn get_state(&self) -> MachineState {
-        let raw_value = unsafe { self.raw_memory.read_u16(self.get_reserved_address(ReservedAddresses::StateVar)) };
+        let raw_value = self.raw_memory.read_cell(self.get_reserved_address(ReservedAddresses::StateVar));
 
         if raw_value == 0 {
             MachineState::Interpreter
@@ -532,17 +982,18 @@ impl MachineMemory {
     pub fn set_state(&mut self, state: MachineState) {
         let raw_value = match state {
             MachineState::Interpreter => 0,
-            MachineState::Compiler => 0xFFFF,
+            MachineState::Compiler => TRUE,
         };
 
-        unsafe {
-            self.raw_memory.write_u16(self.get_reserved_address(ReservedAddresses::StateVar), raw_value);
-        }
+        self.raw_memory.write_cell(self.get_reserved_address(ReservedAddresses::StateVar), raw_value);
     }
 }
 
 #[cfg(test)]
 mod test {
+    use crate::input::StaticStringInput;
+    use crate::print_debug_info::StackFormatOptions;
+
     use super::*;
 
     fn make_mem() -> MachineMemory {
@@ -555,48 +1006,183 @@ mod test {
 
         assert_eq!(mm.data_stack_depth(), 0);
 
-        mm.data_push_u16(10500).unwrap();
+        mm.data_push_cell(10500).unwrap();
         assert_eq!(mm.data_stack_depth(), 1);
 
-        mm.data_push_u16(10501).unwrap();
+        mm.data_push_cell(10501).unwrap();
         assert_eq!(mm.data_stack_depth(), 2);
 
-        mm.data_push_u32(0xf000baaa).unwrap();
+        mm.data_push_double_cell(0xf000baaa).unwrap();
         assert_eq!(mm.data_stack_depth(), 4);
 
-        assert_eq!(mm.data_pop_u32().unwrap(), 0xf000baaa);
-        assert_eq!(mm.data_pop_u16().unwrap(), 10501);
-        assert_eq!(mm.data_pop_u16().unwrap(), 10500);
-        assert!(mm.data_pop_u16().is_err()); // Underflow
+        assert_eq!(mm.data_pop_double_cell().unwrap(), 0xf000baaa);
+        assert_eq!(mm.data_pop_cell().unwrap(), 10501);
+        assert_eq!(mm.data_pop_cell().unwrap(), 10500);
+        assert!(mm.data_pop_cell().is_err()); // Underflow
+    }
+
+    #[test]
+    fn test_validate_wrapped_store() {
+        let mut mm = make_mem();
+        mm.write_protection = true;
+        mm.dict_write_u16(0).unwrap();
+
+        assert!(matches!(mm.validate_store(AddressRange::new(0xFFFF, 0x0000)), Err(MachineError::WriteProtected { address: 0 })));
+        assert!(mm.validate_store(0xFFFE..=0xFFFF).is_ok());
     }
 
     #[test]
     fn test_call_stack() {
         let mut mm = make_mem();
 
-        mm.call_push_u16(0xdead).unwrap();
-        mm.call_push_u16(0xc0de).unwrap();
+        mm.call_push_cell(0xdead).unwrap();
+        mm.call_push_cell(0xc0de).unwrap();
 
-        assert_eq!(mm.call_pop_u16().unwrap(), 0xc0de);
-        assert_eq!(mm.call_pop_u16().unwrap(), 0xdead);
-        assert!(mm.call_pop_u16().is_err()); // Underflow
+        assert_eq!(mm.call_pop_cell().unwrap(), 0xc0de);
+        assert_eq!(mm.call_pop_cell().unwrap(), 0xdead);
+        assert!(mm.call_pop_cell().is_err()); // Underflow
     }
 
     #[test]
     fn test_call_stack_overflow() {
         let mut mm = make_mem();
 
-        assert!(mm.call_pop_u16().is_err()); // Underflow, to ensure that stack pointer does not change
+        assert!(mm.call_pop_cell().is_err()); // Underflow, to ensure that stack pointer does not change
 
         for i in 0..MemoryLayoutConfig::default().max_call_stack_depth {
-            mm.call_push_u16(i).unwrap();
+            mm.call_push_cell(i as Cell).unwrap();
         }
 
-        assert!(mm.call_push_u16(0xdead).is_err());
+        assert!(mm.call_push_cell(0xdead).is_err());
 
-        mm.call_pop_u16().unwrap();
+        mm.call_pop_cell().unwrap();
 
-        mm.call_push_u16(0x0000).unwrap();
+        mm.call_push_cell(0x0000).unwrap();
+    }
+
+    #[test]
+    fn test_data_stack_underflow_error() {
+        let mut mm = make_mem();
+
+        mm.data_push_cell(1).unwrap();
+
+        assert!(matches!(mm.data_pop_double_cell(), Err(MachineError::DataStackUnderflow { requested: 2, .. })));
+        assert_eq!(mm.data_stack_depth(), 1);
+    }
+
+    #[test]
+    fn test_data_stack_overflow_error() {
+        let mut mm = make_mem();
+
+        mm.set_dict_ptr(mm.data_stack_ptr - CELL_BYTES - MemoryLayoutConfig::default().guard_size);
+        mm.data_push_cell(1).unwrap();
+
+        assert!(matches!(mm.data_push_cell(2), Err(MachineError::DataStackOverflow { requested: 1, .. })));
+        assert_eq!(mm.data_stack_depth(), 1);
+    }
+
+    #[test]
+    fn test_call_stack_underflow_error() {
+        let mut mm = make_mem();
+
+        assert!(matches!(mm.call_pop_cell(), Err(MachineError::CallStackUnderflow { requested: 1, .. })));
+        assert!(matches!(mm.call_get_double_cell(), Err(MachineError::CallStackUnderflow { requested: 2, .. })));
+    }
+
+    #[test]
+    fn test_call_stack_overflow_error() {
+        let mut mm = make_mem();
+
+        for i in 0..MemoryLayoutConfig::default().max_call_stack_depth {
+            mm.call_push_cell(i as Cell).unwrap();
+        }
+
+        assert!(matches!(mm.call_push_cell(0xdead), Err(MachineError::CallStackOverflow { requested: 1, .. })));
+    }
+
+    #[test]
+    fn test_data_stack_exact_boundary() {
+        let mut mm = make_mem();
+
+        mm.set_dict_ptr(mm.data_stack_ptr - 2 * CELL_BYTES - MemoryLayoutConfig::default().guard_size);
+        mm.data_push_cell(1).unwrap();
+
+        assert!(matches!(mm.data_push_double_cell(0), Err(MachineError::DataStackOverflow { requested: 2, .. })));
+        assert_eq!(mm.data_stack_depth(), 1);
+
+        mm.data_push_cell(2).unwrap();
+
+        assert!(matches!(mm.data_push_cell(3), Err(MachineError::DataStackOverflow { requested: 1, .. })));
+        assert_eq!(mm.data_stack_depth(), 2);
+
+        mm.data_pop_double_cell().unwrap();
+
+        assert!(matches!(mm.data_pop_cell(), Err(MachineError::DataStackUnderflow { requested: 1, .. })));
+        assert!(matches!(mm.data_pop_double_cell(), Err(MachineError::DataStackUnderflow { requested: 2, .. })));
+        assert_eq!(mm.data_stack_depth(), 0);
+    }
+
+    #[test]
+    fn test_call_stack_exact_boundary() {
+        let mut mm = make_mem();
+
+        for i in 0..(MemoryLayoutConfig::default().max_call_stack_depth - 1) {
+            mm.call_push_cell(i as Cell).unwrap();
+        }
+
+        assert!(matches!(mm.call_push_double_cell(0), Err(MachineError::CallStackOverflow { requested: 2, .. })));
+
+        mm.call_push_cell(0xdead).unwrap();
+
+        assert!(matches!(mm.call_push_cell(0), Err(MachineError::CallStackOverflow { requested: 1, .. })));
+        assert_eq!(mm.call_get_cell().unwrap(), 0xdead);
+        assert_eq!(mm.call_stack_depth(), MemoryLayoutConfig::default().max_call_stack_depth);
+
+        mm.reset();
+        mm.call_push_cell(1).unwrap();
+
+        assert!(matches!(mm.call_get_double_cell(), Err(MachineError::CallStackUnderflow { requested: 2, .. })));
+        assert!(matches!(mm.call_pop_double_cell(), Err(MachineError::CallStackUnderflow { requested: 2, .. })));
+        assert_eq!(mm.call_pop_cell().unwrap(), 1);
+    }
+
+    #[test]
+    fn test_stack_bounds_follow_dictionary_pointer_store() {
+        let mut mm = make_mem();
+        let here_address = mm.get_reserved_address(ReservedAddresses::HereVar);
+        let new_dict_ptr = mm.data_stack_ptr - CELL_BYTES - MemoryLayoutConfig::default().guard_size;
+
+        mm.raw_memory.write_cell(here_address, new_dict_ptr as Cell);
+        mm.note_store(here_address..=here_address.wrapping_add(CELL_BYTES - 1));
+
+        assert_eq!(*mm.get_data_stack_segment().start(), mm.data_stack_ptr - CELL_BYTES);
+        mm.data_push_cell(1).unwrap();
+        assert!(matches!(mm.data_push_cell(2), Err(MachineError::DataStackOverflow { requested: 1, .. })));
+    }
+
+    #[test]
+    fn test_dict_write_into_stack() {
+        let mut mm = make_mem();
+
+        mm.data_push_cell(0x1234).unwrap();
+        mm.set_dict_ptr(mm.data_stack_ptr - MemoryLayoutConfig::default().guard_size - 1);
+
+        assert!(matches!(
+            mm.dict_write_u16(0xdead),
+            Err(MachineError::OutOfDataSpace { needed: 2, available: 1 })
+        ));
+        assert_eq!(mm.data_pop_cell().unwrap(), 0x1234);
+    }
+
+    #[test]
+    fn test_free_segment_when_dictionary_crossed_stack() {
+        let mut mm = make_mem();
+
+        mm.set_dict_ptr(mm.data_stack_ptr + 2);
+
+        assert_eq!(mm.free_data_space(), 0);
+        assert!(mm.get_free_data_segment().is_empty());
+        assert!(matches!(mm.dict_write_u8(0), Err(MachineError::OutOfDataSpace { needed: 1, available: 0 })));
     }
 
     #[test]
@@ -604,8 +1190,156 @@ mod test {
         let mm = make_mem();
 
         assert_eq!(
-            unsafe { mm.raw_memory.read_u16(mm.get_reserved_address(ReservedAddresses::BaseVar)) },
+            mm.raw_memory.read_u16(mm.get_reserved_address(ReservedAddresses::BaseVar)),
             10
         );
     }
+
+    fn tiny_config() -> MemoryLayoutConfig {
+        MemoryLayoutConfig {
+            max_call_stack_depth: 4,
+            max_data_stack_depth: Some(4),
+            max_word_length: 8,
+            pad_size: 16,
+            max_pno_length: 8,
+            guard_size: 0,
+            heap_size: 0,
+        }
+    }
+
+    #[test]
+    fn test_default_layout() {
+        let mm = make_mem();
+
+        assert_eq!(mm.get_word_buffer_address(), 0xFD00 + 256);
+        assert_eq!(mm.get_pad_address(), 0xFD00 + 512);
+        assert_eq!(mm.get_pno_buffer_range(), (0xFD00 + 640)..=0xFFFF);
+    }
+
+    #[test]
+    fn test_tiny_layout_boundaries() {
+        let mut mm = MachineMemory::new(Mem::default(), tiny_config());
+
+        for i in 0..4 {
+            mm.data_push_cell(i).unwrap();
+            mm.call_push_cell(i as Cell).unwrap();
+        }
+        assert!(matches!(mm.data_push_cell(4), Err(MachineError::DataStackOverflow { .. })));
+        assert!(matches!(mm.call_push_cell(4), Err(MachineError::CallStackOverflow { .. })));
+
+        for i in 0..8 {
+            mm.pno_put(b'0' + i).unwrap();
+        }
+        assert!(mm.pno_put(b'8').is_err());
+        assert_eq!(*mm.get_pno_buffer_range().end(), Address::MAX);
+
+        let mut input = StaticStringInput::new("12345678 123456789");
+        assert!(mm.read_input_word(&mut input).unwrap().is_some());
+        assert!(matches!(mm.read_input_word(&mut input), Err(InputError::WordTooLong { .. })));
+    }
+
+    #[test]
+    fn test_data_stack_floor_limits_dictionary() {
+        let mut mm = MachineMemory::new(Mem::default(), tiny_config());
+        let data_stack_floor = *mm.get_data_stack_segment().start();
+
+        assert_eq!(data_stack_floor, mm.data_stack_ptr - 4 * CELL_BYTES);
+
+        mm.set_dict_ptr(data_stack_floor - 1);
+        assert!(matches!(mm.dict_write_u16(0), Err(MachineError::OutOfDataSpace { needed: 2, available: 1 })));
+        mm.dict_write_u8(0).unwrap();
+        assert_eq!(mm.free_data_space(), 0);
+    }
+
+    #[test]
+    fn test_large_layout_boundaries() {
+        let config = MemoryLayoutConfig {
+            max_call_stack_depth: 1024,
+            max_data_stack_depth: Some(2048),
+            max_word_length: 255,
+            pad_size: 1024,
+            max_pno_length: 255,
+            guard_size: 8,
+            heap_size: 4096,
+        };
+        let mut mm = MachineMemory::new(Mem::default(), config);
+
+        for i in 0..1024 {
+            mm.call_push_cell(i as Cell).unwrap();
+        }
+        assert!(matches!(mm.call_push_cell(0), Err(MachineError::CallStackOverflow { .. })));
+
+        for i in 0..2048 {
+            mm.data_push_cell(i).unwrap();
+        }
+        assert!(matches!(mm.data_push_cell(0), Err(MachineError::DataStackOverflow { .. })));
+
+        for _ in 0..255 {
+            mm.pno_put(b'0').unwrap();
+        }
+        assert!(mm.pno_put(b'0').is_err());
+    }
+
+    #[test]
+    fn test_layout_too_large_for_memory() {
+        let config = MemoryLayoutConfig { max_data_stack_depth: Some(0x7F00), ..MemoryLayoutConfig::default() };
+
+        assert!(!config.fits_memory_size(0x10000));
+        assert!(MemoryLayoutConfig::default().fits_memory_size(0x10000));
+    }
+
+    #[test]
+    fn test_format_stack() {
+        let mut mm = make_mem();
+        mm.data_push_cell(-1i16 as Cell).unwrap();
+        mm.data_push_cell(40000).unwrap();
+        mm.data_push_double_cell(0x12345678).unwrap();
+
+        let format = |options| {
+            let mut buf = Vec::new();
+            mm.format_stack(&mut buf, options).unwrap();
+
+            String::from_utf8(buf).unwrap()
+        };
+
+        let cells = format(StackFormatOptions::default());
+        let doubles = format(StackFormatOptions { double_cells: true, ..StackFormatOptions::default() });
+        let top = format(StackFormatOptions { double_cells: true, max_entries: 1, signed: false });
+
+        #[cfg(not(feature = "cell32"))]
+        {
+            assert_eq!(cells, "\tFFFF (65535,     -1), 9C40 (40000, -25536), 1234 ( 4660,   4660), 5678 (22136,  22136)\n");
+            assert_eq!(doubles, "\tFFFF9C40 (4294941760,      -25536), 12345678 ( 305419896,   305419896)\n");
+        }
+
+        #[cfg(feature = "cell32")]
+        {
+            assert_eq!(cells, "\tFFFFFFFF (4294967295,     -1), 9C40 (40000,  40000), 0000 (    0,      0), \
+                12345678 (305419896, 305419896)\n");
+            assert_eq!(doubles, "\tFFFFFFFF00009C40 (18446744069414624320, -4294927296), 12345678 ( 305419896,   305419896)\n");
+        }
+
+        assert_eq!(top, "\t..., 12345678 ( 305419896)\n");
+    }
+
+    #[test]
+    fn test_dump_range() {
+        let mut mm = make_mem();
+        mm.raw_memory.write_slice(0x1000, b"Hello, world!\x00\x01\x7f\xffAB");
+
+        let mut dump = Vec::new();
+        mm.dump_range(&mut dump, 0x1000..0x1013).unwrap();
+
+        assert_eq!(
+            std::str::from_utf8(&dump).unwrap(),
+            "1000: 48 65 6C 6C 6F 2C 20 77 6F 72 6C 64 21 00 01 7F  Hello, world!...\n\
+             1010: FF 41 42                                         .AB\n",
+        );
+
+        let mut dump = Vec::new();
+        mm.dump_range(&mut dump, 0x1000..0x1000).unwrap();
+        mm.dump_range(&mut dump, 0xFFFF..0x10010).unwrap();
+
+        assert_eq!(std::str::from_utf8(&dump).unwrap(), "FFFF: 00                                               .\n");
+    }
 }
@@ -1,12 +1,21 @@
-use crate::input::StaticStringInput;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::io;
+use std::rc::Rc;
+use std::time::Duration;
+
+use crate::cell::{Cell, DoubleCell};
+use crate::clock::Clock;
+use crate::file_system::{FileAccessMode, FileHandle, FileSystem};
+use crate::input::{StaticStringInput, StdinInput};
 use crate::machine::{Machine, MachineExtensions};
 use crate::machine_error::MachineError;
 use crate::machine_memory::MachineMemory;
 use crate::output::StringOutput;
 
 pub enum StackElement {
-    Cell(u16),
-    DoubleCell(u32),
+    Cell(Cell),
+    DoubleCell(DoubleCell),
 }
 
 impl StackElement {
@@ -20,11 +29,11 @@ impl StackElement {
     fn assert(&self, mem: &mut MachineMemory) {
         match self {
             StackElement::Cell(value) => {
-                assert_eq!(mem.data_pop_u16().unwrap(), *value)
+                assert_eq!(mem.data_pop_cell().unwrap(), *value)
             }
 
             StackElement::DoubleCell(value) => {
-                assert_eq!(mem.data_pop_u32().unwrap(), *value)
+                assert_eq!(mem.data_pop_double_cell().unwrap(), *value)
             }
         }
     }
@@ -34,6 +43,7 @@ impl StackElement {
 pub struct TestMachineExtensions {
     pub input: StaticStringInput,
     pub output: StringOutput,
+    pub file_system: Option<Box<dyn FileSystem>>,
 }
 
 impl MachineExtensions for TestMachineExtensions {
@@ -47,10 +57,110 @@ impl MachineExtensions for TestMachineExtensions {
     fn get_output(&mut self) -> &mut Self::TOutput {
         &mut self.output
     }
+
+    fn get_file_system(&mut self) -> Option<&mut dyn FileSystem> {
+        self.file_system.as_mut().map(|fs| fs.as_mut() as &mut dyn FileSystem)
+    }
 }
 
 pub type TestMachine = Machine<TestMachineExtensions>;
 
+/// Extensions reading lines of a script through `StdinInput`, as an interactive session does.
+pub struct ScriptedExtensions {
+    pub input: StdinInput,
+    pub output: StringOutput,
+}
+
+impl ScriptedExtensions {
+    pub fn new(script: &'static str) -> Self {
+        ScriptedExtensions {
+            input: StdinInput::with_reader(Box::new(io::Cursor::new(script))),
+            output: StringOutput::default(),
+        }
+    }
+}
+
+impl MachineExtensions for ScriptedExtensions {
+    type TInput = StdinInput;
+    type TOutput = StringOutput;
+
+    fn get_input(&mut self) -> &mut Self::TInput {
+        &mut self.input
+    }
+
+    fn get_output(&mut self) -> &mut Self::TOutput {
+        &mut self.output
+    }
+}
+
+/// Writer keeping written bytes and counting calls made to it, clones share the same content and counters.
+#[derive(Clone, Default)]
+pub struct SharedBuffer {
+    bytes: Rc<RefCell<Vec<u8>>>,
+    writes: Rc<RefCell<usize>>,
+    flushes: Rc<RefCell<usize>>,
+}
+
+impl SharedBuffer {
+    pub fn content(&self) -> Vec<u8> {
+        self.bytes.borrow().clone()
+    }
+
+    pub fn writes(&self) -> usize {
+        *self.writes.borrow()
+    }
+
+    pub fn flushes(&self) -> usize {
+        *self.flushes.borrow()
+    }
+}
+
+impl io::Write for SharedBuffer {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        *self.writes.borrow_mut() += 1;
+        self.bytes.borrow_mut().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        *self.flushes.borrow_mut() += 1;
+        Ok(())
+    }
+}
+
+/// Clock that advances only when sleeping, clones share the same time.
+#[derive(Clone, Default)]
+pub struct FakeClock {
+    now: Rc<RefCell<Duration>>,
+    longest_sleep: Rc<RefCell<Duration>>,
+}
+
+impl FakeClock {
+    pub fn time(&self) -> Duration {
+        *self.now.borrow()
+    }
+
+    pub fn set_now(&self, now: Duration) {
+        *self.now.borrow_mut() = now;
+    }
+
+    pub fn longest_sleep(&self) -> Duration {
+        *self.longest_sleep.borrow()
+    }
+}
+
+impl Clock for FakeClock {
+    fn now(&mut self) -> Duration {
+        self.time()
+    }
+
+    fn sleep(&mut self, duration: Duration) {
+        *self.now.borrow_mut() += duration;
+
+        let mut longest_sleep = self.longest_sleep.borrow_mut();
+        *longest_sleep = (*longest_sleep).max(duration);
+    }
+}
+
 pub struct TestRunResult {
     pub machine: TestMachine,
     pub result: Result<(), MachineError>,
@@ -71,6 +181,23 @@ impl TestMachine {
         }
     }
 
+    #[cfg(feature = "std-fs")]
+    pub fn with_std_file_system() -> TestMachine {
+        let mut machine = TestMachine::default();
+        machine.extensions.file_system = Some(Box::new(crate::file_system::StdFileSystem::default()));
+
+        machine
+    }
+
+    /// Create a machine with an in-memory file system, returning a handle sharing files with the machine.
+    pub fn with_memory_file_system() -> (TestMachine, MemoryFileSystem) {
+        let file_system = MemoryFileSystem::default();
+        let mut machine = TestMachine::default();
+        machine.extensions.file_system = Some(Box::new(file_system.clone()));
+
+        (machine, file_system)
+    }
+
     pub fn run_with_test_input(input_text: &'static str) -> TestRunResult {
         let mut machine = TestMachine::default();
 
@@ -84,3 +211,114 @@ impl TestMachine {
         }
     }
 }
+
+type MemoryFiles = HashMap<String, Rc<RefCell<Vec<u8>>>>;
+
+/// File system keeping files in memory, clones share the same files.
+#[derive(Clone, Default)]
+pub struct MemoryFileSystem {
+    files: Rc<RefCell<MemoryFiles>>,
+}
+
+impl MemoryFileSystem {
+    pub fn content(&self, path: &str) -> Option<Vec<u8>> {
+        self.files.borrow().get(path).map(|data| data.borrow().clone())
+    }
+}
+
+impl FileSystem for MemoryFileSystem {
+    fn open_file(&mut self, path: &str, mode: FileAccessMode) -> io::Result<Box<dyn FileHandle>> {
+        let data = self.files.borrow().get(path).cloned().ok_or(io::ErrorKind::NotFound)?;
+
+        Ok(Box::new(MemoryFile { data, position: 0, mode }))
+    }
+
+    fn create_file(&mut self, path: &str, mode: FileAccessMode) -> io::Result<Box<dyn FileHandle>> {
+        let data = Rc::new(RefCell::new(Vec::new()));
+        self.files.borrow_mut().insert(path.to_string(), data.clone());
+
+        Ok(Box::new(MemoryFile { data, position: 0, mode }))
+    }
+
+    fn delete_file(&mut self, path: &str) -> io::Result<()> {
+        self.files.borrow_mut().remove(path).map(|_| ()).ok_or(io::ErrorKind::NotFound.into())
+    }
+}
+
+struct MemoryFile {
+    data: Rc<RefCell<Vec<u8>>>,
+    position: usize,
+    mode: FileAccessMode,
+}
+
+impl io::Read for MemoryFile {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.mode == FileAccessMode::WriteOnly {
+            return Err(io::ErrorKind::PermissionDenied.into());
+        }
+
+        let data = self.data.borrow();
+        let available = data.get(self.position..).unwrap_or(&[]);
+        let count = available.len().min(buf.len());
+
+        buf[..count].copy_from_slice(&available[..count]);
+        self.position += count;
+
+        Ok(count)
+    }
+}
+
+impl io::Write for MemoryFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.mode == FileAccessMode::ReadOnly {
+            return Err(io::ErrorKind::PermissionDenied.into());
+        }
+
+        let mut data = self.data.borrow_mut();
+        let end = self.position + buf.len();
+
+        if data.len() < end {
+            data.resize(end, 0);
+        }
+
+        data[self.position..end].copy_from_slice(buf);
+        self.position = end;
+
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl io::Seek for MemoryFile {
+    fn seek(&mut self, pos: io::SeekFrom) -> io::Result<u64> {
+        let position = match pos {
+            io::SeekFrom::Start(offset) => Some(offset as i64),
+            io::SeekFrom::End(offset) => (self.data.borrow().len() as i64).checked_add(offset),
+            io::SeekFrom::Current(offset) => (self.position as i64).checked_add(offset),
+        };
+
+        match position {
+            Some(position) if position >= 0 => {
+                self.position = position as usize;
+
+                Ok(position as u64)
+            }
+            _ => Err(io::ErrorKind::InvalidInput.into()),
+        }
+    }
+}
+
+/// Write a file with given name and content to a temporary directory unique for the test process.
+#[cfg(feature = "std-fs")]
+pub fn write_temp_file(name: &str, content: &str) -> String {
+    let directory = std::env::temp_dir().join(format!("rs4-{}", std::process::id()));
+    std::fs::create_dir_all(&directory).unwrap();
+
+    let path = directory.join(name);
+    std::fs::write(&path, content).unwrap();
+
+    path.to_string_lossy().into_owned()
+}
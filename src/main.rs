@@ -1,18 +1,36 @@
-use std::fs;
-use std::io::{stdout, Write};
+use std::io::{IsTerminal, stdin, stdout};
+use std::path::PathBuf;
 
-use rs4::input::StdinInput;
+use rs4::cli::{CliOptions, run, USAGE};
+use rs4::file_system::{FileSystem, StdFileSystem};
+use rs4::line_editor::ReplInput;
 use rs4::machine::{Machine, MachineExtensions};
-use rs4::output::StdoutOutput;
+use rs4::output::{Output, StdoutOutput};
+
+/// Name of file in home directory keeping history of lines typed in the line editor.
+const HISTORY_FILE_NAME: &str = ".rs4_history";
 
-#[derive(Default)]
 struct InteractiveMachineExtensions {
-    i: StdinInput,
+    i: ReplInput,
     o: StdoutOutput,
+    fs: StdFileSystem,
+}
+
+impl Default for InteractiveMachineExtensions {
+    fn default() -> Self {
+        let o = StdoutOutput::default();
+        let history_path = std::env::var_os("HOME").map(|home| PathBuf::from(home).join(HISTORY_FILE_NAME));
+        let mut i = ReplInput::new(stdin().is_terminal(), history_path);
+
+        let mut prompt_output = o.clone();
+        i.set_before_prompt(Box::new(move || { let _ = prompt_output.flush(); }));
+
+        InteractiveMachineExtensions { i, o, fs: StdFileSystem::default() }
+    }
 }
 
 impl MachineExtensions for InteractiveMachineExtensions {
-    type TInput = StdinInput;
+    type TInput = ReplInput;
     type TOutput = StdoutOutput;
 
     fn get_input(&mut self) -> &mut Self::TInput {
@@ -22,25 +40,46 @@ impl MachineExtensions for InteractiveMachineExtensions {
     fn get_output(&mut self) -> &mut Self::TOutput {
         &mut self.o
     }
+
+    fn get_file_system(&mut self) -> Option<&mut dyn FileSystem> {
+        Some(&mut self.fs)
+    }
+}
+
+/// Interrupt running program on first Ctrl-C, terminate the process on second one (while the first is not handled
+/// yet).
+#[cfg(feature = "signals")]
+fn install_interrupt_handler(machine: &mut Machine<InteractiveMachineExtensions>) {
+    use std::sync::Arc;
+    use std::sync::atomic::AtomicBool;
+
+    use signal_hook::consts::SIGINT;
+
+    let interrupt_flag = Arc::new(AtomicBool::new(false));
+    signal_hook::flag::register_conditional_shutdown(SIGINT, 1, interrupt_flag.clone()).unwrap();
+    signal_hook::flag::register(SIGINT, interrupt_flag.clone()).unwrap();
+    machine.set_interrupt_flag(interrupt_flag);
 }
 
 fn main() {
-    let mut machine = Machine::<InteractiveMachineExtensions>::default();
-
-    loop {
-        match machine.interpret_input() {
-            Ok(_) => { return; }
-            Err(err) => {
-                print!("Error: ");
-                err.pretty_print(&mut stdout(), &machine).unwrap();
-                print!("\n-----\nMachine state:\n");
-                machine.print_state(&mut stdout()).unwrap();
-                machine.print_disassembly(&mut stdout()).unwrap();
-
-                stdout().flush().unwrap();
-
-                machine.memory.raw_memory.dump_to(&mut fs::File::create("./dump.bin").unwrap()).unwrap();
-            }
-        };
-    }
+    let options = match CliOptions::parse(std::env::args().skip(1)) {
+        Ok(options) => options,
+        Err(message) => {
+            eprintln!("{}\n{}", message, USAGE);
+
+            std::process::exit(2);
+        }
+    };
+
+    let mut machine = Machine::with_memory(InteractiveMachineExtensions::default(), options.machine_memory());
+
+    #[cfg(feature = "signals")]
+    install_interrupt_handler(&mut machine);
+
+    machine.interactive = stdin().is_terminal();
+
+    let exit_code = run(&mut machine, &options, &mut stdout()).unwrap();
+    let _ = machine.output().flush();
+
+    std::process::exit(exit_code);
 }
@@ -1,28 +1,84 @@
+use std::borrow::Cow;
+use std::fmt::{Display, Formatter};
 use std::io;
 use std::ops::{Range, RangeInclusive};
+use std::sync::Arc;
 
-const MEM_SIZE: usize = (u16::MAX as usize) + 1;
+use crate::cell::{Cell, DoubleCell};
+use crate::memory_segment::WHOLE_MEMORY;
+
+pub const MEM_SIZE: usize = (u16::MAX as usize) + 1;
+
+/// Size of a unit of memory content shared between clones of `Mem`.
+pub const PAGE_SIZE: usize = 256;
+
+const PAGE_COUNT: usize = MEM_SIZE / PAGE_SIZE;
+
+type Page = [u8; PAGE_SIZE];
 
 /// A piece of memory that allows access to it's random fragments of different sizes.
+///
+/// Multi-byte values are always stored in little-endian byte order, independently of host byte order,
+/// so memory images (e.g. ones written by `dump_to`) are portable between hosts.
+///
+/// Content is stored in reference-counted pages, so cloning a memory is cheap and a page is copied
+/// only when one of memories sharing it is written to.
 #[derive(Clone)]
 pub struct Mem {
-    content: [u8; MEM_SIZE],
+    pages: Vec<Arc<Page>>,
 }
 
 pub type Address = u16;
 
 pub type AddressRange = RangeInclusive<Address>;
 
+/// Kind of memory access that was attempted.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AccessKind {
+    Read,
+    Write,
+    Push,
+    Pop,
+}
+
 #[derive(Debug)]
 pub struct MemoryAccessError {
     pub access_range: AddressRange,
     pub segment: AddressRange,
+
+    /// Human-readable name of the segment the access was validated against.
+    pub segment_name: &'static str,
+    pub kind: AccessKind,
+}
+
+impl MemoryAccessError {
+    /// Number of bytes the failed access tried to touch.
+    pub fn access_size(&self) -> usize {
+        (self.access_range.end().wrapping_sub(*self.access_range.start()) as usize) + 1
+    }
+}
+
+impl Display for MemoryAccessError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let size = self.access_size();
+
+        match self.kind {
+            AccessKind::Pop => write!(f, "{} underflow: tried to pop {} byte(s)", self.segment_name, size),
+            AccessKind::Push => write!(f, "{} overflow: tried to push {} byte(s)", self.segment_name, size),
+            AccessKind::Read | AccessKind::Write => write!(
+                f, "illegal {} of {} byte(s) at {:X?} outside of {} (allowed range is {:X?})",
+                if self.kind == AccessKind::Read { "read" } else { "write" },
+                size, self.access_range, self.segment_name, self.segment,
+            ),
+        }
+    }
 }
 
 impl Default for Mem {
     fn default() -> Self {
+        // All pages share the same zeroed content until written to
         return Mem {
-            content: [0; MEM_SIZE]
+            pages: std::iter::repeat_n(Arc::new([0; PAGE_SIZE]), PAGE_COUNT).collect()
         };
     }
 }
@@ -36,59 +92,171 @@ impl Mem {
         &self,
         address_range: AddressRange,
         segment: AddressRange,
+    ) -> Result<(), MemoryAccessError> {
+        self.validate_named_access(address_range, segment, WHOLE_MEMORY, AccessKind::Read)
+    }
+
+    /// Same as `validate_access` but reports given segment name and access kind on failure.
+    pub fn validate_named_access(
+        &self,
+        address_range: AddressRange,
+        segment: AddressRange,
+        segment_name: &'static str,
+        kind: AccessKind,
     ) -> Result<(), MemoryAccessError> {
         if *address_range.start() > *address_range.end() || *address_range.start() < *segment.start() || *address_range.end() > *segment.end() {
             return Err(MemoryAccessError {
                 access_range: address_range,
                 segment,
+                segment_name,
+                kind,
             });
         }
 
         return Ok(());
     }
 
+    fn byte(&self, index: usize) -> u8 {
+        self.pages[index / PAGE_SIZE][index % PAGE_SIZE]
+    }
+
+    fn byte_mut(&mut self, index: usize) -> &mut u8 {
+        &mut Arc::make_mut(&mut self.pages[index / PAGE_SIZE])[index % PAGE_SIZE]
+    }
+
     pub fn read_u8(&self, offset: Address) -> u8 {
-        self.content[offset as usize]
+        self.byte(offset as usize)
     }
 
     pub fn write_u8(&mut self, offset: Address, value: u8) {
-        self.content[offset as usize] = value
+        *self.byte_mut(offset as usize) = value
+    }
+
+    /// Address of the byte `index` bytes after `offset`.
+    ///
+    /// Multi-byte values that don't fit before the end of memory wrap around to address 0.
+    fn byte_index(offset: Address, index: usize) -> usize {
+        offset.wrapping_add(index as u16) as usize
+    }
+
+    fn read_bytes<const N: usize>(&self, offset: Address) -> [u8; N] {
+        let mut bytes = [0u8; N];
+
+        for (i, byte) in bytes.iter_mut().enumerate() {
+            *byte = self.byte(Self::byte_index(offset, i));
+        }
+
+        bytes
     }
 
-    pub unsafe fn read_u16(&self, offset: Address) -> u16 {
-        (self.content.as_ptr().offset(offset as isize) as *const u16).read()
+    fn write_bytes<const N: usize>(&mut self, offset: Address, bytes: [u8; N]) {
+        for (i, byte) in bytes.into_iter().enumerate() {
+            *self.byte_mut(Self::byte_index(offset, i)) = byte;
+        }
     }
 
-    pub unsafe fn write_u16(&mut self, offset: Address, value: u16) {
-        (self.content.as_mut_ptr().offset(offset as isize) as *mut u16).write(value)
+    /// Read a little-endian 16-bit value.
+    pub fn read_u16(&self, offset: Address) -> u16 {
+        u16::from_le_bytes(self.read_bytes(offset))
     }
 
-    pub unsafe fn read_u32(&self, offset: Address) -> u32 {
-        (self.content.as_ptr().offset(offset as isize) as *const u32).read()
+    /// Write a 16-bit value in little-endian byte order.
+    pub fn write_u16(&mut self, offset: Address, value: u16) {
+        self.write_bytes(offset, value.to_le_bytes())
     }
 
-    pub unsafe fn write_u32(&mut self, offset: Address, value: u32) {
-        (self.content.as_mut_ptr().offset(offset as isize) as *mut u32).write(value)
+    /// Read a little-endian 32-bit value.
+    pub fn read_u32(&self, offset: Address) -> u32 {
+        u32::from_le_bytes(self.read_bytes(offset))
     }
 
-    pub fn slice(&self, range: Range<usize>) -> &[u8] {
-        return &self.content[range];
+    /// Write a 32-bit value in little-endian byte order.
+    pub fn write_u32(&mut self, offset: Address, value: u32) {
+        self.write_bytes(offset, value.to_le_bytes())
     }
 
-    pub fn address_slice(&self, start: Address, length: usize) -> &[u8] {
+    /// Read a little-endian single cell.
+    pub fn read_cell(&self, offset: Address) -> Cell {
+        Cell::from_le_bytes(self.read_bytes(offset))
+    }
+
+    /// Write a single cell in little-endian byte order.
+    pub fn write_cell(&mut self, offset: Address, value: Cell) {
+        self.write_bytes(offset, value.to_le_bytes())
+    }
+
+    /// Read a little-endian double cell.
+    pub fn read_double_cell(&self, offset: Address) -> DoubleCell {
+        DoubleCell::from_le_bytes(self.read_bytes(offset))
+    }
+
+    /// Write a double cell in little-endian byte order.
+    pub fn write_double_cell(&mut self, offset: Address, value: DoubleCell) {
+        self.write_bytes(offset, value.to_le_bytes())
+    }
+
+    /// Content of given range of memory.
+    ///
+    /// Content is borrowed when the range fits in a single page and copied otherwise. Ranges reaching past the end of
+    /// memory wrap around to address 0, as multi-byte values do.
+    pub fn slice(&self, range: Range<usize>) -> Cow<'_, [u8]> {
+        if range.is_empty() {
+            return Cow::Borrowed(&[]);
+        }
+
+        let page = range.start / PAGE_SIZE;
+
+        if page < PAGE_COUNT && (range.end - 1) / PAGE_SIZE == page {
+            return Cow::Borrowed(&self.pages[page][(range.start % PAGE_SIZE)..((range.end - 1) % PAGE_SIZE + 1)]);
+        }
+
+        Cow::Owned(range.map(|index| self.byte(index % MEM_SIZE)).collect())
+    }
+
+    pub fn address_slice(&self, start: Address, length: usize) -> Cow<'_, [u8]> {
         return self.slice((start as usize)..((start as usize) + length));
     }
 
-    pub fn slice_mut(&mut self, range: Range<usize>) -> &mut [u8] {
-        return &mut self.content[range];
+    /// Copy given bytes to memory starting at given address, wrapping around to address 0 at the end of memory.
+    pub fn write_slice(&mut self, start: Address, data: &[u8]) {
+        for (i, byte) in data.iter().enumerate() {
+            *self.byte_mut(Self::byte_index(start, i)) = *byte;
+        }
     }
 
-    pub fn address_slice_mut(&mut self, start: Address, length: usize) -> &mut [u8] {
-        return self.slice_mut((start as usize)..((start as usize) + length));
+    /// Number of bytes of this memory that share storage with `other`.
+    pub fn shared_bytes(&self, other: &Mem) -> usize {
+        self.pages.iter()
+            .zip(other.pages.iter())
+            .filter(|(page, other_page)| Arc::ptr_eq(page, other_page))
+            .count() * PAGE_SIZE
     }
 
     pub fn dump_to(&self, dst: &mut impl io::Write) -> io::Result<()> {
-        dst.write_all(&self.content)
+        for page in &self.pages {
+            dst.write_all(page.as_slice())?;
+        }
+
+        Ok(())
+    }
+
+    /// Replace whole content of the memory by an image written by `dump_to`.
+    ///
+    /// Fails without modifying the memory if the image is shorter or longer than the memory.
+    pub fn load_from(&mut self, src: &mut impl io::Read) -> io::Result<()> {
+        let mut content = vec![0u8; MEM_SIZE];
+
+        src.read_exact(&mut content)?;
+
+        if src.read(&mut [0u8])? != 0 {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "memory image is larger than memory"));
+        }
+
+        self.pages = content.chunks(PAGE_SIZE)
+            .map(|chunk| Arc::new(chunk.try_into().unwrap()))
+            .collect();
+
+        Ok(())
     }
 }
 
@@ -112,39 +280,158 @@ mod test {
     fn test_rw_u16() {
         let mut mem: Mem = Mem::default();
 
-        unsafe {
-            mem.write_u16(54345, 0xabcd);
-        };
+        mem.write_u16(54345, 0xabcd);
 
-        assert_eq!(
-            unsafe {
-                mem.read_u16(54345)
-            },
-            0xabcd,
-        );
+        assert_eq!(mem.read_u16(54345), 0xabcd);
     }
 
     #[test]
     fn test_rw_u32() {
         let mut mem: Mem = Mem::default();
 
-        unsafe {
-            mem.write_u32(12345, 0x1234abcd);
-        };
+        mem.write_u32(12345, 0x1234abcd);
 
-        assert_eq!(
-            unsafe {
-                mem.read_u32(12345)
-            },
-            0x1234abcd,
-        );
+        assert_eq!(mem.read_u32(12345), 0x1234abcd);
+    }
+
+    #[test]
+    fn test_rw_round_trip() {
+        let mut mem: Mem = Mem::default();
+
+        for value in [0u16, 1, 0x00ff, 0xff00, 0x8000, u16::MAX] {
+            mem.write_u16(0x100, value);
+            assert_eq!(mem.read_u16(0x100), value);
+        }
+
+        for value in [0u32, 1, 0x0000ffff, 0xffff0000, 0x80000000, u32::MAX] {
+            mem.write_u32(0x101, value);
+            assert_eq!(mem.read_u32(0x101), value);
+        }
+    }
+
+    #[test]
+    fn test_u16_byte_layout() {
+        let mut mem: Mem = Mem::default();
+
+        mem.write_u16(0x200, 0x1234);
+
+        assert_eq!(mem.address_slice(0x200, 2).as_ref(), &[0x34, 0x12]);
+    }
+
+    #[test]
+    fn test_u32_byte_layout() {
+        let mut mem: Mem = Mem::default();
+
+        mem.write_u32(0x200, 0x12345678);
+
+        assert_eq!(mem.address_slice(0x200, 4).as_ref(), &[0x78, 0x56, 0x34, 0x12]);
+
+        let mut dump = Vec::new();
+        mem.dump_to(&mut dump).unwrap();
+        assert_eq!(&dump[0x200..0x204], &[0x78, 0x56, 0x34, 0x12]);
+    }
+
+    #[test]
+    fn test_rw_u16_wraps_around() {
+        let mut mem: Mem = Mem::default();
+
+        mem.write_u16(0xFFFE, 0x1122);
+        assert_eq!(mem.read_u16(0xFFFE), 0x1122);
+
+        mem.write_u16(0xFFFF, 0xabcd);
+        assert_eq!(mem.read_u16(0xFFFF), 0xabcd);
+        assert_eq!(mem.read_u8(0xFFFF), 0xcd);
+        assert_eq!(mem.read_u8(0x0000), 0xab);
+    }
+
+    #[test]
+    fn test_rw_u32_wraps_around() {
+        let mut mem: Mem = Mem::default();
+
+        mem.write_u32(0xFFFC, 0x11223344);
+        assert_eq!(mem.read_u32(0xFFFC), 0x11223344);
+        assert_eq!(mem.read_u8(0x0000), 0x00);
+
+        for address in [0xFFFD, 0xFFFE, 0xFFFF] {
+            mem.write_u32(address, 0x1234abcd);
+            assert_eq!(mem.read_u32(address), 0x1234abcd);
+        }
+
+        assert_eq!(mem.read_u8(0xFFFF), 0xcd);
+        assert_eq!(mem.read_u8(0x0000), 0xab);
+        assert_eq!(mem.read_u8(0x0001), 0x34);
+        assert_eq!(mem.read_u8(0x0002), 0x12);
+    }
+
+    #[test]
+    fn test_dump_load() {
+        let mut mem: Mem = Mem::default();
+        mem.write_u32(0x1234, 0xdeadbeef);
+
+        let mut image = Vec::new();
+        mem.dump_to(&mut image).unwrap();
+
+        let mut loaded: Mem = Mem::default();
+        loaded.load_from(&mut image.as_slice()).unwrap();
+        assert_eq!(loaded.read_u32(0x1234), 0xdeadbeef);
+    }
+
+    #[test]
+    fn test_load_wrong_size() {
+        let mut mem: Mem = Mem::default();
+
+        let short_image = vec![1u8; MEM_SIZE - 1];
+        assert_eq!(mem.load_from(&mut short_image.as_slice()).unwrap_err().kind(), io::ErrorKind::UnexpectedEof);
+
+        let long_image = vec![1u8; MEM_SIZE + 1];
+        assert_eq!(mem.load_from(&mut long_image.as_slice()).unwrap_err().kind(), io::ErrorKind::InvalidData);
+
+        assert_eq!(mem.read_u8(0), 0);
     }
 
     #[test]
     fn test_min_max_addresses() {
         let mem: Mem = Mem::default();
 
-        assert_eq!(mem.content[*mem.address_range().start() as usize], 0);
-        assert_eq!(mem.content[*mem.address_range().end() as usize], 0);
+        assert_eq!(mem.read_u8(*mem.address_range().start()), 0);
+        assert_eq!(mem.read_u8(*mem.address_range().end()), 0);
+    }
+
+    #[test]
+    fn test_slice_across_pages() {
+        let mut mem: Mem = Mem::default();
+        let start = (PAGE_SIZE - 2) as Address;
+
+        mem.write_slice(start, b"abcd");
+
+        assert!(matches!(mem.address_slice(start, 2), Cow::Borrowed(b"ab")));
+        assert_eq!(mem.address_slice(start, 4).as_ref(), b"abcd");
+        assert_eq!(mem.read_u32(start), u32::from_le_bytes(*b"abcd"));
+    }
+
+    #[test]
+    fn test_slice_wraps_around() {
+        let mut mem: Mem = Mem::default();
+
+        mem.write_slice(0xFFFE, b"abcd");
+
+        assert_eq!(mem.read_u8(0xFFFF), b'b');
+        assert_eq!(mem.address_slice(0, 2).as_ref(), b"cd");
+        assert_eq!(mem.address_slice(0xFFFE, 4).as_ref(), b"abcd");
+        assert_eq!(mem.slice(MEM_SIZE..MEM_SIZE + 2).as_ref(), b"cd");
+    }
+
+    #[test]
+    fn test_clone_shares_pages() {
+        let mut mem: Mem = Mem::default();
+        mem.write_u16(0x1234, 0xabcd);
+
+        let mut copy = mem.clone();
+        assert_eq!(copy.shared_bytes(&mem), MEM_SIZE);
+
+        copy.write_u16(0x1234, 0x4321);
+        assert_eq!(copy.shared_bytes(&mem), MEM_SIZE - PAGE_SIZE);
+        assert_eq!(mem.read_u16(0x1234), 0xabcd);
+        assert_eq!(copy.read_u16(0x1234), 0x4321);
     }
 }
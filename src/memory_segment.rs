@@ -0,0 +1,152 @@
+use crate::machine_memory::MachineMemory;
+use crate::mem::AddressRange;
+
+// Names of memory segments reported by `MemoryAccessError`.
+
+pub const WHOLE_MEMORY: &str = "memory";
+
+pub const DATA_STACK: &str = "data stack";
+
+pub const CALL_STACK: &str = "call stack";
+
+/// Part of memory occupied by compiled dictionary.
+pub const DICTIONARY: &str = "dictionary";
+
+/// Part of data space not occupied by dictionary or data stack.
+pub const FREE_DATA_SPACE: &str = "free data space";
+
+pub const PNO_BUFFER: &str = "pictured numeric output buffer";
+
+/// Region used by `ALLOCATE`, `FREE` and `RESIZE`.
+pub const HEAP: &str = "heap";
+
+/// A named part of machine memory, possibly moving as the machine runs.
+pub trait MemorySegment {
+    /// Name reported when an access falls outside the segment.
+    fn name(&self) -> &'static str;
+
+    /// Addresses the segment currently occupies.
+    fn range(&self, memory: &MachineMemory) -> AddressRange;
+}
+
+pub struct WholeMemorySegment;
+
+impl MemorySegment for WholeMemorySegment {
+    fn name(&self) -> &'static str {
+        WHOLE_MEMORY
+    }
+
+    fn range(&self, memory: &MachineMemory) -> AddressRange {
+        memory.raw_memory.address_range()
+    }
+}
+
+pub struct DataStackSegment;
+
+impl MemorySegment for DataStackSegment {
+    fn name(&self) -> &'static str {
+        DATA_STACK
+    }
+
+    fn range(&self, memory: &MachineMemory) -> AddressRange {
+        memory.get_data_stack_segment()
+    }
+}
+
+pub struct CallStackSegment;
+
+impl MemorySegment for CallStackSegment {
+    fn name(&self) -> &'static str {
+        CALL_STACK
+    }
+
+    fn range(&self, memory: &MachineMemory) -> AddressRange {
+        memory.get_call_stack_segment()
+    }
+}
+
+/// Used part of dictionary, up to dictionary pointer.
+pub struct DictionarySegment;
+
+impl MemorySegment for DictionarySegment {
+    fn name(&self) -> &'static str {
+        DICTIONARY
+    }
+
+    fn range(&self, memory: &MachineMemory) -> AddressRange {
+        memory.get_used_dict_segment()
+    }
+}
+
+pub struct FreeDataSegment;
+
+impl MemorySegment for FreeDataSegment {
+    fn name(&self) -> &'static str {
+        FREE_DATA_SPACE
+    }
+
+    fn range(&self, memory: &MachineMemory) -> AddressRange {
+        memory.get_free_data_segment()
+    }
+}
+
+pub struct HeapSegment;
+
+impl MemorySegment for HeapSegment {
+    fn name(&self) -> &'static str {
+        HEAP
+    }
+
+    fn range(&self, memory: &MachineMemory) -> AddressRange {
+        memory.get_heap_segment()
+    }
+}
+
+/// Content of pictured numeric output buffer, without the length byte.
+pub struct PnoBufferSegment;
+
+impl MemorySegment for PnoBufferSegment {
+    fn name(&self) -> &'static str {
+        PNO_BUFFER
+    }
+
+    fn range(&self, memory: &MachineMemory) -> AddressRange {
+        memory.get_pno_content_range()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::machine_memory::MemoryLayoutConfig;
+    use crate::mem::{AccessKind, Mem};
+
+    use super::*;
+
+    #[test]
+    fn test_segment_ranges() {
+        let mut mm = MachineMemory::new(Mem::default(), MemoryLayoutConfig::default());
+        mm.dict_write_u16(0xBEEF).unwrap();
+
+        assert_eq!(WholeMemorySegment.range(&mm), mm.raw_memory.address_range());
+        assert_eq!(DataStackSegment.range(&mm), mm.get_data_stack_segment());
+        assert_eq!(CallStackSegment.range(&mm), mm.get_call_stack_segment());
+        assert_eq!(DictionarySegment.range(&mm), 0..=1);
+        assert_eq!(FreeDataSegment.range(&mm), mm.get_free_data_segment());
+        assert_eq!(PnoBufferSegment.range(&mm), mm.get_pno_content_range());
+        assert_eq!(HeapSegment.range(&mm), mm.get_heap_segment());
+    }
+
+    #[test]
+    fn test_access_errors_name_segment() {
+        let mut mm = MachineMemory::new(Mem::default(), MemoryLayoutConfig::default());
+        mm.dict_write_u16(0).unwrap();
+
+        let err = mm.resolve_forward_reference(2).unwrap_err();
+        assert_eq!(err.segment_name, DICTIONARY);
+        assert_eq!(err.segment, 0..=1);
+
+        let err = mm.validate_segment_access(&CallStackSegment, 0..=1, AccessKind::Read).unwrap_err();
+        assert_eq!(err.segment_name, CALL_STACK);
+        assert_eq!(err.segment, mm.get_call_stack_segment());
+    }
+}
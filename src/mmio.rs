@@ -0,0 +1,229 @@
+use crate::cell::{Cell, CELL_BYTES, DOUBLE_CELL_BYTES, DoubleCell};
+use crate::machine_error::MachineError;
+use crate::mem::{Address, AddressRange, Mem};
+
+/// A device that handles accesses to memory-mapped address range.
+pub trait MmioHandler {
+    fn read(&mut self, address: Address) -> u8;
+
+    fn write(&mut self, address: Address, value: u8);
+}
+
+struct MmioMapping {
+    range: AddressRange,
+    handler: Box<dyn MmioHandler>,
+}
+
+/// Set of address ranges that are routed to `MmioHandler`s instead of plain memory.
+///
+/// Only memory access op-codes (`Load*`/`Store*`) go through the map, internal accesses to dictionary and
+/// stacks always use plain memory.
+#[derive(Default)]
+pub struct MmioMap {
+    mappings: Vec<MmioMapping>,
+}
+
+impl MmioMap {
+    pub fn map(&mut self, range: AddressRange, handler: Box<dyn MmioHandler>) -> Result<(), MachineError> {
+        if let Some(existing) = self.mappings.iter()
+            .find(|mapping| mapping.range.start() <= range.end() && range.start() <= mapping.range.end()) {
+            return Err(MachineError::OverlappingMmioRange { range, existing: existing.range.clone() });
+        }
+
+        self.mappings.push(MmioMapping { range, handler });
+
+        Ok(())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.mappings.is_empty()
+    }
+
+    fn handler_at(&mut self, address: Address) -> Option<&mut Box<dyn MmioHandler>> {
+        self.mappings.iter_mut()
+            .find(|mapping| mapping.range.contains(&address))
+            .map(|mapping| &mut mapping.handler)
+    }
+
+    pub fn read_u8(&mut self, memory: &Mem, address: Address) -> u8 {
+        match self.handler_at(address) {
+            Some(handler) => handler.read(address),
+            None => memory.read_u8(address),
+        }
+    }
+
+    pub fn write_u8(&mut self, memory: &mut Mem, address: Address, value: u8) {
+        match self.handler_at(address) {
+            Some(handler) => handler.write(address, value),
+            None => memory.write_u8(address, value),
+        }
+    }
+
+    pub fn read_u16(&mut self, memory: &Mem, address: Address) -> u16 {
+        if self.is_empty() {
+            return memory.read_u16(address);
+        }
+
+        let mut bytes = [0u8; 2];
+        self.read_bytes(memory, address, &mut bytes);
+
+        u16::from_le_bytes(bytes)
+    }
+
+    pub fn write_u16(&mut self, memory: &mut Mem, address: Address, value: u16) {
+        if self.is_empty() {
+            return memory.write_u16(address, value);
+        }
+
+        self.write_bytes(memory, address, &value.to_le_bytes())
+    }
+
+    pub fn read_u32(&mut self, memory: &Mem, address: Address) -> u32 {
+        if self.is_empty() {
+            return memory.read_u32(address);
+        }
+
+        let mut bytes = [0u8; 4];
+        self.read_bytes(memory, address, &mut bytes);
+
+        u32::from_le_bytes(bytes)
+    }
+
+    pub fn write_u32(&mut self, memory: &mut Mem, address: Address, value: u32) {
+        if self.is_empty() {
+            return memory.write_u32(address, value);
+        }
+
+        self.write_bytes(memory, address, &value.to_le_bytes())
+    }
+
+    pub fn read_cell(&mut self, memory: &Mem, address: Address) -> Cell {
+        if self.is_empty() {
+            return memory.read_cell(address);
+        }
+
+        let mut bytes = [0u8; CELL_BYTES as usize];
+        self.read_bytes(memory, address, &mut bytes);
+
+        Cell::from_le_bytes(bytes)
+    }
+
+    pub fn write_cell(&mut self, memory: &mut Mem, address: Address, value: Cell) {
+        if self.is_empty() {
+            return memory.write_cell(address, value);
+        }
+
+        self.write_bytes(memory, address, &value.to_le_bytes())
+    }
+
+    pub fn read_double_cell(&mut self, memory: &Mem, address: Address) -> DoubleCell {
+        if self.is_empty() {
+            return memory.read_double_cell(address);
+        }
+
+        let mut bytes = [0u8; DOUBLE_CELL_BYTES as usize];
+        self.read_bytes(memory, address, &mut bytes);
+
+        DoubleCell::from_le_bytes(bytes)
+    }
+
+    pub fn write_double_cell(&mut self, memory: &mut Mem, address: Address, value: DoubleCell) {
+        if self.is_empty() {
+            return memory.write_double_cell(address, value);
+        }
+
+        self.write_bytes(memory, address, &value.to_le_bytes())
+    }
+
+    fn read_bytes(&mut self, memory: &Mem, address: Address, bytes: &mut [u8]) {
+        for (i, byte) in bytes.iter_mut().enumerate() {
+            *byte = self.read_u8(memory, address.wrapping_add(i as u16));
+        }
+    }
+
+    fn write_bytes(&mut self, memory: &mut Mem, address: Address, bytes: &[u8]) {
+        for (i, byte) in bytes.iter().enumerate() {
+            self.write_u8(memory, address.wrapping_add(i as u16), *byte);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    use crate::input::StaticStringInput;
+    use crate::machine_testing::*;
+
+    use super::*;
+
+    const UART_ADDRESS: Address = 0xF000;
+
+    struct FakeUart {
+        transmitted: Rc<RefCell<Vec<u8>>>,
+    }
+
+    impl MmioHandler for FakeUart {
+        fn read(&mut self, _address: Address) -> u8 {
+            0x42
+        }
+
+        fn write(&mut self, _address: Address, value: u8) {
+            self.transmitted.borrow_mut().push(value)
+        }
+    }
+
+    fn make_uart_machine() -> (TestMachine, Rc<RefCell<Vec<u8>>>) {
+        let transmitted = Rc::new(RefCell::new(Vec::new()));
+        let mut machine = TestMachine::default();
+
+        machine.map_io(UART_ADDRESS..=UART_ADDRESS, Box::new(FakeUart { transmitted: transmitted.clone() })).unwrap();
+
+        (machine, transmitted)
+    }
+
+    #[test]
+    fn test_uart_write() {
+        let (mut machine, transmitted) = make_uart_machine();
+
+        machine.extensions.input = StaticStringInput::new(": send 61440 C! ; 104 send 105 send");
+        machine.interpret_input().unwrap();
+
+        assert_eq!(transmitted.borrow().as_slice(), b"hi");
+        assert_eq!(machine.memory.raw_memory.read_u8(UART_ADDRESS), 0);
+        machine.assert_data_stack_state(&[]);
+    }
+
+    #[test]
+    fn test_uart_read() {
+        let (mut machine, _) = make_uart_machine();
+
+        machine.extensions.input = StaticStringInput::new("61440 C@ 61439 @");
+        machine.interpret_input().unwrap();
+
+        machine.assert_data_stack_state(&[StackElement::Cell(0x42), StackElement::Cell(0x4200)]);
+    }
+
+    #[test]
+    fn test_plain_memory_not_affected() {
+        let (mut machine, transmitted) = make_uart_machine();
+
+        machine.extensions.input = StaticStringInput::new("1234 61442 ! 61442 @");
+        machine.interpret_input().unwrap();
+
+        machine.assert_data_stack_state(&[StackElement::Cell(1234)]);
+        assert!(transmitted.borrow().is_empty());
+    }
+
+    #[test]
+    fn test_overlapping_ranges_rejected() {
+        let (mut machine, transmitted) = make_uart_machine();
+
+        assert!(matches!(
+            machine.map_io(0xEFF0..=0xF000, Box::new(FakeUart { transmitted: transmitted.clone() })),
+            Err(MachineError::OverlappingMmioRange { .. })
+        ));
+        machine.map_io(0xF001..=0xF00F, Box::new(FakeUart { transmitted })).unwrap();
+    }
+}
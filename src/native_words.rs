@@ -0,0 +1,204 @@
+use crate::machine::{Machine, MachineExtensions};
+use crate::machine_error::MachineError;
+use crate::machine_state::MachineState;
+use crate::mem::Address;
+use crate::opcodes::OpCode;
+use crate::sized_string::ReadableSizedString;
+
+/// Host function implementing a native word.
+pub type NativeWordFn<TExt> = Box<dyn FnMut(&mut Machine<TExt>) -> Result<(), MachineError>>;
+
+struct NativeWord<TExt: MachineExtensions> {
+    name: Vec<u8>,
+
+    /// `None` while the function is running.
+    function: Option<NativeWordFn<TExt>>,
+}
+
+/// Words implemented by host functions, identified by their index.
+pub struct NativeWords<TExt: MachineExtensions> {
+    words: Vec<NativeWord<TExt>>,
+}
+
+impl<TExt: MachineExtensions> Default for NativeWords<TExt> {
+    fn default() -> Self {
+        NativeWords { words: Vec::new() }
+    }
+}
+
+impl<TExt: MachineExtensions> NativeWords<TExt> {
+    /// Find index of a native word with given name.
+    pub fn find(&self, name: &[u8]) -> Option<u16> {
+        self.words.iter().position(|word| word.name == name).map(|index| index as u16)
+    }
+
+    pub fn name(&self, index: u16) -> Option<&[u8]> {
+        self.words.get(index as usize).map(|word| word.name.as_slice())
+    }
+
+    pub fn len(&self) -> usize {
+        self.words.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.words.is_empty()
+    }
+}
+
+impl<TExt: MachineExtensions> Machine<TExt> {
+    /// Add a word implemented by given host function, returning index of the word.
+    ///
+    /// Registering a word with the same name as an existing native word replaces the function, so code compiled
+    /// earlier calls the new one. Native words take precedence over builtin words but not over dictionary articles.
+    pub fn register_native_word(
+        &mut self,
+        name: &str,
+        function: impl FnMut(&mut Machine<TExt>) -> Result<(), MachineError> + 'static,
+    ) -> u16 {
+        let function: Option<NativeWordFn<TExt>> = Some(Box::new(function));
+
+        if let Some(index) = self.native_words.find(name.as_bytes()) {
+            self.native_words.words[index as usize].function = function;
+
+            return index;
+        }
+
+        self.native_words.words.push(NativeWord { name: name.as_bytes().to_vec(), function });
+
+        (self.native_words.words.len() - 1) as u16
+    }
+
+    /// Run native word with given index.
+    pub fn call_native_word(&mut self, index: u16) -> Result<(), MachineError> {
+        let word = self.native_words.words.get_mut(index as usize)
+            .ok_or(MachineError::UnknownNativeWord { index })?;
+        let mut function = word.function.take()
+            .ok_or(MachineError::NativeWordReentered { index })?;
+
+        let result = function(self);
+
+        self.native_words.words[index as usize].function = Some(function);
+
+        result
+    }
+
+    /// Find index of a native word with name stored as a sized string at given address.
+    pub(crate) fn find_native_word(&self, name_address: Address) -> Result<Option<u16>, MachineError> {
+        if self.native_words.is_empty() {
+            return Ok(None);
+        }
+
+        let name = ReadableSizedString::new(&self.memory.raw_memory, name_address, self.memory.raw_memory.address_range())?;
+
+        Ok(self.native_words.find(&name.as_bytes()))
+    }
+
+    /// Run native word with given index in interpreter state, compile a call of it in compiler state.
+    pub(crate) fn process_native_word(&mut self, index: u16) -> Result<(), MachineError> {
+        match self.memory.get_state() {
+            MachineState::Interpreter => self.call_native_word(index),
+            MachineState::Compiler => {
+                self.memory.dict_write_opcode(OpCode::ExecNative)?;
+                self.memory.dict_write_u16(index)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::str::from_utf8;
+
+    use crate::cell::Cell;
+    use crate::input::StaticStringInput;
+    use crate::machine_testing::*;
+
+    use super::*;
+
+    fn register_gcd(machine: &mut TestMachine) -> u16 {
+        machine.register_native_word("GCD", |machine| {
+            let mut b = machine.memory.data_pop_cell()?;
+            let mut a = machine.memory.data_pop_cell()?;
+
+            while b != 0 {
+                (a, b) = (b, a % b);
+            }
+
+            machine.memory.data_push_cell(a)
+        })
+    }
+
+    #[test]
+    fn test_native_word_interpreted() {
+        let mut machine = TestMachine::default();
+        register_gcd(&mut machine);
+
+        machine.extensions.input = StaticStringInput::new("48 18 GCD 17 5 GCD");
+        machine.interpret_input().unwrap();
+
+        machine.assert_data_stack_state(&[StackElement::Cell(6), StackElement::Cell(1)]);
+    }
+
+    #[test]
+    fn test_native_word_compiled() {
+        let mut machine = TestMachine::default();
+        let index = register_gcd(&mut machine);
+
+        machine.extensions.input = StaticStringInput::new(": reduce 2DUP GCD ; 84 36 reduce");
+        machine.interpret_input().unwrap();
+
+        machine.assert_data_stack_state(&[StackElement::Cell(84), StackElement::Cell(36), StackElement::Cell(12)]);
+
+        let body_address = machine.memory.lookup_article(b"reduce").unwrap().unwrap().body_address();
+        let mut buf = Vec::new();
+        let mut address = body_address;
+
+        while address < machine.memory.get_dict_ptr() {
+            address = OpCode::format_at(&mut buf, &machine, address).unwrap();
+        }
+
+        assert!(from_utf8(&buf).unwrap().contains(&format!("execNative {} GCD\n", index)), "{}", from_utf8(&buf).unwrap());
+    }
+
+    #[test]
+    fn test_native_word_replaced() {
+        let mut machine = TestMachine::default();
+        let index = machine.register_native_word("answer", |machine| machine.memory.data_push_cell(41));
+
+        machine.extensions.input = StaticStringInput::new(": ask answer ;");
+        machine.interpret_input().unwrap();
+
+        assert_eq!(machine.register_native_word("answer", |machine| machine.memory.data_push_cell(42)), index);
+
+        machine.extensions.input = StaticStringInput::new("ask");
+        machine.interpret_input().unwrap();
+
+        machine.assert_data_stack_state(&[StackElement::Cell(42)]);
+    }
+
+    #[test]
+    fn test_unknown_native_word() {
+        let mut machine = TestMachine::default();
+        let index = register_gcd(&mut machine);
+
+        machine.extensions.input = StaticStringInput::new(": bad 1 2 GCD ;");
+        machine.interpret_input().unwrap();
+
+        // Point the compiled call to a word that is not registered
+        let body_address = machine.memory.lookup_article(b"bad").unwrap().unwrap().body_address();
+        let mut address = body_address;
+
+        while machine.memory.raw_memory.read_u8(address) != OpCode::ExecNative as u8 {
+            address = OpCode::format_at(&mut std::io::sink(), &machine, address).unwrap();
+        }
+
+        machine.memory.raw_memory.write_u16(address + 1, index + 1);
+
+        machine.extensions.input = StaticStringInput::new("bad");
+
+        assert!(matches!(machine.interpret_input(), Err(MachineError::UnknownNativeWord { index: 1 })));
+        assert_eq!(machine.native_words.len(), 1);
+        assert_eq!(machine.native_words.name(index), Some(b"GCD".as_slice()));
+        assert_eq!(machine.memory.data_pop_cell().unwrap(), 2 as Cell);
+    }
+}
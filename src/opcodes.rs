@@ -1,15 +1,27 @@
-use std::io;
+use std::borrow::Cow;
+use std::marker::PhantomData;
+use std::ops::{Range, RangeInclusive};
 use std::str::from_utf8;
 use int_enum::IntEnum;
-use crate::builtin_words::process_builtin_word;
+use crate::builtin_words::{opcode_word, opcode_word_stack_comment};
+use crate::cell::{CELL_BYTES, DOUBLE_CELL_BYTES};
 
 use crate::machine::{Machine, MachineExtensions};
 use crate::machine_error::MachineError;
-use crate::machine_state::MachineState;
-use crate::mem::Address;
-use crate::output::Output;
+use crate::mem::{AccessKind, Address};
+use crate::memory_segment::DICTIONARY;
 use crate::sized_string::ReadableSizedString;
-use crate::stack_effect::stack_effect;
+
+mod arith;
+mod control;
+mod io;
+mod memory;
+mod pno;
+mod stack;
+
+pub(crate) use control::{next_name_token, validate_execution_token};
+pub(crate) use memory::{fetch_cell, store_cell, store_double_cell};
+pub(crate) use stack::{dup_cell, swap_cells};
 
 #[repr(u8)]
 #[derive(Clone, Copy, PartialEq, Debug, IntEnum)]
@@ -18,10 +30,11 @@ pub enum OpCode {
 
     /// Op-code placed at beginning of a standard (non-immediate) article.
     ///
-    /// Does nothing in interpreter mode (allowing seamless execution of following instructions).
-    /// Writes a `Call` op-code with address of the next instruction to dictionary and returns in compiler mode.
+    /// Does nothing. Marks the article as one that is compiled as a call to the next instruction rather than
+    /// executed in compiler mode.
     ///
-    /// Can be replaced by `Noop` to make word executable both immediately and from compiled code.
+    /// Can be replaced by `ImmediateArticleStart` to make word immediate or by `CompileOnlyArticleStart` to make it
+    /// compile-only.
     DefaultArticleStart = 1,
 
     /// Pop an address from call stack and go to that address.
@@ -33,7 +46,7 @@ pub enum OpCode {
     /// call stack, and go to that address.
     Call = 3,
 
-    /// Must be followed by an 16-bit value.
+    /// Must be followed by a single-cell value.
     /// Pushes that value to data stack.
     Literal16 = 4,
 
@@ -62,6 +75,74 @@ pub enum OpCode {
     CallRead16 = 13,
     CallRead32 = 14,
 
+    /// Must be followed by address of another instruction.
+    ///
+    /// Writes a `Call` of that address to dictionary.
+    CompileCall = 15,
+
+    /// Must be followed by a single byte.
+    /// Pushes that byte zero-extended to a cell to data stack.
+    Literal8 = 16,
+
+    /// Pushes zero to data stack.
+    Push0 = 17,
+
+    /// Pushes one to data stack.
+    Push1 = 18,
+
+    /// Pushes a cell with all bits set (`TRUE`) to data stack.
+    PushTrue = 19,
+
+    /// Must be followed by a signed 16-bit offset relative to the address following the offset.
+    ///
+    /// Unconditionally goes to the address at that offset.
+    BranchRel = 20,
+
+    /// Must be followed by a signed 16-bit offset relative to the address following the offset.
+    ///
+    /// Takes one cell from data stack and goes to the address at that offset iff value of that cell is zero.
+    BranchRelIfZ = 21,
+
+    /// Must be followed by a signed 16-bit offset relative to the address following the offset.
+    ///
+    /// Same as `Call` with address at that offset.
+    CallRel = 22,
+
+    /// Op-code placed at beginning of a compile-only article instead of `DefaultArticleStart`.
+    ///
+    /// Does nothing. Marks the article as one that fails to execute in interpreter mode.
+    CompileOnlyArticleStart = 23,
+
+    /// Op-code placed at beginning of an article that is both immediate and compile-only.
+    ///
+    /// Does nothing.
+    ImmediateCompileOnlyArticleStart = 24,
+
+    /// Takes a count from data stack and moves that many cells below it, followed by the count, to call stack.
+    ///
+    /// Nothing is moved unless both stacks have enough cells and room for the whole group.
+    CallPushN = 25,
+
+    /// Moves a group of cells pushed by `CallPushN`, followed by their count, back to data stack.
+    CallPopN = 26,
+
+    /// Takes an execution token (an address of code) from data stack and calls it.
+    Execute = 27,
+
+    /// Takes an execution token and a word list identifier from data stack and calls the token with name token of
+    /// every article, the last one first, until it returns false.
+    ///
+    /// Must be followed by `TraverseWordlistNext`, the callback returns to it.
+    TraverseWordlist = 28,
+
+    /// Takes a flag returned by a `TraverseWordlist` callback, the callback's execution token and the last name
+    /// token are kept on call stack.
+    TraverseWordlistNext = 29,
+
+    /// Must be followed by a double-cell value.
+    /// Pushes that value to data stack.
+    Literal32 = 30,
+
     Dup32 = 123,
     Over16 = 124,
     Over32 = 125,
@@ -90,607 +171,715 @@ pub enum OpCode {
     I16ToI32 = 148,
     Abs16 = 149,
 
-    Emit = 200,
-    PnoInit = 201,
-    PnoPut = 202,
-    PnoFinish = 203,
-    PnoPutDigit = 204,
-    EmitString = 205,
-}
-
-impl OpCode {
-    pub fn execute_at<TExt: MachineExtensions>(machine: &mut Machine<TExt>, address: Address) -> Result<Address, MachineError> {
-        let op_code = machine.memory.raw_memory.read_u8(address);
-
-        match OpCode::from_int(op_code) {
-            Err(_) => Err(MachineError::IllegalOpCodeError { address, op_code }),
-            Ok(op) => op.execute(machine, address)
-        }
-    }
-
-    pub fn execute<TExt: MachineExtensions>(self, machine: &mut Machine<TExt>, address: Address) -> Result<Address, MachineError> {
-        Ok(match self {
-            OpCode::Noop => {
-                address + 1
-            }
-
-            OpCode::DefaultArticleStart => {
-                match machine.memory.get_state() {
-                    MachineState::Interpreter => {
-                        address + 1 // Noop
-                    }
-                    MachineState::Compiler => {
-                        machine.memory.dict_write_opcode(OpCode::Call)?;
-                        machine.memory.dict_write_u16(address + 1)?;
+    /// Rounds an address on data stack up to a cell boundary.
+    Aligned = 150,
 
-                        if machine.memory.call_stack_depth() == 0 {
-                            return Err(MachineError::Exited);
-                        }
+    /// Allocates a heap block of size taken from data stack, pushes it's address and `ior`.
+    Allocate = 151,
 
-                        machine.memory.call_pop_u16()?
-                    }
-                }
-            }
+    /// Frees a heap block at address taken from data stack, pushes `ior`.
+    Free = 152,
 
-            OpCode::Return => {
-                if machine.memory.call_stack_depth() == 0 {
-                    return Err(MachineError::Exited);
-                }
+    /// Takes an address of a heap block and a new size, pushes the block's new address and `ior`.
+    Resize = 153,
 
-                machine.memory.call_pop_u16()?
-            }
+    /// Takes a name token, pushes address and length of the name.
+    NameToString = 154,
 
-            OpCode::Call => {
-                machine.memory.raw_memory.validate_access(
-                    address + 1..=address + 2,
-                    machine.memory.get_used_dict_segment(),
-                )?;
+    /// Takes a name token, pushes execution token of the word.
+    NameToInterpret = 155,
 
-                let target_address = unsafe { machine.memory.raw_memory.read_u16(address + 1) };
+    /// Takes an execution token, compiles a call of it into the dictionary.
+    CompileXt = 156,
 
-                machine.memory.call_push_u16(address + 3)?;
+    /// Takes a name token, pushes `TRUE` if the word is immediate, `FALSE` otherwise.
+    NameIsImmediate = 157,
 
-                target_address
-            }
+    /// Does nothing, starts code of words created by `VALUE` and `2VALUE` so `TO` can recognize them.
+    ValuePrologue = 158,
 
-            OpCode::Literal16 => {
-                machine.memory.raw_memory.validate_access(
-                    address + 1..=address + 2,
-                    machine.memory.get_used_dict_segment(),
-                )?;
+    /// Op-code placed at beginning of an immediate article instead of `DefaultArticleStart`.
+    ///
+    /// Does nothing. Articles of older images start with `Noop` instead.
+    ImmediateArticleStart = 159,
 
-                let literal = unsafe { machine.memory.raw_memory.read_u16(address + 1) };
+    /// Does nothing, starts code of synonyms of op-code words, followed by the op-code and `Return`.
+    ///
+    /// References to such synonyms are compiled as the op-code itself, see `find_opcode_synonym`.
+    SynonymPrologue = 160,
 
-                machine.memory.data_push_u16(literal)?;
+    Emit = 200,
+    PnoInit = 201,
+    PnoPut = 202,
+    PnoFinish = 203,
+    PnoPutDigit = 204,
+    EmitString = 205,
 
-                address + 3
-            }
+    /// Takes address and size of a file name from data stack and saves machine image to that file.
+    SaveImage = 206,
 
-            OpCode::GoTo => {
-                machine.memory.raw_memory.validate_access(
-                    address + 1..=address + 2,
-                    machine.memory.get_used_dict_segment(),
-                )?;
+    /// Takes address and size of a file name from data stack and restores machine image from that file.
+    ///
+    /// Execution of current word stops as call stack is cleared when the image is loaded.
+    LoadImage = 207,
 
-                unsafe { machine.memory.raw_memory.read_u16(address + 1) }
-            }
+    /// Pads dictionary with `Noop` op-codes up to a cell boundary.
+    Align = 208,
 
-            OpCode::GoToIfZ => {
-                let value = machine.memory.data_pop_u16()?;
+    /// Aligns dictionary and moves a cell from data stack to dictionary.
+    Comma = 209,
 
-                if value == 0 {
-                    machine.memory.raw_memory.validate_access(
-                        address + 1..=address + 2,
-                        machine.memory.get_used_dict_segment(),
-                    )?;
+    /// Moves a byte from data stack to dictionary.
+    CommaByte = 210,
 
-                    unsafe { machine.memory.raw_memory.read_u16(address + 1) }
-                } else {
-                    address + 3
-                }
-            }
+    /// Emits a line break.
+    Cr = 211,
 
-            OpCode::LiteralString => {
-                let string_range = ReadableSizedString::new(
-                    &machine.memory.raw_memory,
-                    address + 1,
-                    machine.memory.get_used_dict_segment(),
-                )?.content_range();
+    /// Emits a single space.
+    Space = 212,
 
-                let mut fx = stack_effect!(machine; => address:Address, size:u16)?;
-                fx.address(*string_range.start());
-                fx.size(string_range.len() as u16);
-                fx.commit();
+    /// Takes a number from data stack and emits that many spaces, nothing if the number is not positive.
+    Spaces = 213,
 
-                string_range.end().wrapping_add(1)
-            }
+    /// Takes a Unicode code point from data stack and emits it encoded as UTF-8.
+    XEmit = 214,
 
-            OpCode::ExecBuiltin => {
-                let string_range = ReadableSizedString::new(
-                    &machine.memory.raw_memory,
-                    address + 1,
-                    machine.memory.get_used_dict_segment(),
-                )?.full_range();
+    /// Flushes output.
+    Flush = 215,
 
-                process_builtin_word(machine, *string_range.start())?;
+    /// Takes a number of milliseconds from data stack and waits that long.
+    Ms = 216,
 
-                string_range.end().wrapping_add(1)
-            }
+    /// Pushes current second, minute, hour, day, month and year to data stack.
+    TimeAndDate = 217,
 
-            OpCode::Over16 => {
-                let mut fx = stack_effect!(machine; a:u16, _b0:u16 => _a:u16, _b:u16, a_copy:u16)?;
+    /// Stops interpretation with `MachineError::Bye`.
+    Bye = 218,
 
-                fx.a_copy(fx.a());
-                fx.commit();
+    /// Takes file name and access method from data stack, opens the file and pushes its id and `ior`.
+    OpenFile = 219,
 
-                address + 1
-            }
+    /// Takes file name and access method from data stack, creates the file and pushes its id and `ior`.
+    CreateFile = 220,
 
-            OpCode::Over32 => {
-                let mut fx = stack_effect!(machine; a:u32, _b0:u32 => _a:u32, _b:u32, a_copy:u32)?;
+    /// Takes file id from data stack, closes the file and pushes `ior`.
+    CloseFile = 221,
 
-                fx.a_copy(fx.a());
-                fx.commit();
+    /// Takes buffer address, buffer size and file id from data stack, reads the file to the buffer and pushes number
+    /// of bytes read and `ior`.
+    ReadFile = 222,
 
-                address + 1
-            }
+    /// Takes buffer address, buffer size and file id from data stack, reads a line to the buffer and pushes its
+    /// length, a flag that is false at the end of the file, and `ior`.
+    ReadLine = 223,
 
-            OpCode::Swap16 => {
-                let mut fx = stack_effect!(machine; a:u16, b: u16 => b_:u16, a_:u16)?;
-                let (a, b) = (fx.a(), fx.b());
-                fx.a_(a);
-                fx.b_(b);
-                fx.commit();
+    /// Takes string address, string size and file id from data stack, writes the string to the file and pushes `ior`.
+    WriteFile = 224,
 
-                address + 1
-            }
+    /// Same as `WriteFile` but writes a line break after the string.
+    WriteLine = 225,
 
-            OpCode::Swap32 => {
-                let mut fx = stack_effect!(machine; a:u32, b: u32 => b_:u32, a_:u32)?;
-                let (a, b) = (fx.a(), fx.b());
-                fx.a_(a);
-                fx.b_(b);
-                fx.commit();
+    /// Takes file id from data stack and pushes current position in the file as a double cell and `ior`.
+    FilePosition = 226,
 
-                address + 1
-            }
+    /// Takes a double cell position and file id from data stack, moves to the position and pushes `ior`.
+    RepositionFile = 227,
 
-            OpCode::Dup16 => {
-                let mut fx = stack_effect!(machine; x:u16 => _x:u16, x_copy:u16)?;
-                fx.x_copy(fx.x());
-                fx.commit();
+    /// Takes file id from data stack and pushes size of the file as a double cell and `ior`.
+    FileSize = 228,
 
-                address + 1
-            }
+    /// Takes file name from data stack, deletes the file and pushes `ior`.
+    DeleteFile = 229,
 
-            OpCode::Dup32 => {
-                let mut fx = stack_effect!(machine; x:u32 => _x:u32, x_copy:u32)?;
-                fx.x_copy(fx.x());
-                fx.commit();
+    /// Runs a native word with index stored in the next two bytes.
+    ExecNative = 230,
 
-                address + 1
-            }
+    /// Suspends execution with `MachineError::Breakpoint` pointing to the next instruction.
+    Break = 231,
 
-            OpCode::Drop16 => {
-                machine.memory.data_pop_u16()?;
+    /// Pushes number of instructions executed by the machine so far, including this one, as a double cell.
+    Counter = 232,
 
-                address + 1
-            }
+    /// Pushes number of microseconds elapsed since Unix epoch according to the machine's clock as a double cell.
+    UTime = 233,
 
-            OpCode::Add16 => {
-                let mut fx = stack_effect!(machine; a:u16, b:u16 => c:u16)?;
+    /// Pushes data stack pointer as it was before the push.
+    SpFetch = 234,
 
-                fx.c(fx.a().wrapping_add(fx.b()));
-                fx.commit();
+    /// Takes an address from data stack and moves data stack pointer to it.
+    SpStore = 235,
 
-                address + 1
-            }
+    /// Pushes call stack pointer to data stack.
+    RpFetch = 236,
 
-            OpCode::Sub16 => {
-                let mut fx = stack_effect!(machine; a:u16, b:u16 => c:u16)?;
+    /// Takes an address from data stack and moves call stack pointer to it.
+    RpStore = 237,
+}
 
-                fx.c(fx.a().wrapping_sub(fx.b()));
-                fx.commit();
+fn validate_jump_target<TExt: MachineExtensions>(machine: &Machine<TExt>, from: Address, to: Address) -> Result<(), MachineError> {
+    if !machine.memory.is_executable(to) {
+        return Err(MachineError::InvalidJumpTarget { from, to });
+    }
 
-                address + 1
-            }
+    Ok(())
+}
 
-            OpCode::Mul16 => {
-                let mut fx = stack_effect!(machine; a:u16, b:u16 => c:u16)?;
+/// Write an instruction with a relative offset of given address (`CallRel`, `BranchRel` or `BranchRelIfZ`)
+/// to dictionary.
+pub fn compile_relative_jump<TExt: MachineExtensions>(machine: &mut Machine<TExt>, op_code: OpCode, target_address: Address) -> Result<(), MachineError> {
+    let address = machine.memory.get_dict_ptr();
+    validate_jump_target(machine, address, target_address)?;
 
-                fx.c(fx.a().wrapping_mul(fx.b()));
-                fx.commit();
+    machine.memory.dict_write_opcode(op_code)?;
+    machine.memory.dict_write_u16(target_address.wrapping_sub(address.wrapping_add(3)))?;
 
-                address + 1
-            }
+    Ok(())
+}
 
-            OpCode::Div16 => {
-                let mut fx = stack_effect!(machine; a:u16, b:u16 => c:u16)?;
+/// Write a call of given address to dictionary.
+pub fn compile_call<TExt: MachineExtensions>(machine: &mut Machine<TExt>, target_address: Address) -> Result<(), MachineError> {
+    compile_relative_jump(machine, OpCode::CallRel, target_address)
+}
 
-                fx.c(fx.a().wrapping_div(fx.b()));
-                fx.commit();
+/// Read target address of an instruction at given address followed by a relative offset.
+fn read_relative_target<TExt: MachineExtensions>(machine: &Machine<TExt>, address: Address) -> Result<Address, MachineError> {
+    machine.memory.raw_memory.validate_named_access(
+        address + 1..=address + 2,
+        machine.memory.get_used_dict_segment(),
+        DICTIONARY,
+        AccessKind::Read,
+    )?;
 
-                address + 1
-            }
+    let offset = machine.memory.raw_memory.read_u16(address + 1);
+    let target_address = address.wrapping_add(3).wrapping_add(offset);
+    validate_jump_target(machine, address, target_address)?;
 
-            OpCode::Load8 => {
-                let mut fx = stack_effect!(machine; address:Address => value:u16)?;
-                let target_address = fx.address();
+    Ok(target_address)
+}
 
-                fx.machine.memory.raw_memory.validate_access(
-                    target_address..=target_address,
-                    fx.machine.memory.raw_memory.address_range(),
-                )?;
+/// Find code starting at given address that may be copied to another word instead of being called.
+///
+/// The code must end with `Return` and contain no jumps, calls or instructions accessing call stack, as inlined code
+/// runs without a call stack frame of its own. Returns range of instructions preceding the `Return` if it is not
+/// longer than `max_size` bytes.
+pub fn find_inlinable_code<TExt: MachineExtensions>(machine: &Machine<TExt>, start_address: Address, max_size: u16) -> Option<Range<Address>> {
+    let used_dict_segment = machine.memory.get_used_dict_segment();
+    let mut address = start_address;
+
+    loop {
+        if !used_dict_segment.contains(&address) || address.wrapping_sub(start_address) > max_size {
+            return None;
+        }
 
-                fx.value(fx.machine.memory.raw_memory.read_u8(target_address) as u16);
-                fx.commit();
+        match OpCode::from_int(machine.memory.raw_memory.read_u8(address)).ok()? {
+            OpCode::Return => return Some(start_address..address),
+            OpCode::Call | OpCode::GoTo | OpCode::GoToIfZ
+            | OpCode::CallRel | OpCode::BranchRel | OpCode::BranchRelIfZ
+            | OpCode::CallPush16 | OpCode::CallPop16 | OpCode::CallRead16
+            | OpCode::CallPush32 | OpCode::CallPop32 | OpCode::CallRead32
+            | OpCode::CallPushN | OpCode::CallPopN | OpCode::RpFetch | OpCode::RpStore
+            | OpCode::Execute | OpCode::TraverseWordlist | OpCode::TraverseWordlistNext
+            | OpCode::LoadImage => return None,
+            op_code => {
+                let next_address = op_code.format(&mut std::io::sink(), machine, address).ok()?;
+
+                if next_address <= address {
+                    return None;
+                }
 
-                address + 1
+                address = next_address;
             }
+        }
+    }
+}
 
-            OpCode::Store8 => {
-                let fx = stack_effect!(machine; value: u8, address: Address =>)?;
-                let target_address = fx.address();
-
-                fx.machine.memory.raw_memory.validate_access(
-                    target_address..=target_address,
-                    fx.machine.memory.raw_memory.address_range(),
-                )?;
-
-                fx.machine.memory.raw_memory.write_u8(target_address, fx.value());
+/// Find op-code of a builtin word code of a synonym starting at given address stands for, `None` if the code is not
+/// a `SynonymPrologue` followed by an op-code word and `Return`.
+///
+/// References to such synonyms are compiled as the op-code itself rather than called, as some of the words (e.g.
+/// `>R`) work only in call stack frame of the word they are compiled into.
+pub fn find_opcode_synonym<TExt: MachineExtensions>(machine: &Machine<TExt>, start_address: Address) -> Option<OpCode> {
+    let used_dict_segment = machine.memory.get_used_dict_segment();
 
-                fx.commit();
+    if !used_dict_segment.contains(&start_address) || !used_dict_segment.contains(&start_address.wrapping_add(2)) {
+        return None;
+    }
 
-                address + 1
-            }
+    let raw_memory = &machine.memory.raw_memory;
 
-            OpCode::Load16 => {
-                let mut fx = stack_effect!(machine; address:Address => value:u16)?;
-                let target_address = fx.address();
+    if raw_memory.read_u8(start_address) != OpCode::SynonymPrologue.int_value()
+        || raw_memory.read_u8(start_address.wrapping_add(2)) != OpCode::Return.int_value() {
+        return None;
+    }
 
-                fx.machine.memory.raw_memory.validate_access(
-                    target_address..=target_address.wrapping_add(1),
-                    fx.machine.memory.raw_memory.address_range(),
-                )?;
+    OpCode::from_int(raw_memory.read_u8(start_address.wrapping_add(1))).ok()
+        .filter(|&op_code| opcode_word(op_code).is_some())
+}
 
-                fx.value(unsafe { fx.machine.memory.raw_memory.read_u16(target_address) });
-                fx.commit();
+/// Signature of functions executing an op-code located at given address.
+///
+/// Returns address of the next instruction to execute.
+pub type OpHandler<TExt> = fn(&mut Machine<TExt>, Address) -> Result<Address, MachineError>;
 
-                address + 1
-            }
+/// Op-code values no `OpCode` will ever use, reserved for op-codes implemented by `CustomOpHandler`.
+pub const CUSTOM_OP_CODES: RangeInclusive<u8> = 0xF0..=0xFE;
 
-            OpCode::Store16 => {
-                let fx = stack_effect!(machine; value:u16, address: Address =>)?;
-                let target_address = fx.address();
+/// Executes op-codes of `CUSTOM_OP_CODES` range, installed with `Machine::set_custom_op_handler`.
+pub trait CustomOpHandler<TExt: MachineExtensions> {
+    /// Execute custom op-code located at given address, returns address of the next instruction.
+    ///
+    /// Should return `MachineError::IllegalOpCodeError` for op-codes the handler doesn't implement.
+    fn execute(&mut self, machine: &mut Machine<TExt>, op_code: u8, address: Address) -> Result<Address, MachineError>;
 
-                fx.machine.memory.raw_memory.validate_access(
-                    target_address..=target_address.wrapping_add(1),
-                    fx.machine.memory.raw_memory.address_range(),
-                )?;
+    /// Name of custom op-code used by disassembler, `None` if the handler doesn't implement it.
+    fn mnemonic(&self, op_code: u8) -> Option<&'static str>;
 
-                unsafe { fx.machine.memory.raw_memory.write_u16(target_address, fx.value()) };
-                fx.commit();
+    /// Size in bytes of the operand following custom op-code, disassembler prints it as hex bytes.
+    fn operand_size(&self, _op_code: u8) -> u16 {
+        0
+    }
 
-                address + 1
-            }
+    /// Print disassembly of custom op-code located at given address, returns address of the next instruction.
+    ///
+    /// Called only for op-codes having a mnemonic. Prints the mnemonic followed by operand bytes in hex by default.
+    fn format(
+        &self, writer: &mut dyn std::io::Write, machine: &Machine<TExt>, op_code: u8, address: Address,
+    ) -> Result<Address, std::io::Error> {
+        let (mnemonic, operand_size) = (self.mnemonic(op_code).unwrap_or("?"), self.operand_size(op_code));
 
-            OpCode::Load32 => {
-                let mut fx = stack_effect!(machine; address:Address => value:u32)?;
-                let target_address = fx.address();
+        write!(writer, "{}", mnemonic)?;
 
-                fx.machine.memory.raw_memory.validate_access(
-                    target_address..=target_address.wrapping_add(3),
-                    fx.machine.memory.raw_memory.address_range(),
-                )?;
+        for offset in 1..=operand_size {
+            write!(writer, " {:02X}", machine.memory.raw_memory.read_u8(address.wrapping_add(offset)))?;
+        }
 
-                fx.value(unsafe { fx.machine.memory.raw_memory.read_u32(target_address) });
-                fx.commit();
+        writeln!(writer)?;
+        Ok(address.wrapping_add(1 + operand_size))
+    }
+}
 
-                address + 1
-            }
+macro_rules! op_code_table {
+    ($($op_code:ident => $module:ident::$handler:ident, $mnemonic:literal),* $(,)?) => {
+        /// Handlers of all op-codes indexed by op-code value, `None` for illegal op-codes.
+        struct DispatchTable<TExt: MachineExtensions>(PhantomData<TExt>);
+
+        impl<TExt: MachineExtensions> DispatchTable<TExt> {
+            const HANDLERS: [Option<OpHandler<TExt>>; 256] = {
+                let mut handlers: [Option<OpHandler<TExt>>; 256] = [None; 256];
+                $(handlers[OpCode::$op_code as usize] = Some($module::$handler::<TExt>);)*
+                handlers
+            };
+        }
 
-            OpCode::Store32 => {
-                let fx = stack_effect!(machine; value:u32, address: Address =>)?;
-                let target_address = fx.address();
+        /// Mnemonics of all op-codes indexed by op-code value, `None` for illegal op-codes.
+        const MNEMONICS: [Option<&str>; 256] = {
+            let mut mnemonics: [Option<&str>; 256] = [None; 256];
+            $(mnemonics[OpCode::$op_code as usize] = Some($mnemonic);)*
+            mnemonics
+        };
 
-                fx.machine.memory.raw_memory.validate_access(
-                    target_address..=target_address.wrapping_add(3),
-                    fx.machine.memory.raw_memory.address_range(),
-                )?;
+        /// All op-codes, in order of the op-code table.
+        pub const ALL_OP_CODES: &[OpCode] = &[$(OpCode::$op_code),*];
+    };
+}
 
-                unsafe { fx.machine.memory.raw_memory.write_u32(target_address, fx.value()) };
+// The table of op-codes: handler and mnemonic of each one. Execution, disassembly, assembly and `OpCode::is_known`
+// all use it. Every `OpCode` variant must have an entry here, `test_op_code_table_matches_op_codes` checks that.
+op_code_table! {
+    Noop => control::execute_noop, "noop",
+    DefaultArticleStart => control::execute_default_article_start, "start_article",
+    Return => control::execute_return, "ret",
+    Call => control::execute_call, "call",
+    Literal16 => stack::execute_literal16, "push16",
+    Literal32 => stack::execute_literal32, "push32",
+    Literal8 => stack::execute_literal8, "push8",
+    Push0 => stack::execute_push0, "push0",
+    Push1 => stack::execute_push1, "push1",
+    PushTrue => stack::execute_push_true, "push_true",
+    BranchRel => control::execute_branch_rel, "rjump",
+    BranchRelIfZ => control::execute_branch_rel_if_z, "rjumpz",
+    CallRel => control::execute_call_rel, "rcall",
+    CompileOnlyArticleStart => control::execute_default_article_start, "start_compile_only",
+    ImmediateCompileOnlyArticleStart => control::execute_default_article_start, "start_immediate_compile_only",
+    CallPushN => stack::execute_call_push_n, "call_push_n",
+    Execute => control::execute_execute, "execute",
+    TraverseWordlist => control::execute_traverse_wordlist, "traverse_wordlist",
+    TraverseWordlistNext => control::execute_traverse_wordlist_next, "traverse_wordlist_next",
+    CallPopN => stack::execute_call_pop_n, "call_pop_n",
+    GoTo => control::execute_go_to, "jump",
+    GoToIfZ => control::execute_go_to_if_z, "jumpz",
+    LiteralString => stack::execute_literal_string, "pushStr",
+    ExecBuiltin => control::execute_exec_builtin, "execBuiltin",
+    CompileCall => control::execute_compile_call, "compile_call",
+    Over16 => stack::execute_over16, "over",
+    Over32 => stack::execute_over32, "over32",
+    Swap16 => stack::execute_swap16, "swap",
+    Swap32 => stack::execute_swap32, "swap32",
+    Dup16 => stack::execute_dup16, "dup",
+    Dup32 => stack::execute_dup32, "dup32",
+    Drop16 => stack::execute_drop16, "drop",
+    Add16 => arith::execute_add16, "add",
+    Sub16 => arith::execute_sub16, "sub",
+    Mul16 => arith::execute_mul16, "mul",
+    Div16 => arith::execute_div16, "div",
+    Load8 => memory::execute_load8, "load8",
+    Store8 => memory::execute_store8, "store8",
+    Load16 => memory::execute_load16, "load",
+    Store16 => memory::execute_store16, "store",
+    Load32 => memory::execute_load32, "load32",
+    Store32 => memory::execute_store32, "store32",
+    Invert16 => arith::execute_invert16, "invert",
+    And16 => arith::execute_and16, "and",
+    Or16 => arith::execute_or16, "or",
+    Xor16 => arith::execute_xor16, "xor",
+    Eq16 => arith::execute_eq16, "eq",
+    Lt16 => arith::execute_lt16, "lt",
+    Gt16 => arith::execute_gt16, "gt",
+    Emit => io::execute_emit, "emit",
+    Rot16 => stack::execute_rot16, "rot",
+    I16ToI32 => arith::execute_i16_to_i32, "s>d",
+    CallPop16 => stack::execute_call_pop16, "call_pop",
+    CallPush16 => stack::execute_call_push16, "call_push",
+    CallPop32 => stack::execute_call_pop32, "call_pop32",
+    CallPush32 => stack::execute_call_push32, "call_push32",
+    CallRead16 => stack::execute_call_read16, "call_get",
+    CallRead32 => stack::execute_call_read32, "call_get32",
+    Abs16 => arith::execute_abs16, "abs",
+    Aligned => arith::execute_aligned, "aligned",
+    Allocate => memory::execute_allocate, "allocate",
+    Free => memory::execute_free, "free",
+    Resize => memory::execute_resize, "resize",
+    NameToString => control::execute_name_to_string, "name_to_string",
+    NameToInterpret => control::execute_name_to_interpret, "name_to_interpret",
+    CompileXt => control::execute_compile_xt, "compile_xt",
+    NameIsImmediate => control::execute_name_is_immediate, "name_is_immediate",
+    ValuePrologue => control::execute_noop, "value",
+    ImmediateArticleStart => control::execute_default_article_start, "start_immediate",
+    SynonymPrologue => control::execute_noop, "synonym",
+    PnoInit => pno::execute_pno_init, "pno:init",
+    PnoPut => pno::execute_pno_put, "pno:put",
+    PnoFinish => pno::execute_pno_finish, "pno:finish",
+    PnoPutDigit => pno::execute_pno_put_digit, "pno:put_digit",
+    EmitString => io::execute_emit_string, "emit_str",
+    SaveImage => memory::execute_save_image, "save_image",
+    LoadImage => memory::execute_load_image, "load_image",
+    Align => memory::execute_align, "align",
+    Comma => memory::execute_comma, "comma",
+    CommaByte => memory::execute_comma_byte, "c_comma",
+    Cr => io::execute_cr, "cr",
+    Space => io::execute_space, "space",
+    Spaces => io::execute_spaces, "spaces",
+    XEmit => io::execute_xemit, "xemit",
+    Flush => io::execute_flush, "flush",
+    Ms => io::execute_ms, "ms",
+    TimeAndDate => io::execute_time_and_date, "time_and_date",
+    Bye => control::execute_bye, "bye",
+    OpenFile => io::execute_open_file, "open_file",
+    CreateFile => io::execute_create_file, "create_file",
+    CloseFile => io::execute_close_file, "close_file",
+    ReadFile => io::execute_read_file, "read_file",
+    ReadLine => io::execute_read_line, "read_line",
+    WriteFile => io::execute_write_file, "write_file",
+    WriteLine => io::execute_write_line, "write_line",
+    FilePosition => io::execute_file_position, "file_position",
+    RepositionFile => io::execute_reposition_file, "reposition_file",
+    FileSize => io::execute_file_size, "file_size",
+    DeleteFile => io::execute_delete_file, "delete_file",
+    ExecNative => control::execute_exec_native, "execNative",
+    Break => control::execute_break, "break",
+    Counter => control::execute_counter, "counter",
+    UTime => io::execute_utime, "utime",
+    SpFetch => stack::execute_sp_fetch, "sp_fetch",
+    SpStore => stack::execute_sp_store, "sp_store",
+    RpFetch => stack::execute_rp_fetch, "rp_fetch",
+    RpStore => stack::execute_rp_store, "rp_store",
+}
 
-                fx.commit();
+impl OpCode {
+    pub fn execute_at<TExt: MachineExtensions>(machine: &mut Machine<TExt>, address: Address) -> Result<Address, MachineError> {
+        let op_code = machine.memory.raw_memory.read_u8(address);
 
-                address + 1
-            }
+        machine.trace_instruction(address, op_code);
 
-            OpCode::Invert16 => {
-                let mut fx = stack_effect!(machine; a:u16 => b:u16)?;
-                fx.b(!fx.a());
-                fx.commit();
+        match DispatchTable::<TExt>::HANDLERS[op_code as usize] {
+            None if CUSTOM_OP_CODES.contains(&op_code) => machine.execute_custom_op(op_code, address),
+            None => Err(MachineError::IllegalOpCodeError { address, op_code }),
+            Some(handler) => handler(machine, address).map_err(|err| match OpCode::from_int(op_code) {
+                Ok(op_code) => op_code.name_error(err),
+                Err(_) => err,
+            }),
+        }
+    }
 
-                address + 1
-            }
+    pub fn execute<TExt: MachineExtensions>(self, machine: &mut Machine<TExt>, address: Address) -> Result<Address, MachineError> {
+        let handler = DispatchTable::<TExt>::HANDLERS[self.int_value() as usize]
+            .expect("every op-code has a handler");
 
-            OpCode::And16 => {
-                let mut fx = stack_effect!(machine; a:u16, b:u16 => c:u16)?;
-                fx.c(fx.a() & fx.b());
-                fx.commit();
+        handler(machine, address).map_err(|err| self.name_error(err))
+    }
 
-                address + 1
-            }
+    /// Attach mnemonic of this op-code to a stack error it caused.
+    fn name_error(self, err: MachineError) -> MachineError {
+        match self {
+            // Builtin and native words are not op-codes, their errors are not named after the op-code calling them
+            OpCode::ExecBuiltin | OpCode::ExecNative => err,
+            _ => match opcode_word(self) {
+                Some(word) => err.in_word(word, opcode_word_stack_comment(self)),
+                None => err.in_word(self.mnemonic(), None),
+            },
+        }
+    }
 
-            OpCode::Or16 => {
-                let mut fx = stack_effect!(machine; a:u16, b:u16 => c:u16)?;
-                fx.c(fx.a() | fx.b());
-                fx.commit();
+    /// Size in bytes of the operand following the op-code, `None` if the operand is a sized string.
+    pub fn operand_size(self) -> Option<u16> {
+        match self {
+            OpCode::LiteralString | OpCode::ExecBuiltin => None,
+            OpCode::Literal16 => Some(CELL_BYTES),
+            OpCode::Literal32 => Some(DOUBLE_CELL_BYTES),
+            OpCode::Literal8 => Some(1),
+            OpCode::Call | OpCode::CompileCall | OpCode::GoTo | OpCode::GoToIfZ
+            | OpCode::BranchRel | OpCode::BranchRelIfZ | OpCode::CallRel | OpCode::ExecNative => Some(2),
+            _ => Some(0),
+        }
+    }
 
-                address + 1
-            }
+    /// Name of the op-code used by disassembler and assembler.
+    pub fn mnemonic(self) -> &'static str {
+        MNEMONICS[self as usize].expect("every op-code has a mnemonic")
+    }
 
-            OpCode::Xor16 => {
-                let mut fx = stack_effect!(machine; a:u16, b:u16 => c:u16)?;
-                fx.c(fx.a() ^ fx.b());
-                fx.commit();
+    /// Check if given value is an op-code of `ALL_OP_CODES`.
+    pub fn is_known(op_code: u8) -> bool {
+        MNEMONICS[op_code as usize].is_some()
+    }
 
-                address + 1
-            }
+    pub fn format_at<TExt: MachineExtensions>(writer: &mut impl std::io::Write, machine: &Machine<TExt>, address: Address) -> Result<Address, std::io::Error> {
+        let op_code = machine.memory.raw_memory.read_u8(address);
 
-            OpCode::Eq16 => {
-                let mut fx = stack_effect!(machine; a:u16, b:u16 => r:bool)?;
-                fx.r(fx.a() == fx.b());
-                fx.commit();
+        write!(writer, "{:04X}: ", address)?;
 
-                address + 1
+        match OpCode::from_int(op_code) {
+            Err(_) => {
+                let custom = machine.custom_op_handler()
+                    .filter(|handler| CUSTOM_OP_CODES.contains(&op_code) && handler.mnemonic(op_code).is_some());
+
+                match custom {
+                    Some(handler) => handler.format(writer, machine, op_code, address),
+                    None => {
+                        writeln!(writer, "(illegal op-code = {})", op_code)?;
+                        Ok(address + 1)
+                    }
+                }
             }
+            Ok(op) => op.format(writer, machine, address)
+        }
+    }
 
-            OpCode::Lt16 => {
-                let mut fx = stack_effect!(machine; a:i16, b:i16 => r:bool)?;
-                fx.r(fx.a() < fx.b());
-                fx.commit();
-
-                address + 1
+    pub fn format<TExt: MachineExtensions>(self, writer: &mut impl std::io::Write, machine: &Machine<TExt>, address: Address) -> Result<Address, std::io::Error> {
+        match self {
+            OpCode::Literal16 => stack::format_literal16(writer, machine, address),
+            OpCode::Literal32 => stack::format_literal32(writer, machine, address),
+            OpCode::Literal8 => stack::format_literal8(writer, machine, address),
+            OpCode::LiteralString | OpCode::ExecBuiltin => format_sized_string_operand(self, writer, machine, address),
+            OpCode::Call | OpCode::CompileCall | OpCode::GoTo | OpCode::GoToIfZ => {
+                control::format_absolute_target(self, writer, machine, address)
+            }
+            OpCode::BranchRel | OpCode::BranchRelIfZ | OpCode::CallRel => {
+                control::format_relative_target(self, writer, machine, address)
+            }
+            OpCode::ExecNative => control::format_exec_native(writer, machine, address),
+            _ => {
+                writeln!(writer, "{}", self.mnemonic())?;
+                Ok(address + 1)
             }
+        }
+    }
+}
 
-            OpCode::Gt16 => {
-                let mut fx = stack_effect!(machine; a:i16, b:i16 => r:bool)?;
-                fx.r(fx.a() > fx.b());
-                fx.commit();
+/// Format an instruction followed by a sized string (`LiteralString` or `ExecBuiltin`).
+fn format_sized_string_operand<TExt: MachineExtensions>(
+    op_code: OpCode,
+    writer: &mut impl std::io::Write,
+    machine: &Machine<TExt>,
+    address: Address,
+) -> Result<Address, std::io::Error> {
+    let (range, content) = match ReadableSizedString::new(&machine.memory.raw_memory, address + 1, machine.memory.get_used_dict_segment()) {
+        Ok(s) => (s.full_range(), s.as_bytes()),
+        Err(_) => (address + 1..=address + 1, Cow::Borrowed(b"<<<<invalid string>>>>".as_slice()))
+    };
+
+    match from_utf8(&content) {
+        Ok(s) => writeln!(writer, "{} {}", op_code.mnemonic(), s)?,
+        Err(_) => writeln!(writer, "{} {:?}", op_code.mnemonic(), content)?
+    }
 
-                address + 1
-            }
+    Ok(range.end().wrapping_add(1))
+}
 
-            OpCode::Emit => {
-                let char_code = machine.memory.data_pop_u16()?;
+#[cfg(test)]
+mod test {
+    use std::rc::Rc;
 
-                machine.extensions.get_output().putc(char_code)?;
+    use crate::cell::Cell;
+    use crate::input::StaticStringInput;
+    use crate::machine_testing::*;
+    use crate::stack_effect::stack_effect;
 
-                address + 1
-            }
-            OpCode::Rot16 => {
-                let mut fx = stack_effect!(machine; a:u16, b:u16, c:u16 => b1:u16, c1:u16, a1:u16)?;
-                let (a, b, c) = (fx.a(), fx.b(), fx.c());
-                fx.a1(a);
-                fx.b1(b);
-                fx.c1(c);
-                fx.commit();
-
-                address + 1
-            }
-            OpCode::I16ToI32 => {
-                let mut fx = stack_effect!(machine; a:i16 => b:i32)?;
-                fx.b(fx.a() as i32);
-                fx.commit();
+    use super::*;
 
-                address + 1
-            }
-            OpCode::CallPop16 => {
-                let val = machine.memory.call_pop_u16()?;
-                machine.memory.data_push_u16(val)?;
+    const ADD_IMMEDIATE: u8 = 0xF0;
 
-                address + 1
-            }
-            OpCode::CallPush16 => {
-                let val = machine.memory.data_pop_u16()?;
-                machine.memory.call_push_u16(val)?;
+    /// Implements `ADD_IMMEDIATE` op-code adding a byte following it to the cell on top of data stack.
+    struct ToyOpHandler;
 
-                address + 1
+    impl CustomOpHandler<TestMachineExtensions> for ToyOpHandler {
+        fn execute(&mut self, machine: &mut TestMachine, op_code: u8, address: Address) -> Result<Address, MachineError> {
+            if op_code != ADD_IMMEDIATE {
+                return Err(MachineError::IllegalOpCodeError { address, op_code });
             }
-            OpCode::CallPop32 => {
-                let val = machine.memory.call_pop_u32()?;
-                machine.memory.data_push_u32(val)?;
 
-                address + 1
-            }
-            OpCode::CallPush32 => {
-                let val = machine.memory.data_pop_u32()?;
-                machine.memory.call_push_u32(val)?;
+            let operand = machine.memory.raw_memory.read_u8(address + 1);
+            let mut fx = stack_effect!(machine; a:Cell => b:Cell)?;
+            fx.b(fx.a().wrapping_add(operand as Cell));
+            fx.commit();
 
-                address + 1
-            }
-            OpCode::CallRead16 => {
-                let val = machine.memory.call_get_u16()?;
-                machine.memory.data_push_u16(val)?;
+            Ok(address + 2)
+        }
 
-                address + 1
-            }
-            OpCode::CallRead32 => {
-                let val = machine.memory.call_get_u32()?;
-                machine.memory.data_push_u32(val)?;
+        fn mnemonic(&self, op_code: u8) -> Option<&'static str> {
+            (op_code == ADD_IMMEDIATE).then_some("addi")
+        }
 
-                address + 1
-            }
-            OpCode::Abs16 => {
-                let mut fx = stack_effect!(machine; a:i16 => b:i16)?;
-                fx.b(fx.a().abs());
-                fx.commit();
+        fn operand_size(&self, _op_code: u8) -> u16 {
+            1
+        }
+    }
 
-                address + 1
-            }
-            OpCode::PnoInit => {
-                machine.memory.clear_pno_buffer();
+    const PEEK_COUNTER: u8 = 0xF2;
 
-                address + 1
-            }
-            OpCode::PnoPut => {
-                let ch = machine.memory.data_pop_u16()? as u8;
-                machine.memory.pno_put(ch)?;
+    /// Implements `PEEK_COUNTER` op-code pushing current value of a counter owned by host.
+    struct CounterOpHandler {
+        counter: Rc<std::cell::Cell<Cell>>,
+    }
 
-                address + 1
+    impl CustomOpHandler<TestMachineExtensions> for CounterOpHandler {
+        fn execute(&mut self, machine: &mut TestMachine, op_code: u8, address: Address) -> Result<Address, MachineError> {
+            if op_code != PEEK_COUNTER {
+                return Err(MachineError::IllegalOpCodeError { address, op_code });
             }
-            OpCode::PnoFinish => {
-                let (addr, size) = machine.memory.pno_finish();
-                let mut fx = stack_effect!(machine; _x:u32 => address:Address, size:u16)?;
-                fx.address(addr);
-                fx.size(size as u16);
-                fx.commit();
-
-                address + 1
-            }
-            OpCode::PnoPutDigit => {
-                let mut fx = stack_effect!(machine; i:u32 => o:u32)?;
-                let base = fx.machine.memory.get_base() as u32;
-                let i = fx.i();
-
-                let digit = (i % base) as u8;
-                fx.o(i / base);
 
-                fx.commit();
+            machine.memory.data_push_cell(self.counter.get())?;
 
-                let digit_char = if digit < 10 {
-                    b'0'.wrapping_add(digit)
-                } else {
-                    b'A'.wrapping_add(digit).wrapping_sub(10)
-                };
+            Ok(address + 1)
+        }
 
-                machine.memory.pno_put(digit_char)?;
+        fn mnemonic(&self, op_code: u8) -> Option<&'static str> {
+            (op_code == PEEK_COUNTER).then_some("peekCounter")
+        }
 
-                address + 1
-            }
-            OpCode::EmitString => {
-                let fx = stack_effect!(machine; addr: Address, size: u16 => )?;
-                let (addr, size) = (fx.addr(), fx.size());
-                fx.commit();
+        fn format(
+            &self, writer: &mut dyn std::io::Write, _machine: &TestMachine, _op_code: u8, address: Address,
+        ) -> Result<Address, std::io::Error> {
+            writeln!(writer, "peekCounter (host counter = {})", self.counter.get())?;
 
-                let text = machine.memory.raw_memory.address_slice(addr, size as usize);
+            Ok(address + 1)
+        }
+    }
 
-                machine.extensions.get_output().puts(text)?;
+    #[test]
+    fn test_custom_op_codes_are_not_used() {
+        for op_code in CUSTOM_OP_CODES {
+            assert!(OpCode::from_int(op_code).is_err(), "op-code {}", op_code);
+        }
+    }
 
-                address + 1
-            }
-        })
+    #[test]
+    fn test_custom_op_code() {
+        let mut machine = TestMachine::default();
+        let start = machine.memory.get_dict_ptr();
+        machine.memory.raw_memory.write_slice(start, &[
+            OpCode::Push1 as u8, ADD_IMMEDIATE, 41, OpCode::Return as u8, ADD_IMMEDIATE + 1,
+        ]);
+
+        assert!(matches!(
+            machine.run_until_exit(start),
+            Err(MachineError::IllegalOpCodeError { op_code: ADD_IMMEDIATE, .. }),
+        ));
+
+        machine.memory.data_pop_cell().unwrap();
+        machine.set_custom_op_handler(Box::new(ToyOpHandler));
+        machine.run_until_exit(start).unwrap();
+        machine.assert_data_stack_state(&[StackElement::Cell(42)]);
+
+        // Op-codes not implemented by the handler stay illegal
+        assert!(matches!(
+            machine.run_until_exit(start + 4),
+            Err(MachineError::IllegalOpCodeError { op_code, .. }) if op_code == ADD_IMMEDIATE + 1,
+        ));
+
+        let mut listing = Vec::new();
+        machine.disassemble_range(start, start + 5, &mut listing).unwrap();
+        assert_eq!(
+            String::from_utf8(listing).unwrap(),
+            format!(
+                "{:04X}: push1\n{:04X}: addi 29\n{:04X}: ret\n{:04X}: (illegal op-code = {})\n",
+                start, start + 1, start + 3, start + 4, ADD_IMMEDIATE + 1,
+            ),
+        );
+
+        machine.clear_custom_op_handler();
+        assert!(machine.custom_op_handler().is_none());
     }
 
-    pub fn format_at<TExt: MachineExtensions>(writer: &mut impl io::Write, machine: &Machine<TExt>, address: Address) -> Result<Address, io::Error> {
-        let op_code = machine.memory.raw_memory.read_u8(address);
+    #[test]
+    fn test_custom_op_word() {
+        let counter = Rc::new(std::cell::Cell::new(5));
+        let mut machine = TestMachine::default();
+        machine.set_custom_op_handler(Box::new(CounterOpHandler { counter: counter.clone() }));
+        machine.register_custom_op_word("COUNTER@", PEEK_COUNTER);
+
+        machine.extensions.input = StaticStringInput::new(": twice COUNTER@ COUNTER@ + ; COUNTER@");
+        machine.interpret_input().unwrap();
+        machine.assert_data_stack_state(&[StackElement::Cell(5)]);
+
+        counter.set(20);
+        machine.extensions.input = StaticStringInput::new("twice");
+        machine.interpret_input().unwrap();
+        machine.assert_data_stack_state(&[StackElement::Cell(40)]);
+
+        // The op-code is compiled in place of a call
+        let body = machine.memory.lookup_article(b"twice").unwrap().unwrap().body_address();
+        assert_eq!(machine.memory.raw_memory.read_u8(body + 1), PEEK_COUNTER);
+
+        let mut listing = Vec::new();
+        machine.disassemble_range(body + 1, body + 2, &mut listing).unwrap();
+        assert_eq!(
+            String::from_utf8(listing).unwrap(),
+            format!("{:04X}: peekCounter (host counter = 20)\n", body + 1),
+        );
+    }
 
-        write!(writer, "{:04X}: ", address)?;
+    #[test]
+    fn test_custom_op_word_with_operand() {
+        let mut machine = TestMachine::default();
+        machine.set_custom_op_handler(Box::new(ToyOpHandler));
+        machine.register_custom_op_word("ADDI", ADD_IMMEDIATE);
 
-        match OpCode::from_int(op_code) {
-            Err(_) => {
-                writeln!(writer, "(illegal op-code = {})", op_code)?;
-                Ok(address + 1)
-            }
-            Ok(op) => op.format(writer, machine, address)
-        }
+        // The operand following the op-code is not there in interpreter state
+        assert!(matches!(machine.interpret_str("1 ADDI"), Err(MachineError::InvalidJumpTarget { from: 0, to: 2 })));
     }
 
-    pub fn format<TExt: MachineExtensions>(self, writer: &mut impl io::Write, machine: &Machine<TExt>, address: Address) -> Result<Address, io::Error> {
-        fn trivial(writer: &mut impl io::Write, address: Address, name: &str) -> Result<Address, io::Error> {
-            writeln!(writer, "{}", name)?;
-            Ok(address + 1)
-        }
-
-        Ok(match self {
-            OpCode::Noop => trivial(writer, address, "noop")?,
-            OpCode::DefaultArticleStart => trivial(writer, address, "start_article")?,
-            OpCode::Return => trivial(writer, address, "ret")?,
-            OpCode::Call => {
-                let call_address = unsafe { machine.memory.raw_memory.read_u16(address + 1) };
-                writeln!(writer, "call {:04X}", call_address)?;
-                address + 3
-            }
-            OpCode::Literal16 => {
-                let value = unsafe { machine.memory.raw_memory.read_u16(address + 1) };
-                writeln!(writer, "push16 {:04X} ({}, {})", value, value, value as i16)?;
-                address + 3
-            }
-            OpCode::LiteralString => {
-                let (range, content) = match ReadableSizedString::new(&machine.memory.raw_memory, address + 1, machine.memory.get_used_dict_segment()) {
-                    Ok(s) => (s.full_range(), s.as_bytes()),
-                    Err(_) => (address + 1..=address + 1, b"<<<<invalid string>>>>".as_slice())
-                };
-
-                match from_utf8(content) {
-                    Ok(s) => writeln!(writer, "pushStr {}", s)?,
-                    Err(_) => writeln!(writer, "pushStr {:?}", content)?
-                }
+    #[test]
+    fn test_op_code_table_matches_op_codes() {
+        for op_code in 0..=u8::MAX {
+            let known = OpCode::from_int(op_code).is_ok();
 
-                range.end().wrapping_add(1)
-            }
-            OpCode::GoTo => {
-                let call_address = unsafe { machine.memory.raw_memory.read_u16(address + 1) };
-                writeln!(writer, "jump {:04X}", call_address)?;
-                address + 3
-            }
-            OpCode::GoToIfZ => {
-                let call_address = unsafe { machine.memory.raw_memory.read_u16(address + 1) };
-                writeln!(writer, "jumpz {:04X}", call_address)?;
-                address + 3
-            }
-            OpCode::ExecBuiltin => {
-                let (range, content) = match ReadableSizedString::new(&machine.memory.raw_memory, address + 1, machine.memory.get_used_dict_segment()) {
-                    Ok(s) => (s.full_range(), s.as_bytes()),
-                    Err(_) => (address + 1..=address + 1, b"<<<<invalid string>>>>".as_slice())
-                };
-
-                match from_utf8(content) {
-                    Ok(s) => writeln!(writer, "execBuiltin {}", s)?,
-                    Err(_) => writeln!(writer, "execBuiltin {:?}", content)?
-                }
+            assert_eq!(DispatchTable::<TestMachineExtensions>::HANDLERS[op_code as usize].is_some(), known, "op-code {}", op_code);
+            assert_eq!(OpCode::is_known(op_code), known, "op-code {}", op_code);
+            assert_eq!(ALL_OP_CODES.iter().filter(|listed| listed.int_value() == op_code).count(), known as usize);
+        }
 
-                range.end().wrapping_add(1)
-            }
-            OpCode::Dup32 => trivial(writer, address, "dup32")?,
-            OpCode::Over16 => trivial(writer, address, "over")?,
-            OpCode::Over32 => trivial(writer, address, "over32")?,
-            OpCode::Swap16 => trivial(writer, address, "swap")?,
-            OpCode::Swap32 => trivial(writer, address, "swap32")?,
-            OpCode::Dup16 => trivial(writer, address, "dup")?,
-            OpCode::Add16 => trivial(writer, address, "add")?,
-            OpCode::Sub16 => trivial(writer, address, "sub")?,
-            OpCode::Mul16 => trivial(writer, address, "mul")?,
-            OpCode::Div16 => trivial(writer, address, "div")?,
-            OpCode::Load16 => trivial(writer, address, "load")?,
-            OpCode::Store16 => trivial(writer, address, "store")?,
-            OpCode::Load8 => trivial(writer, address, "load8")?,
-            OpCode::Store8 => trivial(writer, address, "store8")?,
-            OpCode::Load32 => trivial(writer, address, "load32")?,
-            OpCode::Store32 => trivial(writer, address, "store32")?,
-            OpCode::Drop16 => trivial(writer, address, "drop")?,
-            OpCode::Invert16 => trivial(writer, address, "invert")?,
-            OpCode::And16 => trivial(writer, address, "and")?,
-            OpCode::Or16 => trivial(writer, address, "or")?,
-            OpCode::Xor16 => trivial(writer, address, "xor")?,
-            OpCode::Eq16 => trivial(writer, address, "eq")?,
-            OpCode::Lt16 => trivial(writer, address, "lt")?,
-            OpCode::Gt16 => trivial(writer, address, "gt")?,
-            OpCode::Rot16 => trivial(writer, address, "rot")?,
-            OpCode::I16ToI32 => trivial(writer, address, "s>d")?,
-            OpCode::CallPop16 => trivial(writer, address, "call_pop")?,
-            OpCode::CallPush16 => trivial(writer, address, "call_push")?,
-            OpCode::CallPop32 => trivial(writer, address, "call_pop32")?,
-            OpCode::CallPush32 => trivial(writer, address, "call_push32")?,
-            OpCode::CallRead16 => trivial(writer, address, "call_get")?,
-            OpCode::CallRead32 => trivial(writer, address, "call_get32")?,
-            OpCode::Abs16 => trivial(writer, address, "abs")?,
-            OpCode::Emit => trivial(writer, address, "emit")?,
-            OpCode::PnoInit => trivial(writer, address, "pno:init")?,
-            OpCode::PnoPut => trivial(writer, address, "pno:put")?,
-            OpCode::PnoFinish => trivial(writer, address, "pno:finish")?,
-            OpCode::PnoPutDigit => trivial(writer, address, "pno:put_digit")?,
-            OpCode::EmitString => trivial(writer, address, "emit_str")?,
-        })
+        let mnemonics: std::collections::HashSet<_> = ALL_OP_CODES.iter().map(|op_code| op_code.mnemonic()).collect();
+        assert_eq!(mnemonics.len(), ALL_OP_CODES.len(), "duplicate mnemonics");
     }
 }
@@ -0,0 +1,121 @@
+use crate::cell::{aligned, Cell, SignedCell, SignedDoubleCell};
+use crate::machine::{Machine, MachineExtensions};
+use crate::machine_error::MachineError;
+use crate::mem::Address;
+use crate::stack_effect::stack_effect;
+
+pub(super) fn execute_add16<TExt: MachineExtensions>(machine: &mut Machine<TExt>, address: Address) -> Result<Address, MachineError> {
+    let mut fx = stack_effect!(machine; a:Cell, b:Cell => c:Cell)?;
+
+    fx.c(fx.a().wrapping_add(fx.b()));
+    fx.commit();
+
+    Ok(address + 1)
+}
+
+pub(super) fn execute_sub16<TExt: MachineExtensions>(machine: &mut Machine<TExt>, address: Address) -> Result<Address, MachineError> {
+    let mut fx = stack_effect!(machine; a:Cell, b:Cell => c:Cell)?;
+
+    fx.c(fx.a().wrapping_sub(fx.b()));
+    fx.commit();
+
+    Ok(address + 1)
+}
+
+pub(super) fn execute_mul16<TExt: MachineExtensions>(machine: &mut Machine<TExt>, address: Address) -> Result<Address, MachineError> {
+    let mut fx = stack_effect!(machine; a:Cell, b:Cell => c:Cell)?;
+
+    fx.c(fx.a().wrapping_mul(fx.b()));
+    fx.commit();
+
+    Ok(address + 1)
+}
+
+pub(super) fn execute_div16<TExt: MachineExtensions>(machine: &mut Machine<TExt>, address: Address) -> Result<Address, MachineError> {
+    let mut fx = stack_effect!(machine; a:Cell, b:Cell => c:Cell)?;
+
+    fx.c(fx.a().wrapping_div(fx.b()));
+    fx.commit();
+
+    Ok(address + 1)
+}
+
+pub(super) fn execute_invert16<TExt: MachineExtensions>(machine: &mut Machine<TExt>, address: Address) -> Result<Address, MachineError> {
+    let mut fx = stack_effect!(machine; a:Cell => b:Cell)?;
+    fx.b(!fx.a());
+    fx.commit();
+
+    Ok(address + 1)
+}
+
+pub(super) fn execute_and16<TExt: MachineExtensions>(machine: &mut Machine<TExt>, address: Address) -> Result<Address, MachineError> {
+    let mut fx = stack_effect!(machine; a:Cell, b:Cell => c:Cell)?;
+    fx.c(fx.a() & fx.b());
+    fx.commit();
+
+    Ok(address + 1)
+}
+
+pub(super) fn execute_or16<TExt: MachineExtensions>(machine: &mut Machine<TExt>, address: Address) -> Result<Address, MachineError> {
+    let mut fx = stack_effect!(machine; a:Cell, b:Cell => c:Cell)?;
+    fx.c(fx.a() | fx.b());
+    fx.commit();
+
+    Ok(address + 1)
+}
+
+pub(super) fn execute_xor16<TExt: MachineExtensions>(machine: &mut Machine<TExt>, address: Address) -> Result<Address, MachineError> {
+    let mut fx = stack_effect!(machine; a:Cell, b:Cell => c:Cell)?;
+    fx.c(fx.a() ^ fx.b());
+    fx.commit();
+
+    Ok(address + 1)
+}
+
+pub(super) fn execute_eq16<TExt: MachineExtensions>(machine: &mut Machine<TExt>, address: Address) -> Result<Address, MachineError> {
+    let mut fx = stack_effect!(machine; a:Cell, b:Cell => r:bool)?;
+    fx.r(fx.a() == fx.b());
+    fx.commit();
+
+    Ok(address + 1)
+}
+
+pub(super) fn execute_lt16<TExt: MachineExtensions>(machine: &mut Machine<TExt>, address: Address) -> Result<Address, MachineError> {
+    let mut fx = stack_effect!(machine; a:SignedCell, b:SignedCell => r:bool)?;
+    fx.r(fx.a() < fx.b());
+    fx.commit();
+
+    Ok(address + 1)
+}
+
+pub(super) fn execute_gt16<TExt: MachineExtensions>(machine: &mut Machine<TExt>, address: Address) -> Result<Address, MachineError> {
+    let mut fx = stack_effect!(machine; a:SignedCell, b:SignedCell => r:bool)?;
+    fx.r(fx.a() > fx.b());
+    fx.commit();
+
+    Ok(address + 1)
+}
+
+pub(super) fn execute_i16_to_i32<TExt: MachineExtensions>(machine: &mut Machine<TExt>, address: Address) -> Result<Address, MachineError> {
+    let mut fx = stack_effect!(machine; a:SignedCell => b:SignedDoubleCell)?;
+    fx.b(fx.a() as SignedDoubleCell);
+    fx.commit();
+
+    Ok(address + 1)
+}
+
+pub(super) fn execute_abs16<TExt: MachineExtensions>(machine: &mut Machine<TExt>, address: Address) -> Result<Address, MachineError> {
+    let mut fx = stack_effect!(machine; a:SignedCell => b:SignedCell)?;
+    fx.b(fx.a().abs());
+    fx.commit();
+
+    Ok(address + 1)
+}
+
+pub(super) fn execute_aligned<TExt: MachineExtensions>(machine: &mut Machine<TExt>, address: Address) -> Result<Address, MachineError> {
+    let mut fx = stack_effect!(machine; a:Address => b:Address)?;
+    fx.b(aligned(fx.a()));
+    fx.commit();
+
+    Ok(address + 1)
+}
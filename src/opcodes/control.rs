@@ -0,0 +1,331 @@
+use std::io;
+
+use crate::builtin_words::process_builtin_word;
+use crate::cell::{Cell, DoubleCell, FALSE, TRUE};
+use crate::machine::{Machine, MachineExtensions};
+use crate::machine_error::MachineError;
+use crate::machine_memory::MachineMemory;
+use crate::mem::{AccessKind, Address};
+use crate::memory_segment::DICTIONARY;
+use crate::opcodes::OpCode;
+use crate::readable_article::ReadableArticle;
+use crate::sized_string::ReadableSizedString;
+use crate::stack_effect::{call_stack_effect, stack_effect};
+
+use super::{compile_call, read_relative_target, validate_jump_target};
+
+pub(super) fn execute_noop<TExt: MachineExtensions>(_machine: &mut Machine<TExt>, address: Address) -> Result<Address, MachineError> {
+    Ok(address + 1)
+}
+
+pub(super) fn execute_default_article_start<TExt: MachineExtensions>(_machine: &mut Machine<TExt>, address: Address) -> Result<Address, MachineError> {
+    Ok(address + 1)
+}
+
+pub(super) fn execute_return<TExt: MachineExtensions>(machine: &mut Machine<TExt>, _address: Address) -> Result<Address, MachineError> {
+    if machine.memory.call_stack_depth() == 0 {
+        return Err(MachineError::Exited);
+    }
+
+    machine.memory.call_pop_address()
+}
+
+pub(super) fn execute_call<TExt: MachineExtensions>(machine: &mut Machine<TExt>, address: Address) -> Result<Address, MachineError> {
+    machine.memory.raw_memory.validate_named_access(
+        address + 1..=address + 2,
+        machine.memory.get_used_dict_segment(),
+        DICTIONARY,
+        AccessKind::Read,
+    )?;
+
+    let target_address = machine.memory.raw_memory.read_u16(address + 1);
+    validate_jump_target(machine, address, target_address)?;
+
+    machine.memory.call_push_address(address + 3)?;
+
+    Ok(target_address)
+}
+
+pub(super) fn execute_go_to<TExt: MachineExtensions>(machine: &mut Machine<TExt>, address: Address) -> Result<Address, MachineError> {
+    machine.memory.raw_memory.validate_named_access(
+        address + 1..=address + 2,
+        machine.memory.get_used_dict_segment(),
+        DICTIONARY,
+        AccessKind::Read,
+    )?;
+
+    let target_address = machine.memory.raw_memory.read_u16(address + 1);
+    validate_jump_target(machine, address, target_address)?;
+
+    Ok(target_address)
+}
+
+pub(super) fn execute_go_to_if_z<TExt: MachineExtensions>(machine: &mut Machine<TExt>, address: Address) -> Result<Address, MachineError> {
+    let value = machine.memory.data_pop_cell()?;
+
+    if value == 0 {
+        machine.memory.raw_memory.validate_named_access(
+            address + 1..=address + 2,
+            machine.memory.get_used_dict_segment(),
+            DICTIONARY,
+            AccessKind::Read,
+        )?;
+
+        let target_address = machine.memory.raw_memory.read_u16(address + 1);
+        validate_jump_target(machine, address, target_address)?;
+
+        Ok(target_address)
+    } else {
+        Ok(address + 3)
+    }
+}
+
+pub(super) fn execute_branch_rel<TExt: MachineExtensions>(machine: &mut Machine<TExt>, address: Address) -> Result<Address, MachineError> {
+    read_relative_target(machine, address)
+}
+
+pub(super) fn execute_branch_rel_if_z<TExt: MachineExtensions>(machine: &mut Machine<TExt>, address: Address) -> Result<Address, MachineError> {
+    let value = machine.memory.data_pop_cell()?;
+
+    if value == 0 {
+        read_relative_target(machine, address)
+    } else {
+        Ok(address + 3)
+    }
+}
+
+pub(super) fn execute_call_rel<TExt: MachineExtensions>(machine: &mut Machine<TExt>, address: Address) -> Result<Address, MachineError> {
+    let target_address = read_relative_target(machine, address)?;
+
+    machine.memory.call_push_address(address + 3)?;
+
+    Ok(target_address)
+}
+
+/// Check that code may be executed at given address.
+pub(crate) fn validate_execution_token(memory: &MachineMemory, xt: Address) -> Result<(), MachineError> {
+    if !memory.is_executable(xt) {
+        return Err(MachineError::InvalidExecutionToken { xt });
+    }
+
+    Ok(())
+}
+
+/// Find article with header at given address, name tokens are addresses of article headers.
+pub(crate) fn name_token_article(memory: &MachineMemory, nt: Address) -> Result<ReadableArticle<'_>, MachineError> {
+    memory.articles()
+        .find(|article| article.get_header_address() == nt)
+        .ok_or(MachineError::InvalidNameToken { nt })
+}
+
+/// Name token `TRAVERSE-WORDLIST` continues with after given one, the first name token if `None` is given.
+///
+/// Articles are visited from the last one to the first one, following links to previous articles, so articles
+/// defined while traversal is in progress are not visited. Given name token is expected to come from a previous step,
+/// so it is not looked up in the list of articles.
+pub(crate) fn next_name_token(memory: &MachineMemory, nt: Option<Address>) -> Result<Option<Address>, MachineError> {
+    let article = match nt {
+        None => memory.articles().next(),
+        Some(nt) => ReadableArticle::new(&memory.raw_memory, nt, memory.get_used_dict_segment())?
+            .previous_article(memory.get_used_dict_segment())?,
+    };
+
+    Ok(article.map(|article| article.get_header_address()))
+}
+
+pub(super) fn execute_execute<TExt: MachineExtensions>(machine: &mut Machine<TExt>, address: Address) -> Result<Address, MachineError> {
+    let xt: Address = machine.peek(0)?;
+    validate_execution_token(&machine.memory, xt)?;
+
+    let mut fx = call_stack_effect!(machine; data: _xt:Address =>; call: => return_address:Address)?;
+    fx.return_address(address + 1);
+    fx.commit();
+
+    Ok(xt)
+}
+
+/// Start `TRAVERSE-WORDLIST`, calling the callback with the first name token so it returns to
+/// `TraverseWordlistNext` following this op-code.
+pub(super) fn execute_traverse_wordlist<TExt: MachineExtensions>(machine: &mut Machine<TExt>, address: Address) -> Result<Address, MachineError> {
+    let xt: Address = machine.peek(1)?;
+
+    let Some(nt) = next_name_token(&machine.memory, None)? else {
+        stack_effect!(machine; _xt:Address, _wid:Cell =>)?.commit();
+
+        return Ok(address + 2);
+    };
+
+    validate_execution_token(&machine.memory, xt)?;
+
+    let mut fx = call_stack_effect!(
+        machine;
+        data: _xt:Address, _wid:Cell => nt:Address;
+        call: => saved_xt:Address, saved_nt:Address, return_address:Address
+    )?;
+    fx.nt(nt).saved_xt(xt).saved_nt(nt).return_address(address + 1);
+    fx.commit();
+
+    Ok(xt)
+}
+
+/// Take flag returned by `TRAVERSE-WORDLIST` callback and call it again with the next name token, unless the flag is
+/// false or there are no more words.
+pub(super) fn execute_traverse_wordlist_next<TExt: MachineExtensions>(machine: &mut Machine<TExt>, address: Address) -> Result<Address, MachineError> {
+    let fx = call_stack_effect!(machine; data: flag:Cell =>; call: xt:Address, nt:Address =>)?;
+    let (flag, xt, nt) = (fx.flag(), fx.xt(), fx.nt());
+
+    if flag == FALSE {
+        fx.commit();
+
+        return Ok(address + 1);
+    }
+
+    let Some(next) = next_name_token(&machine.memory, Some(nt))? else {
+        call_stack_effect!(machine; data: _flag:Cell =>; call: _xt:Address, _nt:Address =>)?.commit();
+
+        return Ok(address + 1);
+    };
+
+    let mut fx = call_stack_effect!(
+        machine;
+        data: _flag:Cell => nt:Address;
+        call: _xt:Address, _nt:Address => saved_xt:Address, saved_nt:Address, return_address:Address
+    )?;
+    fx.nt(next).saved_xt(xt).saved_nt(next).return_address(address);
+    fx.commit();
+
+    Ok(xt)
+}
+
+pub(super) fn execute_name_to_string<TExt: MachineExtensions>(machine: &mut Machine<TExt>, address: Address) -> Result<Address, MachineError> {
+    let article = name_token_article(&machine.memory, machine.peek(0)?)?;
+    let (name_address, length) = (article.name_address() + 1, article.name().read_length() as u16);
+
+    let mut fx = stack_effect!(machine; _nt:Address => name:Address, length:u16)?;
+    fx.name(name_address).length(length);
+    fx.commit();
+
+    Ok(address + 1)
+}
+
+pub(super) fn execute_name_to_interpret<TExt: MachineExtensions>(machine: &mut Machine<TExt>, address: Address) -> Result<Address, MachineError> {
+    let xt = name_token_article(&machine.memory, machine.peek(0)?)?.call_address();
+
+    let mut fx = stack_effect!(machine; _nt:Address => xt:Address)?;
+    fx.xt(xt);
+    fx.commit();
+
+    Ok(address + 1)
+}
+
+pub(super) fn execute_compile_xt<TExt: MachineExtensions>(machine: &mut Machine<TExt>, address: Address) -> Result<Address, MachineError> {
+    let fx = stack_effect!(machine; xt:Address =>)?;
+    let xt = fx.xt();
+    validate_execution_token(&fx.machine.memory, xt)?;
+    fx.machine.compile_reference(xt)?;
+    fx.commit();
+
+    Ok(address + 1)
+}
+
+pub(super) fn execute_name_is_immediate<TExt: MachineExtensions>(machine: &mut Machine<TExt>, address: Address) -> Result<Address, MachineError> {
+    let immediate = name_token_article(&machine.memory, machine.peek(0)?)?.is_immediate();
+
+    let mut fx = stack_effect!(machine; _nt:Address => flag:Cell)?;
+    fx.flag(if immediate { TRUE } else { FALSE });
+    fx.commit();
+
+    Ok(address + 1)
+}
+
+pub(super) fn execute_exec_builtin<TExt: MachineExtensions>(machine: &mut Machine<TExt>, address: Address) -> Result<Address, MachineError> {
+    let string_range = ReadableSizedString::new(
+        &machine.memory.raw_memory,
+        address + 1,
+        machine.memory.get_used_dict_segment(),
+    )?.full_range();
+
+    process_builtin_word(machine, *string_range.start())?;
+
+    Ok(string_range.end().wrapping_add(1))
+}
+
+pub(super) fn execute_exec_native<TExt: MachineExtensions>(machine: &mut Machine<TExt>, address: Address) -> Result<Address, MachineError> {
+    machine.memory.raw_memory.validate_named_access(
+        address + 1..=address + 2,
+        machine.memory.get_used_dict_segment(),
+        DICTIONARY,
+        AccessKind::Read,
+    )?;
+
+    let index = machine.memory.raw_memory.read_u16(address + 1);
+    machine.call_native_word(index)?;
+
+    Ok(address + 3)
+}
+
+pub(super) fn execute_compile_call<TExt: MachineExtensions>(machine: &mut Machine<TExt>, address: Address) -> Result<Address, MachineError> {
+    machine.memory.raw_memory.validate_named_access(
+        address + 1..=address + 2,
+        machine.memory.get_used_dict_segment(),
+        DICTIONARY,
+        AccessKind::Read,
+    )?;
+
+    let target_address = machine.memory.raw_memory.read_u16(address + 1);
+    compile_call(machine, target_address)?;
+
+    Ok(address + 3)
+}
+
+pub(super) fn execute_counter<TExt: MachineExtensions>(machine: &mut Machine<TExt>, address: Address) -> Result<Address, MachineError> {
+    machine.memory.data_push_double_cell(machine.instructions_executed() as DoubleCell)?;
+
+    Ok(address + 1)
+}
+
+pub(super) fn execute_bye<TExt: MachineExtensions>(_machine: &mut Machine<TExt>, _address: Address) -> Result<Address, MachineError> {
+    Err(MachineError::Bye)
+}
+
+pub(super) fn execute_break<TExt: MachineExtensions>(_machine: &mut Machine<TExt>, address: Address) -> Result<Address, MachineError> {
+    Err(MachineError::Breakpoint { address: address + 1 })
+}
+
+/// Format an instruction followed by an absolute address.
+pub(super) fn format_absolute_target<TExt: MachineExtensions>(
+    op_code: OpCode,
+    writer: &mut impl io::Write,
+    machine: &Machine<TExt>,
+    address: Address,
+) -> Result<Address, io::Error> {
+    let target_address = machine.memory.raw_memory.read_u16(address + 1);
+    writeln!(writer, "{} {:04X}", op_code.mnemonic(), target_address)?;
+
+    Ok(address + 3)
+}
+
+/// Format an instruction followed by an offset relative to the next instruction.
+pub(super) fn format_relative_target<TExt: MachineExtensions>(
+    op_code: OpCode,
+    writer: &mut impl io::Write,
+    machine: &Machine<TExt>,
+    address: Address,
+) -> Result<Address, io::Error> {
+    let offset = machine.memory.raw_memory.read_u16(address + 1);
+    let target_address = address.wrapping_add(3).wrapping_add(offset);
+    writeln!(writer, "{} {:+} ({:04X})", op_code.mnemonic(), offset as i16, target_address)?;
+
+    Ok(address + 3)
+}
+
+pub(super) fn format_exec_native<TExt: MachineExtensions>(writer: &mut impl io::Write, machine: &Machine<TExt>, address: Address) -> Result<Address, io::Error> {
+    let index = machine.memory.raw_memory.read_u16(address + 1);
+
+    match machine.native_words.name(index) {
+        Some(name) => writeln!(writer, "{} {} {}", OpCode::ExecNative.mnemonic(), index, String::from_utf8_lossy(name))?,
+        None => writeln!(writer, "{} {} <unknown>", OpCode::ExecNative.mnemonic(), index)?,
+    }
+
+    Ok(address + 3)
+}
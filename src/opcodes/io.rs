@@ -0,0 +1,288 @@
+use std::io;
+
+use crate::cell::{Cell, DoubleCell, FALSE, SignedCell, TRUE};
+use crate::file_access::{file_access_mode, io_result_code, IOR_SUCCESS};
+use crate::machine::{Machine, MachineExtensions};
+use crate::machine_error::MachineError;
+use crate::mem::{AccessKind, Address};
+use crate::memory_segment::WHOLE_MEMORY;
+use crate::output::Output;
+use crate::stack_effect::stack_effect;
+
+pub(super) fn execute_emit<TExt: MachineExtensions>(machine: &mut Machine<TExt>, address: Address) -> Result<Address, MachineError> {
+    let char_code: Cell = machine.memory.data_pop_cell()?;
+
+    machine.output().putc(char_code as u16)?;
+
+    Ok(address + 1)
+}
+
+pub(super) fn execute_xemit<TExt: MachineExtensions>(machine: &mut Machine<TExt>, address: Address) -> Result<Address, MachineError> {
+    let code_point: Cell = machine.memory.data_pop_cell()?;
+
+    machine.output().put_xchar(code_point as u32)?;
+
+    Ok(address + 1)
+}
+
+pub(super) fn execute_flush<TExt: MachineExtensions>(machine: &mut Machine<TExt>, address: Address) -> Result<Address, MachineError> {
+    machine.output().flush()?;
+
+    Ok(address + 1)
+}
+
+pub(super) fn execute_ms<TExt: MachineExtensions>(machine: &mut Machine<TExt>, address: Address) -> Result<Address, MachineError> {
+    let milliseconds = machine.memory.data_pop_cell()?;
+
+    machine.sleep_ms(milliseconds as u64)?;
+
+    Ok(address + 1)
+}
+
+pub(super) fn execute_time_and_date<TExt: MachineExtensions>(machine: &mut Machine<TExt>, address: Address) -> Result<Address, MachineError> {
+    machine.push_date_time()?;
+
+    Ok(address + 1)
+}
+
+pub(super) fn execute_utime<TExt: MachineExtensions>(machine: &mut Machine<TExt>, address: Address) -> Result<Address, MachineError> {
+    let microseconds = machine.clock().now().as_micros();
+
+    machine.memory.data_push_double_cell(microseconds as DoubleCell)?;
+
+    Ok(address + 1)
+}
+
+/// Check that a buffer given to a file access word lies in memory.
+fn validate_file_buffer<TExt: MachineExtensions>(machine: &Machine<TExt>, addr: Address, size: u16, kind: AccessKind) -> Result<(), MachineError> {
+    if size == 0 {
+        return Ok(());
+    }
+
+    let range = addr..=addr.wrapping_add(size - 1);
+
+    machine.memory.raw_memory.validate_named_access(
+        range.clone(),
+        machine.memory.raw_memory.address_range(),
+        WHOLE_MEMORY,
+        kind,
+    )?;
+
+    if kind == AccessKind::Write {
+        machine.memory.validate_store(range)?;
+    }
+
+    Ok(())
+}
+
+/// Write data read from a file to a buffer checked by `validate_file_buffer`.
+fn write_file_buffer<TExt: MachineExtensions>(machine: &mut Machine<TExt>, addr: Address, data: &[u8]) {
+    if data.is_empty() {
+        return;
+    }
+
+    machine.memory.raw_memory.write_slice(addr, data);
+    machine.memory.note_store(addr..=addr.wrapping_add(data.len() as Address - 1));
+}
+
+/// Push `ior` reported by file access words for given result.
+fn push_ior<TExt: MachineExtensions, T>(machine: &mut Machine<TExt>, result: &io::Result<T>) -> Result<(), MachineError> {
+    machine.memory.data_push_cell(match result {
+        Ok(_) => IOR_SUCCESS,
+        Err(err) => io_result_code(err),
+    })
+}
+
+pub(super) fn execute_open_file<TExt: MachineExtensions>(machine: &mut Machine<TExt>, address: Address) -> Result<Address, MachineError> {
+    let fx = stack_effect!(machine; addr: Address, size: u16, fam: Cell => )?;
+    let (addr, size, fam) = (fx.addr(), fx.size(), fx.fam());
+    fx.commit();
+
+    let path = machine.memory.read_string(addr, size)?;
+    let result = file_access_mode(fam).and_then(|mode| machine.open_file(&path, mode));
+
+    machine.memory.data_push_cell(*result.as_ref().unwrap_or(&0))?;
+    push_ior(machine, &result)?;
+
+    Ok(address + 1)
+}
+
+pub(super) fn execute_create_file<TExt: MachineExtensions>(machine: &mut Machine<TExt>, address: Address) -> Result<Address, MachineError> {
+    let fx = stack_effect!(machine; addr: Address, size: u16, fam: Cell => )?;
+    let (addr, size, fam) = (fx.addr(), fx.size(), fx.fam());
+    fx.commit();
+
+    let path = machine.memory.read_string(addr, size)?;
+    let result = file_access_mode(fam).and_then(|mode| machine.create_file(&path, mode));
+
+    machine.memory.data_push_cell(*result.as_ref().unwrap_or(&0))?;
+    push_ior(machine, &result)?;
+
+    Ok(address + 1)
+}
+
+pub(super) fn execute_close_file<TExt: MachineExtensions>(machine: &mut Machine<TExt>, address: Address) -> Result<Address, MachineError> {
+    let id = machine.memory.data_pop_cell()?;
+    let result = machine.close_file(id);
+
+    push_ior(machine, &result)?;
+
+    Ok(address + 1)
+}
+
+pub(super) fn execute_read_file<TExt: MachineExtensions>(machine: &mut Machine<TExt>, address: Address) -> Result<Address, MachineError> {
+    let fx = stack_effect!(machine; addr: Address, size: u16, id: Cell => )?;
+    let (addr, size, id) = (fx.addr(), fx.size(), fx.id());
+    fx.commit();
+
+    validate_file_buffer(machine, addr, size, AccessKind::Write)?;
+
+    let mut buffer = vec![0u8; size as usize];
+    let result = machine.read_file(id, &mut buffer);
+    let read = *result.as_ref().unwrap_or(&0);
+
+    write_file_buffer(machine, addr, &buffer[..read]);
+    machine.memory.data_push_cell(read as Cell)?;
+    push_ior(machine, &result)?;
+
+    Ok(address + 1)
+}
+
+pub(super) fn execute_read_line<TExt: MachineExtensions>(machine: &mut Machine<TExt>, address: Address) -> Result<Address, MachineError> {
+    let fx = stack_effect!(machine; addr: Address, size: u16, id: Cell => )?;
+    let (addr, size, id) = (fx.addr(), fx.size(), fx.id());
+    fx.commit();
+
+    validate_file_buffer(machine, addr, size, AccessKind::Write)?;
+
+    let result = machine.read_line(id, size as usize);
+    let (length, flag) = match &result {
+        Ok(Some(line)) => {
+            write_file_buffer(machine, addr, line);
+
+            (line.len() as Cell, TRUE)
+        }
+        Ok(None) | Err(_) => (0, FALSE),
+    };
+
+    machine.memory.data_push_cell(length)?;
+    machine.memory.data_push_cell(flag)?;
+    push_ior(machine, &result)?;
+
+    Ok(address + 1)
+}
+
+fn write_file<TExt: MachineExtensions>(machine: &mut Machine<TExt>, line_break: bool) -> Result<(), MachineError> {
+    let fx = stack_effect!(machine; addr: Address, size: u16, id: Cell => )?;
+    let (addr, size, id) = (fx.addr(), fx.size(), fx.id());
+    fx.commit();
+
+    validate_file_buffer(machine, addr, size, AccessKind::Read)?;
+
+    let mut data = machine.memory.raw_memory.address_slice(addr, size as usize).into_owned();
+
+    if line_break {
+        data.push(b'\n');
+    }
+
+    let result = machine.write_file(id, &data);
+
+    push_ior(machine, &result)
+}
+
+pub(super) fn execute_write_file<TExt: MachineExtensions>(machine: &mut Machine<TExt>, address: Address) -> Result<Address, MachineError> {
+    write_file(machine, false)?;
+
+    Ok(address + 1)
+}
+
+pub(super) fn execute_write_line<TExt: MachineExtensions>(machine: &mut Machine<TExt>, address: Address) -> Result<Address, MachineError> {
+    write_file(machine, true)?;
+
+    Ok(address + 1)
+}
+
+pub(super) fn execute_file_position<TExt: MachineExtensions>(machine: &mut Machine<TExt>, address: Address) -> Result<Address, MachineError> {
+    let id = machine.memory.data_pop_cell()?;
+    let result = machine.file_position(id);
+
+    machine.memory.data_push_double_cell(*result.as_ref().unwrap_or(&0) as DoubleCell)?;
+    push_ior(machine, &result)?;
+
+    Ok(address + 1)
+}
+
+// `DoubleCell` is `u64` with `cell32` feature
+#[allow(clippy::unnecessary_cast)]
+pub(super) fn execute_reposition_file<TExt: MachineExtensions>(machine: &mut Machine<TExt>, address: Address) -> Result<Address, MachineError> {
+    let fx = stack_effect!(machine; position: DoubleCell, id: Cell => )?;
+    let (position, id) = (fx.position(), fx.id());
+    fx.commit();
+
+    let result = machine.reposition_file(id, position as u64);
+
+    push_ior(machine, &result)?;
+
+    Ok(address + 1)
+}
+
+pub(super) fn execute_file_size<TExt: MachineExtensions>(machine: &mut Machine<TExt>, address: Address) -> Result<Address, MachineError> {
+    let id = machine.memory.data_pop_cell()?;
+    let result = machine.file_size(id);
+
+    machine.memory.data_push_double_cell(*result.as_ref().unwrap_or(&0) as DoubleCell)?;
+    push_ior(machine, &result)?;
+
+    Ok(address + 1)
+}
+
+pub(super) fn execute_delete_file<TExt: MachineExtensions>(machine: &mut Machine<TExt>, address: Address) -> Result<Address, MachineError> {
+    let fx = stack_effect!(machine; addr: Address, size: u16 => )?;
+    let (addr, size) = (fx.addr(), fx.size());
+    fx.commit();
+
+    let path = machine.memory.read_string(addr, size)?;
+    let result = machine.delete_file(&path);
+
+    push_ior(machine, &result)?;
+
+    Ok(address + 1)
+}
+
+pub(super) fn execute_cr<TExt: MachineExtensions>(machine: &mut Machine<TExt>, address: Address) -> Result<Address, MachineError> {
+    machine.output().putc(b'\n' as u16)?;
+
+    Ok(address + 1)
+}
+
+pub(super) fn execute_space<TExt: MachineExtensions>(machine: &mut Machine<TExt>, address: Address) -> Result<Address, MachineError> {
+    machine.output().putc(b' ' as u16)?;
+
+    Ok(address + 1)
+}
+
+pub(super) fn execute_spaces<TExt: MachineExtensions>(machine: &mut Machine<TExt>, address: Address) -> Result<Address, MachineError> {
+    const CHUNK: [u8; 64] = [b' '; 64];
+
+    let mut remaining = (machine.memory.data_pop_cell()? as SignedCell).max(0) as usize;
+
+    while remaining > 0 {
+        let chunk_size = remaining.min(CHUNK.len());
+        machine.output().puts(&CHUNK[..chunk_size])?;
+        remaining -= chunk_size;
+    }
+
+    Ok(address + 1)
+}
+
+pub(super) fn execute_emit_string<TExt: MachineExtensions>(machine: &mut Machine<TExt>, address: Address) -> Result<Address, MachineError> {
+    let fx = stack_effect!(machine; addr: Address, size: u16 => )?;
+    let (addr, size) = (fx.addr(), fx.size());
+    fx.commit();
+
+    let text = machine.memory.raw_memory.address_slice(addr, size as usize).into_owned();
+
+    machine.output().puts(&text)?;
+
+    Ok(address + 1)
+}
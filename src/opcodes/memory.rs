@@ -0,0 +1,209 @@
+use crate::cell::{Cell, CELL_BYTES, DOUBLE_CELL_BYTES, DoubleCell};
+use crate::heap::{HeapError, IOR_SUCCESS};
+use crate::machine::{Machine, MachineExtensions};
+use crate::machine_error::MachineError;
+use crate::mem::{AccessKind, Address};
+use crate::memory_segment::WHOLE_MEMORY;
+use crate::stack_effect::stack_effect;
+
+pub(super) fn execute_load8<TExt: MachineExtensions>(machine: &mut Machine<TExt>, address: Address) -> Result<Address, MachineError> {
+    let mut fx = stack_effect!(machine; address:Address => value:Cell)?;
+    let target_address = fx.address();
+
+    fx.machine.memory.raw_memory.validate_access(
+        target_address..=target_address,
+        fx.machine.memory.raw_memory.address_range(),
+    )?;
+
+    let value = fx.machine.mmio.read_u8(&fx.machine.memory.raw_memory, target_address) as Cell;
+    fx.value(value);
+    fx.commit();
+
+    Ok(address + 1)
+}
+
+pub(super) fn execute_store8<TExt: MachineExtensions>(machine: &mut Machine<TExt>, address: Address) -> Result<Address, MachineError> {
+    let fx = stack_effect!(machine; value: u8, address: Address =>)?;
+    let target_address = fx.address();
+
+    fx.machine.memory.raw_memory.validate_named_access(
+        target_address..=target_address,
+        fx.machine.memory.raw_memory.address_range(),
+        WHOLE_MEMORY,
+        AccessKind::Write,
+    )?;
+    fx.machine.memory.validate_store(target_address..=target_address)?;
+
+    let value = fx.value();
+    fx.machine.mmio.write_u8(&mut fx.machine.memory.raw_memory, target_address, value);
+    fx.machine.memory.note_store(target_address..=target_address);
+
+    fx.commit();
+
+    Ok(address + 1)
+}
+
+/// Read a cell the way `@` does.
+pub(crate) fn fetch_cell<TExt: MachineExtensions>(machine: &mut Machine<TExt>, target_address: Address) -> Result<Cell, MachineError> {
+    machine.memory.raw_memory.validate_access(
+        target_address..=target_address.wrapping_add(CELL_BYTES - 1),
+        machine.memory.raw_memory.address_range(),
+    )?;
+
+    Ok(machine.mmio.read_cell(&machine.memory.raw_memory, target_address))
+}
+
+/// Write a cell the way `!` does.
+pub(crate) fn store_cell<TExt: MachineExtensions>(machine: &mut Machine<TExt>, target_address: Address, value: Cell) -> Result<(), MachineError> {
+    machine.memory.raw_memory.validate_named_access(
+        target_address..=target_address.wrapping_add(CELL_BYTES - 1),
+        machine.memory.raw_memory.address_range(),
+        WHOLE_MEMORY,
+        AccessKind::Write,
+    )?;
+    machine.memory.validate_store(target_address..=target_address.wrapping_add(CELL_BYTES - 1))?;
+
+    machine.mmio.write_cell(&mut machine.memory.raw_memory, target_address, value);
+    machine.memory.note_store(target_address..=target_address.wrapping_add(CELL_BYTES - 1));
+
+    Ok(())
+}
+
+pub(super) fn execute_load16<TExt: MachineExtensions>(machine: &mut Machine<TExt>, address: Address) -> Result<Address, MachineError> {
+    let mut fx = stack_effect!(machine; address:Address => value:Cell)?;
+    let value = fetch_cell(fx.machine, fx.address())?;
+    fx.value(value);
+    fx.commit();
+
+    Ok(address + 1)
+}
+
+pub(super) fn execute_store16<TExt: MachineExtensions>(machine: &mut Machine<TExt>, address: Address) -> Result<Address, MachineError> {
+    let fx = stack_effect!(machine; value:Cell, address: Address =>)?;
+    store_cell(fx.machine, fx.address(), fx.value())?;
+    fx.commit();
+
+    Ok(address + 1)
+}
+
+pub(super) fn execute_load32<TExt: MachineExtensions>(machine: &mut Machine<TExt>, address: Address) -> Result<Address, MachineError> {
+    let mut fx = stack_effect!(machine; address:Address => value:DoubleCell)?;
+    let target_address = fx.address();
+
+    fx.machine.memory.raw_memory.validate_access(
+        target_address..=target_address.wrapping_add(DOUBLE_CELL_BYTES - 1),
+        fx.machine.memory.raw_memory.address_range(),
+    )?;
+
+    let value = fx.machine.mmio.read_double_cell(&fx.machine.memory.raw_memory, target_address);
+    fx.value(value);
+    fx.commit();
+
+    Ok(address + 1)
+}
+
+/// Write a double cell the way `2!` does.
+pub(crate) fn store_double_cell<TExt: MachineExtensions>(machine: &mut Machine<TExt>, target_address: Address, value: DoubleCell) -> Result<(), MachineError> {
+    machine.memory.raw_memory.validate_named_access(
+        target_address..=target_address.wrapping_add(DOUBLE_CELL_BYTES - 1),
+        machine.memory.raw_memory.address_range(),
+        WHOLE_MEMORY,
+        AccessKind::Write,
+    )?;
+    machine.memory.validate_store(target_address..=target_address.wrapping_add(DOUBLE_CELL_BYTES - 1))?;
+
+    machine.mmio.write_double_cell(&mut machine.memory.raw_memory, target_address, value);
+    machine.memory.note_store(target_address..=target_address.wrapping_add(DOUBLE_CELL_BYTES - 1));
+
+    Ok(())
+}
+
+pub(super) fn execute_store32<TExt: MachineExtensions>(machine: &mut Machine<TExt>, address: Address) -> Result<Address, MachineError> {
+    let fx = stack_effect!(machine; value:DoubleCell, address: Address =>)?;
+    store_double_cell(fx.machine, fx.address(), fx.value())?;
+    fx.commit();
+
+    Ok(address + 1)
+}
+
+pub(super) fn execute_save_image<TExt: MachineExtensions>(machine: &mut Machine<TExt>, address: Address) -> Result<Address, MachineError> {
+    let fx = stack_effect!(machine; addr: Address, size: u16 => )?;
+    let (addr, size) = (fx.addr(), fx.size());
+    fx.commit();
+
+    let path = machine.memory.read_string(addr, size)?;
+    machine.save_image(&path)?;
+
+    Ok(address + 1)
+}
+
+pub(super) fn execute_load_image<TExt: MachineExtensions>(machine: &mut Machine<TExt>, _address: Address) -> Result<Address, MachineError> {
+    let fx = stack_effect!(machine; addr: Address, size: u16 => )?;
+    let (addr, size) = (fx.addr(), fx.size());
+    fx.commit();
+
+    let path = machine.memory.read_string(addr, size)?;
+    machine.load_image(&path)?;
+
+    Err(MachineError::Exited)
+}
+
+pub(super) fn execute_align<TExt: MachineExtensions>(machine: &mut Machine<TExt>, address: Address) -> Result<Address, MachineError> {
+    machine.memory.dict_align()?;
+
+    Ok(address + 1)
+}
+
+pub(super) fn execute_comma<TExt: MachineExtensions>(machine: &mut Machine<TExt>, address: Address) -> Result<Address, MachineError> {
+    let value = machine.memory.data_pop_cell()?;
+
+    machine.memory.dict_align()?;
+    let data_address = machine.memory.get_dict_ptr();
+    machine.memory.dict_write_cell(value)?;
+    machine.memory.mark_data_space(data_address..=data_address.wrapping_add(CELL_BYTES - 1));
+
+    Ok(address + 1)
+}
+
+pub(super) fn execute_comma_byte<TExt: MachineExtensions>(machine: &mut Machine<TExt>, address: Address) -> Result<Address, MachineError> {
+    let value = machine.memory.data_pop_cell()?;
+
+    let data_address = machine.memory.get_dict_ptr();
+    machine.memory.dict_write_u8(value as u8)?;
+    machine.memory.mark_data_space(data_address..=data_address);
+
+    Ok(address + 1)
+}
+
+pub(super) fn execute_allocate<TExt: MachineExtensions>(machine: &mut Machine<TExt>, address: Address) -> Result<Address, MachineError> {
+    let mut fx = stack_effect!(machine; size: Cell => addr: Address, ior: Cell)?;
+    let result = fx.machine.memory.heap_allocate(fx.size());
+
+    fx.addr(*result.as_ref().unwrap_or(&0));
+    fx.ior(result.map_or_else(HeapError::ior, |_| IOR_SUCCESS));
+    fx.commit();
+
+    Ok(address + 1)
+}
+
+pub(super) fn execute_free<TExt: MachineExtensions>(machine: &mut Machine<TExt>, address: Address) -> Result<Address, MachineError> {
+    let mut fx = stack_effect!(machine; addr: Address => ior: Cell)?;
+    let result = fx.machine.memory.heap_free(fx.addr());
+
+    fx.ior(result.map_or_else(HeapError::ior, |_| IOR_SUCCESS));
+    fx.commit();
+
+    Ok(address + 1)
+}
+
+pub(super) fn execute_resize<TExt: MachineExtensions>(machine: &mut Machine<TExt>, address: Address) -> Result<Address, MachineError> {
+    let mut fx = stack_effect!(machine; addr: Address, size: Cell => new_addr: Address, ior: Cell)?;
+    let old_addr = fx.addr();
+    let result = fx.machine.memory.heap_resize(old_addr, fx.size());
+
+    fx.new_addr(*result.as_ref().unwrap_or(&old_addr));
+    fx.ior(result.map_or_else(HeapError::ior, |_| IOR_SUCCESS));
+    fx.commit();
+
+    Ok(address + 1)
+}
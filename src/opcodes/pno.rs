@@ -0,0 +1,49 @@
+use crate::cell::DoubleCell;
+use crate::machine::{Machine, MachineExtensions};
+use crate::machine_error::MachineError;
+use crate::mem::Address;
+use crate::stack_effect::stack_effect;
+
+pub(super) fn execute_pno_init<TExt: MachineExtensions>(machine: &mut Machine<TExt>, address: Address) -> Result<Address, MachineError> {
+    machine.memory.clear_pno_buffer();
+
+    Ok(address + 1)
+}
+
+pub(super) fn execute_pno_put<TExt: MachineExtensions>(machine: &mut Machine<TExt>, address: Address) -> Result<Address, MachineError> {
+    let ch = machine.memory.data_pop_cell()? as u8;
+    machine.memory.pno_put(ch)?;
+
+    Ok(address + 1)
+}
+
+pub(super) fn execute_pno_finish<TExt: MachineExtensions>(machine: &mut Machine<TExt>, address: Address) -> Result<Address, MachineError> {
+    let (addr, size) = machine.memory.pno_finish();
+    let mut fx = stack_effect!(machine; _x:DoubleCell => address:Address, size:u16)?;
+    fx.address(addr);
+    fx.size(size as u16);
+    fx.commit();
+
+    Ok(address + 1)
+}
+
+pub(super) fn execute_pno_put_digit<TExt: MachineExtensions>(machine: &mut Machine<TExt>, address: Address) -> Result<Address, MachineError> {
+    let mut fx = stack_effect!(machine; i:DoubleCell => o:DoubleCell)?;
+    let base = fx.machine.memory.get_base() as DoubleCell;
+    let i = fx.i();
+
+    let digit = (i % base) as u8;
+    fx.o(i / base);
+
+    fx.commit();
+
+    let digit_char = if digit < 10 {
+        b'0'.wrapping_add(digit)
+    } else {
+        b'A'.wrapping_add(digit).wrapping_sub(10)
+    };
+
+    machine.memory.pno_put(digit_char)?;
+
+    Ok(address + 1)
+}
@@ -0,0 +1,338 @@
+use std::io;
+
+use crate::cell::{Cell, CELL_BYTES, DOUBLE_CELL_BYTES, DoubleCell, SignedCell, SignedDoubleCell, TRUE};
+use crate::machine::{Machine, MachineExtensions};
+use crate::machine_error::MachineError;
+use crate::mem::{AccessKind, Address};
+use crate::memory_segment::DICTIONARY;
+use crate::opcodes::OpCode;
+use crate::sized_string::ReadableSizedString;
+use crate::stack_effect::{call_stack_effect, stack_effect, StackEffect, StackShape};
+
+pub(super) fn execute_literal16<TExt: MachineExtensions>(machine: &mut Machine<TExt>, address: Address) -> Result<Address, MachineError> {
+    machine.memory.raw_memory.validate_named_access(
+        address + 1..=address + CELL_BYTES,
+        machine.memory.get_used_dict_segment(),
+        DICTIONARY,
+        AccessKind::Read,
+    )?;
+
+    let literal = machine.memory.raw_memory.read_cell(address + 1);
+
+    machine.memory.data_push_cell(literal)?;
+
+    Ok(address + 1 + CELL_BYTES)
+}
+
+pub(super) fn execute_literal32<TExt: MachineExtensions>(machine: &mut Machine<TExt>, address: Address) -> Result<Address, MachineError> {
+    machine.memory.raw_memory.validate_named_access(
+        address + 1..=address + DOUBLE_CELL_BYTES,
+        machine.memory.get_used_dict_segment(),
+        DICTIONARY,
+        AccessKind::Read,
+    )?;
+
+    let literal = machine.memory.raw_memory.read_double_cell(address + 1);
+
+    machine.memory.data_push_double_cell(literal)?;
+
+    Ok(address + 1 + DOUBLE_CELL_BYTES)
+}
+
+pub(super) fn execute_literal8<TExt: MachineExtensions>(machine: &mut Machine<TExt>, address: Address) -> Result<Address, MachineError> {
+    machine.memory.raw_memory.validate_named_access(
+        address + 1..=address + 1,
+        machine.memory.get_used_dict_segment(),
+        DICTIONARY,
+        AccessKind::Read,
+    )?;
+
+    let literal = machine.memory.raw_memory.read_u8(address + 1);
+
+    machine.memory.data_push_cell(literal as Cell)?;
+
+    Ok(address + 2)
+}
+
+pub(super) fn execute_push0<TExt: MachineExtensions>(machine: &mut Machine<TExt>, address: Address) -> Result<Address, MachineError> {
+    machine.memory.data_push_cell(0)?;
+
+    Ok(address + 1)
+}
+
+pub(super) fn execute_push1<TExt: MachineExtensions>(machine: &mut Machine<TExt>, address: Address) -> Result<Address, MachineError> {
+    machine.memory.data_push_cell(1)?;
+
+    Ok(address + 1)
+}
+
+pub(super) fn execute_push_true<TExt: MachineExtensions>(machine: &mut Machine<TExt>, address: Address) -> Result<Address, MachineError> {
+    machine.memory.data_push_cell(TRUE)?;
+
+    Ok(address + 1)
+}
+
+pub(super) fn execute_literal_string<TExt: MachineExtensions>(machine: &mut Machine<TExt>, address: Address) -> Result<Address, MachineError> {
+    let string_range = ReadableSizedString::new(
+        &machine.memory.raw_memory,
+        address + 1,
+        machine.memory.get_used_dict_segment(),
+    )?.content_range();
+
+    let mut fx = stack_effect!(machine; => address:Address, size:u16)?;
+    fx.address(*string_range.start());
+    fx.size(string_range.len() as u16);
+    fx.commit();
+
+    Ok(string_range.end().wrapping_add(1))
+}
+
+pub(super) fn execute_over16<TExt: MachineExtensions>(machine: &mut Machine<TExt>, address: Address) -> Result<Address, MachineError> {
+    let mut fx = stack_effect!(machine; => a_copy:Cell; peek a:Cell, _b:Cell)?;
+
+    fx.a_copy(fx.a());
+    fx.commit();
+
+    Ok(address + 1)
+}
+
+pub(super) fn execute_over32<TExt: MachineExtensions>(machine: &mut Machine<TExt>, address: Address) -> Result<Address, MachineError> {
+    let mut fx = stack_effect!(machine; => a_copy:DoubleCell; peek a:DoubleCell, _b:DoubleCell)?;
+
+    fx.a_copy(fx.a());
+    fx.commit();
+
+    Ok(address + 1)
+}
+
+/// Exchange two cells on top of data stack the way `SWAP` does.
+pub(crate) fn swap_cells<TExt: MachineExtensions>(machine: &mut Machine<TExt>) -> Result<(), MachineError> {
+    let mut fx = stack_effect!(machine; a:Cell, b: Cell => b_:Cell, a_:Cell)?;
+    let (a, b) = (fx.a(), fx.b());
+    fx.a_(a);
+    fx.b_(b);
+    fx.commit();
+
+    Ok(())
+}
+
+pub(super) fn execute_swap16<TExt: MachineExtensions>(machine: &mut Machine<TExt>, address: Address) -> Result<Address, MachineError> {
+    swap_cells(machine)?;
+
+    Ok(address + 1)
+}
+
+pub(super) fn execute_swap32<TExt: MachineExtensions>(machine: &mut Machine<TExt>, address: Address) -> Result<Address, MachineError> {
+    let mut fx = stack_effect!(machine; a:DoubleCell, b: DoubleCell => b_:DoubleCell, a_:DoubleCell)?;
+    let (a, b) = (fx.a(), fx.b());
+    fx.a_(a);
+    fx.b_(b);
+    fx.commit();
+
+    Ok(address + 1)
+}
+
+/// Duplicate the cell on top of data stack the way `DUP` does.
+pub(crate) fn dup_cell<TExt: MachineExtensions>(machine: &mut Machine<TExt>) -> Result<(), MachineError> {
+    let mut fx = stack_effect!(machine; => x_copy:Cell; peek x:Cell)?;
+    fx.x_copy(fx.x());
+    fx.commit();
+
+    Ok(())
+}
+
+pub(super) fn execute_dup16<TExt: MachineExtensions>(machine: &mut Machine<TExt>, address: Address) -> Result<Address, MachineError> {
+    dup_cell(machine)?;
+
+    Ok(address + 1)
+}
+
+pub(super) fn execute_dup32<TExt: MachineExtensions>(machine: &mut Machine<TExt>, address: Address) -> Result<Address, MachineError> {
+    let mut fx = stack_effect!(machine; => x_copy:DoubleCell; peek x:DoubleCell)?;
+    fx.x_copy(fx.x());
+    fx.commit();
+
+    Ok(address + 1)
+}
+
+pub(super) fn execute_drop16<TExt: MachineExtensions>(machine: &mut Machine<TExt>, address: Address) -> Result<Address, MachineError> {
+    machine.memory.data_pop_cell()?;
+
+    Ok(address + 1)
+}
+
+pub(super) fn execute_sp_fetch<TExt: MachineExtensions>(machine: &mut Machine<TExt>, address: Address) -> Result<Address, MachineError> {
+    machine.memory.data_push_cell(machine.memory.data_stack_ptr as Cell)?;
+
+    Ok(address + 1)
+}
+
+pub(super) fn execute_sp_store<TExt: MachineExtensions>(machine: &mut Machine<TExt>, address: Address) -> Result<Address, MachineError> {
+    let fx = stack_effect!(machine; ptr: Cell =>)?;
+    let ptr = fx.ptr() as Address;
+
+    // The pointer is validated before it is popped, as setting it replaces the pointer the effect is committed to
+    fx.machine.memory.validate_data_stack_ptr(ptr)?;
+    fx.commit();
+
+    machine.memory.set_data_stack_ptr(ptr)?;
+
+    Ok(address + 1)
+}
+
+pub(super) fn execute_rp_fetch<TExt: MachineExtensions>(machine: &mut Machine<TExt>, address: Address) -> Result<Address, MachineError> {
+    machine.memory.data_push_cell(machine.memory.call_stack_ptr as Cell)?;
+
+    Ok(address + 1)
+}
+
+pub(super) fn execute_rp_store<TExt: MachineExtensions>(machine: &mut Machine<TExt>, address: Address) -> Result<Address, MachineError> {
+    let fx = stack_effect!(machine; ptr: Cell =>)?;
+    let ptr = fx.ptr();
+
+    fx.machine.memory.set_call_stack_ptr(ptr as Address)?;
+    fx.commit();
+
+    Ok(address + 1)
+}
+
+pub(super) fn execute_rot16<TExt: MachineExtensions>(machine: &mut Machine<TExt>, address: Address) -> Result<Address, MachineError> {
+    let mut fx = stack_effect!(machine; a:Cell, b:Cell, c:Cell => b1:Cell, c1:Cell, a1:Cell)?;
+    let (a, b, c) = (fx.a(), fx.b(), fx.c());
+    fx.a1(a);
+    fx.b1(b);
+    fx.c1(c);
+    fx.commit();
+
+    Ok(address + 1)
+}
+
+pub(super) fn execute_call_pop16<TExt: MachineExtensions>(machine: &mut Machine<TExt>, address: Address) -> Result<Address, MachineError> {
+    let mut fx = call_stack_effect!(machine; data: => value:Cell; call: saved:Cell =>)?;
+    fx.value(fx.saved());
+    fx.commit();
+
+    Ok(address + 1)
+}
+
+pub(super) fn execute_call_push16<TExt: MachineExtensions>(machine: &mut Machine<TExt>, address: Address) -> Result<Address, MachineError> {
+    let mut fx = call_stack_effect!(machine; data: value:Cell =>; call: => saved:Cell)?;
+    fx.saved(fx.value());
+    fx.commit();
+
+    Ok(address + 1)
+}
+
+/// Shapes of data and call stack effects of `CallPushN` moving given number of cells.
+fn push_n_shapes(count: Cell) -> (StackShape, StackShape) {
+    let words = count.min((u16::MAX - 1) as Cell) as Address + 1;
+
+    (
+        StackShape { in_words: words, out_words: 0 },
+        StackShape { in_words: 0, out_words: words },
+    )
+}
+
+pub(super) fn execute_call_push_n<TExt: MachineExtensions>(machine: &mut Machine<TExt>, address: Address) -> Result<Address, MachineError> {
+    let count: Cell = machine.peek(0)?;
+    let (data_shape, call_shape) = push_n_shapes(count);
+
+    data_shape.validate_stack(&machine.memory.raw_memory, machine.memory.data_stack_ptr, machine.memory.get_data_stack_segment())?;
+    call_shape.validate_call_stack(&machine.memory.raw_memory, machine.memory.call_stack_ptr, machine.memory.get_call_stack_segment())?;
+
+    machine.memory.data_pop_cell()?;
+
+    let values = (0..count).map(|_| machine.memory.data_pop_cell()).collect::<Result<Vec<_>, _>>()?;
+
+    for value in values {
+        machine.memory.call_push_cell(value)?;
+    }
+
+    machine.memory.call_push_cell(count)?;
+
+    Ok(address + 1)
+}
+
+pub(super) fn execute_call_pop_n<TExt: MachineExtensions>(machine: &mut Machine<TExt>, address: Address) -> Result<Address, MachineError> {
+    StackShape { in_words: 1, out_words: 0 }.validate_call_stack(
+        &machine.memory.raw_memory, machine.memory.call_stack_ptr, machine.memory.get_call_stack_segment(),
+    )?;
+
+    let count = machine.memory.raw_memory.read_cell(machine.memory.call_stack_ptr);
+    let (data_shape, call_shape) = push_n_shapes(count);
+
+    // The reverse of `CallPushN`
+    StackShape { in_words: call_shape.out_words, out_words: 0 }.validate_call_stack(
+        &machine.memory.raw_memory, machine.memory.call_stack_ptr, machine.memory.get_call_stack_segment(),
+    )?;
+    StackShape { in_words: 0, out_words: data_shape.in_words }.validate_stack(
+        &machine.memory.raw_memory, machine.memory.data_stack_ptr, machine.memory.get_data_stack_segment(),
+    )?;
+
+    machine.memory.call_pop_cell()?;
+
+    for _ in 0..count {
+        let value = machine.memory.call_pop_cell()?;
+        machine.memory.data_push_cell(value)?;
+    }
+
+    machine.memory.data_push_cell(count)?;
+
+    Ok(address + 1)
+}
+
+pub(super) fn execute_call_pop32<TExt: MachineExtensions>(machine: &mut Machine<TExt>, address: Address) -> Result<Address, MachineError> {
+    let mut fx = call_stack_effect!(machine; data: => value:DoubleCell; call: saved:DoubleCell =>)?;
+    fx.value(fx.saved());
+    fx.commit();
+
+    Ok(address + 1)
+}
+
+pub(super) fn execute_call_push32<TExt: MachineExtensions>(machine: &mut Machine<TExt>, address: Address) -> Result<Address, MachineError> {
+    let mut fx = call_stack_effect!(machine; data: value:DoubleCell =>; call: => saved:DoubleCell)?;
+    fx.saved(fx.value());
+    fx.commit();
+
+    Ok(address + 1)
+}
+
+pub(super) fn execute_call_read16<TExt: MachineExtensions>(machine: &mut Machine<TExt>, address: Address) -> Result<Address, MachineError> {
+    let mut fx = call_stack_effect!(machine; data: => value:Cell; call: saved:Cell => _saved:Cell)?;
+    fx.value(fx.saved());
+    fx.commit();
+
+    Ok(address + 1)
+}
+
+pub(super) fn execute_call_read32<TExt: MachineExtensions>(machine: &mut Machine<TExt>, address: Address) -> Result<Address, MachineError> {
+    let mut fx = call_stack_effect!(machine; data: => value:DoubleCell; call: saved:DoubleCell => _saved:DoubleCell)?;
+    fx.value(fx.saved());
+    fx.commit();
+
+    Ok(address + 1)
+}
+
+pub(super) fn format_literal16<TExt: MachineExtensions>(writer: &mut impl io::Write, machine: &Machine<TExt>, address: Address) -> Result<Address, io::Error> {
+    let value = machine.memory.raw_memory.read_cell(address + 1);
+    writeln!(writer, "{} {:04X} ({}, {})", OpCode::Literal16.mnemonic(), value, value, value as SignedCell)?;
+
+    Ok(address + 1 + CELL_BYTES)
+}
+
+pub(super) fn format_literal32<TExt: MachineExtensions>(writer: &mut impl io::Write, machine: &Machine<TExt>, address: Address) -> Result<Address, io::Error> {
+    let value = machine.memory.raw_memory.read_double_cell(address + 1);
+    writeln!(
+        writer, "{} {:0width$X} ({}, {})",
+        OpCode::Literal32.mnemonic(), value, value, value as SignedDoubleCell, width = 2 * DOUBLE_CELL_BYTES as usize,
+    )?;
+
+    Ok(address + 1 + DOUBLE_CELL_BYTES)
+}
+
+pub(super) fn format_literal8<TExt: MachineExtensions>(writer: &mut impl io::Write, machine: &Machine<TExt>, address: Address) -> Result<Address, io::Error> {
+    let value = machine.memory.raw_memory.read_u8(address + 1);
+    writeln!(writer, "{} {:02X} ({})", OpCode::Literal8.mnemonic(), value, value)?;
+
+    Ok(address + 2)
+}
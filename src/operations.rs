@@ -0,0 +1,207 @@
+//! Operations host may perform on a machine, mirroring op-codes executed by compiled code.
+//!
+//! Every operation validates its access the same way the corresponding op-code does and fails with the same
+//! `MachineError`, leaving the machine intact. A word may be called by pushing its arguments, running it and popping
+//! its results:
+//!
+//! ```
+//! use rs4::input::StaticStringInput;
+//! use rs4::machine::{BorrowedExtensions, Machine};
+//!
+//! let (mut input, mut output) = (StaticStringInput::new(": average + 2 / ;"), Vec::new());
+//! let mut machine = Machine::new(BorrowedExtensions { input: &mut input, output: &mut output });
+//! machine.interpret_input().unwrap();
+//!
+//! machine.push(3u8).unwrap();
+//! machine.push(7u8).unwrap();
+//! machine.run_word("average").unwrap();
+//! machine.emit_str("done").unwrap();
+//!
+//! assert_eq!(machine.pop::<u8>().unwrap(), 5);
+//! assert!(machine.pop::<u8>().is_err());
+//! drop(machine);
+//! assert_eq!(output, b"done");
+//! ```
+
+use crate::cell::{Cell, CELL_BYTES};
+use crate::machine::{Machine, MachineExtensions};
+use crate::machine_error::MachineError;
+use crate::mem::Address;
+use crate::opcodes::{dup_cell, fetch_cell, store_cell, swap_cells};
+use crate::output::Output;
+use crate::stack_effect::{StackEffect, StackShape, Stackable};
+
+impl<TExt: MachineExtensions> Machine<TExt> {
+    fn validate_data_stack(&self, shape: StackShape) -> Result<(), MachineError> {
+        shape.validate_stack(
+            &self.memory.raw_memory,
+            self.memory.data_stack_ptr,
+            self.memory.get_data_stack_segment(),
+        )
+    }
+
+    /// Push a value to data stack, see `Stackable` for how values of different types occupy cells.
+    pub fn push<T: Stackable>(&mut self, value: T) -> Result<(), MachineError> {
+        let shape = StackShape { in_words: 0, out_words: T::SIZE_WORDS };
+        self.validate_data_stack(shape)?;
+
+        let ptr = shape.resulting_ptr(self.memory.data_stack_ptr);
+        value.write(&mut self.memory.raw_memory, ptr);
+        self.memory.data_stack_ptr = ptr;
+        self.memory.update_stack_usage();
+
+        Ok(())
+    }
+
+    /// Pop a value from data stack.
+    pub fn pop<T: Stackable>(&mut self) -> Result<T, MachineError> {
+        let shape = StackShape { in_words: T::SIZE_WORDS, out_words: 0 };
+        self.validate_data_stack(shape)?;
+
+        let value = T::read(&self.memory.raw_memory, self.memory.data_stack_ptr);
+        self.memory.data_stack_ptr = shape.resulting_ptr(self.memory.data_stack_ptr);
+
+        Ok(value)
+    }
+
+    /// Read a value lying given number of cells below top of data stack without removing it, `peek(0)` reads the
+    /// value on top.
+    pub fn peek<T: Stackable>(&self, depth: u16) -> Result<T, MachineError> {
+        let shape = StackShape { in_words: depth.saturating_add(T::SIZE_WORDS), out_words: 0 };
+        self.validate_data_stack(shape)?;
+
+        Ok(T::read(&self.memory.raw_memory, self.memory.data_stack_ptr.wrapping_add(depth.wrapping_mul(CELL_BYTES))))
+    }
+
+    /// Duplicate the cell on top of data stack, as `DUP` does.
+    pub fn dup(&mut self) -> Result<(), MachineError> {
+        dup_cell(self)
+    }
+
+    /// Exchange two cells on top of data stack, as `SWAP` does.
+    pub fn swap(&mut self) -> Result<(), MachineError> {
+        swap_cells(self)
+    }
+
+    /// Read a cell at given address, as `@` does, going through memory-mapped devices.
+    pub fn fetch(&mut self, address: Address) -> Result<Cell, MachineError> {
+        fetch_cell(self, address)
+    }
+
+    /// Write a cell to given address, as `!` does, honoring write protection and memory-mapped devices.
+    pub fn store(&mut self, address: Address, value: Cell) -> Result<(), MachineError> {
+        store_cell(self, address, value)
+    }
+
+    /// Write a string to machine output, as `TYPE` does.
+    pub fn emit_str(&mut self, text: &str) -> Result<(), MachineError> {
+        Ok(self.output().puts(text.as_bytes())?)
+    }
+
+    /// Run a word with given name as if it was read from input, taking arguments from and leaving results on data
+    /// stack.
+    pub fn run_word(&mut self, name: &str) -> Result<(), MachineError> {
+        let name_address = self.memory.set_input_word(name.as_bytes()).ok_or(MachineError::IllegalWord(None))?;
+
+        self.execute_word(name_address)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::cell::{DoubleCell, SignedCell, TRUE};
+    use crate::machine_memory::ReservedAddresses;
+    use crate::machine_state::MachineState;
+    use crate::mem::MEM_SIZE;
+    use crate::machine_testing::*;
+
+    use super::*;
+
+    #[test]
+    fn test_push_pop() {
+        let mut machine = TestMachine::default();
+        machine.push(-5i8).unwrap();
+        machine.push(true).unwrap();
+        machine.push(0x12345678 as DoubleCell).unwrap();
+        machine.push(0xBEEFu16).unwrap();
+
+        assert_eq!(machine.pop::<u16>().unwrap(), 0xBEEF);
+        assert_eq!(machine.pop::<DoubleCell>().unwrap(), 0x12345678);
+        assert_eq!(machine.peek::<Cell>(0).unwrap(), TRUE);
+        assert!(machine.pop::<bool>().unwrap());
+        assert_eq!(machine.pop::<SignedCell>().unwrap(), -5);
+
+        assert!(matches!(machine.pop::<Cell>(), Err(MachineError::DataStackUnderflow { requested: 1, .. })));
+        assert!(matches!(machine.pop::<DoubleCell>(), Err(MachineError::DataStackUnderflow { requested: 2, .. })));
+
+        while machine.push(1u8).is_ok() {}
+
+        let depth = machine.memory.data_stack_depth();
+        assert!(matches!(machine.push(1u8), Err(MachineError::DataStackOverflow { requested: 1, .. })));
+        assert_eq!(machine.memory.data_stack_depth(), depth);
+    }
+
+    #[test]
+    fn test_peek() {
+        let mut machine = TestMachine::default();
+        machine.push(1u8).unwrap();
+        machine.push(2u8).unwrap();
+        machine.push(3u8).unwrap();
+
+        assert_eq!(machine.peek::<Cell>(0).unwrap(), 3);
+        assert_eq!(machine.peek::<Cell>(2).unwrap(), 1);
+        assert!(matches!(machine.peek::<Cell>(3), Err(MachineError::DataStackUnderflow { requested: 4, .. })));
+        assert!(matches!(machine.peek::<Cell>(u16::MAX), Err(MachineError::DataStackUnderflow { .. })));
+
+        machine.assert_data_stack_state(&[StackElement::Cell(1), StackElement::Cell(2), StackElement::Cell(3)]);
+    }
+
+    #[test]
+    fn test_dup_swap() {
+        let mut machine = TestMachine::default();
+
+        assert!(matches!(machine.dup(), Err(MachineError::DataStackUnderflow { requested: 1, .. })));
+
+        machine.push(1u8).unwrap();
+        assert!(matches!(machine.swap(), Err(MachineError::DataStackUnderflow { requested: 2, .. })));
+
+        machine.push(2u8).unwrap();
+        machine.swap().unwrap();
+        machine.dup().unwrap();
+
+        machine.assert_data_stack_state(&[StackElement::Cell(2), StackElement::Cell(1), StackElement::Cell(1)]);
+    }
+
+    #[test]
+    fn test_fetch_store() {
+        let mut machine = TestMachine::default();
+        let address = machine.memory.get_reserved_address(ReservedAddresses::BaseVar);
+
+        machine.store(address, 16).unwrap();
+        assert_eq!(machine.fetch(address).unwrap(), 16);
+        assert_eq!(machine.memory.raw_memory.read_cell(address), 16);
+
+        // The last cell would cross the end of memory
+        let last = (MEM_SIZE - 1) as Address;
+        assert!(matches!(machine.fetch(last), Err(MachineError::MemoryAccessError(_))));
+        assert!(matches!(machine.store(last, 1), Err(MachineError::MemoryAccessError(_))));
+
+        machine.interpret_str(": x ;").unwrap();
+        machine.memory.write_protection = true;
+        assert!(matches!(machine.store(0, 1), Err(MachineError::WriteProtected { address: 0 })));
+    }
+
+    #[test]
+    fn test_emit_str_and_run_word() {
+        let mut machine = TestMachine::default();
+        machine.interpret_str(": greet 72 EMIT ;").unwrap();
+
+        machine.emit_str("Hi, ").unwrap();
+        machine.run_word("greet").unwrap();
+        assert_eq!(machine.extensions.output.content.take(), b"Hi, H");
+
+        assert!(matches!(machine.run_word("no-such-word"), Err(MachineError::IllegalWord(_))));
+        assert!(matches!(machine.run_word("IF"), Err(MachineError::CompileOnlyWord { .. })));
+        assert_eq!(machine.memory.get_state(), MachineState::Interpreter);
+    }
+}
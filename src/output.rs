@@ -1,5 +1,5 @@
 use std::cell::RefCell;
-use std::io::{Error as IOError, stdout, Stdout, Write};
+use std::io::{BufWriter, Error as IOError, stdout, Stdout, Write};
 use std::rc::Rc;
 
 fn word_to_char(word: u16) -> u8 {
@@ -23,16 +23,30 @@ pub trait Output {
     fn puts(&mut self, data: &[u8]) -> Result<(), OutputError>;
 
     fn flush(&mut self) -> Result<(), OutputError>;
+
+    /// Write a Unicode character encoded as UTF-8.
+    ///
+    /// Values that are not valid code points are written as U+FFFD replacement character.
+    fn put_xchar(&mut self, code_point: u32) -> Result<(), OutputError> {
+        let mut buffer = [0u8; 4];
+        let character = char::from_u32(code_point).unwrap_or(char::REPLACEMENT_CHARACTER);
+
+        self.puts(character.encode_utf8(&mut buffer).as_bytes())
+    }
 }
 
+/// Buffered standard output.
+///
+/// Clones share the same buffer, so one of them may be used to flush output written through another.
+#[derive(Clone)]
 pub struct StdoutOutput {
-    stdout: Stdout,
+    stdout: Rc<RefCell<BufWriter<Stdout>>>,
 }
 
 impl StdoutOutput {
     pub fn new() -> StdoutOutput {
         StdoutOutput {
-            stdout: stdout()
+            stdout: Rc::new(RefCell::new(BufWriter::new(stdout()))),
         }
     }
 }
@@ -45,19 +59,19 @@ impl Default for StdoutOutput {
 
 impl Output for StdoutOutput {
     fn putc(&mut self, character: u16) -> Result<(), OutputError> {
-        self.stdout.write(&[word_to_char(character)])?;
+        self.stdout.borrow_mut().write_all(&[word_to_char(character)])?;
 
         Ok(())
     }
 
     fn puts(&mut self, data: &[u8]) -> Result<(), OutputError> {
-        self.stdout.write(data)?;
+        self.stdout.borrow_mut().write_all(data)?;
 
         Ok(())
     }
 
     fn flush(&mut self) -> Result<(), OutputError> {
-        self.stdout.flush()?;
+        self.stdout.borrow_mut().flush()?;
 
         Ok(())
     }
@@ -91,3 +105,274 @@ impl Output for StringOutput {
         Ok(())
     }
 }
+
+/// Output collected in a buffer owned by host, e.g. one borrowed by `BorrowedExtensions`.
+impl Output for Vec<u8> {
+    fn putc(&mut self, character: u16) -> Result<(), OutputError> {
+        self.push(word_to_char(character));
+
+        Ok(())
+    }
+
+    fn puts(&mut self, data: &[u8]) -> Result<(), OutputError> {
+        self.extend_from_slice(data);
+
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<(), OutputError> {
+        Ok(())
+    }
+}
+
+/// Output writing to any `Write` implementation.
+pub struct WriterOutput<W: Write> {
+    writer: W,
+}
+
+impl<W: Write> WriterOutput<W> {
+    pub fn new(writer: W) -> WriterOutput<W> {
+        WriterOutput { writer }
+    }
+
+    pub fn get_ref(&self) -> &W {
+        &self.writer
+    }
+
+    pub fn get_mut(&mut self) -> &mut W {
+        &mut self.writer
+    }
+
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+impl<W: Write> Output for WriterOutput<W> {
+    fn putc(&mut self, character: u16) -> Result<(), OutputError> {
+        self.writer.write_all(&[word_to_char(character)])?;
+
+        Ok(())
+    }
+
+    fn puts(&mut self, data: &[u8]) -> Result<(), OutputError> {
+        self.writer.write_all(data)?;
+
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<(), OutputError> {
+        self.writer.flush()?;
+
+        Ok(())
+    }
+}
+
+impl<T: Output + ?Sized> Output for &mut T {
+    fn putc(&mut self, character: u16) -> Result<(), OutputError> {
+        (**self).putc(character)
+    }
+
+    fn puts(&mut self, data: &[u8]) -> Result<(), OutputError> {
+        (**self).puts(data)
+    }
+
+    fn flush(&mut self) -> Result<(), OutputError> {
+        (**self).flush()
+    }
+}
+
+impl<T: Output + ?Sized> Output for Box<T> {
+    fn putc(&mut self, character: u16) -> Result<(), OutputError> {
+        (**self).putc(character)
+    }
+
+    fn puts(&mut self, data: &[u8]) -> Result<(), OutputError> {
+        (**self).puts(data)
+    }
+
+    fn flush(&mut self) -> Result<(), OutputError> {
+        (**self).flush()
+    }
+}
+
+/// `Write` implementation writing to an output, e.g. to print reports produced for `io::Write` to machine output.
+pub struct OutputWriter<O: Output> {
+    output: O,
+}
+
+impl<O: Output> OutputWriter<O> {
+    pub fn new(output: O) -> OutputWriter<O> {
+        OutputWriter { output }
+    }
+
+    pub fn into_inner(self) -> O {
+        self.output
+    }
+}
+
+impl From<OutputError> for IOError {
+    fn from(err: OutputError) -> Self {
+        match err {
+            OutputError::StdIOError(err) => err,
+        }
+    }
+}
+
+impl<O: Output> Write for OutputWriter<O> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.output.puts(buf)?;
+
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(self.output.flush()?)
+    }
+}
+
+/// Output forwarding everything to two other outputs.
+///
+/// Both outputs are written to even if the first one fails, the first error is returned.
+pub struct TeeOutput<A: Output, B: Output> {
+    pub first: A,
+    pub second: B,
+}
+
+impl<A: Output, B: Output> TeeOutput<A, B> {
+    pub fn new(first: A, second: B) -> TeeOutput<A, B> {
+        TeeOutput { first, second }
+    }
+
+    pub fn into_inner(self) -> (A, B) {
+        (self.first, self.second)
+    }
+}
+
+impl<A: Output, B: Output> Output for TeeOutput<A, B> {
+    fn putc(&mut self, character: u16) -> Result<(), OutputError> {
+        let first_result = self.first.putc(character);
+        let second_result = self.second.putc(character);
+
+        first_result.and(second_result)
+    }
+
+    fn puts(&mut self, data: &[u8]) -> Result<(), OutputError> {
+        let first_result = self.first.puts(data);
+        let second_result = self.second.puts(data);
+
+        first_result.and(second_result)
+    }
+
+    fn flush(&mut self) -> Result<(), OutputError> {
+        let first_result = self.first.flush();
+        let second_result = self.second.flush();
+
+        first_result.and(second_result)
+    }
+}
+
+/// Output discarding everything when `None`.
+impl<T: Output> Output for Option<T> {
+    fn putc(&mut self, character: u16) -> Result<(), OutputError> {
+        self.as_mut().map_or(Ok(()), |output| output.putc(character))
+    }
+
+    fn puts(&mut self, data: &[u8]) -> Result<(), OutputError> {
+        self.as_mut().map_or(Ok(()), |output| output.puts(data))
+    }
+
+    fn flush(&mut self) -> Result<(), OutputError> {
+        self.as_mut().map_or(Ok(()), |output| output.flush())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::{self, BufWriter};
+
+    use super::*;
+
+    #[test]
+    fn test_put_xchar() {
+        let mut output = StringOutput::default();
+
+        output.put_xchar(0x41).unwrap();
+        output.put_xchar(0xE9).unwrap();
+        output.put_xchar(0x20AC).unwrap();
+        output.put_xchar(0xD800).unwrap();
+        output.put_xchar(0x1F600).unwrap();
+
+        assert_eq!(output.content.borrow().as_slice(), "Aé€\u{FFFD}😀".as_bytes());
+    }
+
+    #[test]
+    fn test_writer_output_vec() {
+        let mut output = WriterOutput::new(Vec::new());
+
+        output.putc(b'f' as u16).unwrap();
+        output.putc(0x100 + b'o' as u16).unwrap();
+        output.puts("o bär".as_bytes()).unwrap();
+        output.flush().unwrap();
+
+        assert_eq!(output.into_inner(), "foo bär".as_bytes());
+    }
+
+    #[test]
+    fn test_writer_output_file_flush() {
+        let path = std::env::temp_dir().join(format!("rs4-{}-writer-output.txt", std::process::id()));
+        let file = std::fs::File::create(&path).unwrap();
+        let mut output = WriterOutput::new(BufWriter::new(file));
+
+        output.puts(b"hello, ").unwrap();
+        output.puts(b"world").unwrap();
+        assert_eq!(std::fs::read(&path).unwrap(), b"");
+
+        output.flush().unwrap();
+        assert_eq!(std::fs::read(&path).unwrap(), b"hello, world");
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_output_through_references() {
+        fn greet(mut output: impl Output) {
+            output.puts(b"hi").unwrap();
+        }
+
+        let mut string_output = StringOutput::default();
+        greet(&mut string_output);
+        assert_eq!(string_output.content.borrow().as_slice(), b"hi");
+
+        let boxed: Box<dyn Output> = Box::new(StringOutput::new(string_output.content.clone()));
+        greet(boxed);
+        assert_eq!(string_output.content.borrow().as_slice(), b"hihi");
+    }
+
+    struct FailingOutput;
+
+    impl Output for FailingOutput {
+        fn putc(&mut self, _character: u16) -> Result<(), OutputError> {
+            Err(io::Error::new(io::ErrorKind::BrokenPipe, "closed").into())
+        }
+
+        fn puts(&mut self, _data: &[u8]) -> Result<(), OutputError> {
+            Err(io::Error::new(io::ErrorKind::BrokenPipe, "closed").into())
+        }
+
+        fn flush(&mut self) -> Result<(), OutputError> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_tee_output_error() {
+        let mut tee = TeeOutput::new(FailingOutput, WriterOutput::new(Vec::new()));
+
+        assert!(matches!(tee.puts(b"abc"), Err(OutputError::StdIOError(_))));
+        assert!(matches!(tee.putc(b'd' as u16), Err(OutputError::StdIOError(_))));
+        tee.flush().unwrap();
+
+        assert_eq!(tee.into_inner().1.into_inner(), b"abcd");
+    }
+}
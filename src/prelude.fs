@@ -0,0 +1,42 @@
+( Standard words defined on top of builtin words, interpreted by Machine::load_prelude )
+
+( Flags )
+: TRUE -1 ;
+: FALSE 0 ;
+
+( Stack )
+: 2DROP DROP DROP ;
+: NIP SWAP DROP ;
+: TUCK SWAP OVER ;
+: ?DUP DUP IF DUP THEN ;
+
+( Arithmetic )
+: NEGATE 0 SWAP - ;
+: 1+ 1 + ;
+: 1- 1 - ;
+: 2* DUP + ;
+: MAX 2DUP < IF SWAP THEN DROP ;
+: MIN 2DUP > IF SWAP THEN DROP ;
+: +! DUP @ ROT + SWAP ! ;
+
+( Comparison )
+: 0= 0 = ;
+: 0< 0 < ;
+: 0> 0 > ;
+: 0<> 0 = INVERT ;
+: <> = INVERT ;
+
+( Memory )
+: CELLS [ 1 ALIGNED ] LITERAL * ;
+: CELL+ [ 1 ALIGNED ] LITERAL + ;
+: CHARS ;
+: CHAR+ 1 + ;
+
+( Number base )
+: DECIMAL 10 BASE ! ;
+: HEX 16 BASE ! ;
+
+( Name tokens )
+: (execute) EXECUTE ;
+: (compile,) COMPILE, ;
+: NAME>COMPILE DUP NAME>INTERPRET SWAP IMMEDIATE? IF ['] (execute) ELSE ['] (compile,) THEN ;
@@ -1,39 +1,125 @@
 use std::cmp::min;
 use std::io;
+use std::ops::Range;
 use std::str::from_utf8;
-use crate::input::Input;
 
+use int_enum::IntEnum;
+
+use crate::cell::{Cell, CELL_BYTES, SignedCell, SignedDoubleCell};
+use crate::literal::format_literal;
 use crate::machine::{Machine, MachineExtensions};
-use crate::machine_memory::MachineMemory;
-use crate::mem::Address;
+use crate::machine_error::MachineError;
+use crate::machine_memory::{MachineMemory, ReservedAddresses};
+use crate::mem::{AccessKind, Address, MEM_SIZE, MemoryAccessError};
+use crate::memory_segment::WHOLE_MEMORY;
 use crate::opcodes::OpCode;
+use crate::output::{Output, OutputError};
 use crate::readable_article::ReadableArticle;
+use crate::sized_string::ReadableSizedString;
 
 const MAX_STACK_ENTRIES_TO_PRINT: u16 = 16;
 
+/// Number of bytes shown on a single line by `dump_range`.
+pub const DUMP_LINE_BYTES: usize = 16;
+
+/// Number of instructions preceding an address of interest shown by `print_code_context`.
+const CODE_CONTEXT_INSTRUCTIONS_BEFORE: usize = 5;
+
+/// Number of instructions following an address of interest shown by `print_code_context`.
+const CODE_CONTEXT_INSTRUCTIONS_AFTER: usize = 2;
+
+/// How stack values are shown by `MachineMemory::format_stack`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct StackFormatOptions {
+    /// Maximal number of entries shown, deeper ones are replaced by `...`.
+    pub max_entries: u16,
+
+    /// Show values as signed decimal numbers along with hex and unsigned decimal.
+    pub signed: bool,
+
+    /// Show pairs of adjacent cells, starting from the top, as double cells combined the way
+    /// `data_pop_double_cell` does.
+    pub double_cells: bool,
+}
+
+impl Default for StackFormatOptions {
+    fn default() -> Self {
+        StackFormatOptions {
+            max_entries: MAX_STACK_ENTRIES_TO_PRINT,
+            signed: true,
+            double_cells: false,
+        }
+    }
+}
+
 impl MachineMemory {
-    fn print_stack_state(&self, f: &mut impl io::Write, sp: Address, depth: u16) -> io::Result<()> {
+    fn print_stack_state(
+        &self,
+        f: &mut impl io::Write,
+        sp: Address,
+        depth: u16,
+        options: StackFormatOptions,
+    ) -> io::Result<()> {
         write!(f, "\t")?;
 
         if depth == 0 {
             write!(f, "(empty)\n")?;
-        } else {
-            let entries_to_print = min(MAX_STACK_ENTRIES_TO_PRINT, depth);
+            return Ok(());
+        }
 
-            if entries_to_print < depth {
-                write!(f, "..., ")?;
-            }
+        // Offsets (in cells) from the top of the stack and whether the entry is a double cell, top first
+        let mut entries = Vec::new();
+        let mut offset = 0;
 
-            for i in (0..entries_to_print).rev() {
-                let value = unsafe { self.raw_memory.read_u16(sp + 2 * i) };
+        while offset < depth && entries.len() < options.max_entries as usize {
+            let double = options.double_cells && offset + 1 < depth;
+            entries.push((offset, double));
+            offset += if double { 2 } else { 1 };
+        }
+
+        if offset < depth {
+            write!(f, "..., ")?;
+        }
+
+        for (i, &(offset, double)) in entries.iter().enumerate().rev() {
+            let address = sp + CELL_BYTES * offset;
+
+            if double {
+                let value = self.raw_memory.read_double_cell(address);
+
+                write!(f, "{value:08X} ({value:>10}")?;
+
+                if options.signed {
+                    write!(f, ", {:>11}", value as SignedDoubleCell)?;
+                }
+            } else {
+                let value = self.raw_memory.read_cell(address);
+
+                write!(f, "{value:04X} ({value:>5}")?;
 
-                write!(f, "{value:04X} ({value:>5}){}", if i == 0 { "\n" } else { ", " })?;
+                if options.signed {
+                    write!(f, ", {:>6}", value as SignedCell)?;
+                }
             }
+
+            write!(f, "){}", if i == 0 { "\n" } else { ", " })?;
         }
 
         Ok(())
     }
 
+    /// Print data stack values, bottom to top, in hex and decimal.
+    pub fn format_stack(&self, f: &mut impl io::Write, options: StackFormatOptions) -> io::Result<()> {
+        self.print_stack_state(f, self.data_stack_ptr, self.data_stack_depth(), options)
+    }
+
+    /// Print call stack entries, bottom to top, as unsigned numbers in hex and decimal.
+    pub fn format_call_stack(&self, f: &mut impl io::Write) -> io::Result<()> {
+        let addresses = StackFormatOptions { signed: false, ..StackFormatOptions::default() };
+
+        self.print_stack_state(f, self.call_stack_ptr, self.call_stack_depth(), addresses)
+    }
+
     fn print_articles(&self, f: &mut impl io::Write) -> io::Result<()> {
         let article_count = self.articles().count();
 
@@ -45,7 +131,7 @@ impl MachineMemory {
         write!(f, "Article(s) ({article_count}):\n\t")?;
 
         for article in self.articles() {
-            write!(f, "{}, ", from_utf8(article.name().as_bytes()).unwrap_or("(not printable)"))?;
+            write!(f, "{}, ", from_utf8(&article.name().as_bytes()).unwrap_or("(not printable)"))?;
         }
 
         write!(f, "\n")?;
@@ -53,14 +139,93 @@ impl MachineMemory {
         Ok(())
     }
 
+    /// Print a hex dump of given range of memory, as `DUMP` does.
+    ///
+    /// Each line shows address of it's first byte, up to `DUMP_LINE_BYTES` bytes in hex and the same bytes as ASCII
+    /// characters, with non-printable ones replaced by dots. The part of range past the end of memory is ignored.
+    pub fn dump_range(&self, f: &mut impl io::Write, range: Range<usize>) -> io::Result<()> {
+        let end = range.end.min(MEM_SIZE);
+        let mut line_start = range.start;
+
+        while line_start < end {
+            let line = self.raw_memory.slice(line_start..min(line_start + DUMP_LINE_BYTES, end));
+
+            write!(f, "{:04X}:", line_start)?;
+
+            for byte in line.iter() {
+                write!(f, " {:02X}", byte)?;
+            }
+
+            write!(f, "{:width$}  ", "", width = 3 * (DUMP_LINE_BYTES - line.len()))?;
+
+            for &byte in line.iter() {
+                f.write_all(&[if byte.is_ascii_graphic() || byte == b' ' { byte } else { b'.' }])?;
+            }
+
+            writeln!(f)?;
+
+            line_start += DUMP_LINE_BYTES;
+        }
+
+        Ok(())
+    }
+
+    /// Print address, size and purpose of every region of memory, in order of addresses, along with usage of dictionary
+    /// and stacks, as `.MEM` does.
+    ///
+    /// Sizes of regions add up to the memory size.
+    pub fn print_memory_map(&self, f: &mut impl io::Write) -> io::Result<()> {
+        let memory_range = self.raw_memory.address_range();
+        let dict_ptr = self.get_dict_ptr() as usize;
+        // Dictionary may have crossed the data stack
+        let data_stack_ptr = (self.data_stack_ptr as usize).max(dict_ptr);
+        let stacks_border = *self.get_call_stack_segment().start() as usize;
+        let heap_start = *self.get_heap_segment().start() as usize;
+        let reserved_space_start = self.get_reserved_address(ReservedAddresses::HereVar) as usize;
+        let word_buffer = self.get_word_buffer_address() as usize;
+        let pad = self.get_pad_address() as usize;
+        let pno_buffer = self.get_pno_buffer_address() as usize;
+        let data_stack_capacity = self.get_data_stack_segment().len() / CELL_BYTES as usize;
+
+        let regions = [
+            (*memory_range.start() as usize, "dictionary".to_string()),
+            (dict_ptr, format!("free space, dictionary may grow by {} byte(s)", self.get_free_data_segment().len())),
+            (data_stack_ptr, format!(
+                "data stack, {} of {} cell(s) used", self.data_stack_depth(), data_stack_capacity,
+            )),
+            (stacks_border, format!(
+                "call stack, {} of {} cell(s) used", self.call_stack_depth(), self.layout_config().max_call_stack_depth,
+            )),
+            (heap_start, format!("heap, {} byte(s) free", self.heap_free_space())),
+            (reserved_space_start, "built-in variables".to_string()),
+            (word_buffer, "word buffer".to_string()),
+            (pad, "PAD".to_string()),
+            (pno_buffer, "pictured numeric output buffer".to_string()),
+        ];
+
+        writeln!(f, "Memory map ({} bytes):", memory_range.len())?;
+
+        for (i, (start, description)) in regions.iter().enumerate() {
+            let end = regions.get(i + 1).map_or(*memory_range.end() as usize + 1, |(end, _)| *end);
+
+            writeln!(f, "\t{:04X} {:>5} {}", start, end - start, description)?;
+        }
+
+        Ok(())
+    }
+
+    fn format_max_depth(&self, max_depth: u16) -> String {
+        if self.track_stack_usage { format!(", max: {max_depth}") } else { String::new() }
+    }
+
     pub fn print_memory_state(&self, f: &mut impl io::Write) -> io::Result<()> {
         let data_stack_depth = self.data_stack_depth();
-        write!(f, "Data stack (depth: {data_stack_depth}):\n")?;
-        self.print_stack_state(f, self.data_stack_ptr, data_stack_depth)?;
+        write!(f, "Data stack (depth: {data_stack_depth}{}):\n", self.format_max_depth(self.max_data_depth()))?;
+        self.format_stack(f, StackFormatOptions::default())?;
 
         let call_stack_depth = self.call_stack_depth();
-        write!(f, "Call stack (depth: {call_stack_depth}):\n")?;
-        self.print_stack_state(f, self.call_stack_ptr, call_stack_depth)?;
+        write!(f, "Call stack (depth: {call_stack_depth}{}):\n", self.format_max_depth(self.max_call_depth()))?;
+        self.format_call_stack(f)?;
 
         write!(f, "Dictionary size: {} byte(s)\n", self.dictionary_size())?;
 
@@ -79,19 +244,50 @@ impl<'m> ReadableArticle<'m> {
         let mut address = self.body_address();
 
         while address < limit {
-            address = OpCode::format_at(writer, machine, address)?;
+            address = match machine.memory.data_range_containing(address) {
+                Some(range) => print_data(writer, machine, address, min(*range.end() as usize + 1, limit as usize))?,
+                None => OpCode::format_at(writer, machine, address)?,
+            };
         }
 
         Ok(())
     }
 }
 
+/// Print bytes of a data range (e.g. a variable) as `db` lines instead of decoding them as instructions.
+///
+/// Returns address following the range.
+fn print_data<TExt: MachineExtensions>(
+    writer: &mut impl io::Write,
+    machine: &Machine<TExt>,
+    start: Address,
+    end: usize,
+) -> io::Result<Address> {
+    let mut line_start = start as usize;
+
+    while line_start < end {
+        let line_end = min(line_start + DUMP_LINE_BYTES, end);
+
+        write!(writer, "{:04X}: db", line_start)?;
+
+        for byte in machine.memory.raw_memory.slice(line_start..line_end).iter() {
+            write!(writer, " {:02X}", byte)?;
+        }
+
+        writeln!(writer)?;
+
+        line_start = line_end;
+    }
+
+    Ok(end as Address)
+}
+
 impl<TExt: MachineExtensions> Machine<TExt> {
     pub fn print_state(&mut self, f: &mut impl io::Write) -> io::Result<()> {
         self.memory.print_memory_state(f)?;
 
         write!(f, "State: {}\n", self.memory.get_state())?;
-        match self.extensions.get_input().tell() {
+        match self.input().tell() {
             Ok(position) => write!(f, "Input position: {position}\n"),
             Err(err) => write!(f, "Input broken: {err:?}\n"),
         }?;
@@ -99,6 +295,118 @@ impl<TExt: MachineExtensions> Machine<TExt> {
         Ok(())
     }
 
+    /// Print disassembly of few instructions around given address along with name of article
+    /// containing it.
+    pub fn print_code_context(&self, writer: &mut impl io::Write, address: Address) -> io::Result<()> {
+        let start_address = match self.memory.article_containing(address) {
+            Some(article) => {
+                writeln!(writer, "In article {}:", article.name())?;
+                article.body_address()
+            }
+            None => {
+                writeln!(writer, "Not in any article:")?;
+                address
+            }
+        };
+
+        let mut instruction_addresses = Vec::new();
+        let mut current = start_address;
+
+        while current < address {
+            instruction_addresses.push(current);
+
+            let next = OpCode::format_at(&mut io::sink(), self, current)?;
+
+            if next <= current {
+                break;
+            }
+
+            current = next;
+        }
+
+        let mut current = instruction_addresses
+            .len()
+            .checked_sub(CODE_CONTEXT_INSTRUCTIONS_BEFORE)
+            .map_or(start_address, |i| instruction_addresses[i]);
+
+        while current < address {
+            write!(writer, "   ")?;
+            current = OpCode::format_at(writer, self, current)?;
+        }
+
+        // Instructions found before may end in the middle of instruction at the address
+        current = address;
+
+        for i in 0..=CODE_CONTEXT_INSTRUCTIONS_AFTER {
+            write!(writer, "{}", if i == 0 { "=> " } else { "   " })?;
+
+            let next = OpCode::format_at(writer, self, current)?;
+
+            if next <= current {
+                break;
+            }
+
+            current = next;
+        }
+
+        Ok(())
+    }
+
+    /// Print instructions starting at `start` until reaching or passing `end`, returning address of the first
+    /// instruction not printed.
+    ///
+    /// An instruction is printed only if all of it's bytes are below `end`, so disassembly stops before an instruction
+    /// crossing the end of range. Illegal op-codes are printed as single-byte instructions.
+    pub fn disassemble_range(&self, start: Address, end: Address, writer: &mut impl io::Write) -> io::Result<Address> {
+        let mut address = start;
+
+        while address < end {
+            let length = match OpCode::from_int(self.memory.raw_memory.read_u8(address)) {
+                Err(_) => 1,
+                Ok(op_code) => match op_code.operand_size() {
+                    Some(size) => 1 + size as usize,
+                    None if address + 1 < end => 2 + self.memory.raw_memory.read_u8(address + 1) as usize,
+                    None => break,
+                },
+            };
+
+            if address as usize + length > end as usize {
+                break;
+            }
+
+            let next = OpCode::format_at(writer, self, address)?;
+
+            if next <= address {
+                break;
+            }
+
+            address = next;
+        }
+
+        Ok(address)
+    }
+
+    /// Print frames of code being executed, the innermost first: the instruction at program counter followed by
+    /// return addresses on call stack, each with name of the article containing it and offset from the article body.
+    pub fn format_backtrace(&self, writer: &mut impl io::Write) -> io::Result<()> {
+        let return_addresses = (0..self.memory.call_stack_depth()).map(|i| {
+            let value: Cell = self.memory.raw_memory.read_cell(self.memory.call_stack_ptr + CELL_BYTES * i);
+
+            value as Address
+        });
+
+        for (index, address) in self.program_counter().into_iter().chain(return_addresses).enumerate() {
+            match self.memory.article_containing(address) {
+                Some(article) => {
+                    writeln!(writer, "#{} {} +0x{:02X}", index, article.name(), address.wrapping_sub(article.body_address()))?
+                }
+                None => writeln!(writer, "#{} {:04X} (not in any article)", index, address)?,
+            }
+        }
+
+        Ok(())
+    }
+
     pub fn print_disassembly(&self, writer: &mut impl io::Write) -> io::Result<()> {
         let mut limit = self.memory.get_dict_ptr();
 
@@ -110,4 +418,65 @@ impl<TExt: MachineExtensions> Machine<TExt> {
 
         Ok(())
     }
+
+    /// Print depth of data stack followed by all of it's values, bottom to top, as signed numbers in current base,
+    /// as `.S` does.
+    pub fn print_data_stack(&self, writer: &mut impl io::Write) -> io::Result<()> {
+        let depth = self.memory.data_stack_depth();
+        let base: Cell = self.memory.get_base();
+
+        write!(writer, "<{}> ", depth)?;
+
+        for i in (0..depth).rev() {
+            let value = self.memory.raw_memory.read_cell(self.memory.data_stack_ptr + CELL_BYTES * i);
+
+            write!(writer, "{} ", format_literal(value as SignedCell, base as u32))?;
+        }
+
+        Ok(())
+    }
+
+    /// Write a hex dump of `length` bytes of memory starting at `start` to machine output, as `DUMP` does.
+    ///
+    /// Fails without printing anything if the range does not fit into memory.
+    pub fn dump_memory(&mut self, start: Cell, length: Cell) -> Result<(), MachineError> {
+        let (start, end) = (start as usize, start as usize + length as usize);
+
+        if end > MEM_SIZE {
+            return Err(MemoryAccessError {
+                access_range: (start.min(MEM_SIZE - 1) as Address)..=Address::MAX,
+                segment: self.memory.raw_memory.address_range(),
+                segment_name: WHOLE_MEMORY,
+                kind: AccessKind::Read,
+            }.into());
+        }
+
+        let mut listing = Vec::new();
+        self.memory.dump_range(&mut listing, start..end).map_err(OutputError::from)?;
+
+        Ok(self.output().puts(&listing)?)
+    }
+
+    /// Print disassembly of the word with name stored as a sized string at given address, as `SEE` does.
+    ///
+    /// Disassembly of an article ends at the header of the article defined after it. Native and builtin words are
+    /// reported with a single line. Fails with `MachineError::IllegalWord` if there is no such word.
+    pub fn print_word_definition(&self, writer: &mut impl io::Write, name_address: Address) -> Result<(), MachineError> {
+        let print_result = if let Some(article) = self.memory.lookup_article_name_buf(name_address)? {
+            article.disassemble(writer, self, self.memory.article_end(&article))
+        } else {
+            let name = ReadableSizedString::new(&self.memory.raw_memory, name_address, self.memory.raw_memory.address_range())?;
+            let name = String::from_utf8_lossy(&name.as_bytes()).into_owned();
+
+            if self.native_words.find(name.as_bytes()).is_some() {
+                writeln!(writer, "{} is a native word", name)
+            } else if self.find_builtin_word(name.as_bytes()).is_some() {
+                writeln!(writer, "{} is a built-in word", name)
+            } else {
+                return Err(MachineError::IllegalWord(Some(name_address)));
+            }
+        };
+
+        print_result.map_err(|err| MachineError::OutputError(err.into()))
+    }
 }
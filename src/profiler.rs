@@ -0,0 +1,172 @@
+use std::collections::HashMap;
+use std::io;
+
+use int_enum::IntEnum;
+
+use crate::machine::{Machine, MachineExtensions};
+use crate::machine_memory::MachineMemory;
+use crate::mem::Address;
+use crate::opcodes::OpCode;
+
+/// Execution statistics of a single article.
+#[derive(Clone, Copy, Default, Debug, PartialEq, Eq)]
+pub struct WordStats {
+    /// Number of times the article was called or started from host.
+    pub entered: u64,
+
+    /// Number of instructions executed inside the article.
+    pub instructions: u64,
+}
+
+/// Collects per-article execution statistics.
+#[derive(Default)]
+pub struct Profiler {
+    /// Statistics by article header address.
+    stats: HashMap<Address, WordStats>,
+
+    /// Header addresses of all articles in ascending order.
+    headers: Vec<Address>,
+
+    /// Dictionary pointer and last article pointer `headers` were collected at.
+    headers_version: Option<(Address, Option<Address>)>,
+
+    /// Next recorded instruction is the first one of an entered article.
+    entering: bool,
+}
+
+impl Profiler {
+    pub fn stats(&self) -> &HashMap<Address, WordStats> {
+        &self.stats
+    }
+
+    pub fn reset(&mut self) {
+        self.stats.clear();
+    }
+
+    /// Note that execution starts from host at the next recorded instruction.
+    pub fn start_run(&mut self) {
+        self.entering = true;
+    }
+
+    /// Account for an instruction about to be executed at given address.
+    pub fn record(&mut self, memory: &MachineMemory, address: Address) {
+        let entering = self.entering;
+        let op_code = memory.raw_memory.read_u8(address);
+        self.entering = op_code == OpCode::Call.int_value() || op_code == OpCode::CallRel.int_value();
+
+        if let Some(header_address) = self.article_at(memory, address) {
+            let stats = self.stats.entry(header_address).or_default();
+
+            stats.instructions += 1;
+
+            if entering {
+                stats.entered += 1;
+            }
+        }
+    }
+
+    fn article_at(&mut self, memory: &MachineMemory, address: Address) -> Option<Address> {
+        let dict_ptr = memory.get_dict_ptr();
+        let version = (dict_ptr, memory.last_article_ptr);
+
+        if self.headers_version != Some(version) {
+            self.headers = memory.articles().map(|article| article.get_header_address()).collect();
+            self.headers.sort_unstable();
+            self.headers_version = Some(version);
+        }
+
+        if address >= dict_ptr {
+            return None;
+        }
+
+        match self.headers.partition_point(|&header| header <= address) {
+            0 => None,
+            i => Some(self.headers[i - 1]),
+        }
+    }
+}
+
+impl<TExt: MachineExtensions> Machine<TExt> {
+    /// Print statistics collected by profiler, most busy articles first.
+    pub fn profile_report(&self, writer: &mut impl io::Write) -> io::Result<()> {
+        let Some(profiler) = self.profiler() else {
+            return writeln!(writer, "Profiling is disabled");
+        };
+
+        let mut rows: Vec<_> = profiler.stats().iter().collect();
+        rows.sort_by(|(a_address, a), (b_address, b)| {
+            b.instructions.cmp(&a.instructions).then(a_address.cmp(b_address))
+        });
+
+        writeln!(writer, "{:>12} {:>12}  word", "instructions", "entered")?;
+
+        for (&header_address, stats) in rows {
+            write!(writer, "{:>12} {:>12}  ", stats.instructions, stats.entered)?;
+
+            match self.memory.article_containing(header_address) {
+                Some(article) => writeln!(writer, "{}", article.name())?,
+                None => writeln!(writer, "<{:04X}>", header_address)?,
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::str::from_utf8;
+
+    use crate::input::StaticStringInput;
+    use crate::machine_testing::*;
+
+    use super::*;
+
+    fn stats_of(machine: &TestMachine, name: &[u8]) -> WordStats {
+        let header_address = machine.memory.lookup_article(name).unwrap().unwrap().get_header_address();
+
+        machine.profiler().unwrap().stats()[&header_address]
+    }
+
+    #[test]
+    fn test_profile_recursion() {
+        let mut machine = TestMachine::default();
+        machine.enable_profiling();
+        machine.extensions.input = StaticStringInput::new("
+            : 1- 1 - ;
+            : FACTORIAL DUP 2 < IF DROP 1 EXIT THEN DUP 1- RECURSE * ;
+            8 FACTORIAL
+        ");
+        machine.interpret_input().unwrap();
+
+        machine.assert_data_stack_state(&[StackElement::Cell(40320)]);
+        assert_eq!(stats_of(&machine, b"FACTORIAL").entered, 8);
+        assert_eq!(stats_of(&machine, b"1-").entered, 7);
+        assert_eq!(stats_of(&machine, b"1-").instructions, 7 * 3);
+
+        let mut buf = Vec::new();
+        machine.profile_report(&mut buf).unwrap();
+        let report = from_utf8(&buf).unwrap();
+        let lines: Vec<_> = report.lines().collect();
+
+        assert_eq!(lines.len(), 3, "{}", report);
+        assert!(lines[1].ends_with("  FACTORIAL"), "{}", report);
+        assert!(lines[2].ends_with("  1-"), "{}", report);
+
+        machine.reset_profile();
+        assert!(machine.profiler().unwrap().stats().is_empty());
+    }
+
+    #[test]
+    fn test_profiling_disabled() {
+        let mut machine = TestMachine::default();
+        machine.extensions.input = StaticStringInput::new(": foo 1 ; foo");
+        machine.interpret_input().unwrap();
+
+        assert!(machine.profiler().is_none());
+
+        let mut buf = Vec::new();
+        machine.profile_report(&mut buf).unwrap();
+        assert_eq!(from_utf8(&buf).unwrap(), "Profiling is disabled\n");
+    }
+}
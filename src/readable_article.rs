@@ -1,4 +1,7 @@
+use int_enum::IntEnum;
+
 use crate::mem::{Address, AddressRange, Mem, MemoryAccessError};
+use crate::opcodes::OpCode;
 use crate::sized_string::ReadableSizedString;
 
 #[derive(Copy, Clone)]
@@ -57,9 +60,45 @@ impl<'m> ReadableArticle<'m> {
         self.name_address().wrapping_add(self.name().read_length() as u16).wrapping_add(1)
     }
 
+    fn start_op_code(&self) -> Option<OpCode> {
+        OpCode::from_int(self.memory.read_u8(self.body_address())).ok()
+    }
+
+    /// Check if the article is executed rather than compiled in compiler mode.
+    pub fn is_immediate(&self) -> bool {
+        matches!(
+            self.start_op_code(),
+            Some(OpCode::ImmediateArticleStart | OpCode::Noop | OpCode::ImmediateCompileOnlyArticleStart),
+        )
+    }
+
+    /// Check if the article fails to execute in interpreter mode.
+    pub fn is_compile_only(&self) -> bool {
+        matches!(
+            self.start_op_code(),
+            Some(OpCode::CompileOnlyArticleStart | OpCode::ImmediateCompileOnlyArticleStart),
+        )
+    }
+
+    /// Address compiled calls of this article go to, skipping `DefaultArticleStart` (or an op-code replacing it to
+    /// mark an immediate or compile-only article) if the body starts with it.
+    pub fn call_address(&self) -> Address {
+        let body_address = self.body_address();
+
+        match self.start_op_code() {
+            Some(
+                OpCode::DefaultArticleStart
+                | OpCode::ImmediateArticleStart
+                | OpCode::CompileOnlyArticleStart
+                | OpCode::ImmediateCompileOnlyArticleStart
+            ) => body_address.wrapping_add(1),
+            _ => body_address,
+        }
+    }
+
     /// Address of header of the previous article
     pub fn previous_address(&self) -> Address {
-        unsafe { self.memory.read_u16(self.header_address) }
+        self.memory.read_u16(self.header_address)
     }
 
     /// Previous article represented as a ReadableArticle.
@@ -0,0 +1,235 @@
+//! Recognizers handle words that are not names of words, such as number literals.
+//!
+//! A word that is neither a dictionary article, native word nor builtin word, and is not handled by the fallback
+//! handler either, is offered to recognizers of `Machine::recognizers` in order. The first recognizer accepting the
+//! word finds a value in it and a `Translation` telling what to do with the value. Recognizers of double-cell values,
+//! such as double-cell numbers, find them with `Recognizer::recognize_double` and a `DoubleTranslation` instead.
+
+use crate::builtin_words::{compile_cell_literal, compile_double_literal};
+use crate::cell::{Cell, DoubleCell};
+use crate::literal::{parse_double_literal, parse_literal};
+use crate::machine::{Machine, MachineExtensions};
+use crate::machine_error::MachineError;
+use crate::machine_memory::ReservedAddresses;
+use crate::machine_state::MachineState;
+use crate::mem::Address;
+use crate::sized_string::ReadableSizedString;
+
+/// Actions performed on a value found by a recognizer in interpreter and compiler states.
+pub struct Translation<TExt: MachineExtensions> {
+    pub interpret: fn(&mut Machine<TExt>, Cell) -> Result<(), MachineError>,
+    pub compile: fn(&mut Machine<TExt>, Cell) -> Result<(), MachineError>,
+}
+
+impl<TExt: MachineExtensions> Clone for Translation<TExt> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<TExt: MachineExtensions> Copy for Translation<TExt> {}
+
+impl<TExt: MachineExtensions> Translation<TExt> {
+    /// Push the value in interpreter state, compile it as a literal in compiler state.
+    pub const LITERAL: Self = Translation {
+        interpret: |machine, value| machine.push(value),
+        compile: compile_cell_literal,
+    };
+}
+
+/// Actions performed on a double-cell value found by a recognizer in interpreter and compiler states.
+pub struct DoubleTranslation<TExt: MachineExtensions> {
+    pub interpret: fn(&mut Machine<TExt>, DoubleCell) -> Result<(), MachineError>,
+    pub compile: fn(&mut Machine<TExt>, DoubleCell) -> Result<(), MachineError>,
+}
+
+impl<TExt: MachineExtensions> Clone for DoubleTranslation<TExt> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<TExt: MachineExtensions> Copy for DoubleTranslation<TExt> {}
+
+impl<TExt: MachineExtensions> DoubleTranslation<TExt> {
+    /// Push the value in interpreter state, compile it as a double-cell literal in compiler state.
+    pub const LITERAL: Self = DoubleTranslation {
+        interpret: |machine, value| machine.push(value),
+        compile: compile_double_literal,
+    };
+}
+
+pub trait Recognizer<TExt: MachineExtensions> {
+    /// Find a value and translation for given word, `None` if the word is not recognized.
+    fn recognize(&self, machine: &Machine<TExt>, word: &[u8]) -> Option<(Cell, Translation<TExt>)>;
+
+    /// Find a double-cell value and translation for given word, tried if `recognize` doesn't accept the word.
+    fn recognize_double(&self, _machine: &Machine<TExt>, _word: &[u8]) -> Option<(DoubleCell, DoubleTranslation<TExt>)> {
+        None
+    }
+}
+
+impl<TExt: MachineExtensions, F: Fn(&Machine<TExt>, &[u8]) -> Option<(Cell, Translation<TExt>)>> Recognizer<TExt> for F {
+    fn recognize(&self, machine: &Machine<TExt>, word: &[u8]) -> Option<(Cell, Translation<TExt>)> {
+        self(machine, word)
+    }
+}
+
+/// Recognizes single-cell number literals in radix set by `BASE` or given by a prefix, see `parse_literal`.
+pub struct NumberRecognizer;
+
+impl<TExt: MachineExtensions> Recognizer<TExt> for NumberRecognizer {
+    fn recognize(&self, machine: &Machine<TExt>, word: &[u8]) -> Option<(Cell, Translation<TExt>)> {
+        parse_literal(word, base(machine)).map(|value| (value, Translation::LITERAL))
+    }
+}
+
+/// Recognizes double-cell number literals, numbers followed by a dot, see `parse_double_literal`.
+pub struct DoubleNumberRecognizer;
+
+impl<TExt: MachineExtensions> Recognizer<TExt> for DoubleNumberRecognizer {
+    fn recognize(&self, _machine: &Machine<TExt>, _word: &[u8]) -> Option<(Cell, Translation<TExt>)> {
+        None
+    }
+
+    fn recognize_double(&self, machine: &Machine<TExt>, word: &[u8]) -> Option<(DoubleCell, DoubleTranslation<TExt>)> {
+        parse_double_literal(word, base(machine)).map(|value| (value, DoubleTranslation::LITERAL))
+    }
+}
+
+fn base<TExt: MachineExtensions>(machine: &Machine<TExt>) -> u32 {
+    let base_address = machine.memory.get_reserved_address(ReservedAddresses::BaseVar);
+    let base: Cell = machine.memory.raw_memory.read_cell(base_address);
+
+    base as u32
+}
+
+/// Recognizers a machine starts with.
+pub fn default_recognizers<TExt: MachineExtensions>() -> Vec<Box<dyn Recognizer<TExt>>> {
+    vec![Box::new(NumberRecognizer), Box::new(DoubleNumberRecognizer)]
+}
+
+/// Value and translation found by a recognizer.
+enum Recognized<TExt: MachineExtensions> {
+    Single((Cell, Translation<TExt>)),
+    Double((DoubleCell, DoubleTranslation<TExt>)),
+}
+
+impl<TExt: MachineExtensions> Machine<TExt> {
+    /// Offer word with name at given address to recognizers and perform the action of the first one accepting it.
+    ///
+    /// Fails with `MachineError::IllegalWord` if no recognizer accepts the word.
+    pub fn process_recognized_word(&mut self, name_address: Address) -> Result<(), MachineError> {
+        let mut name_buffer = [0u8; u8::MAX as usize];
+        let word = ReadableSizedString::new(&self.memory.raw_memory, name_address, self.memory.raw_memory.address_range())?
+            .copy_to(&mut name_buffer);
+
+        let recognized = self.recognizers.iter()
+            .find_map(|recognizer| match recognizer.recognize(self, word) {
+                Some(single) => Some(Recognized::Single(single)),
+                None => recognizer.recognize_double(self, word).map(Recognized::Double),
+            })
+            .ok_or(MachineError::IllegalWord(Some(name_address)))?;
+
+        match (recognized, self.memory.get_state()) {
+            (Recognized::Single((value, translation)), MachineState::Interpreter) => (translation.interpret)(self, value),
+            (Recognized::Single((value, translation)), MachineState::Compiler) => (translation.compile)(self, value),
+            (Recognized::Double((value, translation)), MachineState::Interpreter) => (translation.interpret)(self, value),
+            (Recognized::Double((value, translation)), MachineState::Compiler) => (translation.compile)(self, value),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+
+    use int_enum::IntEnum;
+
+    use crate::input::StaticStringInput;
+    use crate::machine_testing::*;
+    use crate::opcodes::OpCode;
+
+    use super::*;
+
+    /// Recognizes `@name` words as symbols with ids assigned by host.
+    fn symbol_recognizer(symbols: HashMap<&'static [u8], Cell>) -> Box<dyn Recognizer<TestMachineExtensions>> {
+        Box::new(move |_: &TestMachine, word: &[u8]| {
+            let id = *symbols.get(word.strip_prefix(b"@")?)?;
+
+            Some((id, Translation::LITERAL))
+        })
+    }
+
+    #[test]
+    fn test_custom_recognizer() {
+        let mut machine = TestMachine::default();
+        machine.recognizers.insert(0, symbol_recognizer(HashMap::from([(&b"red"[..], 7), (&b"blue"[..], 9)])));
+
+        machine.interpret_str("
+            @red
+            : blue @blue ;
+            blue
+            16 $10 +
+        ").unwrap();
+        machine.assert_data_stack_state(&[StackElement::Cell(7), StackElement::Cell(9), StackElement::Cell(32)]);
+
+        let blue = machine.memory.lookup_article(b"blue").unwrap().unwrap().body_address();
+        assert_eq!(machine.memory.raw_memory.read_u8(blue + 1), OpCode::Literal8.int_value());
+
+        machine.extensions.input = StaticStringInput::new("@green");
+        assert!(matches!(machine.interpret_input(), Err(MachineError::IllegalWord(Some(_)))));
+    }
+
+    /// Recognizes `N:digits` words as decimal double-cell numbers.
+    struct DecimalDoubleRecognizer;
+
+    impl Recognizer<TestMachineExtensions> for DecimalDoubleRecognizer {
+        fn recognize(&self, _machine: &TestMachine, _word: &[u8]) -> Option<(Cell, Translation<TestMachineExtensions>)> {
+            None
+        }
+
+        fn recognize_double(&self, _machine: &TestMachine, word: &[u8]) -> Option<(DoubleCell, DoubleTranslation<TestMachineExtensions>)> {
+            let value = std::str::from_utf8(word.strip_prefix(b"N:")?).ok()?.parse().ok()?;
+
+            Some((value, DoubleTranslation::LITERAL))
+        }
+    }
+
+    #[test]
+    fn test_double_recognizer() {
+        let mut machine = TestMachine::default();
+        machine.recognizers.insert(0, Box::new(DecimalDoubleRecognizer));
+
+        machine.interpret_str(": big N:100000 ; N:7 big 5 5.").unwrap();
+        machine.assert_data_stack_state(&[
+            StackElement::DoubleCell(7),
+            StackElement::DoubleCell(100000),
+            StackElement::Cell(5),
+            StackElement::DoubleCell(5),
+        ]);
+    }
+
+    #[test]
+    fn test_translation() {
+        let mut machine = TestMachine::default();
+        machine.recognizers.push(Box::new(|_: &TestMachine, word: &[u8]| {
+            let translation = Translation {
+                interpret: |machine: &mut TestMachine, value| machine.emit_str(&value.to_string()),
+                compile: |_: &mut TestMachine, _| Err(MachineError::IllegalCompilerState),
+            };
+
+            (word == b"answer!").then_some((42, translation))
+        }));
+
+        machine.interpret_str("answer!").unwrap();
+        assert_eq!(machine.extensions.output.content.take(), b"42");
+
+        machine.extensions.input = StaticStringInput::new(": x answer! ;");
+        assert!(matches!(machine.interpret_input(), Err(MachineError::IllegalCompilerState)));
+
+        machine.recognizers.clear();
+        machine.extensions.input = StaticStringInput::new("1");
+        assert!(matches!(machine.interpret_input(), Err(MachineError::IllegalWord(_))));
+    }
+}
@@ -1,6 +1,8 @@
+use std::borrow::Cow;
 use std::fmt::{Display, Formatter};
 use std::str::from_utf8;
-use crate::mem::{Address, AddressRange, Mem, MemoryAccessError};
+use crate::mem::{AccessKind, Address, AddressRange, Mem, MemoryAccessError};
+use crate::memory_segment::WHOLE_MEMORY;
 
 pub struct ReadableSizedString<'m> {
     memory: &'m Mem,
@@ -55,7 +57,18 @@ impl<'m> ReadableSizedString<'m> {
         ReadableSizedString { memory, address }
     }
 
-    pub fn as_bytes(&self) -> &'m [u8] {
+    /// Copy content of the string to given buffer.
+    ///
+    /// Unlike `as_bytes`, the result does not borrow the memory, so the memory may be modified while it is in use.
+    pub fn copy_to<'b>(&self, buffer: &'b mut [u8; u8::MAX as usize]) -> &'b [u8] {
+        let content = &mut buffer[..self.read_length() as usize];
+
+        content.copy_from_slice(&self.as_bytes());
+
+        content
+    }
+
+    pub fn as_bytes(&self) -> Cow<'m, [u8]> {
         let length = self.read_length() as usize;
 
         return self.memory.slice((self.address as usize + 1)..(self.address as usize + 1 + length));
@@ -64,7 +77,7 @@ impl<'m> ReadableSizedString<'m> {
 
 impl<'m> Display for ReadableSizedString<'m> {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        match from_utf8(self.as_bytes()) {
+        match from_utf8(&self.as_bytes()) {
             Ok(s) => write!(f, "{}", s),
             Err(_) => write!(f, "UNPRINTABLE STRING({:?})", self.as_bytes())
         }
@@ -103,6 +116,8 @@ impl<'m> SizedStringWriter<'m> {
             return Err(MemoryAccessError {
                 access_range: self.address..=(self.address.wrapping_add(self.len as u16).wrapping_add(1)),
                 segment: self.writeable_range(),
+                segment_name: WHOLE_MEMORY,
+                kind: AccessKind::Write,
             });
         }
 
@@ -118,7 +133,7 @@ impl<'m> SizedStringWriter<'m> {
             self.writeable_range(),
         )?;
 
-        self.memory.address_slice_mut(self.address + 1 + self.len as u16, value.len()).copy_from_slice(value);
+        self.memory.write_slice(self.address + 1 + self.len as u16, value);
 
         self.len += value.len() as u8;
 
@@ -190,7 +205,7 @@ mod test {
         writer.append_u8(b'R').unwrap();
 
         assert_eq!(
-            writer.finish().as_bytes(),
+            writer.finish().as_bytes().as_ref(),
             b"FO0BAR"
         )
     }
@@ -232,7 +247,7 @@ mod test {
         writer.append_slice(b"World!").unwrap();
 
         assert_eq!(
-            writer.finish().as_bytes(),
+            writer.finish().as_bytes().as_ref(),
             b"Hello World!"
         )
     }
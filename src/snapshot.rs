@@ -0,0 +1,521 @@
+use std::fmt::{Display, Formatter};
+use std::io;
+
+use crate::cell::CELL_BYTES;
+use crate::file_system::FileAccessMode;
+use crate::machine::{Machine, MachineExtensions};
+use crate::machine_error::MachineError;
+use crate::machine_memory::{MachineMemory, MemoryLayoutConfig};
+use crate::mem::{Address, AddressRange, Mem, MEM_SIZE};
+
+/// First bytes of every snapshot.
+pub const SNAPSHOT_MAGIC: [u8; 4] = *b"RS4S";
+
+/// Version of snapshot format written by `Machine::snapshot`.
+pub const SNAPSHOT_VERSION: u16 = 6;
+
+/// Snapshot header consists of:
+///
+/// - magic (4 bytes)
+/// - format version (u16)
+/// - memory size (u32)
+/// - cell size in bytes (u8)
+/// - memory layout config:
+///   - maximal call stack depth (u16)
+///   - maximal data stack depth presence flag (u8) followed by the depth (u16)
+///   - maximal word length (u8)
+///   - PAD size (u16)
+///   - maximal pictured numeric output length (u8)
+///   - guard region size (u16)
+///   - heap size (u16)
+/// - last article pointer presence flag (u8) followed by the pointer (u16)
+/// - data stack pointer (u16)
+/// - call stack pointer (u16)
+///
+/// Header is followed by dictionary annotations:
+///
+/// - number of data ranges (u16) followed by first and last address of each range (u16 each)
+/// - number of relocations (u16) followed by addresses of relocated operands (u16 each)
+///
+/// and then by raw memory content. All values are little-endian.
+const SNAPSHOT_HEADER_SIZE: usize = 4 + 2 + 4 + 1 + (2 + 1 + 2 + 1 + 2 + 1 + 2 + 2) + 1 + 2 + 2 + 2;
+
+#[derive(Debug)]
+pub enum SnapshotError {
+    IOError(io::Error),
+    BadMagic,
+    UnsupportedVersion(u16),
+    MemorySizeMismatch {
+        expected: u32,
+        actual: u32,
+    },
+    /// Snapshot was made by a machine with different cell width.
+    CellSizeMismatch {
+        expected: u8,
+        actual: u8,
+    },
+    /// Layout or register values stored in snapshot are inconsistent.
+    InvalidRegisters,
+}
+
+impl From<io::Error> for SnapshotError {
+    fn from(err: io::Error) -> Self {
+        SnapshotError::IOError(err)
+    }
+}
+
+impl Display for SnapshotError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SnapshotError::IOError(err) => write!(f, "IO error: {}", err),
+            SnapshotError::BadMagic => write!(f, "not a machine snapshot"),
+            SnapshotError::UnsupportedVersion(version) => write!(f, "unsupported snapshot version {}", version),
+            SnapshotError::MemorySizeMismatch { expected, actual } => write!(
+                f, "snapshot memory size is {} bytes, expected {} bytes", actual, expected,
+            ),
+            SnapshotError::CellSizeMismatch { expected, actual } => write!(
+                f, "snapshot cells are {} bytes wide, expected {} bytes", actual, expected,
+            ),
+            SnapshotError::InvalidRegisters => write!(f, "snapshot contains invalid register values"),
+        }
+    }
+}
+
+struct HeaderReader<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> HeaderReader<'a> {
+    fn take<const N: usize>(&mut self) -> [u8; N] {
+        let (head, rest) = self.bytes.split_at(N);
+        self.bytes = rest;
+
+        head.try_into().unwrap()
+    }
+
+    fn u8(&mut self) -> u8 {
+        self.take::<1>()[0]
+    }
+
+    fn u16(&mut self) -> u16 {
+        u16::from_le_bytes(self.take())
+    }
+
+    fn u32(&mut self) -> u32 {
+        u32::from_le_bytes(self.take())
+    }
+}
+
+fn read_u16(src: &mut impl io::Read) -> io::Result<u16> {
+    let mut bytes = [0u8; 2];
+    src.read_exact(&mut bytes)?;
+
+    Ok(u16::from_le_bytes(bytes))
+}
+
+/// Data ranges and relocations of dictionary, they are not stored in memory itself.
+struct DictionaryAnnotations {
+    data_ranges: Vec<AddressRange>,
+    relocations: Vec<Address>,
+}
+
+impl DictionaryAnnotations {
+    fn write(memory: &MachineMemory, dst: &mut impl io::Write) -> io::Result<()> {
+        let mut bytes = Vec::new();
+
+        bytes.extend_from_slice(&(memory.data_ranges().len() as u16).to_le_bytes());
+        for range in memory.data_ranges() {
+            bytes.extend_from_slice(&range.start().to_le_bytes());
+            bytes.extend_from_slice(&range.end().to_le_bytes());
+        }
+
+        bytes.extend_from_slice(&(memory.relocations().len() as u16).to_le_bytes());
+        for address in memory.relocations() {
+            bytes.extend_from_slice(&address.to_le_bytes());
+        }
+
+        dst.write_all(&bytes)
+    }
+
+    fn read(src: &mut impl io::Read) -> Result<Self, SnapshotError> {
+        let data_ranges_count = read_u16(src)?;
+        let mut data_ranges = Vec::with_capacity(data_ranges_count as usize);
+        for _ in 0..data_ranges_count {
+            let start = read_u16(src)?;
+            let end = read_u16(src)?;
+
+            if start > end {
+                return Err(SnapshotError::InvalidRegisters);
+            }
+
+            data_ranges.push(start..=end);
+        }
+
+        let relocations_count = read_u16(src)?;
+        let mut relocations = Vec::with_capacity(relocations_count as usize);
+        for _ in 0..relocations_count {
+            relocations.push(read_u16(src)?);
+        }
+
+        Ok(DictionaryAnnotations { data_ranges, relocations })
+    }
+}
+
+fn restore_memory(
+    header: &[u8; SNAPSHOT_HEADER_SIZE],
+    raw_memory: Mem,
+    annotations: DictionaryAnnotations,
+) -> Result<MachineMemory, SnapshotError> {
+    let mut reader = HeaderReader { bytes: header };
+
+    if reader.take::<4>() != SNAPSHOT_MAGIC {
+        return Err(SnapshotError::BadMagic);
+    }
+
+    let version = reader.u16();
+    if version != SNAPSHOT_VERSION {
+        return Err(SnapshotError::UnsupportedVersion(version));
+    }
+
+    let memory_size = reader.u32();
+    if memory_size != MEM_SIZE as u32 {
+        return Err(SnapshotError::MemorySizeMismatch { expected: MEM_SIZE as u32, actual: memory_size });
+    }
+
+    let cell_size = reader.u8();
+    if cell_size != CELL_BYTES as u8 {
+        return Err(SnapshotError::CellSizeMismatch { expected: CELL_BYTES as u8, actual: cell_size });
+    }
+
+    let max_call_stack_depth = reader.u16();
+    let has_max_data_stack_depth = reader.u8() != 0;
+    let max_data_stack_depth = reader.u16();
+    let config = MemoryLayoutConfig {
+        max_call_stack_depth,
+        max_data_stack_depth: if has_max_data_stack_depth { Some(max_data_stack_depth) } else { None },
+        max_word_length: reader.u8(),
+        pad_size: reader.u16(),
+        max_pno_length: reader.u8(),
+        guard_size: reader.u16(),
+        heap_size: reader.u16(),
+    };
+    let has_last_article = reader.u8() != 0;
+    let last_article_ptr = reader.u16();
+    let data_stack_ptr = reader.u16();
+    let call_stack_ptr = reader.u16();
+
+    if !config.fits_memory_size(memory_size) {
+        return Err(SnapshotError::InvalidRegisters);
+    }
+
+    let mut memory = MachineMemory::attach_memory(
+        raw_memory,
+        config,
+        if has_last_article { Some(last_article_ptr) } else { None },
+    );
+
+    let data_stack_segment = memory.get_data_stack_segment();
+    let call_stack_segment = memory.get_call_stack_segment();
+
+    if data_stack_ptr < *data_stack_segment.start() || data_stack_ptr > data_stack_segment.end().wrapping_add(1)
+        || call_stack_ptr < *call_stack_segment.start() || call_stack_ptr > call_stack_segment.end().wrapping_add(1) {
+        return Err(SnapshotError::InvalidRegisters);
+    }
+
+    memory.data_stack_ptr = data_stack_ptr;
+    memory.call_stack_ptr = call_stack_ptr;
+
+    for range in annotations.data_ranges {
+        memory.mark_data_space(range);
+    }
+
+    for address in annotations.relocations {
+        memory.mark_relocation(address);
+    }
+
+    Ok(memory)
+}
+
+impl<TExt: MachineExtensions> Machine<TExt> {
+    /// Write complete state of machine memory (including stacks and layout) to given writer.
+    pub fn snapshot(&self, dst: &mut impl io::Write) -> io::Result<()> {
+        let mut header = Vec::with_capacity(SNAPSHOT_HEADER_SIZE);
+
+        header.extend_from_slice(&SNAPSHOT_MAGIC);
+        header.extend_from_slice(&SNAPSHOT_VERSION.to_le_bytes());
+        header.extend_from_slice(&(MEM_SIZE as u32).to_le_bytes());
+        header.push(CELL_BYTES as u8);
+        let config = self.memory.layout_config();
+        header.extend_from_slice(&config.max_call_stack_depth.to_le_bytes());
+        header.push(config.max_data_stack_depth.is_some() as u8);
+        header.extend_from_slice(&config.max_data_stack_depth.unwrap_or(0).to_le_bytes());
+        header.push(config.max_word_length);
+        header.extend_from_slice(&config.pad_size.to_le_bytes());
+        header.push(config.max_pno_length);
+        header.extend_from_slice(&config.guard_size.to_le_bytes());
+        header.extend_from_slice(&config.heap_size.to_le_bytes());
+        header.push(self.memory.last_article_ptr.is_some() as u8);
+        header.extend_from_slice(&self.memory.last_article_ptr.unwrap_or(0).to_le_bytes());
+        header.extend_from_slice(&self.memory.data_stack_ptr.to_le_bytes());
+        header.extend_from_slice(&self.memory.call_stack_ptr.to_le_bytes());
+
+        dst.write_all(&header)?;
+        DictionaryAnnotations::write(&self.memory, dst)?;
+        self.memory.raw_memory.dump_to(dst)
+    }
+
+    /// Replace machine memory by one stored by `snapshot`.
+    ///
+    /// Machine is not modified if the snapshot is invalid.
+    pub fn restore(&mut self, src: &mut impl io::Read) -> Result<(), SnapshotError> {
+        let mut header = [0u8; SNAPSHOT_HEADER_SIZE];
+        src.read_exact(&mut header)?;
+
+        if header[0..4] != SNAPSHOT_MAGIC {
+            return Err(SnapshotError::BadMagic);
+        }
+
+        // Snapshots of other versions may have different layout after the header
+        let version = u16::from_le_bytes([header[4], header[5]]);
+        if version != SNAPSHOT_VERSION {
+            return Err(SnapshotError::UnsupportedVersion(version));
+        }
+
+        let annotations = DictionaryAnnotations::read(src)?;
+        let mut raw_memory = Mem::default();
+        raw_memory.load_from(src)?;
+
+        self.memory = restore_memory(&header, raw_memory, annotations)?;
+
+        Ok(())
+    }
+
+    /// Save machine snapshot to a file in machine's file system.
+    pub fn save_image(&mut self, path: &str) -> Result<(), MachineError> {
+        let image_error = |err: io::Error| MachineError::ImageError { path: path.to_string(), err: err.into() };
+
+        let mut file = self.extensions.get_file_system()
+            .ok_or(MachineError::FileSystemUnavailable)?
+            .create_file(path, FileAccessMode::WriteOnly)
+            .map_err(image_error)?;
+
+        self.snapshot(&mut file).map_err(image_error)
+    }
+
+    /// Restore machine snapshot from a file in machine's file system.
+    ///
+    /// Call stack is cleared as return addresses saved in the image are meaningless for the code currently running.
+    pub fn load_image(&mut self, path: &str) -> Result<(), MachineError> {
+        let mut file = self.extensions.get_file_system()
+            .ok_or(MachineError::FileSystemUnavailable)?
+            .open_file(path, FileAccessMode::ReadOnly)
+            .map_err(|err| MachineError::ImageError { path: path.to_string(), err: err.into() })?;
+
+        self.restore(&mut file).map_err(|err| MachineError::ImageError { path: path.to_string(), err })?;
+        self.memory.call_stack_ptr = *self.memory.get_call_stack_segment().end() + 1;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::input::StaticStringInput;
+    use crate::machine_testing::*;
+
+    use super::*;
+
+    fn make_snapshot() -> Vec<u8> {
+        let mut machine = TestMachine::default();
+        machine.extensions.input = StaticStringInput::new(": sq DUP * ; 7 5");
+        machine.interpret_input().unwrap();
+        machine.memory.call_push_cell(0x1234).unwrap();
+
+        let mut snapshot = Vec::new();
+        machine.snapshot(&mut snapshot).unwrap();
+
+        snapshot
+    }
+
+    #[test]
+    fn test_snapshot_restore() {
+        let snapshot = make_snapshot();
+
+        let mut machine = TestMachine::default();
+        machine.restore(&mut snapshot.as_slice()).unwrap();
+
+        assert_eq!(machine.memory.call_pop_cell().unwrap(), 0x1234);
+
+        machine.extensions.input = StaticStringInput::new("sq");
+        machine.interpret_input().unwrap();
+        machine.assert_data_stack_state(&[StackElement::Cell(7), StackElement::Cell(25)]);
+    }
+
+    #[test]
+    fn test_snapshot_preserves_dictionary_annotations() {
+        let mut machine = TestMachine::default();
+        machine.interpret_str("VARIABLE x : get-x x @ ;").unwrap();
+
+        let mut snapshot = Vec::new();
+        machine.snapshot(&mut snapshot).unwrap();
+
+        let mut restored = TestMachine::default();
+        restored.restore(&mut snapshot.as_slice()).unwrap();
+        assert_eq!(restored.memory.data_ranges(), machine.memory.data_ranges());
+        assert_eq!(restored.memory.relocations(), machine.memory.relocations());
+
+        restored.memory.write_protection = true;
+        restored.interpret_str("42 x ! get-x").unwrap();
+        restored.assert_data_stack_state(&[StackElement::Cell(42)]);
+    }
+
+    #[test]
+    fn test_snapshot_preserves_layout() {
+        let config = MemoryLayoutConfig {
+            max_call_stack_depth: 16,
+            max_data_stack_depth: Some(32),
+            max_word_length: 31,
+            pad_size: 64,
+            max_pno_length: 40,
+            guard_size: 4,
+            heap_size: 64,
+        };
+        let machine = TestMachine::with_memory(
+            TestMachineExtensions::default(),
+            MachineMemory::new(Mem::default(), config),
+        );
+
+        let mut snapshot = Vec::new();
+        machine.snapshot(&mut snapshot).unwrap();
+
+        let mut restored = TestMachine::default();
+        restored.restore(&mut snapshot.as_slice()).unwrap();
+        assert_eq!(restored.memory.layout_config(), config);
+        assert_eq!(restored.memory.get_pad_address(), machine.memory.get_pad_address());
+    }
+
+    #[test]
+    fn test_restore_bad_magic() {
+        let mut snapshot = make_snapshot();
+        snapshot[0] = b'X';
+
+        let mut machine = TestMachine::default();
+        assert!(matches!(machine.restore(&mut snapshot.as_slice()), Err(SnapshotError::BadMagic)));
+    }
+
+    #[test]
+    fn test_restore_bad_version() {
+        let mut snapshot = make_snapshot();
+        snapshot[4] = 99;
+
+        let mut machine = TestMachine::default();
+        assert!(matches!(machine.restore(&mut snapshot.as_slice()), Err(SnapshotError::UnsupportedVersion(99))));
+    }
+
+    #[test]
+    fn test_restore_bad_memory_size() {
+        let mut snapshot = make_snapshot();
+        snapshot[6..10].copy_from_slice(&1024u32.to_le_bytes());
+
+        let mut machine = TestMachine::default();
+        assert!(matches!(
+            machine.restore(&mut snapshot.as_slice()),
+            Err(SnapshotError::MemorySizeMismatch { actual: 1024, .. })
+        ));
+    }
+
+    #[test]
+    fn test_restore_bad_cell_size() {
+        let mut snapshot = make_snapshot();
+        snapshot[10] = 8;
+
+        let mut machine = TestMachine::default();
+        assert!(matches!(
+            machine.restore(&mut snapshot.as_slice()),
+            Err(SnapshotError::CellSizeMismatch { actual: 8, .. })
+        ));
+    }
+
+    #[test]
+    fn test_restore_truncated() {
+        let snapshot = make_snapshot();
+
+        let mut machine = TestMachine::default();
+        assert!(matches!(
+            machine.restore(&mut &snapshot[..snapshot.len() - 1]),
+            Err(SnapshotError::IOError(_))
+        ));
+    }
+
+    #[cfg(feature = "std-fs")]
+    fn make_fs_machine() -> TestMachine {
+        let mut machine = TestMachine::default();
+        machine.extensions.file_system = Some(Box::new(crate::file_system::StdFileSystem::default()));
+
+        machine
+    }
+
+    #[cfg(feature = "std-fs")]
+    fn temp_image_path(name: &str) -> String {
+        std::env::temp_dir()
+            .join(format!("rs4-{}-{}.img", std::process::id(), name))
+            .to_string_lossy()
+            .into_owned()
+    }
+
+    #[cfg(feature = "std-fs")]
+    #[test]
+    fn test_save_load_image_words() {
+        let path = temp_image_path("save-load");
+        let mut machine = make_fs_machine();
+
+        let definitions = format!(": img S\" {}\" ; : sq DUP * ; img SAVE-IMAGE", path);
+        machine.interpret_str(&definitions).unwrap();
+
+        machine.extensions.input = StaticStringInput::new(": sq 0 ; 1 2 3");
+        machine.interpret_input().unwrap();
+
+        machine.extensions.input = StaticStringInput::new("img LOAD-IMAGE 5 sq");
+        machine.interpret_input().unwrap();
+        machine.assert_data_stack_state(&[StackElement::Cell(25)]);
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[cfg(feature = "std-fs")]
+    #[test]
+    fn test_load_image_from_compiled_word() {
+        let path = temp_image_path("compiled-load");
+        let mut machine = make_fs_machine();
+
+        let definitions = format!(": img S\" {}\" ; 42 img SAVE-IMAGE : restore img LOAD-IMAGE 13 ;", path);
+        machine.interpret_str(&definitions).unwrap();
+
+        machine.extensions.input = StaticStringInput::new("DROP restore");
+        machine.interpret_input().unwrap();
+        assert_eq!(machine.memory.call_stack_depth(), 0);
+        assert!(machine.memory.lookup_article(b"restore").unwrap().is_none());
+        machine.assert_data_stack_state(&[StackElement::Cell(42)]);
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[cfg(feature = "std-fs")]
+    #[test]
+    fn test_load_missing_image() {
+        let path = temp_image_path("missing");
+        let mut machine = make_fs_machine();
+
+        assert!(matches!(
+            machine.load_image(&path),
+            Err(MachineError::ImageError { path: err_path, err: SnapshotError::IOError(_) }) if err_path == path
+        ));
+    }
+
+    #[test]
+    fn test_image_without_file_system() {
+        let mut machine = TestMachine::default();
+
+        assert!(matches!(machine.save_image("x.img"), Err(MachineError::FileSystemUnavailable)));
+    }
+}
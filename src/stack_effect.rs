@@ -1,20 +1,28 @@
-use crate::mem::{Address, AddressRange, Mem, MemoryAccessError};
+use crate::cell::{Cell, CELL_BYTES, DoubleCell, FALSE, SignedCell, SignedDoubleCell, TRUE};
+use crate::mem::{AccessKind, Address, AddressRange, Mem, MemoryAccessError};
+use crate::machine_error::{MachineError, StackOperation};
+use crate::memory_segment::{CALL_STACK, DATA_STACK};
 
 pub trait StackEffect {
-    /// Size of data popped from stack, in 16-bit words
+    /// Size of data popped from stack, in cells
     fn in_words(&self) -> u16;
 
-    /// Size of data pushed to stack, in 16-bit words
+    /// Size of data pushed to stack, in cells
     fn out_words(&self) -> u16;
 
+    /// Size of data read but left on stack below the popped data, in cells
+    fn peek_words(&self) -> u16 {
+        0
+    }
+
     /// Address of the highest byte touched by this stack effect with given stack pointer
     fn max_ptr(&self, base: Address) -> Address {
-        base.wrapping_add(self.in_words().wrapping_mul(2).wrapping_sub(1))
+        base.wrapping_add((self.in_words() + self.peek_words()).wrapping_mul(CELL_BYTES).wrapping_sub(1))
     }
 
     /// Value of stack pointer after this stack effect is applied
     fn resulting_ptr(&self, base: Address) -> Address {
-        self.max_ptr(base).wrapping_sub(self.out_words().wrapping_mul(2)).wrapping_add(1)
+        base.wrapping_add(self.in_words().wrapping_mul(CELL_BYTES)).wrapping_sub(self.out_words().wrapping_mul(CELL_BYTES))
     }
 
     /// Address of the lowest byte touched by this stack effect with given stack pointer
@@ -26,94 +34,213 @@ pub trait StackEffect {
         }
     }
 
-    fn validate_access(&self, mem: &Mem, ptr: Address, segment: AddressRange) -> Result<(), MemoryAccessError> {
-        mem.validate_access(
+    fn validate_access(
+        &self,
+        mem: &Mem,
+        ptr: Address,
+        segment: AddressRange,
+        segment_name: &'static str,
+    ) -> Result<(), MemoryAccessError> {
+        let read_words = self.in_words() + self.peek_words();
+
+        if read_words == 0 && self.out_words() == 0 {
+            // Nothing is touched, the stack may even be full
+            return Ok(());
+        }
+
+        // Inputs that reach past the end of the segment mean underflow, anything else is an overflow.
+        let kind = if read_words > 0 && (self.max_ptr(ptr) > *segment.end() || self.max_ptr(ptr) < ptr) {
+            AccessKind::Pop
+        } else {
+            AccessKind::Push
+        };
+
+        mem.validate_named_access(
             self.min_ptr(ptr)..=self.max_ptr(ptr),
             segment,
+            segment_name,
+            kind,
         )
     }
+
+    /// Validate this effect against data stack, reporting failures as stack underflow or overflow.
+    fn validate_stack(&self, mem: &Mem, ptr: Address, segment: AddressRange) -> Result<(), MachineError> {
+        let operation = StackOperation::default();
+
+        self.validate_access(mem, ptr, segment, DATA_STACK).map_err(|err| match err.kind {
+            AccessKind::Pop => MachineError::DataStackUnderflow {
+                requested: self.in_words() + self.peek_words(),
+                operation,
+            },
+            _ => MachineError::DataStackOverflow {
+                requested: self.out_words().saturating_sub(self.in_words()),
+                operation,
+            },
+        })
+    }
+
+    /// Validate this effect against call stack, reporting failures as stack underflow or overflow.
+    fn validate_call_stack(&self, mem: &Mem, ptr: Address, segment: AddressRange) -> Result<(), MachineError> {
+        let operation = StackOperation::default();
+
+        self.validate_access(mem, ptr, segment, CALL_STACK).map_err(|err| match err.kind {
+            AccessKind::Pop => MachineError::CallStackUnderflow {
+                requested: self.in_words() + self.peek_words(),
+                operation,
+            },
+            _ => MachineError::CallStackOverflow {
+                requested: self.out_words().saturating_sub(self.in_words()),
+                operation,
+            },
+        })
+    }
+}
+
+/// Effect on one of the stacks, part of an effect touching both of them.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct StackShape {
+    pub in_words: u16,
+    pub out_words: u16,
+}
+
+impl StackEffect for StackShape {
+    fn in_words(&self) -> u16 {
+        self.in_words
+    }
+
+    fn out_words(&self) -> u16 {
+        self.out_words
+    }
 }
 
+/// Type that may be read from and written to stack by `stack_effect!`.
+///
+/// Values narrower than a cell occupy a whole cell: they are zero- (unsigned types) or sign-extended (signed types)
+/// when written and truncated to the low bits of the cell when read. `u32`/`i32` are double cells with 16-bit cells and
+/// single cells with `cell32` feature.
 pub trait Stackable {
     const SIZE_WORDS: u16;
 
-    unsafe fn read(memory: &Mem, address: Address) -> Self;
+    fn read(memory: &Mem, address: Address) -> Self;
+
+    fn write(&self, memory: &mut Mem, address: Address);
+}
+
+impl Stackable for Cell {
+    const SIZE_WORDS: u16 = 1;
 
-    unsafe fn write(&self, memory: &mut Mem, address: Address);
+    fn read(memory: &Mem, address: Address) -> Self {
+        memory.read_cell(address)
+    }
+
+    fn write(&self, memory: &mut Mem, address: Address) {
+        memory.write_cell(address, *self)
+    }
 }
 
+impl Stackable for SignedCell {
+    const SIZE_WORDS: u16 = 1;
+
+    fn read(memory: &Mem, address: Address) -> Self {
+        memory.read_cell(address) as SignedCell
+    }
+
+    fn write(&self, memory: &mut Mem, address: Address) {
+        memory.write_cell(address, (*self) as Cell)
+    }
+}
+
+/// Addresses take a whole cell, zero-extended, when cells are wider than addresses.
+#[cfg(feature = "cell32")]
 impl Stackable for u16 {
     const SIZE_WORDS: u16 = 1;
 
-    unsafe fn read(memory: &Mem, address: Address) -> Self {
-        memory.read_u16(address)
+    fn read(memory: &Mem, address: Address) -> Self {
+        memory.read_cell(address) as u16
     }
 
-    unsafe fn write(&self, memory: &mut Mem, address: Address) {
-        memory.write_u16(address, *self)
+    fn write(&self, memory: &mut Mem, address: Address) {
+        memory.write_cell(address, (*self) as Cell)
     }
 }
 
+/// Sign-extended into a whole cell when cells are wider than 16 bits.
+#[cfg(feature = "cell32")]
 impl Stackable for i16 {
     const SIZE_WORDS: u16 = 1;
 
-    unsafe fn read(memory: &Mem, address: Address) -> Self {
-        memory.read_u16(address) as i16
+    fn read(memory: &Mem, address: Address) -> Self {
+        memory.read_cell(address) as i16
     }
 
-    unsafe fn write(&self, memory: &mut Mem, address: Address) {
-        memory.write_u16(address, (*self) as u16)
+    fn write(&self, memory: &mut Mem, address: Address) {
+        memory.write_cell(address, (*self) as SignedCell as Cell)
     }
 }
 
+/// Any non-zero cell is true, written as canonical `TRUE` (all bits set) or `FALSE`.
 impl Stackable for bool {
     const SIZE_WORDS: u16 = 1;
 
-    unsafe fn read(memory: &Mem, address: Address) -> Self {
-        memory.read_u16(address) != 0
+    fn read(memory: &Mem, address: Address) -> Self {
+        memory.read_cell(address) != FALSE
     }
 
-    unsafe fn write(&self, memory: &mut Mem, address: Address) {
-        memory.write_u16(
+    fn write(&self, memory: &mut Mem, address: Address) {
+        memory.write_cell(
             address,
-            if *self { 0xffff } else { 0 },
+            if *self { TRUE } else { FALSE },
         )
     }
 }
 
+/// Zero-extended into a whole cell, only the low byte is read.
 impl Stackable for u8 {
     const SIZE_WORDS: u16 = 1;
 
-    unsafe fn read(memory: &Mem, address: Address) -> Self {
-        memory.read_u16(address) as u8
+    fn read(memory: &Mem, address: Address) -> Self {
+        memory.read_cell(address) as u8
     }
 
-    unsafe fn write(&self, memory: &mut Mem, address: Address) {
-        memory.write_u16(address, (*self) as u16);
+    fn write(&self, memory: &mut Mem, address: Address) {
+        memory.write_cell(address, (*self) as Cell);
     }
 }
 
-impl Stackable for u32 {
+/// Sign-extended into a whole cell, only the low byte is read.
+impl Stackable for i8 {
+    const SIZE_WORDS: u16 = 1;
+
+    fn read(memory: &Mem, address: Address) -> Self {
+        memory.read_cell(address) as i8
+    }
+
+    fn write(&self, memory: &mut Mem, address: Address) {
+        memory.write_cell(address, (*self) as SignedCell as Cell);
+    }
+}
+
+impl Stackable for DoubleCell {
     const SIZE_WORDS: u16 = 2;
 
-    unsafe fn read(memory: &Mem, address: Address) -> Self {
-        memory.read_u32(address)
+    fn read(memory: &Mem, address: Address) -> Self {
+        memory.read_double_cell(address)
     }
 
-    unsafe fn write(&self, memory: &mut Mem, address: Address) {
-        memory.write_u32(address, *self)
+    fn write(&self, memory: &mut Mem, address: Address) {
+        memory.write_double_cell(address, *self)
     }
 }
 
-impl Stackable for i32 {
+impl Stackable for SignedDoubleCell {
     const SIZE_WORDS: u16 = 2;
 
-    unsafe fn read(memory: &Mem, address: Address) -> Self {
-        memory.read_u32(address) as i32
+    fn read(memory: &Mem, address: Address) -> Self {
+        memory.read_double_cell(address) as SignedDoubleCell
     }
 
-    unsafe fn write(&self, memory: &mut Mem, address: Address) {
-        memory.write_u32(address, *self as u32)
+    fn write(&self, memory: &mut Mem, address: Address) {
+        memory.write_double_cell(address, *self as DoubleCell)
     }
 }
 
@@ -123,58 +250,67 @@ macro_rules! count_size {
     ($t:ty, $($rest:ty),+) => (count_size!($t) + count_size!($($rest),+));
 }
 
+/// Implements getters of inputs of an effect on stack with pointer in given field of `MachineMemory`, skipping given
+/// number of cells on top of the stack.
 macro_rules! implement_getters {
-    () => ();
-    ($n:ident : $t:ty) => (implement_getters!($n : $t ,););
-    ($n:ident : $t:ty, $($ns:ident : $ts:ty),*) => (
+    ($ptr:ident, $skip:expr;) => ();
+    ($ptr:ident, $skip:expr; $n:ident : $t:ty) => (implement_getters!($ptr, $skip; $n : $t ,););
+    ($ptr:ident, $skip:expr; $n:ident : $t:ty, $($ns:ident : $ts:ty),*) => (
         pub fn $n(&self) -> $t {
-            let address = self.machine.memory.data_stack_ptr + (count_size!($($ts),*)) * 2;
+            let address = self.machine.memory.$ptr + ($skip + count_size!($($ts),*)) * crate::cell::CELL_BYTES;
 
-            unsafe {
-                <$t as crate::stack_effect::Stackable>::read(
-                    &self.machine.memory.raw_memory,
-                    address,
-                )
-            }
+            <$t as crate::stack_effect::Stackable>::read(
+                &self.machine.memory.raw_memory,
+                address,
+            )
         }
 
-        implement_getters!($($ns : $ts),*);
+        implement_getters!($ptr, $skip; $($ns : $ts),*);
     );
 }
 
+/// Implements setters of outputs of an effect on stack with pointer in given field of `MachineMemory`, the effect on
+/// that stack is returned by given method.
 macro_rules! implement_setters {
-    () => ();
-    ($n:ident : $t:ty) => (implement_setters!($n : $t ,););
-    ($n:ident : $t:ty, $($ns:ident : $ts:ty),*) => (
+    ($ptr:ident, $shape:ident;) => ();
+    ($ptr:ident, $shape:ident; $n:ident : $t:ty) => (implement_setters!($ptr, $shape; $n : $t ,););
+    ($ptr:ident, $shape:ident; $n:ident : $t:ty, $($ns:ident : $ts:ty),*) => (
         pub fn $n(&mut self, value: $t) -> &mut Self {
-            use crate::stack_effect::Stackable;
+            use crate::stack_effect::{StackEffect, Stackable};
 
-            let address = self.resulting_ptr(self.machine.memory.data_stack_ptr) + (count_size!($($ts),*)) * 2;
+            let address = self.$shape().resulting_ptr(self.machine.memory.$ptr) + (count_size!($($ts),*)) * crate::cell::CELL_BYTES;
 
-            unsafe {
-                value.write(
-                    &mut self.machine.memory.raw_memory,
-                    address,
-                )
-            }
+            value.write(
+                &mut self.machine.memory.raw_memory,
+                address,
+            );
 
             self
         }
 
-        implement_setters!($($ns : $ts),*);
+        implement_setters!($ptr, $shape; $($ns : $ts),*);
     );
 }
 
+/// Declares an effect on data stack: cells popped from the stack, cells pushed to it and, optionally, cells below the
+/// popped ones that are read but left in place, e.g. `stack_effect!(machine; => x_copy:Cell; peek x:Cell)`.
 macro_rules! stack_effect {
-    ($machine:expr; $($in_name:ident : $in_type:ty),* => $($out_name:ident : $out_type:ty),*) => ({
+    ($machine:expr; $($in_name:ident : $in_type:ty),* => $($out_name:ident : $out_type:ty),*) => (
+        stack_effect!($machine; $($in_name : $in_type),* => $($out_name : $out_type),*; peek)
+    );
+    (
+        $machine:expr;
+        $($in_name:ident : $in_type:ty),* => $($out_name:ident : $out_type:ty),*;
+        peek $($peek_name:ident : $peek_type:ty),*
+    ) => ({
         use std::fmt::{Debug, Formatter};
 
         use crate::stack_effect::count_size;
         use crate::stack_effect::implement_getters;
         use crate::stack_effect::implement_setters;
         use crate::stack_effect::StackEffect;
-        use crate::mem::MemoryAccessError;
         use crate::machine::MachineExtensions;
+        use crate::machine_error::MachineError;
 
         struct Effect<'m, TExt: MachineExtensions> {
             machine: &'m mut crate::machine::Machine<TExt>,
@@ -185,9 +321,9 @@ macro_rules! stack_effect {
                 let current_ptr = self.machine.memory.data_stack_ptr;
 
                 write!(
-                    f, "({} -- {})(-{} +{})[{:04X};{:04X}][{:04X} -> {:04X}]",
+                    f, "({} -- {})(-{} +{} ?{})[{:04X};{:04X}][{:04X} -> {:04X}]",
                     stringify!($($in_name : $in_type),*), stringify!($($out_name : $out_type),*),
-                    self.in_words(), self.out_words(),
+                    self.in_words(), self.out_words(), self.peek_words(),
                     self.min_ptr(current_ptr), self.max_ptr(current_ptr),
                     current_ptr, self.resulting_ptr(current_ptr),
                 )
@@ -202,18 +338,29 @@ macro_rules! stack_effect {
             fn out_words(&self) -> u16 {
                 count_size!($($out_type),*)
             }
+
+            fn peek_words(&self) -> u16 {
+                count_size!($($peek_type),*)
+            }
         }
 
         impl <'m, TExt: MachineExtensions>Effect<'m, TExt> {
-            implement_getters!($($in_name : $in_type),*);
-            implement_setters!($($out_name : $out_type),*);
+            implement_getters!(data_stack_ptr, 0; $($in_name : $in_type),*);
+            implement_getters!(data_stack_ptr, count_size!($($in_type),*); $($peek_name : $peek_type),*);
+            implement_setters!(data_stack_ptr, data_shape; $($out_name : $out_type),*);
+
+            #[allow(dead_code)] // used by setters only
+            fn data_shape(&self) -> &Self {
+                self
+            }
 
             fn commit(self) {
                 self.machine.memory.data_stack_ptr = self.resulting_ptr(self.machine.memory.data_stack_ptr);
+                self.machine.memory.update_stack_usage();
             }
 
-            fn validate(self) -> Result<Self, MemoryAccessError> {
-                self.validate_access(
+            fn validate(self) -> Result<Self, MachineError> {
+                self.validate_stack(
                     &self.machine.memory.raw_memory,
                     self.machine.memory.data_stack_ptr,
                     self.machine.memory.get_data_stack_segment(),
@@ -227,18 +374,161 @@ macro_rules! stack_effect {
     })
 }
 
+/// Same as `stack_effect!` but declares effects on both data and call stacks, e.g.
+/// `call_stack_effect!(machine; data: => value:Cell; call: saved:Cell =>)` moves a cell from call stack to data stack.
+///
+/// Both effects are validated before any of them may be committed, so a failed effect leaves both stacks intact.
+macro_rules! call_stack_effect {
+    (
+        $machine:expr;
+        data: $($in_name:ident : $in_type:ty),* => $($out_name:ident : $out_type:ty),*;
+        call: $($call_in_name:ident : $call_in_type:ty),* => $($call_out_name:ident : $call_out_type:ty),*
+    ) => ({
+        use std::fmt::{Debug, Formatter};
+
+        use crate::stack_effect::count_size;
+        use crate::stack_effect::implement_getters;
+        use crate::stack_effect::implement_setters;
+        use crate::stack_effect::{StackEffect, StackShape};
+        use crate::machine::MachineExtensions;
+        use crate::machine_error::MachineError;
+
+        struct Effect<'m, TExt: MachineExtensions> {
+            machine: &'m mut crate::machine::Machine<TExt>,
+        }
+
+        impl<'m, TExt: MachineExtensions> Debug for Effect<'m, TExt> {
+            fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+                let (data_ptr, call_ptr) = (self.machine.memory.data_stack_ptr, self.machine.memory.call_stack_ptr);
+
+                write!(
+                    f, "({} -- {})[{:04X} -> {:04X}] (R: {} -- {})[{:04X} -> {:04X}]",
+                    stringify!($($in_name : $in_type),*), stringify!($($out_name : $out_type),*),
+                    data_ptr, self.data_shape().resulting_ptr(data_ptr),
+                    stringify!($($call_in_name : $call_in_type),*), stringify!($($call_out_name : $call_out_type),*),
+                    call_ptr, self.call_shape().resulting_ptr(call_ptr),
+                )
+            }
+        }
+
+        impl <'m, TExt: MachineExtensions>Effect<'m, TExt> {
+            implement_getters!(data_stack_ptr, 0; $($in_name : $in_type),*);
+            implement_setters!(data_stack_ptr, data_shape; $($out_name : $out_type),*);
+            implement_getters!(call_stack_ptr, 0; $($call_in_name : $call_in_type),*);
+            implement_setters!(call_stack_ptr, call_shape; $($call_out_name : $call_out_type),*);
+
+            fn data_shape(&self) -> StackShape {
+                StackShape {
+                    in_words: count_size!($($in_type),*),
+                    out_words: count_size!($($out_type),*),
+                }
+            }
+
+            fn call_shape(&self) -> StackShape {
+                StackShape {
+                    in_words: count_size!($($call_in_type),*),
+                    out_words: count_size!($($call_out_type),*),
+                }
+            }
+
+            fn commit(self) {
+                let (data_shape, call_shape) = (self.data_shape(), self.call_shape());
+                let memory = &mut self.machine.memory;
+                memory.data_stack_ptr = data_shape.resulting_ptr(memory.data_stack_ptr);
+                memory.call_stack_ptr = call_shape.resulting_ptr(memory.call_stack_ptr);
+                memory.update_stack_usage();
+            }
+
+            fn validate(self) -> Result<Self, MachineError> {
+                let memory = &self.machine.memory;
+
+                self.data_shape().validate_stack(
+                    &memory.raw_memory,
+                    memory.data_stack_ptr,
+                    memory.get_data_stack_segment(),
+                )?;
+                self.call_shape().validate_call_stack(
+                    &memory.raw_memory,
+                    memory.call_stack_ptr,
+                    memory.get_call_stack_segment(),
+                )?;
+
+                Ok(self)
+            }
+        }
+
+        (Effect { machine: $machine }).validate()
+    })
+}
+
 #[cfg(test)]
 mod test {
-    use crate::mem::MemoryAccessError;
+    use crate::cell::{Cell, DoubleCell, SignedCell, SignedDoubleCell};
+    use crate::machine_error::MachineError;
     use crate::machine_testing::{StackElement, TestMachine};
-    use crate::stack_effect::StackEffect;
+    use crate::mem::Mem;
+    use crate::stack_effect::{StackEffect, Stackable};
+
+    /// Write a value as one type and read it back as another.
+    fn convert<A: Stackable, B: Stackable>(value: A) -> B {
+        let mut mem = Mem::default();
+        value.write(&mut mem, 0x100);
+
+        B::read(&mem, 0x100)
+    }
+
+    #[test]
+    fn test_narrow_values_layout() {
+        assert_eq!(u8::SIZE_WORDS, 1);
+        assert_eq!(i8::SIZE_WORDS, 1);
+
+        assert_eq!(convert::<u8, Cell>(0xF0), 0xF0);
+        assert_eq!(convert::<i8, Cell>(-2), Cell::MAX - 1);
+        assert_eq!(convert::<i8, SignedCell>(-128), -128);
+        assert_eq!(convert::<Cell, u8>(0x1234), 0x34);
+        assert_eq!(convert::<Cell, i8>(0x12F0), -16);
+        assert_eq!(convert::<SignedCell, i8>(-1), -1);
+        assert_eq!(convert::<i8, u8>(-1), 0xFF);
+    }
+
+    #[test]
+    fn test_cell_values_layout() {
+        assert_eq!(convert::<SignedCell, Cell>(-1), Cell::MAX);
+        assert_eq!(convert::<Cell, SignedCell>(Cell::MAX), -1);
+        assert_eq!(convert::<i16, u16>(-2), 0xFFFE);
+        assert_eq!(convert::<u16, i16>(0x8000), i16::MIN);
+
+        // Addresses and 16-bit numbers are zero- or sign-extended to whole cells
+        assert_eq!(convert::<u16, Cell>(0xFFFF), 0xFFFF);
+        assert_eq!(convert::<i16, SignedCell>(-300), -300);
+        assert_eq!(u16::SIZE_WORDS, 1);
+        assert_eq!(i16::SIZE_WORDS, 1);
+    }
+
+    #[test]
+    fn test_double_cell_values_layout() {
+        assert_eq!(DoubleCell::SIZE_WORDS, 2);
+        assert_eq!(convert::<SignedDoubleCell, DoubleCell>(-1), DoubleCell::MAX);
+        assert_eq!(convert::<DoubleCell, SignedDoubleCell>(DoubleCell::MAX - 1), -2);
+        assert_eq!(convert::<DoubleCell, DoubleCell>(0x12345678), 0x12345678);
+    }
+
+    #[test]
+    fn test_bool_layout() {
+        assert_eq!(convert::<bool, Cell>(true), Cell::MAX);
+        assert_eq!(convert::<bool, Cell>(false), 0);
+        assert_eq!(convert::<bool, SignedCell>(true), -1);
+        assert!(convert::<Cell, bool>(1));
+        assert!(convert::<u8, bool>(0x80));
+        assert!(!convert::<Cell, bool>(0));
+    }
 
     #[test]
     fn test_2_to_1_effect() {
         let mut machine = TestMachine::default();
 
-        machine.memory.data_push_u16(0x1234).unwrap();
-        machine.memory.data_push_u16(0xabcd).unwrap();
+        machine.memory.data_push_cell(0x1234).unwrap();
+        machine.memory.data_push_cell(0xabcd).unwrap();
 
         let mut fx = stack_effect!(&mut machine; a:u16, b:u16 => c:u16).unwrap();
 
@@ -259,7 +549,7 @@ mod test {
     fn test_1_to_2_effect() {
         let mut machine = TestMachine::default();
 
-        machine.memory.data_push_u16(0x1234).unwrap();
+        machine.memory.data_push_cell(0x1234).unwrap();
 
         let mut fx = stack_effect!(&mut machine; a:u16 => b:u16, c:u16).unwrap();
 
@@ -279,21 +569,21 @@ mod test {
     fn test_underflow() {
         let mut machine = TestMachine::default();
 
-        machine.memory.data_push_u16(0x1234).unwrap();
+        machine.memory.data_push_cell(0x1234).unwrap();
 
         #[allow(dead_code)] // commit() not used
             let res = stack_effect!(&mut machine; _a:u16, _b:u16 => _c:u16);
 
-        assert!(matches!(res, Err(MemoryAccessError { .. })));
+        assert!(matches!(res, Err(MachineError::DataStackUnderflow { requested: 2, .. })));
     }
 
     #[test]
     fn test_overflow() {
         let mut machine = TestMachine::default();
 
-        machine.memory.data_stack_ptr = 4;
+        machine.memory.data_stack_ptr = 2 * crate::cell::CELL_BYTES + machine.memory.layout_config().guard_size;
 
-        machine.memory.data_push_u16(0x1234).unwrap();
+        machine.memory.data_push_cell(0x1234).unwrap();
 
         #[allow(dead_code)] // commit() not used
         stack_effect!(&mut machine; _a:u16 => _b:u16, _c:u16).unwrap(); // No overflow yet
@@ -302,13 +592,159 @@ mod test {
             let res = stack_effect!(&mut machine; _a:u16 => _b:u16, _c:u16, _d:u16);
 
         assert!(
-            matches!(res, Err(MemoryAccessError { .. })),
+            matches!(res, Err(MachineError::DataStackOverflow { requested: 2, .. })),
+            "{:?}", res
+        );
+    }
+
+    #[test]
+    fn test_peek_effect() {
+        let mut machine = TestMachine::default();
+
+        machine.memory.data_push_cell(0x1234).unwrap();
+        machine.memory.data_push_cell(0xabcd).unwrap();
+        machine.memory.data_push_cell(0x5678).unwrap();
+
+        let base = machine.memory.data_stack_ptr;
+        let mut fx = stack_effect!(&mut machine; a:u16 => b:u16, c:u16; peek d:u16, e:u16).unwrap();
+
+        assert_eq!(fx.in_words(), 1);
+        assert_eq!(fx.out_words(), 2);
+        assert_eq!(fx.peek_words(), 2);
+
+        let cell = crate::cell::CELL_BYTES;
+        assert_eq!(fx.min_ptr(base), base - cell);
+        assert_eq!(fx.max_ptr(base), base + 3 * cell - 1);
+        assert_eq!(fx.resulting_ptr(base), base - cell);
+
+        assert_eq!(fx.a(), 0x5678);
+        assert_eq!(fx.d(), 0x1234);
+        assert_eq!(fx.e(), 0xabcd);
+
+        let (a, d) = (fx.a(), fx.d());
+        fx.b(d).c(a);
+
+        fx.commit();
+
+        machine.assert_data_stack_state(&[
+            StackElement::Cell(0x1234),
+            StackElement::Cell(0xabcd),
+            StackElement::Cell(0x1234),
+            StackElement::Cell(0x5678),
+        ]);
+    }
+
+    #[test]
+    fn test_peek_only_effect() {
+        let mut machine = TestMachine::default();
+
+        machine.memory.data_push_cell(0x1234).unwrap();
+
+        let base = machine.memory.data_stack_ptr;
+        let fx = stack_effect!(&mut machine; => ; peek a:u16).unwrap();
+
+        assert_eq!(fx.min_ptr(base), base);
+        assert_eq!(fx.max_ptr(base), base + crate::cell::CELL_BYTES - 1);
+        assert_eq!(fx.resulting_ptr(base), base);
+        assert_eq!(fx.a(), 0x1234);
+
+        fx.commit();
+
+        machine.assert_data_stack_state(&[StackElement::Cell(0x1234)]);
+    }
+
+    #[test]
+    fn test_peek_underflow() {
+        let mut machine = TestMachine::default();
+
+        machine.memory.data_push_cell(0x1234).unwrap();
+
+        #[allow(dead_code)] // commit() not used
+            let res = stack_effect!(&mut machine; _a:u16 => _b:u16; peek _c:u16);
+
+        assert!(matches!(res, Err(MachineError::DataStackUnderflow { requested: 2, .. })), "{:?}", res);
+
+        #[allow(dead_code)] // commit() not used
+            let res = stack_effect!(&mut machine; => ; peek _a:u16, _b:u16);
+
+        assert!(matches!(res, Err(MachineError::DataStackUnderflow { requested: 2, .. })), "{:?}", res);
+    }
+
+    #[test]
+    fn test_call_stack_underflow() {
+        let mut machine = TestMachine::default();
+
+        machine.memory.call_push_cell(0x1234).unwrap();
+
+        #[allow(dead_code)] // commit() not used
+            let res = call_stack_effect!(&mut machine; data: => _a:u16; call: _b:u16, _c:u16 =>);
+
+        assert!(matches!(res, Err(MachineError::CallStackUnderflow { requested: 2, .. })), "{:?}", res);
+    }
+
+    #[test]
+    fn test_call_stack_overflow() {
+        let mut machine = TestMachine::default();
+
+        machine.memory.call_stack_ptr = machine.memory.get_call_stack_segment().start() + 2 * crate::cell::CELL_BYTES;
+
+        machine.memory.call_push_cell(0x1234).unwrap();
+
+        #[allow(dead_code)] // commit() not used
+        call_stack_effect!(&mut machine; data: =>; call: _a:u16 => _b:u16, _c:u16).unwrap(); // No overflow yet
+
+        #[allow(dead_code)] // commit() not used
+            let res = call_stack_effect!(&mut machine; data: =>; call: _a:u16 => _b:u16, _c:u16, _d:u16);
+
+        assert!(
+            matches!(res, Err(MachineError::CallStackOverflow { requested: 2, .. })),
             "{:?}", res
         );
     }
+
+    #[test]
+    fn test_mixed_effect() {
+        let mut machine = TestMachine::default();
+
+        machine.memory.data_push_cell(0x1234).unwrap();
+        machine.memory.data_push_cell(0xabcd).unwrap();
+        machine.memory.call_push_cell(0x5678).unwrap();
+
+        let mut fx = call_stack_effect!(&mut machine; data: a:u16, b:u16 => c:u16; call: d:u16 => e:u16, f:u16).unwrap();
+
+        assert_eq!(fx.a(), 0x1234);
+        assert_eq!(fx.b(), 0xabcd);
+        assert_eq!(fx.d(), 0x5678);
+
+        fx.c(0xef56).e(0x4213).f(0x1111);
+
+        fx.commit();
+
+        machine.assert_data_stack_state(&[StackElement::Cell(0xef56)]);
+        assert_eq!(machine.memory.call_pop_cell().unwrap(), 0x1111);
+        assert_eq!(machine.memory.call_pop_cell().unwrap(), 0x4213);
+        assert_eq!(machine.memory.call_stack_depth(), 0);
+    }
+
+    #[test]
+    fn test_failed_mixed_effect_keeps_both_stacks() {
+        let mut machine = TestMachine::default();
+
+        machine.memory.data_push_cell(0x1234).unwrap();
+
+        // The data stack part is valid but nothing may be popped from empty call stack
+        #[allow(dead_code)] // commit() not used
+            let res = call_stack_effect!(&mut machine; data: _a:u16 => ; call: _b:u16 => _c:u16);
+
+        assert!(matches!(res, Err(MachineError::CallStackUnderflow { requested: 1, .. })), "{:?}", res);
+
+        machine.assert_data_stack_state(&[StackElement::Cell(0x1234)]);
+        assert_eq!(machine.memory.call_stack_depth(), 0);
+    }
 }
 
 pub(crate) use count_size;
 pub(crate) use implement_getters;
 pub(crate) use implement_setters;
 pub(crate) use stack_effect;
+pub(crate) use call_stack_effect;
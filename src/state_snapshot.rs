@@ -0,0 +1,118 @@
+use crate::cell::{Cell, CELL_BYTES};
+use crate::machine::{Machine, MachineExtensions};
+use crate::machine_memory::MachineMemory;
+use crate::machine_state::MachineState;
+use crate::mem::Address;
+
+/// Dictionary article as listed in `MachineSnapshot`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct WordSnapshot {
+    pub name: String,
+
+    /// Address of article header.
+    pub address: Address,
+}
+
+/// Registers of a machine and values derived from them, for use by external tools.
+///
+/// Unlike `Machine::snapshot`, does not include memory content. With `serde` feature enabled it can be serialized,
+/// e.g. as JSON.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct MachineSnapshot {
+    /// Data stack values, bottom to top.
+    pub data_stack: Vec<Cell>,
+
+    /// Call stack values, bottom to top.
+    pub call_stack: Vec<Cell>,
+
+    pub dict_ptr: Address,
+
+    /// Dictionary size in bytes.
+    pub dictionary_size: u16,
+
+    pub state: MachineState,
+
+    pub base: Cell,
+
+    /// Dictionary articles, the most recently defined first.
+    pub words: Vec<WordSnapshot>,
+
+    /// Position of current input, `None` if it can't be determined.
+    pub input_position: Option<u32>,
+}
+
+fn stack_values(memory: &MachineMemory, sp: Address, depth: u16) -> Vec<Cell> {
+    (0..depth).rev().map(|i| memory.raw_memory.read_cell(sp + CELL_BYTES * i)).collect()
+}
+
+impl<TExt: MachineExtensions> Machine<TExt> {
+    /// Take a snapshot of machine registers and derived values.
+    pub fn state_snapshot(&mut self) -> MachineSnapshot {
+        let memory = &self.memory;
+        let words = memory.articles()
+            .map(|article| WordSnapshot {
+                name: String::from_utf8_lossy(&article.name().as_bytes()).into_owned(),
+                address: article.get_header_address(),
+            })
+            .collect();
+
+        MachineSnapshot {
+            data_stack: stack_values(memory, memory.data_stack_ptr, memory.data_stack_depth()),
+            call_stack: stack_values(memory, memory.call_stack_ptr, memory.call_stack_depth()),
+            dict_ptr: memory.get_dict_ptr(),
+            dictionary_size: memory.dictionary_size(),
+            state: memory.get_state(),
+            base: memory.get_base(),
+            words,
+            input_position: self.input().tell().ok(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::input::StaticStringInput;
+    use crate::machine_testing::*;
+
+    use super::*;
+
+    fn run_program() -> TestMachine {
+        let mut machine = TestMachine::default();
+        machine.extensions.input = StaticStringInput::new(": square DUP * ; : cube DUP square * ; 3 cube 16 BASE ! 7");
+        machine.interpret_input().unwrap();
+
+        machine
+    }
+
+    #[test]
+    fn test_state_snapshot() {
+        let mut machine = run_program();
+        let snapshot = machine.state_snapshot();
+
+        assert_eq!(snapshot.data_stack, vec![27, 7]);
+        assert!(snapshot.call_stack.is_empty());
+        assert_eq!(snapshot.dict_ptr, machine.memory.get_dict_ptr());
+        assert_eq!(snapshot.dictionary_size, machine.memory.dictionary_size());
+        assert_eq!(snapshot.state, MachineState::Interpreter);
+        assert_eq!(snapshot.base, 16);
+        assert_eq!(snapshot.words.iter().map(|word| word.name.as_str()).collect::<Vec<_>>(), vec!["cube", "square"]);
+        assert_eq!(snapshot.words[1].address, machine.memory.lookup_article(b"square").unwrap().unwrap().get_header_address());
+        assert_eq!(snapshot.input_position, Some(57));
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_serialize_snapshot() {
+        let snapshot = run_program().state_snapshot();
+        let json: serde_json::Value = serde_json::from_str(&serde_json::to_string(&snapshot).unwrap()).unwrap();
+
+        assert_eq!(json["data_stack"], serde_json::json!([27, 7]));
+        assert_eq!(json["state"], "interpreter");
+        assert_eq!(json["base"], 16);
+        assert_eq!(json["words"][0]["name"], "cube");
+        assert_eq!(json["words"][1]["address"], snapshot.words[1].address);
+        assert_eq!(json["input_position"], 57);
+    }
+}
@@ -0,0 +1,324 @@
+//! Words of the tester used by Forth test suites: `T{ code -> expected results }T`.
+//!
+//! `T{` remembers data stack depth and `BASE`, `->` takes results left by the code above that depth and restores
+//! `BASE`, so expected results are read in the radix the test started with. `}T` takes the expected results and
+//! compares them with the actual ones. A mismatch is reported to machine output as
+//!
+//! ```text
+//! INCORRECT RESULT: expected 3, actual 4
+//! WRONG NUMBER OF RESULTS: expected 3, actual 3 4
+//! ```
+//!
+//! with values printed in the radix the test started with. Mismatches are counted by `Tester::failures` and fail
+//! with `MachineError::TestFailed` if `Tester::fail_on_mismatch` is set. Tests may be nested. Tests left unfinished
+//! by an error are forgotten.
+
+use crate::cell::{Cell, SignedCell};
+use crate::literal::format_literal;
+use crate::machine::{Machine, MachineExtensions};
+use crate::machine_error::MachineError;
+use crate::machine_memory::ReservedAddresses;
+use crate::mem::Address;
+
+/// A test started by `T{` and not yet finished by `}T`.
+struct Frame {
+    /// Data stack depth below the results.
+    depth: u16,
+
+    /// `BASE` at the start of the test.
+    base: Cell,
+
+    /// Results taken by `->`.
+    actual: Option<Vec<Cell>>,
+
+    /// Code or expected results didn't take cells from below the results.
+    balanced: bool,
+}
+
+/// State of `T{`, `->` and `}T`.
+#[derive(Default)]
+pub struct Tester {
+    frames: Vec<Frame>,
+
+    /// Number of mismatches found by `}T`.
+    pub failures: u32,
+
+    /// Fail with `MachineError::TestFailed` on a mismatch after reporting it.
+    pub fail_on_mismatch: bool,
+}
+
+impl Tester {
+    /// Forget tests that are not finished, as interpretation of their code was aborted.
+    pub fn abort(&mut self) {
+        self.frames.clear();
+    }
+}
+
+fn unmatched(name: &str) -> MachineError {
+    MachineError::UnmatchedTestWord { name: name.to_string() }
+}
+
+fn base_address<TExt: MachineExtensions>(machine: &Machine<TExt>) -> Address {
+    machine.memory.get_reserved_address(ReservedAddresses::BaseVar)
+}
+
+/// Take cells above given depth from data stack, the deepest one first.
+///
+/// Returns `false` as the second element if the stack is already shallower than the depth.
+fn take_results<TExt: MachineExtensions>(machine: &mut Machine<TExt>, depth: u16) -> Result<(Vec<Cell>, bool), MachineError> {
+    let current_depth = machine.memory.data_stack_depth();
+    let mut results = Vec::new();
+
+    for _ in depth..current_depth {
+        results.push(machine.pop::<Cell>()?);
+    }
+    results.reverse();
+
+    Ok((results, current_depth >= depth))
+}
+
+// `Cell` is `u32` with `cell32` feature
+#[allow(clippy::unnecessary_cast)]
+fn format_results(results: &[Cell], base: Cell) -> String {
+    if results.is_empty() {
+        return "none".to_string();
+    }
+
+    results.iter().map(|&value| format_literal(value as SignedCell, base as u32)).collect::<Vec<_>>().join(" ")
+}
+
+impl<TExt: MachineExtensions> Machine<TExt> {
+    /// Start a test, as `T{` does.
+    pub fn begin_test(&mut self) -> Result<(), MachineError> {
+        let frame = Frame {
+            depth: self.memory.data_stack_depth(),
+            base: self.memory.raw_memory.read_cell(base_address(self)),
+            actual: None,
+            balanced: true,
+        };
+        self.tester.frames.push(frame);
+
+        Ok(())
+    }
+
+    /// Take actual results of the current test, as `->` does.
+    pub fn take_test_results(&mut self) -> Result<(), MachineError> {
+        let (depth, base) = match self.tester.frames.last() {
+            Some(Frame { depth, base, actual: None, .. }) => (*depth, *base),
+            _ => return Err(unmatched("->")),
+        };
+
+        let (results, balanced) = take_results(self, depth)?;
+        let current_depth = self.memory.data_stack_depth();
+        let address = base_address(self);
+        self.memory.raw_memory.write_cell(address, base);
+
+        let frame = self.tester.frames.last_mut().expect("frame is checked above");
+        frame.depth = current_depth;
+        frame.actual = Some(results);
+        frame.balanced = balanced;
+
+        Ok(())
+    }
+
+    /// Compare expected results of the current test with the actual ones and finish the test, as `}T` does.
+    pub fn end_test(&mut self) -> Result<(), MachineError> {
+        if !matches!(self.tester.frames.last(), Some(Frame { actual: Some(_), .. })) {
+            return Err(unmatched("}T"));
+        }
+
+        let frame = self.tester.frames.pop().expect("frame is checked above");
+        let actual = frame.actual.expect("frame is checked above");
+        let (expected, balanced) = take_results(self, frame.depth)?;
+
+        let kind = if !(frame.balanced && balanced) || actual.len() != expected.len() {
+            "WRONG NUMBER OF RESULTS"
+        } else if actual != expected {
+            "INCORRECT RESULT"
+        } else {
+            return Ok(());
+        };
+
+        let message = format!(
+            "{}: expected {}, actual {}",
+            kind, format_results(&expected, frame.base), format_results(&actual, frame.base),
+        );
+
+        self.tester.failures += 1;
+        self.emit_str(&message)?;
+        self.emit_str("\n")?;
+
+        if self.tester.fail_on_mismatch {
+            return Err(MachineError::TestFailed { message });
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::input::StaticStringInput;
+    use crate::machine_testing::*;
+
+    use super::*;
+
+    /// Part of the core word set tests of Forth 2012 test suite, limited to implemented words.
+    const CORE_TESTS: &str = "
+        : <FALSE> 0 ;
+        : <TRUE> -1 ;
+
+        T{ -> }T
+        T{ : BITSSET? IF 0 0 ELSE 0 THEN ; -> }T
+        T{  0 BITSSET? -> 0 }T
+        T{  1 BITSSET? -> 0 0 }T
+        T{ -1 BITSSET? -> 0 0 }T
+
+        T{ 0 0 AND -> 0 }T
+        T{ 0 1 AND -> 0 }T
+        T{ 1 0 AND -> 0 }T
+        T{ 1 1 AND -> 1 }T
+        T{ 0 INVERT 1 AND -> 1 }T
+        T{ 1 INVERT 1 AND -> 0 }T
+        T{ 0 0 OR -> 0 }T
+        T{ 0 1 OR -> 1 }T
+        T{ 1 0 OR -> 1 }T
+        T{ 1 1 OR -> 1 }T
+        T{ 0 0 XOR -> 0 }T
+        T{ 0 1 XOR -> 1 }T
+        T{ 1 0 XOR -> 1 }T
+        T{ 1 1 XOR -> 0 }T
+
+        T{ 1 2 2DROP -> }T
+        T{ 1 2 2DUP -> 1 2 1 2 }T
+        T{ 1 2 3 4 2OVER -> 1 2 3 4 1 2 }T
+        T{ 1 2 3 4 2SWAP -> 3 4 1 2 }T
+        T{ 1 2 DROP -> 1 }T
+        T{ 1 DUP -> 1 1 }T
+        T{ 1 2 OVER -> 1 2 1 }T
+        T{ 1 2 3 ROT -> 2 3 1 }T
+        T{ 1 2 SWAP -> 2 1 }T
+
+        T{ 0 5 + -> 5 }T
+        T{ 5 0 + -> 5 }T
+        T{ 0 -5 + -> -5 }T
+        T{ -1 1 + -> 0 }T
+        T{ 0 5 - -> -5 }T
+        T{ 5 0 - -> 5 }T
+        T{ 0 -5 - -> 5 }T
+        T{ 1 2 - -> -1 }T
+        T{ 0 0 * -> 0 }T
+        T{ 1 2 * -> 2 }T
+        T{ -1 2 * -> -2 }T
+        T{ -3 -3 * -> 9 }T
+
+        T{ 0 0 = -> <TRUE> }T
+        T{ 1 1 = -> <TRUE> }T
+        T{ 1 0 = -> <FALSE> }T
+        T{ 0 1 < -> <TRUE> }T
+        T{ 1 0 < -> <FALSE> }T
+        T{ -1 0 < -> <TRUE> }T
+        T{ 0 1 > -> <FALSE> }T
+        T{ 1 0 > -> <TRUE> }T
+
+        T{ : GR1 >R R> ; -> }T
+        T{ : GR2 >R R@ R> DROP ; -> }T
+        T{ 123 GR1 -> 123 }T
+        T{ 123 GR2 -> 123 }T
+        T{ : GR3 2>R 2R> ; -> }T
+        T{ 1 2 GR3 -> 1 2 }T
+
+        T{ : GT1 123 ; -> }T
+        T{ ' GT1 EXECUTE -> 123 }T
+        T{ : GI1 IF 123 THEN ; -> }T
+        T{ : GI2 IF 123 ELSE 234 THEN ; -> }T
+        T{  0 GI1 -> }T
+        T{  1 GI1 -> 123 }T
+        T{ -1 GI1 -> 123 }T
+        T{  0 GI2 -> 234 }T
+        T{  1 GI2 -> 123 }T
+        T{ : GI3 BEGIN DUP 5 < WHILE DUP 1 + REPEAT ; -> }T
+        T{ 0 GI3 -> 0 1 2 3 4 5 }T
+        T{ 4 GI3 -> 4 5 }T
+        T{ 6 GI3 -> 6 }T
+    ";
+
+    #[test]
+    fn test_core_tests() {
+        let mut machine = TestMachine::with_prelude(TestMachineExtensions::default()).unwrap();
+        machine.interpret_str(CORE_TESTS).unwrap();
+
+        assert_eq!(machine.tester.failures, 0);
+        assert!(machine.extensions.output.content.take().is_empty());
+        machine.assert_data_stack_state(&[]);
+    }
+
+    #[test]
+    fn test_mismatches() {
+        let mut machine = TestMachine::default();
+        machine.interpret_str("
+            100
+            T{ 1 2 + -> 4 }T
+            T{ 1 2 -> 1 }T
+            T{ DROP -> }T
+            16 BASE ! T{ 10 -> 11 }T
+        ").unwrap();
+
+        assert_eq!(
+            String::from_utf8(machine.extensions.output.content.take()).unwrap(),
+            "INCORRECT RESULT: expected 4, actual 3\n\
+             WRONG NUMBER OF RESULTS: expected 1, actual 1 2\n\
+             WRONG NUMBER OF RESULTS: expected none, actual none\n\
+             INCORRECT RESULT: expected 11, actual 10\n",
+        );
+        assert_eq!(machine.tester.failures, 4);
+        machine.assert_data_stack_state(&[]);
+
+        machine.tester.fail_on_mismatch = true;
+        machine.extensions.input = StaticStringInput::new("#10 BASE ! T{ -1 -> $FF }T");
+        assert!(matches!(
+            machine.interpret_input(),
+            Err(MachineError::TestFailed { message }) if message == "INCORRECT RESULT: expected 255, actual -1",
+        ));
+        assert_eq!(machine.tester.failures, 5);
+    }
+
+    #[test]
+    fn test_nested_and_sequential_tests() {
+        let mut machine = TestMachine::default();
+        machine.interpret_str("
+            T{ 1 T{ 2 -> 2 }T -> 1 }T
+            T{ T{ 3 -> 4 }T 5 -> 5 }T
+            : check T{ 2 3 + -> 5 }T ;
+            T{ check check -> }T
+            T{ 16 BASE ! 11 -> 17 }T
+            BASE @
+        ").unwrap();
+
+        assert_eq!(machine.extensions.output.content.take(), b"INCORRECT RESULT: expected 4, actual 3\n");
+        assert_eq!(machine.tester.failures, 1);
+        machine.assert_data_stack_state(&[StackElement::Cell(10)]);
+
+        for source in ["->", "}T", "T{ 1 }T", "T{ -> -> }T"] {
+            let mut machine = TestMachine::default();
+            machine.extensions.input = StaticStringInput::new(source);
+
+            assert!(matches!(machine.interpret_input(), Err(MachineError::UnmatchedTestWord { .. })), "{}", source);
+        }
+    }
+
+    #[test]
+    fn test_aborted_test() {
+        let mut machine = TestMachine::default();
+        machine.extensions.input = StaticStringInput::new("T{ 1 T{ 2 oops");
+        assert!(matches!(machine.interpret_input(), Err(MachineError::IllegalWord(_))));
+
+        machine.extensions.input = StaticStringInput::new("->");
+        assert!(matches!(machine.interpret_input(), Err(MachineError::UnmatchedTestWord { .. })));
+
+        machine.reset();
+        machine.interpret_str("T{ 3 -> 3 }T").unwrap();
+        assert_eq!(machine.tester.failures, 0);
+        machine.assert_data_stack_state(&[]);
+    }
+}
@@ -0,0 +1,110 @@
+use std::io;
+
+use crate::machine::{Machine, MachineExtensions};
+use crate::mem::Address;
+use crate::opcodes::OpCode;
+
+/// Receives every instruction a machine is about to execute.
+pub trait Tracer<TExt: MachineExtensions> {
+    fn trace(&mut self, machine: &Machine<TExt>, address: Address, op_code: OpCode);
+}
+
+impl<TExt: MachineExtensions, F: FnMut(&Machine<TExt>, Address, OpCode)> Tracer<TExt> for F {
+    fn trace(&mut self, machine: &Machine<TExt>, address: Address, op_code: OpCode) {
+        self(machine, address, op_code)
+    }
+}
+
+/// Tracer writing disassembly of each executed instruction, in the same format as `OpCode::format_at`.
+///
+/// Errors writing to the underlying writer are ignored.
+pub struct WriteTracer<W: io::Write> {
+    writer: W,
+}
+
+impl<W: io::Write> WriteTracer<W> {
+    pub fn new(writer: W) -> Self {
+        WriteTracer { writer }
+    }
+}
+
+impl<TExt: MachineExtensions, W: io::Write> Tracer<TExt> for WriteTracer<W> {
+    fn trace(&mut self, machine: &Machine<TExt>, address: Address, _op_code: OpCode) {
+        let _ = OpCode::format_at(&mut self.writer, machine, address);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    use crate::input::StaticStringInput;
+    use crate::machine_testing::*;
+
+    use super::*;
+
+    #[test]
+    fn test_trace_branches() {
+        let mut machine = TestMachine::default();
+        machine.extensions.input = StaticStringInput::new(": choose IF 10 ELSE 20 THEN ;");
+        machine.interpret_input().unwrap();
+
+        let body_address = machine.memory.lookup_article(b"choose").unwrap().unwrap().body_address();
+        let trace = Rc::new(RefCell::new(Vec::new()));
+        let trace_sink = trace.clone();
+
+        machine.set_tracer(Box::new(move |_: &TestMachine, address, op_code| {
+            trace_sink.borrow_mut().push((address, op_code));
+        }));
+
+        machine.extensions.input = StaticStringInput::new("1 choose");
+        machine.interpret_input().unwrap();
+
+        let not_taken = trace.take();
+        assert_eq!(
+            not_taken.iter().map(|(_, op_code)| *op_code).collect::<Vec<_>>(),
+            vec![OpCode::DefaultArticleStart, OpCode::BranchRelIfZ, OpCode::Literal8, OpCode::BranchRel, OpCode::Return],
+        );
+        assert_eq!(not_taken[0].0, body_address);
+        assert_eq!(not_taken[1].0, body_address + 1);
+
+        machine.extensions.input = StaticStringInput::new("0 choose");
+        machine.interpret_input().unwrap();
+
+        let taken = trace.take();
+        assert_eq!(
+            taken.iter().map(|(_, op_code)| *op_code).collect::<Vec<_>>(),
+            vec![OpCode::DefaultArticleStart, OpCode::BranchRelIfZ, OpCode::Literal8, OpCode::Return],
+        );
+        // The taken branch skips the first literal and the jump following it
+        assert_eq!(taken[2].0, not_taken[3].0 + 3);
+        assert_eq!(taken[3].0, not_taken[4].0);
+
+        machine.assert_data_stack_state(&[StackElement::Cell(10), StackElement::Cell(20)]);
+    }
+
+    #[test]
+    fn test_write_tracer() {
+        let mut machine = TestMachine::default();
+        machine.extensions.input = StaticStringInput::new(": sum 1 2 + ;");
+        machine.interpret_input().unwrap();
+
+        let buffer = SharedBuffer::default();
+        machine.trace_to(buffer.clone());
+
+        machine.extensions.input = StaticStringInput::new("sum");
+        machine.interpret_input().unwrap();
+
+        let body_address = machine.memory.lookup_article(b"sum").unwrap().unwrap().body_address();
+        let mut expected = Vec::new();
+        let mut address = body_address;
+
+        while address < machine.memory.get_dict_ptr() {
+            address = OpCode::format_at(&mut expected, &machine, address).unwrap();
+        }
+
+        assert_eq!(String::from_utf8(buffer.content()).unwrap(), String::from_utf8(expected).unwrap());
+        machine.assert_data_stack_state(&[StackElement::Cell(3)]);
+    }
+}